@@ -0,0 +1,41 @@
+use blockifier::transaction::errors::TransactionExecutionError;
+use starknet_api::{block::BlockNumber, transaction::TransactionHash};
+use thiserror::Error;
+
+pub type ReplayResult<T> = Result<T, ReplayError>;
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    /// The genesis block can't be replayed, as there is no state before it
+    #[error("block {} has no previous block", block_number.0)]
+    NoPreviousBlock { block_number: BlockNumber },
+    /// The header or the transaction hashes of the block couldn't be fetched
+    #[error("failed to fetch block {}", block_number.0)]
+    FetchBlock {
+        block_number: BlockNumber,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The transaction, or the class it declares, couldn't be fetched
+    #[error("failed to fetch transaction {}", hash.0.to_hex_string())]
+    FetchTransaction {
+        hash: TransactionHash,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The state or the classes of the block couldn't be fetched ahead of
+    /// its execution
+    #[error("failed to warm up block {}", block_number.0)]
+    WarmUp {
+        block_number: BlockNumber,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The transaction failed to execute, as opposed to being reverted
+    #[error("failed to execute transaction {}", hash.0.to_hex_string())]
+    Execution {
+        hash: TransactionHash,
+        #[source]
+        source: TransactionExecutionError,
+    },
+}
@@ -0,0 +1,22 @@
+//! Library to replay Starknet blocks and transactions with the blockifier,
+//! for projects that embed the replay (e.g. to shadow a sequencer).
+//!
+//! The entry point is [`Replayer`], which executes on top of the cached state
+//! of a chain:
+//! - [`Replayer::execute_block`] executes every transaction of a block.
+//! - [`Replayer::execute_txs`] executes a subset of its transactions.
+//! - [`Replayer::execute_raw`] executes a transaction built by the caller with
+//!   the context of an existing block.
+//!
+//! To control how each transaction is executed, [`Replayer::open_block`]
+//! returns a [`BlockReplay`], which holds the state of the block as its
+//! transactions are executed. This is what the `replay` CLI is built on.
+//!
+//! Every function returns a [`ReplayError`], telling apart the data that
+//! couldn't be fetched from the transactions that failed to execute.
+
+mod error;
+mod replayer;
+
+pub use error::{ReplayError, ReplayResult};
+pub use replayer::{BlockReplay, ExecutedTransaction, Replayer};
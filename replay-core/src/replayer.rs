@@ -0,0 +1,203 @@
+use blockifier::{
+    context::BlockContext,
+    state::cached_state::CachedState,
+    transaction::{
+        account_transaction::ExecutionFlags, errors::TransactionExecutionError,
+        objects::TransactionExecutionInfo, transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use rpc_state_reader::{
+    cache::{prefetch_block, RpcCachedStateReader},
+    execution::{fetch_block_context, fetch_blockifier_transaction, precompile_block},
+    reader::{RpcStateReader, StateReader},
+};
+use starknet_api::{block::BlockNumber, core::ChainId, transaction::TransactionHash};
+
+use crate::{ReplayError, ReplayResult};
+
+/// Executes blocks and transactions on top of the cached state of a chain.
+pub struct Replayer {
+    chain: ChainId,
+}
+
+impl Replayer {
+    pub fn new(chain: ChainId) -> Self {
+        Self { chain }
+    }
+
+    /// Opens the block for execution, on top of the state of the previous
+    /// block. Nothing is executed until its transactions are.
+    pub fn open_block(&self, block_number: BlockNumber) -> ReplayResult<BlockReplay> {
+        let previous_block_number = block_number
+            .prev()
+            .ok_or(ReplayError::NoPreviousBlock { block_number })?;
+
+        let reader = self.reader(block_number);
+        let fetch_error = |source| ReplayError::FetchBlock {
+            block_number,
+            source,
+        };
+        let context = fetch_block_context(&reader).map_err(fetch_error)?;
+        let tx_hashes = reader
+            .get_block_with_tx_hashes()
+            .map_err(|err| fetch_error(err.into()))?
+            .transactions;
+
+        Ok(BlockReplay {
+            block_number,
+            reader,
+            state: CachedState::new(self.reader(previous_block_number)),
+            context,
+            tx_hashes,
+        })
+    }
+
+    /// Executes every transaction of the block, in order, on top of the state
+    /// of the previous block.
+    pub fn execute_block(
+        &self,
+        block_number: BlockNumber,
+        flags: ExecutionFlags,
+    ) -> ReplayResult<Vec<ExecutedTransaction>> {
+        let mut block = self.open_block(block_number)?;
+        block.prefetch()?;
+        block.execute_all(flags)
+    }
+
+    /// Executes the given transactions of the block, in order, on top of the
+    /// state of the previous block. Each transaction sees the state changes
+    /// of the previous ones, but not those of the omitted transactions.
+    pub fn execute_txs(
+        &self,
+        block_number: BlockNumber,
+        tx_hashes: &[TransactionHash],
+        flags: ExecutionFlags,
+    ) -> ReplayResult<Vec<ExecutedTransaction>> {
+        let mut block = self.open_block(block_number)?;
+        block.tx_hashes = tx_hashes.to_vec();
+        block.prefetch()?;
+        block.execute_all(flags)
+    }
+
+    /// Executes the transaction with the context of the given block, on top
+    /// of the state of the previous block.
+    ///
+    /// Each call starts from a fresh state, so state changes are discarded.
+    pub fn execute_raw(
+        &self,
+        tx: BlockiTransaction,
+        block_number: BlockNumber,
+    ) -> ReplayResult<TransactionExecutionInfo> {
+        let mut block = self.open_block(block_number)?;
+        let hash = tx.tx_hash();
+        block
+            .execute_with(&tx, |tx, state, context| tx.execute(state, context))
+            .map_err(|source| ReplayError::Execution { hash, source })
+    }
+
+    fn reader(&self, block_number: BlockNumber) -> RpcCachedStateReader {
+        RpcCachedStateReader::new(RpcStateReader::new(self.chain.clone(), block_number))
+    }
+}
+
+/// A block being replayed, transaction by transaction.
+pub struct BlockReplay {
+    pub block_number: BlockNumber,
+    /// Reader of the block, for its header, transactions and receipts
+    pub reader: RpcCachedStateReader,
+    /// State of the previous block, with the changes of the transactions
+    /// executed so far
+    pub state: CachedState<RpcCachedStateReader>,
+    pub context: BlockContext,
+    /// Transactions to execute, in order. It may be changed to skip some of
+    /// them, or to execute only a subset.
+    pub tx_hashes: Vec<TransactionHash>,
+}
+
+impl BlockReplay {
+    /// Fetches the state modified by the transactions in batches, instead of
+    /// one entry at a time as they are executed.
+    pub fn prefetch(&self) -> ReplayResult<()> {
+        prefetch_block(&self.reader, &self.state.state, &self.tx_hashes)
+            .map_err(|source| self.warm_up_error(source))
+    }
+
+    /// Starts compiling the classes called by the transactions in the
+    /// background. Returns the number of classes.
+    pub fn precompile(&self) -> ReplayResult<usize> {
+        precompile_block(&self.reader, &self.state.state, &self.tx_hashes)
+            .map_err(|source| self.warm_up_error(source))
+    }
+
+    /// Fetches the transaction, converted for execution
+    pub fn fetch_transaction(
+        &self,
+        hash: TransactionHash,
+        flags: ExecutionFlags,
+    ) -> ReplayResult<BlockiTransaction> {
+        fetch_blockifier_transaction(&self.reader, flags, hash)
+            .map_err(|source| ReplayError::FetchTransaction { hash, source })
+    }
+
+    /// Fetches and executes the transaction on the block state
+    pub fn execute_transaction(
+        &mut self,
+        hash: TransactionHash,
+        flags: ExecutionFlags,
+    ) -> ReplayResult<TransactionExecutionInfo> {
+        let tx = self.fetch_transaction(hash, flags)?;
+        self.execute_with(&tx, |tx, state, context| tx.execute(state, context))
+            .map_err(|source| ReplayError::Execution { hash, source })
+    }
+
+    /// Executes the transaction on the block state with the given function,
+    /// e.g. to fall back to another executor or to instrument the execution.
+    pub fn execute_with<F>(
+        &mut self,
+        tx: &BlockiTransaction,
+        execute: F,
+    ) -> Result<TransactionExecutionInfo, TransactionExecutionError>
+    where
+        F: FnOnce(
+            &BlockiTransaction,
+            &mut CachedState<RpcCachedStateReader>,
+            &BlockContext,
+        ) -> Result<TransactionExecutionInfo, TransactionExecutionError>,
+    {
+        execute(tx, &mut self.state, &self.context)
+    }
+
+    /// Executes the transactions of the block, in order.
+    ///
+    /// Fails if any transaction can't be fetched. Execution failures are
+    /// instead returned in the result of each transaction.
+    pub fn execute_all(&mut self, flags: ExecutionFlags) -> ReplayResult<Vec<ExecutedTransaction>> {
+        let tx_hashes = self.tx_hashes.clone();
+
+        let mut executions = Vec::with_capacity(tx_hashes.len());
+        for hash in tx_hashes {
+            let result = match self.execute_transaction(hash, flags.clone()) {
+                Ok(execution_info) => Ok(execution_info),
+                Err(ReplayError::Execution { source, .. }) => Err(source),
+                Err(err) => return Err(err),
+            };
+            executions.push(ExecutedTransaction { hash, result });
+        }
+
+        Ok(executions)
+    }
+
+    fn warm_up_error(&self, source: anyhow::Error) -> ReplayError {
+        ReplayError::WarmUp {
+            block_number: self.block_number,
+            source,
+        }
+    }
+}
+
+/// Result of executing a transaction as part of a block
+pub struct ExecutedTransaction {
+    pub hash: TransactionHash,
+    pub result: Result<TransactionExecutionInfo, TransactionExecutionError>,
+}
@@ -0,0 +1,160 @@
+//! Append-only log with a JSON line for every executed transaction.
+//!
+//! Each line is written with a single call and flushed immediately,
+//! so that if a long job dies, the log contains every transaction
+//! executed up to that point.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use blockifier::{execution::call_info::CallInfo, transaction::objects::TransactionExecutionInfo};
+use rpc_state_reader::{
+    executors::executor_for,
+    reader::{self, Executor},
+};
+use serde::{Deserialize, Serialize};
+use starknet_api::core::ClassHash;
+use tracing::error;
+
+static AUDIT_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Opens the audit log at the given path, appending to it if it already exists.
+pub fn init(path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = File::options().create(true).append(true).open(path)?;
+
+    AUDIT_LOG
+        .set(Mutex::new(file))
+        .map_err(|_| anyhow::anyhow!("audit log was already initialized"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Reverted,
+    Failed,
+}
+
+/// Executor that produced the outcome of a transaction
+#[derive(Serialize)]
+pub struct UsedExecutor {
+    pub executor: Executor,
+    /// Whether the transaction was executed again with the Cairo VM, after
+    /// Cairo Native failed
+    pub fallback: bool,
+    /// Classes executed with the executor of the executors config, instead
+    /// of `executor`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overridden_classes: Vec<ClassHash>,
+}
+
+impl UsedExecutor {
+    /// Returns the executor of an execution, or of a transaction that
+    /// wasn't executed if there is none
+    pub fn new(execution_info: Option<&TransactionExecutionInfo>, fallback: bool) -> Self {
+        // The fallback executes every class with the VM, regardless of the config
+        if fallback {
+            return Self {
+                executor: Executor::Vm,
+                fallback,
+                overridden_classes: Vec::new(),
+            };
+        }
+
+        let executor = reader::executor();
+        let mut overridden_classes = Vec::new();
+        for call_info in execution_info
+            .into_iter()
+            .flat_map(TransactionExecutionInfo::non_optional_call_infos)
+        {
+            collect_overridden_classes(call_info, executor, &mut overridden_classes);
+        }
+
+        Self {
+            executor,
+            fallback,
+            overridden_classes,
+        }
+    }
+}
+
+fn collect_overridden_classes(
+    call_info: &CallInfo,
+    executor: Executor,
+    classes: &mut Vec<ClassHash>,
+) {
+    if let Some(class_hash) = call_info.call.class_hash {
+        if executor_for(class_hash) != executor && !classes.contains(&class_hash) {
+            classes.push(class_hash);
+        }
+    }
+    for inner_call in &call_info.inner_calls {
+        collect_overridden_classes(inner_call, executor, classes);
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuditEntry<'a> {
+    /// Unix time in milliseconds
+    pub timestamp: u128,
+    pub tx_hash: &'a str,
+    pub chain: &'a str,
+    pub block_number: u64,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    #[serde(flatten)]
+    pub executor: &'a UsedExecutor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Appends an entry to the audit log. Does nothing if it was not initialized.
+pub fn record(
+    tx_hash: &str,
+    chain: &str,
+    block_number: u64,
+    outcome: Outcome,
+    duration: Duration,
+    executor: &UsedExecutor,
+    error: Option<String>,
+) {
+    let Some(audit_log) = AUDIT_LOG.get() else {
+        return;
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    let entry = AuditEntry {
+        timestamp,
+        tx_hash,
+        chain,
+        block_number,
+        outcome,
+        duration,
+        executor,
+        error,
+    };
+
+    let mut line = match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(err) => return error!("failed to serialize audit entry: {err}"),
+    };
+    line.push('\n');
+
+    let mut file = audit_log.lock().unwrap();
+    if let Err(err) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+        error!("failed to write audit entry: {err}");
+    }
+}
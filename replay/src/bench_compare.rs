@@ -0,0 +1,270 @@
+//! Statistical comparison of two benchmark outputs, e.g. before and after
+//! updating Cairo Native.
+//!
+//! Changes are relative to the first benchmark, with 95% confidence
+//! intervals computed from the time of each run (or of each transaction
+//! across runs) with the normal approximation. A transaction regressed if
+//! its whole interval is above the threshold.
+
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use starknet_api::transaction::TransactionHash;
+
+/// Quantile of the normal distribution for a 95% confidence interval
+const Z_95: f64 = 1.96;
+
+/// The fields of the benchmark data needed to compare it
+#[derive(Deserialize)]
+pub struct BenchmarkFile {
+    pub runs: Vec<RunFile>,
+    #[serde(default)]
+    pub transactions: Vec<TransactionFile>,
+}
+
+#[derive(Deserialize)]
+pub struct RunFile {
+    pub total_time: Duration,
+}
+
+#[derive(Deserialize)]
+pub struct TransactionFile {
+    pub tx_hash: TransactionHash,
+    pub time: TimeFile,
+}
+
+#[derive(Deserialize)]
+pub struct TimeFile {
+    pub mean: Duration,
+    pub stddev: Duration,
+}
+
+pub fn load_benchmark(path: &Path) -> anyhow::Result<BenchmarkFile> {
+    let benchmark = fs::read_to_string(path)
+        .with_context(|| format!("failed to read benchmark {}", path.display()))?;
+    let benchmark: BenchmarkFile = serde_json::from_str(&benchmark)
+        .with_context(|| format!("failed to parse benchmark {}", path.display()))?;
+    ensure!(
+        !benchmark.runs.is_empty(),
+        "benchmark {} has no runs",
+        path.display()
+    );
+    Ok(benchmark)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+/// Relative change of the mean time, with its confidence interval
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub change: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+#[derive(Serialize)]
+pub struct TransactionComparison {
+    pub tx_hash: TransactionHash,
+    pub before: Duration,
+    pub after: Duration,
+    pub speedup: f64,
+    #[serde(flatten)]
+    pub change: Change,
+    pub verdict: Verdict,
+}
+
+#[derive(Serialize)]
+pub struct Comparison {
+    pub before: Duration,
+    pub after: Duration,
+    /// Mean time before divided by the mean time after
+    pub speedup: f64,
+    pub change: Change,
+    pub verdict: Verdict,
+    pub regressions: usize,
+    pub improvements: usize,
+    /// Transactions present in both benchmarks, from the largest regression
+    pub transactions: Vec<TransactionComparison>,
+}
+
+/// Mean and variance of the mean, in seconds
+#[derive(Clone, Copy)]
+struct Estimate {
+    mean: f64,
+    variance: f64,
+}
+
+impl Estimate {
+    fn from_samples(samples: &[f64]) -> Self {
+        let count = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let variance = if samples.len() > 1 {
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0)
+        } else {
+            0.0
+        };
+        Self {
+            mean,
+            variance: variance / count,
+        }
+    }
+
+    fn from_stats(mean: Duration, stddev: Duration, runs: usize) -> Self {
+        Self {
+            mean: mean.as_secs_f64(),
+            variance: stddev.as_secs_f64().powi(2) / runs as f64,
+        }
+    }
+}
+
+fn change(before: Estimate, after: Estimate) -> Change {
+    let difference = after.mean - before.mean;
+    let margin = Z_95 * (before.variance + after.variance).sqrt();
+    Change {
+        change: difference / before.mean,
+        low: (difference - margin) / before.mean,
+        high: (difference + margin) / before.mean,
+    }
+}
+
+fn verdict(change: &Change, threshold: f64) -> Verdict {
+    if change.low > threshold {
+        Verdict::Regression
+    } else if change.high < -threshold {
+        Verdict::Improvement
+    } else {
+        Verdict::Unchanged
+    }
+}
+
+fn speedup(before: Estimate, after: Estimate) -> f64 {
+    before.mean / after.mean
+}
+
+/// Compares the benchmarks, reporting changes over the relative threshold
+/// (e.g. 0.05) as regressions or improvements.
+pub fn compare(before: &BenchmarkFile, after: &BenchmarkFile, threshold: f64) -> Comparison {
+    let run_times = |benchmark: &BenchmarkFile| {
+        benchmark
+            .runs
+            .iter()
+            .map(|run| run.total_time.as_secs_f64())
+            .collect::<Vec<_>>()
+    };
+    let before_runs = Estimate::from_samples(&run_times(before));
+    let after_runs = Estimate::from_samples(&run_times(after));
+
+    let before_transactions = before
+        .transactions
+        .iter()
+        .map(|transaction| (transaction.tx_hash, &transaction.time))
+        .collect::<HashMap<_, _>>();
+
+    let mut transactions = after
+        .transactions
+        .iter()
+        .filter_map(|transaction| {
+            let before_time = before_transactions.get(&transaction.tx_hash)?;
+            let before_estimate =
+                Estimate::from_stats(before_time.mean, before_time.stddev, before.runs.len());
+            let after_estimate = Estimate::from_stats(
+                transaction.time.mean,
+                transaction.time.stddev,
+                after.runs.len(),
+            );
+
+            let change = change(before_estimate, after_estimate);
+            Some(TransactionComparison {
+                tx_hash: transaction.tx_hash,
+                before: before_time.mean,
+                after: transaction.time.mean,
+                speedup: speedup(before_estimate, after_estimate),
+                verdict: verdict(&change, threshold),
+                change,
+            })
+        })
+        .collect::<Vec<_>>();
+    transactions.sort_by(|a, b| b.change.change.total_cmp(&a.change.change));
+
+    let count = |verdict| {
+        transactions
+            .iter()
+            .filter(|transaction| transaction.verdict == verdict)
+            .count()
+    };
+    let change = change(before_runs, after_runs);
+
+    Comparison {
+        before: Duration::from_secs_f64(before_runs.mean),
+        after: Duration::from_secs_f64(after_runs.mean),
+        speedup: speedup(before_runs, after_runs),
+        verdict: verdict(&change, threshold),
+        change,
+        regressions: count(Verdict::Regression),
+        improvements: count(Verdict::Improvement),
+        transactions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::hash::StarkHash;
+
+    use super::*;
+
+    fn benchmark(runs: &[u64], transactions: &[(u64, u64)]) -> BenchmarkFile {
+        BenchmarkFile {
+            runs: runs
+                .iter()
+                .map(|time| RunFile {
+                    total_time: Duration::from_millis(*time),
+                })
+                .collect(),
+            transactions: transactions
+                .iter()
+                .enumerate()
+                .map(|(tx, (mean, stddev))| TransactionFile {
+                    tx_hash: TransactionHash(StarkHash::from(tx as u64)),
+                    time: TimeFile {
+                        mean: Duration::from_millis(*mean),
+                        stddev: Duration::from_millis(*stddev),
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let before = benchmark(&[100, 102, 98, 100], &[(10, 1), (10, 1), (10, 5)]);
+        let after = benchmark(&[50, 51, 49, 50], &[(20, 1), (5, 1), (11, 5)]);
+
+        let comparison = compare(&before, &after, 0.05);
+        assert!((comparison.speedup - 2.0).abs() < 1e-9);
+        assert_eq!(comparison.verdict, Verdict::Improvement);
+        assert!(comparison.change.low < -0.5 && -0.5 < comparison.change.high);
+
+        let verdicts = comparison
+            .transactions
+            .iter()
+            .map(|transaction| transaction.verdict)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Regression,
+                Verdict::Unchanged,
+                Verdict::Improvement
+            ]
+        );
+        assert_eq!(comparison.regressions, 1);
+        assert_eq!(comparison.improvements, 1);
+    }
+}
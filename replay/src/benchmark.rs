@@ -1,4 +1,8 @@
-use std::time::Duration;
+use std::{
+    cell::Cell,
+    collections::{hash_map::Entry, HashMap},
+    time::{Duration, Instant},
+};
 
 use blockifier::{
     context::BlockContext,
@@ -11,7 +15,7 @@ use blockifier::{
     },
 };
 use rpc_state_reader::{
-    cache::RpcCachedStateReader,
+    cache::{prefetch_block, RpcCachedStateReader},
     execution::{fetch_block_context, fetch_blockifier_transaction},
     reader::{RpcStateReader, StateReader},
 };
@@ -23,6 +27,12 @@ use starknet_api::{
     hash::StarkHash,
     transaction::TransactionHash,
 };
+use tracing::warn;
+
+use crate::{
+    fallback::VmStateReader,
+    memory::{MemoryStats, MemoryTracker},
+};
 
 pub type BlockCachedData = (
     CachedState<OptionalStateReader<RpcCachedStateReader>>,
@@ -59,18 +69,20 @@ pub fn fetch_block_range_data(
         };
 
         // Fetch transactions for the block
-        let transactions = reader
-            .get_block_with_tx_hashes()
-            .unwrap()
-            .transactions
-            .into_iter()
-            .map(|hash| fetch_blockifier_transaction(&reader, flags.clone(), hash).unwrap())
+        let tx_hashes = reader.get_block_with_tx_hashes().unwrap().transactions;
+        let transactions = tx_hashes
+            .iter()
+            .map(|hash| fetch_blockifier_transaction(&reader, flags.clone(), *hash).unwrap())
             .collect::<Vec<_>>();
 
         // Create cached state
         let previous_block_number = block_number.prev().unwrap();
         let previous_reader =
             RpcCachedStateReader::new(RpcStateReader::new(chain.clone(), previous_block_number));
+
+        if let Err(err) = prefetch_block(&reader, &previous_reader, &tx_hashes) {
+            warn!("failed to prefetch block {block_number} state: {err:#}");
+        }
         let cached_state = CachedState::new(OptionalStateReader::new(previous_reader));
 
         block_caches.push((cached_state, block_context, transactions));
@@ -79,43 +91,437 @@ pub fn fetch_block_range_data(
     block_caches
 }
 
+pub struct BlockRangeExecution {
+    pub executions: Vec<TransactionExecutionInfo>,
+    /// Hash of the transaction of each execution
+    pub tx_hashes: Vec<TransactionHash>,
+    /// Time of each execution, including its state reads
+    pub tx_times: Vec<Duration>,
+    /// Time spent reading from the block states, during all executions
+    pub state_read_time: Duration,
+    pub memory: MemoryStats,
+}
+
 /// Executes the given block range, discarding any state changes applied to it
 ///
 /// Can also be used to fill up the cache
-pub fn execute_block_range(
-    block_range_data: &mut Vec<BlockCachedData>,
-) -> Vec<TransactionExecutionInfo> {
+pub fn execute_block_range(block_range_data: &mut Vec<BlockCachedData>) -> BlockRangeExecution {
     let mut executions = Vec::new();
+    let mut tx_hashes = Vec::new();
+    let mut tx_times = Vec::new();
+    let mut state_read_time = Duration::ZERO;
+    let memory = MemoryTracker::start();
 
     for (state, block_context, transactions) in block_range_data {
         // For each block
 
-        // The transactional state is used to execute a transaction while discarding state changes applied to it.
-        let mut transactional_state = CachedState::create_transactional(state);
+        // A new state on top of the block state is used to execute a transaction
+        // while discarding state changes applied to it. Every read that is
+        // not served by this top state is timed.
+        let mut transactional_state = CachedState::new(TimedStateReader::new(&*state));
 
-        for transaction in transactions {
+        for transaction in transactions.iter() {
             // Execute each transaction
+            let pre_execution_instant = Instant::now();
             let execution = transaction.execute(&mut transactional_state, block_context);
+            let execution_time = pre_execution_instant.elapsed();
             let Ok(execution) = execution else { continue };
 
             executions.push(execution);
+            tx_hashes.push(transaction.tx_hash());
+            tx_times.push(execution_time);
+        }
+
+        state_read_time += transactional_state.state.elapsed();
+    }
+
+    BlockRangeExecution {
+        executions,
+        tx_hashes,
+        tx_times,
+        state_read_time,
+        memory: memory.finish(),
+    }
+}
+
+/// Executes the given block range twice, once with Cairo Native and once with
+/// the Cairo VM, discarding any state changes applied to it.
+///
+/// Both executions share the same underlying state, so the caches filled by
+/// one of them are reused by the other. Returns the execution time of each
+/// transaction with each executor.
+pub fn execute_block_range_dual(
+    block_range_data: &mut Vec<BlockCachedData>,
+) -> Vec<DualTransactionExecution> {
+    let mut executions = Vec::new();
+
+    for (state, block_context, transactions) in block_range_data {
+        // For each block
+        let block_number = block_context.block_info().block_number;
+
+        let native_times = {
+            let mut transactional_state = CachedState::create_transactional(state);
+            time_transactions(transactions, &mut transactional_state, block_context)
+        };
+
+        let vm_times = {
+            let mut vm_state = CachedState::new(VmStateReader(&*state));
+            time_transactions(transactions, &mut vm_state, block_context)
+        };
+
+        for ((transaction, native_time), vm_time) in
+            transactions.iter().zip(native_times).zip(vm_times)
+        {
+            // Skip transactions that failed with any of the executors
+            let (Some(native_time), Some(vm_time)) = (native_time, vm_time) else {
+                continue;
+            };
+
+            executions.push(DualTransactionExecution {
+                block_number,
+                tx_hash: transaction.tx_hash(),
+                native_time,
+                vm_time,
+            });
         }
     }
 
     executions
 }
 
+/// Executes each transaction, returning its execution time, or `None` if it failed.
+fn time_transactions<S: BlockifierStateReader>(
+    transactions: &[BlockiTransaction],
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+) -> Vec<Option<Duration>> {
+    transactions
+        .iter()
+        .map(|transaction| {
+            let pre_execution_instant = Instant::now();
+            let execution = transaction.execute(state, block_context);
+            let execution_time = pre_execution_instant.elapsed();
+
+            execution.ok().map(|_| execution_time)
+        })
+        .collect()
+}
+
+pub struct DualTransactionExecution {
+    pub block_number: BlockNumber,
+    pub tx_hash: TransactionHash,
+    pub native_time: Duration,
+    pub vm_time: Duration,
+}
+
+#[derive(Serialize)]
+pub struct DualBenchmarkingData {
+    pub native_average_time: Duration,
+    pub vm_average_time: Duration,
+    pub speedup: f64,
+    pub transactions: Vec<DualTransactionInfo>,
+}
+
+#[derive(Serialize)]
+pub struct DualTransactionInfo {
+    block_number: BlockNumber,
+    tx_hash: TransactionHash,
+    native_time: Duration,
+    vm_time: Duration,
+    speedup: f64,
+}
+
+/// Pairs the executions of each transaction across all runs, averaging their times
+pub fn aggregate_dual_executions(
+    executions: Vec<Vec<DualTransactionExecution>>,
+) -> DualBenchmarkingData {
+    let number_of_runs = executions.len() as u32;
+
+    let mut transactions: Vec<DualTransactionInfo> = Vec::new();
+    let mut transaction_indices: HashMap<TransactionHash, usize> = HashMap::new();
+
+    for execution in executions.into_iter().flatten() {
+        match transaction_indices.entry(execution.tx_hash) {
+            Entry::Occupied(entry) => {
+                let info = &mut transactions[*entry.get()];
+                info.native_time += execution.native_time;
+                info.vm_time += execution.vm_time;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(transactions.len());
+                transactions.push(DualTransactionInfo {
+                    block_number: execution.block_number,
+                    tx_hash: execution.tx_hash,
+                    native_time: execution.native_time,
+                    vm_time: execution.vm_time,
+                    speedup: 0.0,
+                });
+            }
+        }
+    }
+
+    for info in &mut transactions {
+        info.native_time /= number_of_runs;
+        info.vm_time /= number_of_runs;
+        info.speedup = info.vm_time.as_secs_f64() / info.native_time.as_secs_f64();
+    }
+
+    let native_average_time: Duration = transactions.iter().map(|info| info.native_time).sum();
+    let vm_average_time: Duration = transactions.iter().map(|info| info.vm_time).sum();
+    let speedup = vm_average_time.as_secs_f64() / native_average_time.as_secs_f64();
+
+    DualBenchmarkingData {
+        native_average_time,
+        vm_average_time,
+        speedup,
+        transactions,
+    }
+}
+
+/// Maximum fraction of a warm run that may be spent reading state
+///
+/// As all data is cached before benchmarking, reads should be in-memory
+/// lookups. Exceeding it means that the benchmark measured a cold path.
+const MAX_WARM_STATE_READ_RATIO: f64 = 0.01;
+
 #[derive(Serialize)]
 pub struct BenchmarkingData {
     pub average_time: Duration,
+    pub average_state_read_time: Duration,
+    /// Statistics of the total time of the runs
+    pub time: TimeStats,
+    pub average_allocations: u64,
+    /// Largest peak resident set size of the runs, in bytes
+    pub peak_rss: Option<u64>,
+    pub runs: Vec<RunData>,
     pub class_executions: Vec<ClassExecutionInfo>,
+    /// Entry points ranked by their time across runs
+    pub entrypoints: Vec<EntrypointStats>,
+    pub transactions: Vec<TransactionStats>,
+    /// Timings of the first execution after the caches were filled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_call: Option<FirstCallData>,
+}
+
+#[derive(Serialize)]
+pub struct FirstCallData {
+    pub run: RunData,
+    pub class_executions: Vec<ClassExecutionInfo>,
+}
+
+/// Executes the given block range once, calling every cached executor and
+/// entry point, and times it.
+///
+/// It must run after the caches are filled and the inner state readers are
+/// disabled, so that no fetches or compilations are timed, and before the
+/// measured runs. Its execution time includes the first call overhead of the
+/// native libraries (page faults, lazy symbol binding).
+pub fn measure_first_call(block_range_data: &mut Vec<BlockCachedData>) -> FirstCallData {
+    assert!(
+        block_range_data
+            .iter()
+            .all(|(cached_state, ..)| cached_state.state.0.is_none()),
+        "first calls must be measured with the state readers disabled"
+    );
+
+    let pre_execution_instant = Instant::now();
+    let execution = execute_block_range(block_range_data);
+    let total_time = pre_execution_instant.elapsed();
+
+    FirstCallData {
+        run: RunData::new(total_time, execution.state_read_time, execution.memory),
+        class_executions: aggregate_executions(execution.executions),
+    }
+}
+
+#[derive(Serialize)]
+pub struct RunData {
+    pub total_time: Duration,
+    /// Time spent reading from the block states
+    pub state_read_time: Duration,
+    /// Total time, minus the state read time
+    pub execution_time: Duration,
+    pub memory: MemoryStats,
+}
+
+impl BenchmarkingData {
+    pub fn new(
+        runs: Vec<RunData>,
+        class_executions: Vec<ClassExecutionInfo>,
+        transactions: Vec<TransactionStats>,
+        first_call: Option<FirstCallData>,
+    ) -> Self {
+        let number_of_runs = runs.len() as u32;
+
+        let total_time: Duration = runs.iter().map(|run| run.total_time).sum();
+        let state_read_time: Duration = runs.iter().map(|run| run.state_read_time).sum();
+
+        Self {
+            average_time: total_time / number_of_runs,
+            average_state_read_time: state_read_time / number_of_runs,
+            time: TimeStats::new(runs.iter().map(|run| run.total_time).collect()),
+            average_allocations: runs.iter().map(|run| run.memory.allocations).sum::<u64>()
+                / number_of_runs as u64,
+            peak_rss: runs.iter().filter_map(|run| run.memory.peak_rss).max(),
+            runs,
+            entrypoints: rank_entrypoints(&class_executions),
+            class_executions,
+            transactions,
+            first_call,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct TimeStats {
+    pub mean: Duration,
+    pub median: Duration,
+    pub stddev: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl TimeStats {
+    pub fn new(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+
+        let count = samples.len();
+        let mean = samples.iter().sum::<Duration>() / count as u32;
+        let variance = samples
+            .iter()
+            .map(|sample| (sample.as_secs_f64() - mean.as_secs_f64()).powi(2))
+            .sum::<f64>()
+            / count as f64;
+
+        // Nearest rank percentile
+        let percentile = |p: f64| samples[((p * count as f64).ceil() as usize).clamp(1, count) - 1];
+
+        Self {
+            mean,
+            median: percentile(0.5),
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            min: samples[0],
+            max: samples[count - 1],
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TransactionStats {
+    pub tx_hash: TransactionHash,
+    /// Statistics of the time of the transaction across runs
+    pub time: TimeStats,
+}
+
+/// Aggregates the execution times of each transaction across runs, in order
+/// of first execution.
+pub fn aggregate_transactions(
+    tx_times: impl IntoIterator<Item = (TransactionHash, Duration)>,
+) -> Vec<TransactionStats> {
+    let mut order = Vec::new();
+    let mut samples: HashMap<TransactionHash, Vec<Duration>> = HashMap::new();
+    for (tx_hash, time) in tx_times {
+        samples
+            .entry(tx_hash)
+            .or_insert_with(|| {
+                order.push(tx_hash);
+                Vec::new()
+            })
+            .push(time);
+    }
+
+    order
+        .into_iter()
+        .map(|tx_hash| TransactionStats {
+            tx_hash,
+            time: TimeStats::new(samples.remove(&tx_hash).unwrap_or_default()),
+        })
+        .collect()
+}
+
+impl RunData {
+    pub fn new(total_time: Duration, state_read_time: Duration, memory: MemoryStats) -> Self {
+        Self {
+            total_time,
+            state_read_time,
+            execution_time: total_time.saturating_sub(state_read_time),
+            memory,
+        }
+    }
+
+    /// Returns whether the state read time is negligible
+    pub fn is_warm(&self) -> bool {
+        self.state_read_time.as_secs_f64()
+            <= self.total_time.as_secs_f64() * MAX_WARM_STATE_READ_RATIO
+    }
 }
 
 #[derive(Serialize)]
 pub struct ClassExecutionInfo {
-    class_hash: ClassHash,
-    selector: EntryPointSelector,
-    time: Duration,
+    pub class_hash: ClassHash,
+    pub selector: EntryPointSelector,
+    pub time: Duration,
+    /// Sierra gas consumed by the call, excluding its inner calls
+    pub gas_consumed: u64,
+}
+
+#[derive(Serialize)]
+pub struct EntrypointStats {
+    pub class_hash: ClassHash,
+    pub selector: EntryPointSelector,
+    pub calls: usize,
+    /// Time of every call, excluding its inner calls
+    pub total_time: Duration,
+    pub mean_time: Duration,
+    /// Fraction of the time of all entry points
+    pub share: f64,
+    /// Sierra gas consumed by every call, excluding its inner calls
+    pub gas_consumed: u64,
+}
+
+/// Aggregates the calls by class hash and selector, from the hottest entry
+/// point to the coldest.
+pub fn rank_entrypoints(class_executions: &[ClassExecutionInfo]) -> Vec<EntrypointStats> {
+    let mut entrypoints: HashMap<(ClassHash, EntryPointSelector), EntrypointStats> = HashMap::new();
+    for execution in class_executions {
+        let entrypoint = entrypoints
+            .entry((execution.class_hash, execution.selector))
+            .or_insert_with(|| EntrypointStats {
+                class_hash: execution.class_hash,
+                selector: execution.selector,
+                calls: 0,
+                total_time: Duration::ZERO,
+                mean_time: Duration::ZERO,
+                share: 0.0,
+                gas_consumed: 0,
+            });
+        entrypoint.calls += 1;
+        entrypoint.total_time += execution.time;
+        entrypoint.gas_consumed += execution.gas_consumed;
+    }
+
+    let total_time = class_executions
+        .iter()
+        .map(|execution| execution.time)
+        .sum::<Duration>();
+
+    let mut entrypoints = entrypoints
+        .into_values()
+        .map(|mut entrypoint| {
+            entrypoint.mean_time = entrypoint.total_time / entrypoint.calls as u32;
+            entrypoint.share = entrypoint.total_time.as_secs_f64() / total_time.as_secs_f64();
+            entrypoint
+        })
+        .collect::<Vec<_>>();
+    entrypoints.sort_by(|a, b| b.total_time.cmp(&a.total_time));
+    entrypoints
 }
 
 pub fn aggregate_executions(executions: Vec<TransactionExecutionInfo>) -> Vec<ClassExecutionInfo> {
@@ -143,12 +549,14 @@ fn get_class_executions(call: CallInfo) -> Vec<ClassExecutionInfo> {
     let class_hash = call.call.class_hash.unwrap();
 
     let mut inner_time = Duration::ZERO;
+    let mut inner_gas_consumed = 0;
 
     let mut classes = call
         .inner_calls
         .into_iter()
         .flat_map(|call| {
             inner_time += call.time;
+            inner_gas_consumed += call.execution.gas_consumed;
             get_class_executions(call)
         })
         .collect::<Vec<_>>();
@@ -157,11 +565,16 @@ fn get_class_executions(call: CallInfo) -> Vec<ClassExecutionInfo> {
         panic!("contract time should never be zero, there is a bug somewhere")
     }
     let time = call.time - inner_time;
+    let gas_consumed = call
+        .execution
+        .gas_consumed
+        .saturating_sub(inner_gas_consumed);
 
     let top_class = ClassExecutionInfo {
         class_hash,
         selector: call.call.entry_point_selector,
         time,
+        gas_consumed,
     };
 
     classes.push(top_class);
@@ -253,3 +666,115 @@ impl<S: BlockifierStateReader> BlockifierStateReader for OptionalStateReader<S>
         self.get_inner().get_compiled_class_hash(class_hash)
     }
 }
+
+/// An implementation of StateReader that measures the time spent in each read
+pub struct TimedStateReader<'a, S: BlockifierStateReader> {
+    state_reader: &'a S,
+    elapsed: Cell<Duration>,
+}
+
+impl<'a, S: BlockifierStateReader> TimedStateReader<'a, S> {
+    pub fn new(state_reader: &'a S) -> Self {
+        Self {
+            state_reader,
+            elapsed: Cell::new(Duration::ZERO),
+        }
+    }
+
+    /// Returns the total time spent reading
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.get()
+    }
+
+    fn time<T>(&self, read: impl FnOnce(&S) -> T) -> T {
+        let pre_read_instant = Instant::now();
+        let result = read(self.state_reader);
+        self.elapsed
+            .set(self.elapsed.get() + pre_read_instant.elapsed());
+        result
+    }
+}
+
+impl<S: BlockifierStateReader> BlockifierStateReader for TimedStateReader<'_, S> {
+    fn get_storage_at(
+        &self,
+        contract_address: starknet_api::core::ContractAddress,
+        key: starknet_api::state::StorageKey,
+    ) -> blockifier::state::state_api::StateResult<StarkHash> {
+        self.time(|state| state.get_storage_at(contract_address, key))
+    }
+
+    fn get_nonce_at(
+        &self,
+        contract_address: starknet_api::core::ContractAddress,
+    ) -> blockifier::state::state_api::StateResult<starknet_api::core::Nonce> {
+        self.time(|state| state.get_nonce_at(contract_address))
+    }
+
+    fn get_class_hash_at(
+        &self,
+        contract_address: starknet_api::core::ContractAddress,
+    ) -> blockifier::state::state_api::StateResult<starknet_api::core::ClassHash> {
+        self.time(|state| state.get_class_hash_at(contract_address))
+    }
+
+    fn get_compiled_class(
+        &self,
+        class_hash: starknet_api::core::ClassHash,
+    ) -> blockifier::state::state_api::StateResult<RunnableCompiledClass> {
+        self.time(|state| state.get_compiled_class(class_hash))
+    }
+
+    fn get_compiled_class_hash(
+        &self,
+        class_hash: starknet_api::core::ClassHash,
+    ) -> blockifier::state::state_api::StateResult<starknet_api::core::CompiledClassHash> {
+        self.time(|state| state.get_compiled_class_hash(class_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_stats() {
+        let stats = TimeStats::new((1..=100).rev().map(Duration::from_millis).collect());
+
+        assert_eq!(stats.mean, Duration::from_micros(50500));
+        assert_eq!(stats.median, Duration::from_millis(50));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert_eq!(stats.stddev.as_millis(), 28);
+
+        assert_eq!(TimeStats::new(Vec::new()), TimeStats::default());
+    }
+
+    #[test]
+    fn test_rank_entrypoints() {
+        let execution = |class_hash: u8, selector: u8, millis| ClassExecutionInfo {
+            class_hash: ClassHash(StarkHash::from(class_hash)),
+            selector: EntryPointSelector(StarkHash::from(selector)),
+            time: Duration::from_millis(millis),
+            gas_consumed: millis * 10,
+        };
+        let executions = [
+            execution(1, 1, 10),
+            execution(1, 2, 30),
+            execution(2, 1, 20),
+            execution(1, 1, 40),
+        ];
+
+        let entrypoints = rank_entrypoints(&executions);
+        assert_eq!(entrypoints.len(), 3);
+        assert_eq!(entrypoints[0].class_hash, ClassHash(StarkHash::ONE));
+        assert_eq!(entrypoints[0].selector, EntryPointSelector(StarkHash::ONE));
+        assert_eq!(entrypoints[0].calls, 2);
+        assert_eq!(entrypoints[0].mean_time, Duration::from_millis(25));
+        assert_eq!(entrypoints[0].gas_consumed, 500);
+        assert!((entrypoints[0].share - 0.5).abs() < 1e-9);
+        assert_eq!(entrypoints[2].total_time, Duration::from_millis(20));
+    }
+}
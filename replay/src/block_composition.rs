@@ -0,0 +1,307 @@
+//! Composition of the executed blocks: which entry points were called, and
+//! by whom.
+//!
+//! Each node of the call graph is an entry point of a contract, weighted by
+//! the number of times it was called in the block. Each edge goes from a
+//! caller to a callee, weighted by the number of calls between them. Top
+//! level calls (validate, execute and fee transfer) have no caller.
+//!
+//! The graph is written as JSON, and optionally as DOT to visualize it, e.g.
+//! with `dot -Tsvg block648461.dot -o block648461.svg`. The graphs of a
+//! range can also be aggregated by selector, to find the hottest entry
+//! points.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    io::Write,
+    path::Path,
+};
+
+use blockifier::{execution::call_info::CallInfo, transaction::objects::TransactionExecutionInfo};
+use serde::Serialize;
+use starknet_api::core::{ClassHash, ContractAddress, EntryPointSelector};
+
+use crate::output;
+
+#[derive(Debug, Serialize)]
+pub struct EntryPointNode {
+    pub contract_address: ContractAddress,
+    pub class_hash: Option<ClassHash>,
+    pub selector: EntryPointSelector,
+    pub calls: u64,
+    /// Sierra gas consumed by every call, including its inner calls
+    pub gas_consumed: u64,
+    /// Felts of calldata of every call
+    pub calldata_len: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallEdge {
+    /// Index of the calling entry point
+    pub caller: usize,
+    /// Index of the called entry point
+    pub callee: usize,
+    pub calls: u64,
+}
+
+/// Entry point of a block, as a row of tabular formats
+#[derive(Serialize)]
+pub struct EntryPointRecord<'a> {
+    pub block_number: u64,
+    #[serde(flatten)]
+    pub node: &'a EntryPointNode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockComposition {
+    pub block_number: u64,
+    pub transactions: usize,
+    /// Entry points, in the order they were first called
+    pub entry_points: Vec<EntryPointNode>,
+    /// Edges, sorted by caller and callee
+    pub calls: Vec<CallEdge>,
+}
+
+/// Builds the call graph of the successful executions of the block
+pub fn compose<'a>(
+    block_number: u64,
+    execution_infos: impl IntoIterator<Item = &'a TransactionExecutionInfo>,
+) -> BlockComposition {
+    let mut graph = CallGraph::default();
+    let mut transactions = 0;
+    for execution_info in execution_infos {
+        transactions += 1;
+        for call_info in execution_info.non_optional_call_infos() {
+            graph.add(call_info, None);
+        }
+    }
+
+    BlockComposition {
+        block_number,
+        transactions,
+        entry_points: graph.nodes,
+        calls: graph
+            .edges
+            .into_iter()
+            .map(|((caller, callee), calls)| CallEdge {
+                caller,
+                callee,
+                calls,
+            })
+            .collect(),
+    }
+}
+
+#[derive(Default)]
+struct CallGraph {
+    nodes: Vec<EntryPointNode>,
+    indices: HashMap<(ContractAddress, EntryPointSelector), usize>,
+    edges: BTreeMap<(usize, usize), u64>,
+}
+
+impl CallGraph {
+    fn add(&mut self, call_info: &CallInfo, caller: Option<usize>) {
+        let call = &call_info.call;
+        let index = *self
+            .indices
+            .entry((call.storage_address, call.entry_point_selector))
+            .or_insert_with(|| {
+                self.nodes.push(EntryPointNode {
+                    contract_address: call.storage_address,
+                    class_hash: call.class_hash,
+                    selector: call.entry_point_selector,
+                    calls: 0,
+                    gas_consumed: 0,
+                    calldata_len: 0,
+                });
+                self.nodes.len() - 1
+            });
+        let node = &mut self.nodes[index];
+        node.calls += 1;
+        node.gas_consumed += call_info.execution.gas_consumed;
+        node.calldata_len += call.calldata.0.len();
+
+        if let Some(caller) = caller {
+            *self.edges.entry((caller, index)).or_default() += 1;
+        }
+        for inner_call in &call_info.inner_calls {
+            self.add(inner_call, Some(index));
+        }
+    }
+}
+
+impl BlockComposition {
+    /// Returns the entry points as records, without the edges, so that the
+    /// blocks of a range can be loaded as a single table
+    pub fn records(&self) -> Vec<EntryPointRecord> {
+        self.entry_points
+            .iter()
+            .map(|node| EntryPointRecord {
+                block_number: self.block_number,
+                node,
+            })
+            .collect()
+    }
+
+    /// Renders the call graph in the DOT language. Nodes and edges are
+    /// labeled with their call count, and drawn thicker the more calls.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph block{} {{\n", self.block_number);
+        dot.push_str("    node [shape=box];\n");
+        for (index, node) in self.entry_points.iter().enumerate() {
+            writeln!(
+                dot,
+                "    n{index} [label=\"{}\\n{}\\ncalls: {}\", penwidth={:.2}];",
+                node.contract_address.0.key().to_hex_string(),
+                node.selector.0.to_hex_string(),
+                node.calls,
+                penwidth(node.calls),
+            )
+            .unwrap();
+        }
+        for edge in &self.calls {
+            writeln!(
+                dot,
+                "    n{} -> n{} [label=\"{}\", penwidth={:.2}];",
+                edge.caller,
+                edge.callee,
+                edge.calls,
+                penwidth(edge.calls),
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelectorStats {
+    pub selector: EntryPointSelector,
+    pub calls: u64,
+    /// Sierra gas consumed by every call, including its inner calls
+    pub total_gas: u64,
+    pub mean_calldata_len: f64,
+}
+
+/// Statistics of the entry points of several blocks, aggregated by selector
+/// regardless of the called contract
+#[derive(Default)]
+pub struct SelectorSummary {
+    selectors: HashMap<EntryPointSelector, (u64, u64, usize)>,
+}
+
+impl SelectorSummary {
+    pub fn add(&mut self, composition: &BlockComposition) {
+        for node in &composition.entry_points {
+            let (calls, gas_consumed, calldata_len) =
+                self.selectors.entry(node.selector).or_default();
+            *calls += node.calls;
+            *gas_consumed += node.gas_consumed;
+            *calldata_len += node.calldata_len;
+        }
+    }
+
+    /// Returns the statistics of each selector, from the most called to the
+    /// least
+    pub fn stats(&self) -> Vec<SelectorStats> {
+        let mut stats = self
+            .selectors
+            .iter()
+            .map(
+                |(selector, (calls, gas_consumed, calldata_len))| SelectorStats {
+                    selector: *selector,
+                    calls: *calls,
+                    total_gas: *gas_consumed,
+                    mean_calldata_len: *calldata_len as f64 / *calls as f64,
+                },
+            )
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| {
+            b.calls
+                .cmp(&a.calls)
+                .then(b.total_gas.cmp(&a.total_gas))
+                .then(a.selector.cmp(&b.selector))
+        });
+        stats
+    }
+}
+
+/// Grows logarithmically, so that hot entry points don't hide the rest
+fn penwidth(calls: u64) -> f64 {
+    1.0 + (calls.max(1) as f64).log2()
+}
+
+pub fn write_dot(path: &Path, composition: &BlockComposition) -> anyhow::Result<()> {
+    output::write_atomic(path, |writer| {
+        Ok(writer.write_all(composition.to_dot().as_bytes())?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use blockifier::execution::entry_point::CallEntryPoint;
+    use starknet_api::{hash::StarkHash, transaction::fields::Calldata};
+
+    use super::*;
+
+    fn call(contract: u128, selector: u8, inner_calls: Vec<CallInfo>) -> CallInfo {
+        CallInfo {
+            call: CallEntryPoint {
+                storage_address: ContractAddress::from(contract),
+                entry_point_selector: EntryPointSelector(StarkHash::from(selector)),
+                calldata: Calldata(vec![StarkHash::ZERO; contract as usize].into()),
+                ..Default::default()
+            },
+            inner_calls,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compose() {
+        let transfer = || call(2, 1, Vec::new());
+        let executions = [
+            TransactionExecutionInfo {
+                execute_call_info: Some(call(1, 1, vec![transfer(), transfer()])),
+                ..Default::default()
+            },
+            TransactionExecutionInfo {
+                execute_call_info: Some(call(3, 1, vec![transfer()])),
+                ..Default::default()
+            },
+        ];
+
+        let composition = compose(1, &executions);
+        assert_eq!(composition.transactions, 2);
+        assert_eq!(
+            composition
+                .entry_points
+                .iter()
+                .map(|node| node.calls)
+                .collect::<Vec<_>>(),
+            [1, 3, 1]
+        );
+        assert_eq!(
+            composition
+                .calls
+                .iter()
+                .map(|edge| (edge.caller, edge.callee, edge.calls))
+                .collect::<Vec<_>>(),
+            [(0, 1, 2), (2, 1, 1)]
+        );
+
+        let dot = composition.to_dot();
+        assert!(dot.starts_with("digraph block1 {"));
+        assert!(dot.contains("n0 -> n1 [label=\"2\", penwidth=2.00];"));
+
+        let mut summary = SelectorSummary::default();
+        summary.add(&composition);
+        summary.add(&compose(2, &executions[1..]));
+        let stats = summary.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].calls, 7);
+        assert_eq!(stats[0].mean_calldata_len, 15.0 / 7.0);
+    }
+}
@@ -0,0 +1,110 @@
+//! Performance budgets of classes, checked against benchmark results.
+//!
+//! The budgets file maps class hashes to the maximum execution time, in
+//! nanoseconds, per unit of Sierra gas consumed:
+//!
+//! ```json
+//! { "0x0298e56befa6d1446b86ed5b900a9ba51fd2faa683cd6f50e8f833c0fb847216": 0.5 }
+//! ```
+//!
+//! It allows guarding the execution time of popular classes against
+//! regressions, e.g. when updating Cairo Native.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Context;
+use serde::Serialize;
+use starknet_api::core::ClassHash;
+
+use crate::benchmark::ClassExecutionInfo;
+
+pub type Budgets = HashMap<ClassHash, f64>;
+
+#[derive(Debug, Serialize)]
+pub struct BudgetViolation {
+    pub class_hash: ClassHash,
+    /// Maximum nanoseconds per unit of gas
+    pub budget: f64,
+    /// Measured nanoseconds per unit of gas
+    pub actual: f64,
+}
+
+pub fn load_budgets(path: &Path) -> anyhow::Result<Budgets> {
+    let budgets = fs::read_to_string(path)
+        .with_context(|| format!("failed to read budgets file {}", path.display()))?;
+    serde_json::from_str(&budgets)
+        .with_context(|| format!("failed to parse budgets file {}", path.display()))
+}
+
+/// Returns the classes whose execution time per gas, across all their
+/// executions, exceeds their budget. Classes that consumed no gas (i.e.
+/// Cairo 0 classes) can't be checked, and are ignored.
+pub fn check_budgets(
+    budgets: &Budgets,
+    class_executions: &[ClassExecutionInfo],
+) -> Vec<BudgetViolation> {
+    let mut totals: HashMap<ClassHash, (f64, u64)> = HashMap::new();
+    for execution in class_executions {
+        if !budgets.contains_key(&execution.class_hash) {
+            continue;
+        }
+        let (time, gas) = totals.entry(execution.class_hash).or_default();
+        *time += execution.time.as_nanos() as f64;
+        *gas += execution.gas_consumed;
+    }
+
+    let mut violations = totals
+        .into_iter()
+        .filter(|(_, (_, gas))| *gas > 0)
+        .filter_map(|(class_hash, (time, gas))| {
+            let budget = budgets[&class_hash];
+            let actual = time / gas as f64;
+            (actual > budget).then_some(BudgetViolation {
+                class_hash,
+                budget,
+                actual,
+            })
+        })
+        .collect::<Vec<_>>();
+    violations.sort_by_key(|violation| violation.class_hash);
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use starknet_api::{class_hash, core::EntryPointSelector, felt};
+
+    use super::*;
+
+    fn execution(class_hash: ClassHash, nanos: u64, gas_consumed: u64) -> ClassExecutionInfo {
+        ClassExecutionInfo {
+            class_hash,
+            selector: EntryPointSelector(felt!("0x1")),
+            time: Duration::from_nanos(nanos),
+            gas_consumed,
+        }
+    }
+
+    #[test]
+    fn test_check_budgets() {
+        let fast = class_hash!("0x1");
+        let slow = class_hash!("0x2");
+        let unchecked = class_hash!("0x3");
+        let budgets = Budgets::from([(fast, 1.0), (slow, 1.0)]);
+
+        let executions = [
+            execution(fast, 100, 200),
+            execution(slow, 100, 50),
+            execution(slow, 100, 50),
+            execution(unchecked, 1000, 1),
+        ];
+
+        let violations = check_budgets(&budgets, &executions);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].class_hash, slow);
+        assert_eq!(violations[0].actual, 2.0);
+    }
+}
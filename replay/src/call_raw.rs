@@ -0,0 +1,121 @@
+//! Execution of an arbitrary entry point on the state of a block, without an
+//! on-chain transaction exercising it.
+//!
+//! The call is wrapped in an unsigned invoke transaction from an existing
+//! account, whose `__execute__` receives a multicall with the single call.
+//! As the transaction is not validated, the account needs no private key.
+
+use std::sync::Arc;
+
+use blockifier::{
+    context::BlockContext,
+    state::{cached_state::CachedState, state_api::StateReader as BlockifierStateReader},
+    transaction::{
+        account_transaction::ExecutionFlags,
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use serde::Serialize;
+use starknet_api::{
+    abi::abi_utils::selector_from_name,
+    core::{ContractAddress, EntryPointSelector},
+    felt,
+    hash::StarkHash,
+    transaction::{
+        fields::{Calldata, Fee, TransactionSignature},
+        InvokeTransaction, InvokeTransactionV1, Transaction as SNTransaction, TransactionHash,
+    },
+};
+
+use crate::shrink::{encode_multicall, Call};
+
+#[derive(Serialize)]
+pub struct RawCallResult {
+    pub call: Call,
+    pub retdata: Vec<StarkHash>,
+    pub failed: bool,
+    pub gas_consumed: u64,
+    pub events: usize,
+    /// Error of the transaction wrapping the call, e.g. a panic of the call
+    pub error: Option<String>,
+}
+
+/// Parses an entry point given either by name (e.g. `balanceOf`) or by its
+/// selector
+pub fn parse_selector(entry_point: &str) -> EntryPointSelector {
+    if entry_point.starts_with("0x") {
+        EntryPointSelector(felt!(entry_point))
+    } else {
+        selector_from_name(entry_point)
+    }
+}
+
+/// Executes the call from `account`, discarding the state changes
+///
+/// Validation is skipped regardless of the flags, as the transaction is unsigned.
+pub fn execute_call<S: BlockifierStateReader>(
+    call: Call,
+    account: ContractAddress,
+    flags: ExecutionFlags,
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+) -> anyhow::Result<RawCallResult> {
+    let transaction = SNTransaction::Invoke(InvokeTransaction::V1(InvokeTransactionV1 {
+        max_fee: Fee(u128::MAX),
+        signature: TransactionSignature::default(),
+        nonce: state.get_nonce_at(account)?,
+        sender_address: account,
+        calldata: Calldata(Arc::new(encode_multicall(std::slice::from_ref(&call)))),
+    }));
+    let flags = ExecutionFlags {
+        validate: false,
+        ..flags
+    };
+    // The transaction is never committed, so its hash is irrelevant
+    let tx = BlockiTransaction::from_api(
+        transaction,
+        TransactionHash::default(),
+        None,
+        None,
+        None,
+        flags,
+    )?;
+
+    let mut transactional_state = CachedState::create_transactional(state);
+    let execution_info = tx.execute(&mut transactional_state, block_context)?;
+
+    let call_info = execution_info
+        .execute_call_info
+        .as_ref()
+        .and_then(|execute_call_info| execute_call_info.inner_calls.first());
+
+    Ok(RawCallResult {
+        call,
+        retdata: call_info
+            .map(|call_info| call_info.execution.retdata.0.clone())
+            .unwrap_or_default(),
+        failed: call_info.is_none_or(|call_info| call_info.execution.failed),
+        gas_consumed: call_info
+            .map(|call_info| call_info.execution.gas_consumed)
+            .unwrap_or_default(),
+        events: call_info
+            .map(|call_info| call_info.execution.events.len())
+            .unwrap_or_default(),
+        error: execution_info
+            .revert_error
+            .as_ref()
+            .map(ToString::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selector() {
+        assert_eq!(parse_selector("balanceOf"), selector_from_name("balanceOf"));
+        assert_eq!(parse_selector("0x2"), EntryPointSelector(felt!("0x2")));
+    }
+}
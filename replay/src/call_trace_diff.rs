@@ -0,0 +1,130 @@
+//! Call traces of the execution of a transaction with Cairo Native and with
+//! the Sierra emulator, to find which call of a native execution goes wrong.
+//!
+//! Both executions run in the same process, on top of the same state, each
+//! reading the state through an `ExecutorStateReader` that compiles every
+//! Sierra class for its executor. Their call traces, in the format of
+//! `starknet_traceTransaction`, are written with the same file name to a
+//! directory per executor, so they can be compared with `diff -r`.
+//!
+//! The traces hold the calls, with their results, events and messages, but
+//! not the Sierra statements they executed, which neither executor reports.
+
+use std::path::{Path, PathBuf};
+
+use blockifier::{
+    context::BlockContext,
+    execution::contract_class::RunnableCompiledClass,
+    state::{
+        cached_state::CachedState,
+        state_api::{StateReader as BlockifierStateReader, StateResult},
+    },
+    transaction::{
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use rpc_state_reader::reader::{compile_contract_class_with, Executor, StateReader};
+use serde_json::json;
+use starknet_api::{
+    core::{ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    hash::StarkHash,
+    state::StorageKey,
+};
+use tracing::{error, info};
+
+use crate::{output, rpc_trace};
+
+/// Executors whose call traces are compared
+const EXECUTORS: [Executor; 2] = [Executor::Native, Executor::SierraEmu];
+
+/// Executes the transaction with each executor, discarding the state
+/// changes, and writes the call trace of each execution to
+/// `{dir}/{executor}/{tx_hash}.json`. Returns the written paths, and whether
+/// the traces are equal.
+pub fn write_call_traces<S: BlockifierStateReader>(
+    tx: &BlockiTransaction,
+    state: &mut CachedState<S>,
+    classes: &impl StateReader,
+    block_context: &BlockContext,
+    tx_type: &str,
+    tx_hash: &str,
+    dir: &Path,
+) -> anyhow::Result<(Vec<PathBuf>, bool)> {
+    let mut paths = Vec::with_capacity(EXECUTORS.len());
+    let mut traces = Vec::with_capacity(EXECUTORS.len());
+
+    for executor in EXECUTORS {
+        let name = executor.name();
+        let mut executor_state = CachedState::new(ExecutorStateReader {
+            state: &*state,
+            classes,
+            executor,
+        });
+        let trace = match tx.execute(&mut executor_state, block_context) {
+            Ok(execution_info) => {
+                info!(
+                    executor = name,
+                    reverted = execution_info.is_reverted(),
+                    "execution finished"
+                );
+                rpc_trace::transaction_trace(tx_type, &execution_info)
+            }
+            Err(err) => {
+                error!(executor = name, "execution failed: {err}");
+                json!({ "error": err.to_string() })
+            }
+        };
+
+        let path = dir.join(name).join(format!("{tx_hash}.json"));
+        output::write_json(&path, &trace)?;
+        paths.push(path);
+        traces.push(trace);
+    }
+
+    let equal = traces.windows(2).all(|traces| traces[0] == traces[1]);
+    Ok((paths, equal))
+}
+
+/// A state reader that reads from an inner state, but compiles every Sierra
+/// class for the given executor, reading it from `classes`.
+pub struct ExecutorStateReader<'a, S: BlockifierStateReader, R: StateReader> {
+    pub state: &'a S,
+    pub classes: &'a R,
+    pub executor: Executor,
+}
+
+impl<S: BlockifierStateReader, R: StateReader> BlockifierStateReader
+    for ExecutorStateReader<'_, S, R>
+{
+    fn get_storage_at(
+        &self,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> StateResult<StarkHash> {
+        self.state.get_storage_at(contract_address, key)
+    }
+
+    fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
+        self.state.get_nonce_at(contract_address)
+    }
+
+    fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        self.state.get_class_hash_at(contract_address)
+    }
+
+    fn get_compiled_class(&self, class_hash: ClassHash) -> StateResult<RunnableCompiledClass> {
+        Ok(match self.state.get_compiled_class(class_hash)? {
+            RunnableCompiledClass::V0(class) => RunnableCompiledClass::V0(class),
+            _ => compile_contract_class_with(
+                self.classes.get_contract_class(&class_hash)?,
+                class_hash,
+                self.executor,
+            ),
+        })
+    }
+
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.state.get_compiled_class_hash(class_hash)
+    }
+}
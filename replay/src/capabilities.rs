@@ -0,0 +1,211 @@
+//! Support of the replay for the protocol features of each block.
+//!
+//! Blocks using features the replay only partially supports are executed
+//! anyway, but may not match the on-chain results. The capabilities are
+//! logged once per protocol version, warning about every limitation.
+
+use std::{collections::BTreeSet, fmt, sync::Mutex};
+
+use blockifier::versioned_constants::VersionedConstants;
+use rpc_state_reader::{execution, objects::BlockHeader, reader::StateReader};
+use starknet_api::block::StarknetVersion;
+use tracing::{info, warn};
+
+/// First version charging L2 gas, whose price is only reported since RPC v0.8
+const L2_GAS_VERSION: StarknetVersion = StarknetVersion::V0_13_4;
+
+/// Protocol versions, along with whether the node reports their l2 gas
+/// price, already logged
+static REPORTED: Mutex<BTreeSet<(String, bool)>> = Mutex::new(BTreeSet::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    Yes,
+    Partial,
+    No,
+}
+
+impl fmt::Display for Support {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Support::Yes => write!(f, "yes"),
+            Support::Partial => write!(f, "partial"),
+            Support::No => write!(f, "no"),
+        }
+    }
+}
+
+pub struct Capability {
+    pub name: &'static str,
+    pub support: Support,
+    /// Why the capability is not fully supported
+    pub reason: Option<&'static str>,
+}
+
+impl Capability {
+    fn new(name: &'static str, limitation: Option<(Support, &'static str)>) -> Self {
+        match limitation {
+            Some((support, reason)) => Self {
+                name,
+                support,
+                reason: Some(reason),
+            },
+            None => Self {
+                name,
+                support: Support::Yes,
+                reason: None,
+            },
+        }
+    }
+}
+
+/// Returns the support of each protocol feature used by the block.
+pub fn capabilities(header: &BlockHeader) -> Vec<Capability> {
+    let Ok(version) = StarknetVersion::try_from(header.starknet_version.as_str()) else {
+        return ["fees", "sierra gas", "data availability", "traces"]
+            .into_iter()
+            .map(|name| Capability::new(name, Some((Support::No, "unknown starknet version"))))
+            .collect();
+    };
+    let has_constants = VersionedConstants::get(&version).is_ok();
+
+    let fees = if execution::constants_override().is_some() {
+        Some((Support::Partial, "the versioned constants are overridden"))
+    } else if !has_constants && version < execution::OLDEST_CONSTANTS_VERSION {
+        Some((
+            Support::Partial,
+            "no versioned constants for this version, the oldest ones are used",
+        ))
+    } else if !has_constants {
+        Some((
+            Support::Partial,
+            "no versioned constants for this version, the latest ones are used",
+        ))
+    } else if version >= L2_GAS_VERSION && header.l2_gas_price.is_none() {
+        Some((
+            Support::Partial,
+            "the node doesn't report the l2 gas price, so the minimum one is used",
+        ))
+    } else {
+        None
+    };
+
+    let sierra_gas = if version < L2_GAS_VERSION {
+        Some((
+            Support::Partial,
+            "resources are tracked in cairo steps, which cairo native doesn't measure",
+        ))
+    } else {
+        None
+    };
+
+    let traces = (!has_constants).then_some((
+        Support::Partial,
+        "execution resources depend on the versioned constants, which are missing",
+    ));
+
+    vec![
+        Capability::new("fees", fees),
+        Capability::new("sierra gas", sierra_gas),
+        Capability::new("data availability", None),
+        Capability::new("traces", traces),
+    ]
+}
+
+/// Logs the capability matrix of the block, if it wasn't logged yet for its
+/// protocol version, warning about every capability not fully supported.
+pub fn check(reader: &impl StateReader) {
+    let header = match reader.get_block_with_tx_hashes() {
+        Ok(block) => block.header,
+        Err(err) => {
+            warn!("failed to check protocol capabilities: {err}");
+            return;
+        }
+    };
+
+    let key = (
+        header.starknet_version.clone(),
+        header.l2_gas_price.is_some(),
+    );
+    if !REPORTED.lock().unwrap().insert(key) {
+        return;
+    }
+
+    let capabilities = capabilities(&header);
+
+    let matrix = capabilities
+        .iter()
+        .map(|capability| format!("{}: {}", capability.name, capability.support))
+        .collect::<Vec<_>>()
+        .join(", ");
+    info!(
+        starknet_version = %header.starknet_version,
+        "protocol capabilities: {matrix}"
+    );
+
+    for capability in capabilities {
+        if let Some(reason) = capability.reason {
+            warn!(
+                starknet_version = %header.starknet_version,
+                capability = capability.name,
+                support = %capability.support,
+                "{reason}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn header(starknet_version: &str, l1_da_mode: &str) -> BlockHeader {
+        let price = json!({"price_in_fri": "0x1", "price_in_wei": "0x1"});
+        serde_json::from_value(json!({
+            "block_hash": "0x1",
+            "parent_hash": "0x1",
+            "block_number": 1,
+            "sequencer_address": "0x1",
+            "new_root": "0x1",
+            "timestamp": 1,
+            "l1_gas_price": price,
+            "l1_data_gas_price": price,
+            "l1_da_mode": l1_da_mode,
+            "starknet_version": starknet_version,
+        }))
+        .unwrap()
+    }
+
+    fn support(capabilities: &[Capability], name: &str) -> Support {
+        capabilities
+            .iter()
+            .find(|capability| capability.name == name)
+            .unwrap()
+            .support
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let matrix = capabilities(&header("0.13.2", "BLOB"));
+        assert_eq!(support(&matrix, "fees"), Support::Yes);
+        assert_eq!(support(&matrix, "sierra gas"), Support::Partial);
+        assert_eq!(support(&matrix, "data availability"), Support::Yes);
+
+        let matrix = capabilities(&header("0.13.2", "CALLDATA"));
+        assert_eq!(support(&matrix, "data availability"), Support::Yes);
+
+        let matrix = capabilities(&header("0.13.4", "BLOB"));
+        assert_eq!(support(&matrix, "fees"), Support::Partial);
+        assert_eq!(support(&matrix, "sierra gas"), Support::Yes);
+
+        let mut with_l2_gas_price = header("0.13.4", "BLOB");
+        with_l2_gas_price.l2_gas_price = Some(Default::default());
+        let matrix = capabilities(&with_l2_gas_price);
+        assert_eq!(support(&matrix, "fees"), Support::Yes);
+
+        let matrix = capabilities(&header("invalid", "BLOB"));
+        assert_eq!(support(&matrix, "traces"), Support::No);
+    }
+}
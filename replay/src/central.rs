@@ -0,0 +1,457 @@
+//! Comparison of replayed transactions against the central objects written
+//! by the sequencer.
+//!
+//! A central blob is the JSON object that the sequencer writes for each
+//! block it builds (`AerospikeBlob`, in the `cende` module of the Apollo
+//! sequencer). Only the fields that are compared are read:
+//! - `block_number`
+//! - `transactions`, with the hash of each at `tx.hash_value`
+//! - `execution_infos`, in the same order as the transactions. Each has the
+//!   `validate_call_info`, `execute_call_info` and `fee_transfer_call_info`
+//!   serialized as the blockifier `CallInfo`, the `revert_error` and the
+//!   `actual_fee`.
+//!
+//! Each execution info is compared against the state dump of the replayed
+//! transaction, so the block must have been replayed with the `state_dump`
+//! feature.
+//!
+//! Revert errors are only compared by presence, as their messages differ
+//! between executors.
+
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use starknet_api::{
+    core::{ContractAddress, EntryPointSelector},
+    transaction::{fields::Fee, EventContent},
+};
+use starknet_types_core::felt::Felt;
+
+use crate::dump_schema;
+
+#[derive(Deserialize)]
+struct CentralBlob {
+    block_number: u64,
+    transactions: Vec<CentralBlobTransaction>,
+    execution_infos: Vec<CentralExecutionInfo>,
+}
+
+#[derive(Deserialize)]
+struct CentralBlobTransaction {
+    tx: CentralTransactionHash,
+}
+
+#[derive(Deserialize)]
+struct CentralTransactionHash {
+    hash_value: String,
+}
+
+/// Execution info of a central blob
+#[derive(Deserialize, Serialize)]
+pub struct CentralExecutionInfo {
+    #[serde(flatten)]
+    calls: ExecutionCalls,
+    actual_fee: Fee,
+}
+
+/// Execution info of a state dump. See `dump_schema::SerializableExecutionInfo`
+#[derive(Deserialize)]
+struct ReplayExecutionInfo {
+    #[serde(flatten)]
+    calls: ExecutionCalls,
+    receipt: ReplayReceipt,
+}
+
+#[derive(Deserialize)]
+struct ReplayReceipt {
+    fee: Fee,
+}
+
+/// Fields shared by the execution infos of the blob and of the dumps
+#[derive(Deserialize, Serialize)]
+struct ExecutionCalls {
+    validate_call_info: Option<ComparedCallInfo>,
+    execute_call_info: Option<ComparedCallInfo>,
+    fee_transfer_call_info: Option<ComparedCallInfo>,
+    revert_error: Option<Value>,
+}
+
+/// Fields of a blockifier `CallInfo` that must match
+#[derive(Deserialize, Serialize)]
+struct ComparedCallInfo {
+    call: ComparedCallEntryPoint,
+    execution: ComparedCallExecution,
+    #[serde(default)]
+    inner_calls: Vec<ComparedCallInfo>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ComparedCallEntryPoint {
+    entry_point_selector: EntryPointSelector,
+    storage_address: ContractAddress,
+    calldata: Vec<Felt>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ComparedCallExecution {
+    retdata: Vec<Felt>,
+    failed: bool,
+    gas_consumed: u64,
+    events: Vec<ComparedEvent>,
+    l2_to_l1_messages: Vec<ComparedMessage>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq)]
+struct ComparedEvent {
+    order: usize,
+    event: EventContent,
+}
+
+#[derive(Deserialize, Serialize, PartialEq)]
+struct ComparedMessage {
+    order: usize,
+    message: ComparedMessageContent,
+}
+
+#[derive(Deserialize, Serialize, PartialEq)]
+struct ComparedMessageContent {
+    to_address: Felt,
+    payload: Vec<Felt>,
+}
+
+pub struct CentralTransaction {
+    pub tx_hash: String,
+    pub execution_info: CentralExecutionInfo,
+}
+
+pub struct CentralBlock {
+    pub block_number: u64,
+    pub transactions: Vec<CentralTransaction>,
+}
+
+#[derive(Serialize)]
+pub struct TransactionComparison {
+    pub tx_hash: String,
+    #[serde(flatten)]
+    pub status: ComparisonStatus,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ComparisonStatus {
+    Match,
+    Differ {
+        differences: Vec<Difference>,
+    },
+    /// The transaction has no state dump
+    Missing,
+}
+
+#[derive(Serialize)]
+pub struct Difference {
+    pub path: String,
+    pub central: Value,
+    pub replay: Value,
+}
+
+/// Reads the transactions and execution infos of a central blob
+pub fn import_central_blob(path: &Path) -> anyhow::Result<CentralBlock> {
+    let blob =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let blob: CentralBlob = serde_json::from_str(&blob)
+        .with_context(|| format!("failed to parse central blob {}", path.display()))?;
+
+    ensure!(
+        blob.transactions.len() == blob.execution_infos.len(),
+        "central blob has {} transactions but {} execution infos",
+        blob.transactions.len(),
+        blob.execution_infos.len()
+    );
+
+    let transactions = blob
+        .transactions
+        .into_iter()
+        .zip(blob.execution_infos)
+        .map(|(transaction, execution_info)| CentralTransaction {
+            tx_hash: transaction.tx.hash_value,
+            execution_info,
+        })
+        .collect();
+
+    Ok(CentralBlock {
+        block_number: blob.block_number,
+        transactions,
+    })
+}
+
+/// Compares every transaction of the block against its state dump
+/// (`{dumps_root}/block{block_number}/{tx_hash}.json`).
+pub fn compare_block(block: &CentralBlock, dumps_root: &Path) -> Vec<TransactionComparison> {
+    let block_root = dumps_root.join(format!("block{}", block.block_number));
+
+    block
+        .transactions
+        .iter()
+        .map(|transaction| {
+            let mut path = block_root.join(&transaction.tx_hash);
+            path.set_extension("json");
+
+            let status = match dump_schema::read_dump(&path).ok() {
+                Some(dump) => {
+                    let differences = compare_execution_info(&transaction.execution_info, &dump);
+                    if differences.is_empty() {
+                        ComparisonStatus::Match
+                    } else {
+                        ComparisonStatus::Differ { differences }
+                    }
+                }
+                None => ComparisonStatus::Missing,
+            };
+
+            TransactionComparison {
+                tx_hash: transaction.tx_hash.clone(),
+                status,
+            }
+        })
+        .collect()
+}
+
+fn compare_execution_info(central: &CentralExecutionInfo, dump: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    // The replay failed, so there is no execution info to compare with
+    if let Some(error) = dump.get("reverted") {
+        differences.push(Difference {
+            path: "execution_info".to_string(),
+            central: serde_json::to_value(central).expect("failed to serialize central value"),
+            replay: error.clone(),
+        });
+        return differences;
+    }
+
+    let replay = match ReplayExecutionInfo::deserialize(&dump["execution_info"]) {
+        Ok(replay) => replay,
+        Err(err) => {
+            differences.push(Difference {
+                path: "execution_info".to_string(),
+                central: Value::Null,
+                replay: format!("invalid state dump: {err}").into(),
+            });
+            return differences;
+        }
+    };
+
+    for (name, central_call, replay_call) in [
+        (
+            "validate_call_info",
+            &central.calls.validate_call_info,
+            &replay.calls.validate_call_info,
+        ),
+        (
+            "execute_call_info",
+            &central.calls.execute_call_info,
+            &replay.calls.execute_call_info,
+        ),
+        (
+            "fee_transfer_call_info",
+            &central.calls.fee_transfer_call_info,
+            &replay.calls.fee_transfer_call_info,
+        ),
+    ] {
+        match (central_call, replay_call) {
+            (Some(central_call), Some(replay_call)) => {
+                compare_call(central_call, replay_call, name, &mut differences)
+            }
+            (None, None) => {}
+            _ => differences.push(difference(name.to_string(), central_call, replay_call)),
+        }
+    }
+
+    if central.calls.revert_error.is_some() != replay.calls.revert_error.is_some() {
+        differences.push(difference(
+            "revert_error".to_string(),
+            &central.calls.revert_error,
+            &replay.calls.revert_error,
+        ));
+    }
+
+    compare_value(
+        &central.actual_fee,
+        &replay.receipt.fee,
+        "actual_fee".to_string(),
+        &mut differences,
+    );
+
+    differences
+}
+
+fn compare_call(
+    central: &ComparedCallInfo,
+    replay: &ComparedCallInfo,
+    path: &str,
+    differences: &mut Vec<Difference>,
+) {
+    let (central_call, replay_call) = (&central.call, &replay.call);
+    compare_value(
+        &central_call.entry_point_selector,
+        &replay_call.entry_point_selector,
+        format!("{path}.call.entry_point_selector"),
+        differences,
+    );
+    compare_value(
+        &central_call.storage_address,
+        &replay_call.storage_address,
+        format!("{path}.call.storage_address"),
+        differences,
+    );
+    compare_value(
+        &central_call.calldata,
+        &replay_call.calldata,
+        format!("{path}.call.calldata"),
+        differences,
+    );
+
+    let (central_execution, replay_execution) = (&central.execution, &replay.execution);
+    compare_value(
+        &central_execution.retdata,
+        &replay_execution.retdata,
+        format!("{path}.execution.retdata"),
+        differences,
+    );
+    compare_value(
+        &central_execution.failed,
+        &replay_execution.failed,
+        format!("{path}.execution.failed"),
+        differences,
+    );
+    compare_value(
+        &central_execution.gas_consumed,
+        &replay_execution.gas_consumed,
+        format!("{path}.execution.gas_consumed"),
+        differences,
+    );
+    compare_value(
+        &central_execution.events,
+        &replay_execution.events,
+        format!("{path}.execution.events"),
+        differences,
+    );
+    compare_value(
+        &central_execution.l2_to_l1_messages,
+        &replay_execution.l2_to_l1_messages,
+        format!("{path}.execution.l2_to_l1_messages"),
+        differences,
+    );
+
+    if central.inner_calls.len() != replay.inner_calls.len() {
+        differences.push(Difference {
+            path: format!("{path}.inner_calls.len"),
+            central: central.inner_calls.len().into(),
+            replay: replay.inner_calls.len().into(),
+        });
+    }
+
+    for (index, (central_call, replay_call)) in central
+        .inner_calls
+        .iter()
+        .zip(&replay.inner_calls)
+        .enumerate()
+    {
+        compare_call(
+            central_call,
+            replay_call,
+            &format!("{path}.inner_calls[{index}]"),
+            differences,
+        );
+    }
+}
+
+fn compare_value<T: PartialEq + Serialize>(
+    central: &T,
+    replay: &T,
+    path: String,
+    differences: &mut Vec<Difference>,
+) {
+    if central != replay {
+        differences.push(difference(path, central, replay));
+    }
+}
+
+fn difference<T: Serialize>(path: String, central: &T, replay: &T) -> Difference {
+    Difference {
+        path,
+        central: serde_json::to_value(central).expect("failed to serialize central value"),
+        replay: serde_json::to_value(replay).expect("failed to serialize replayed value"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn call(retdata: Value, inner_calls: Vec<Value>) -> Value {
+        json!({
+            "call": {
+                "entry_point_selector": "0x1",
+                "storage_address": "0x2",
+                "calldata": ["0x3"],
+            },
+            "execution": {
+                "retdata": retdata,
+                "failed": false,
+                "gas_consumed": 100,
+                "events": [],
+                "l2_to_l1_messages": [],
+            },
+            "inner_calls": inner_calls,
+        })
+    }
+
+    #[test]
+    fn test_compare_execution_info() {
+        let central = CentralExecutionInfo::deserialize(json!({
+            "execute_call_info": call(json!(["0x1"]), vec![call(json!([]), vec![])]),
+            "validate_call_info": null,
+            "fee_transfer_call_info": null,
+            "revert_error": null,
+            "actual_fee": "0x10",
+        }))
+        .unwrap();
+        let dump = json!({
+            "execution_info": {
+                "execute_call_info": call(json!(["0x1"]), vec![call(json!([]), vec![])]),
+                "validate_call_info": null,
+                "fee_transfer_call_info": null,
+                "revert_error": null,
+                "receipt": { "fee": "0x10" },
+            },
+            "state_maps": {},
+        });
+        assert!(compare_execution_info(&central, &dump).is_empty());
+
+        let dump = json!({
+            "execution_info": {
+                "execute_call_info": call(json!(["0x1"]), vec![call(json!(["0x2"]), vec![])]),
+                "validate_call_info": null,
+                "fee_transfer_call_info": null,
+                "revert_error": "reverted",
+                "receipt": { "fee": "0x10" },
+            },
+        });
+        let paths = compare_execution_info(&central, &dump)
+            .into_iter()
+            .map(|difference| difference.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "execute_call_info.inner_calls[0].execution.retdata",
+                "revert_error"
+            ]
+        );
+    }
+}
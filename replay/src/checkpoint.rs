@@ -0,0 +1,103 @@
+//! Progress of a block range execution, persisted after every block so that
+//! an interrupted run can resume where it left off.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use starknet_api::transaction::TransactionHash;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub chain: String,
+    pub block_start: u64,
+    pub block_end: u64,
+    /// Last block whose transactions were all executed
+    pub last_executed_block: Option<u64>,
+    /// Transactions that failed to execute, in execution order
+    pub failed_txs: Vec<TransactionHash>,
+}
+
+impl Checkpoint {
+    pub fn new(chain: &str, block_start: u64, block_end: u64) -> Self {
+        Self {
+            chain: chain.to_string(),
+            block_start,
+            block_end,
+            last_executed_block: None,
+            failed_txs: Vec::new(),
+        }
+    }
+
+    /// Loads the checkpoint of the given range, or starts a new one if the file doesn't exist.
+    /// Fails if the file belongs to a different range.
+    pub fn load_or_new(
+        path: &Path,
+        chain: &str,
+        block_start: u64,
+        block_end: u64,
+    ) -> anyhow::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(Self::new(chain, block_start, block_end))
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+
+        let checkpoint: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+        ensure!(
+            checkpoint.chain == chain
+                && checkpoint.block_start == block_start
+                && checkpoint.block_end == block_end,
+            "checkpoint {} belongs to the range {} - {} of {}",
+            path.display(),
+            checkpoint.block_start,
+            checkpoint.block_end,
+            checkpoint.chain
+        );
+
+        Ok(checkpoint)
+    }
+
+    /// First block that wasn't fully executed
+    pub fn next_block(&self) -> u64 {
+        self.last_executed_block
+            .map_or(self.block_start, |block_number| block_number + 1)
+    }
+
+    pub fn record_block(&mut self, block_number: u64, failed_txs: Vec<TransactionHash>) {
+        self.last_executed_block = Some(block_number);
+        self.failed_txs.extend(failed_txs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::felt;
+
+    use super::*;
+
+    #[test]
+    fn test_resume() {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+
+        let mut checkpoint = Checkpoint::load_or_new(&path, "mainnet", 10, 20).unwrap();
+        assert_eq!(checkpoint.next_block(), 10);
+
+        checkpoint.record_block(10, vec![TransactionHash(felt!("0x1"))]);
+        checkpoint.record_block(11, Vec::new());
+        fs::write(&path, serde_json::to_string(&checkpoint).unwrap()).unwrap();
+
+        let resumed = Checkpoint::load_or_new(&path, "mainnet", 10, 20).unwrap();
+        assert_eq!(resumed, checkpoint);
+        assert_eq!(resumed.next_block(), 12);
+
+        assert!(Checkpoint::load_or_new(&path, "mainnet", 10, 30).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
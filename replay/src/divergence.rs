@@ -0,0 +1,372 @@
+//! Structured comparison of the execution of a transaction with Cairo Native
+//! and with the Cairo VM.
+//!
+//! Both executions run in the same process, on top of the same state. The
+//! VM execution reads the state through a `VmStateReader`, so every Sierra
+//! class is executed from its CASM.
+
+use std::{collections::HashMap, hash::Hash};
+
+use blockifier::{
+    context::BlockContext,
+    execution::call_info::CallInfo,
+    state::{
+        cached_state::{CachedState, StateMaps},
+        state_api::StateReader as BlockifierStateReader,
+    },
+    transaction::{
+        errors::TransactionExecutionError, objects::TransactionExecutionInfo,
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::fallback::VmStateReader;
+
+#[derive(Serialize)]
+pub struct Divergence {
+    pub path: String,
+    pub native: Value,
+    pub vm: Value,
+}
+
+struct Execution {
+    result: Result<TransactionExecutionInfo, TransactionExecutionError>,
+    writes: StateMaps,
+}
+
+/// Executes the transaction with both executors, discarding the state
+/// changes, and returns every divergence between them.
+pub fn diff_transaction<S: BlockifierStateReader>(
+    tx: &BlockiTransaction,
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+) -> anyhow::Result<Vec<Divergence>> {
+    let native = {
+        let mut native_state = CachedState::create_transactional(state);
+        let result = tx.execute(&mut native_state, block_context);
+        let writes = native_state.to_state_diff()?.state_maps;
+        Execution { result, writes }
+    };
+
+    let vm = {
+        let mut vm_state = CachedState::new(VmStateReader(&*state));
+        let result = tx.execute(&mut vm_state, block_context);
+        let writes = vm_state.to_state_diff()?.state_maps;
+        Execution { result, writes }
+    };
+
+    let mut divergences = Vec::new();
+
+    match (&native.result, &vm.result) {
+        (Ok(native_info), Ok(vm_info)) => {
+            diff_execution_info(native_info, vm_info, &mut divergences);
+            diff_state(&native.writes, &vm.writes, &mut divergences);
+        }
+        // Error messages differ between executors, so they are not compared
+        (Err(_), Err(_)) => {}
+        (native_result, vm_result) => divergences.push(Divergence {
+            path: "result".to_string(),
+            native: result_json(native_result),
+            vm: result_json(vm_result),
+        }),
+    }
+
+    Ok(divergences)
+}
+
+/// Returns the first point of divergence in the call tree: the deepest
+/// diverging call of the first diverging subtree, in execution order, as a
+/// parent usually diverges only because one of its inner calls did.
+/// Falls back to the first divergence if no call diverged.
+pub fn first_divergence(divergences: &[Divergence]) -> Option<&Divergence> {
+    let Some(mut first) = divergences
+        .iter()
+        .find(|divergence| is_call(&divergence.path))
+    else {
+        return divergences.first();
+    };
+    while let Some(inner) = divergences.iter().find(|divergence| {
+        is_call(&divergence.path)
+            && call_path(&divergence.path)
+                .starts_with(&format!("{}.inner_calls[", call_path(&first.path)))
+    }) {
+        first = inner;
+    }
+    Some(first)
+}
+
+fn is_call(path: &str) -> bool {
+    ["validate", "execute", "fee_transfer"]
+        .iter()
+        .any(|root| path == *root || path.starts_with(&format!("{root}.")))
+}
+
+/// Returns the path of the call a divergence belongs to
+fn call_path(path: &str) -> &str {
+    if let Some(call) = path.strip_suffix(".inner_calls.len") {
+        return call;
+    }
+    match path.rsplit_once('.') {
+        Some((call, field)) if !field.contains('[') => call,
+        _ => path,
+    }
+}
+
+fn result_json(result: &Result<TransactionExecutionInfo, TransactionExecutionError>) -> Value {
+    match result {
+        Ok(_) => json!("succeeded"),
+        Err(err) => json!({ "failed": err.to_string() }),
+    }
+}
+
+fn diff_execution_info(
+    native: &TransactionExecutionInfo,
+    vm: &TransactionExecutionInfo,
+    divergences: &mut Vec<Divergence>,
+) {
+    diff_call(
+        native.validate_call_info.as_ref(),
+        vm.validate_call_info.as_ref(),
+        "validate",
+        divergences,
+    );
+    diff_call(
+        native.execute_call_info.as_ref(),
+        vm.execute_call_info.as_ref(),
+        "execute",
+        divergences,
+    );
+    diff_call(
+        native.fee_transfer_call_info.as_ref(),
+        vm.fee_transfer_call_info.as_ref(),
+        "fee_transfer",
+        divergences,
+    );
+
+    diff_value(
+        &native.is_reverted(),
+        &vm.is_reverted(),
+        "reverted".to_string(),
+        divergences,
+    );
+    diff_value(
+        &native.receipt.fee,
+        &vm.receipt.fee,
+        "receipt.fee".to_string(),
+        divergences,
+    );
+    diff_value(
+        &native.receipt.gas,
+        &vm.receipt.gas,
+        "receipt.gas".to_string(),
+        divergences,
+    );
+}
+
+fn diff_call(
+    native: Option<&CallInfo>,
+    vm: Option<&CallInfo>,
+    path: &str,
+    divergences: &mut Vec<Divergence>,
+) {
+    let (native, vm) = match (native, vm) {
+        (Some(native), Some(vm)) => (native, vm),
+        (None, None) => return,
+        (native, vm) => {
+            divergences.push(Divergence {
+                path: path.to_string(),
+                native: json!(native.is_some()),
+                vm: json!(vm.is_some()),
+            });
+            return;
+        }
+    };
+
+    diff_value(
+        &native.call.entry_point_selector,
+        &vm.call.entry_point_selector,
+        format!("{path}.selector"),
+        divergences,
+    );
+    diff_value(
+        &native.call.storage_address,
+        &vm.call.storage_address,
+        format!("{path}.storage_address"),
+        divergences,
+    );
+    diff_value(
+        &native.call.calldata,
+        &vm.call.calldata,
+        format!("{path}.calldata"),
+        divergences,
+    );
+    diff_value(
+        &native.execution.retdata,
+        &vm.execution.retdata,
+        format!("{path}.retdata"),
+        divergences,
+    );
+    diff_value(
+        &native.execution.failed,
+        &vm.execution.failed,
+        format!("{path}.failed"),
+        divergences,
+    );
+    diff_value(
+        &native.execution.gas_consumed,
+        &vm.execution.gas_consumed,
+        format!("{path}.gas_consumed"),
+        divergences,
+    );
+    diff_value(
+        &native.execution.events,
+        &vm.execution.events,
+        format!("{path}.events"),
+        divergences,
+    );
+    diff_value(
+        &native.execution.l2_to_l1_messages,
+        &vm.execution.l2_to_l1_messages,
+        format!("{path}.messages"),
+        divergences,
+    );
+
+    diff_value(
+        &native.inner_calls.len(),
+        &vm.inner_calls.len(),
+        format!("{path}.inner_calls.len"),
+        divergences,
+    );
+    for (index, (native_call, vm_call)) in
+        native.inner_calls.iter().zip(&vm.inner_calls).enumerate()
+    {
+        diff_call(
+            Some(native_call),
+            Some(vm_call),
+            &format!("{path}.inner_calls[{index}]"),
+            divergences,
+        );
+    }
+}
+
+fn diff_state(native: &StateMaps, vm: &StateMaps, divergences: &mut Vec<Divergence>) {
+    diff_map(
+        &native.storage,
+        &vm.storage,
+        |(contract_address, key)| {
+            format!(
+                "state.storage[{}][{}]",
+                contract_address.0.key().to_hex_string(),
+                key.0.key().to_hex_string()
+            )
+        },
+        divergences,
+    );
+    diff_map(
+        &native.nonces,
+        &vm.nonces,
+        |contract_address| format!("state.nonces[{}]", contract_address.0.key().to_hex_string()),
+        divergences,
+    );
+    diff_map(
+        &native.class_hashes,
+        &vm.class_hashes,
+        |contract_address| {
+            format!(
+                "state.class_hashes[{}]",
+                contract_address.0.key().to_hex_string()
+            )
+        },
+        divergences,
+    );
+    diff_map(
+        &native.compiled_class_hashes,
+        &vm.compiled_class_hashes,
+        |class_hash| {
+            format!(
+                "state.compiled_class_hashes[{}]",
+                class_hash.to_hex_string()
+            )
+        },
+        divergences,
+    );
+    diff_map(
+        &native.declared_contracts,
+        &vm.declared_contracts,
+        |class_hash| format!("state.declared_contracts[{}]", class_hash.to_hex_string()),
+        divergences,
+    );
+}
+
+/// Compares the values of every key in any of the maps, in key order
+fn diff_map<K: Eq + Hash + Ord, V: Serialize + PartialEq>(
+    native: &HashMap<K, V>,
+    vm: &HashMap<K, V>,
+    path: impl Fn(&K) -> String,
+    divergences: &mut Vec<Divergence>,
+) {
+    let mut keys = native.keys().chain(vm.keys()).collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys.dedup();
+
+    for key in keys {
+        diff_value(&native.get(key), &vm.get(key), path(key), divergences);
+    }
+}
+
+fn diff_value<T: Serialize + PartialEq>(
+    native: &T,
+    vm: &T,
+    path: String,
+    divergences: &mut Vec<Divergence>,
+) {
+    if native != vm {
+        divergences.push(Divergence {
+            path,
+            native: json!(native),
+            vm: json!(vm),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn divergence(path: &str) -> Divergence {
+        Divergence {
+            path: path.to_string(),
+            native: json!(1),
+            vm: json!(2),
+        }
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let divergences = [
+            divergence("execute.retdata"),
+            divergence("execute.inner_calls[0].retdata"),
+            divergence("execute.inner_calls[0].inner_calls.len"),
+            divergence("execute.inner_calls[0].inner_calls[1].calldata"),
+            divergence("execute.inner_calls[1].gas_consumed"),
+            divergence("receipt.fee"),
+        ];
+        assert_eq!(
+            first_divergence(&divergences).unwrap().path,
+            "execute.inner_calls[0].inner_calls[1].calldata"
+        );
+
+        let divergences = [divergence("receipt.fee"), divergence("fee_transfer")];
+        assert_eq!(first_divergence(&divergences).unwrap().path, "fee_transfer");
+        assert_eq!(
+            first_divergence(&[divergence("state.nonces[0x1]")])
+                .unwrap()
+                .path,
+            "state.nonces[0x1]"
+        );
+        assert!(first_divergence(&[]).is_none());
+    }
+}
@@ -0,0 +1,220 @@
+//! Structural comparison of two state dumps of the same transaction, e.g.
+//! replayed with Native and with the VM.
+//!
+//! The state maps are compared entry by entry, so only the storage slots,
+//! nonces and class hashes that differ are reported. The execution info is
+//! compared field by field, down to each event and inner call. Revert
+//! errors are only compared by presence, as their messages differ between
+//! executors.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::dump_schema::schema_version;
+
+/// Sections of the state maps, serialized as lists of entries
+const STATE_MAPS: [&str; 5] = [
+    "nonces",
+    "class_hashes",
+    "storage",
+    "compiled_class_hashes",
+    "declared_contracts",
+];
+
+#[derive(Debug, Serialize)]
+pub struct Difference {
+    pub path: String,
+    pub a: Value,
+    pub b: Value,
+}
+
+/// Returns the differences between the dumps
+pub fn diff_dumps(a: &Value, b: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    // Dumps of different versions may differ only because of the schema
+    if schema_version(a) != schema_version(b) {
+        differences.push(Difference {
+            path: "schema_version".to_string(),
+            a: schema_version(a).into(),
+            b: schema_version(b).into(),
+        });
+    }
+
+    // Failed executions are dumped as their error alone
+    let a_failed = a.get("reverted").is_some();
+    let b_failed = b.get("reverted").is_some();
+    if a_failed || b_failed {
+        if a_failed != b_failed {
+            differences.push(Difference {
+                path: "reverted".to_string(),
+                a: a.get("reverted").cloned().unwrap_or_default(),
+                b: b.get("reverted").cloned().unwrap_or_default(),
+            });
+        }
+        return differences;
+    }
+
+    let (a_info, b_info) = (&a["execution_info"], &b["execution_info"]);
+    if a_info["revert_error"].is_null() != b_info["revert_error"].is_null() {
+        differences.push(Difference {
+            path: "execution_info.revert_error".to_string(),
+            a: a_info["revert_error"].clone(),
+            b: b_info["revert_error"].clone(),
+        });
+    }
+    for field in [
+        "validate_call_info",
+        "execute_call_info",
+        "fee_transfer_call_info",
+        "receipt",
+    ] {
+        diff_value(
+            &a_info[field],
+            &b_info[field],
+            format!("execution_info.{field}"),
+            &mut differences,
+        );
+    }
+
+    for section in STATE_MAPS {
+        diff_entries(
+            &a["state_maps"][section],
+            &b["state_maps"][section],
+            &format!("state_maps.{section}"),
+            &mut differences,
+        );
+    }
+
+    differences
+}
+
+/// Compares lists of `[key, value]` entries as maps
+fn diff_entries(a: &Value, b: &Value, path: &str, differences: &mut Vec<Difference>) {
+    let entries = |value: &Value| {
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Some((entry.get(0)?.to_string(), entry.get(1)?.clone())))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let a = entries(a);
+    let mut b = entries(b);
+
+    for (key, a_value) in a {
+        let b_value = b.remove(&key).unwrap_or_default();
+        if a_value != b_value {
+            differences.push(Difference {
+                path: format!("{path}[{key}]"),
+                a: a_value,
+                b: b_value,
+            });
+        }
+    }
+    for (key, b_value) in b {
+        differences.push(Difference {
+            path: format!("{path}[{key}]"),
+            a: Value::Null,
+            b: b_value,
+        });
+    }
+}
+
+/// Compares the values recursively, reporting the differing leaves
+fn diff_value(a: &Value, b: &Value, path: String, differences: &mut Vec<Difference>) {
+    match (a, b) {
+        (Value::Object(a_fields), Value::Object(b_fields)) => {
+            let mut fields = a_fields.keys().chain(b_fields.keys()).collect::<Vec<_>>();
+            fields.sort();
+            fields.dedup();
+            for field in fields {
+                diff_value(
+                    a_fields.get(field).unwrap_or(&Value::Null),
+                    b_fields.get(field).unwrap_or(&Value::Null),
+                    format!("{path}.{field}"),
+                    differences,
+                );
+            }
+        }
+        (Value::Array(a_items), Value::Array(b_items)) => {
+            if a_items.len() != b_items.len() {
+                differences.push(Difference {
+                    path: format!("{path}.len"),
+                    a: a_items.len().into(),
+                    b: b_items.len().into(),
+                });
+            }
+            for (index, (a_item, b_item)) in a_items.iter().zip(b_items).enumerate() {
+                diff_value(a_item, b_item, format!("{path}[{index}]"), differences);
+            }
+        }
+        _ if a != b => differences.push(Difference {
+            path,
+            a: a.clone(),
+            b: b.clone(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn dump(retdata: Value, events: Value, storage: Value) -> Value {
+        json!({
+            "execution_info": {
+                "execute_call_info": {
+                    "call": { "entry_point_selector": "0x1", "calldata": ["0x2"] },
+                    "execution": { "retdata": retdata, "events": events, "gas_consumed": 10 },
+                    "inner_calls": [],
+                },
+                "revert_error": null,
+                "receipt": { "fee": "0x10" },
+            },
+            "state_maps": { "storage": storage, "nonces": [["0x1", "0x1"]] },
+        })
+    }
+
+    #[test]
+    fn test_diff_dumps() {
+        let a = dump(
+            json!(["0x1"]),
+            json!([{ "order": 0, "data": ["0x1"] }]),
+            json!([[["0x1", "0x2"], "0x3"], [["0x1", "0x3"], "0x4"]]),
+        );
+        assert!(diff_dumps(&a, &a).is_empty());
+
+        let b = dump(
+            json!(["0x1"]),
+            json!([{ "order": 0, "data": ["0x2"] }, { "order": 1, "data": [] }]),
+            json!([
+                [["0x1", "0x2"], "0x3"],
+                [["0x1", "0x3"], "0x5"],
+                [["0x2", "0x1"], "0x1"]
+            ]),
+        );
+        let paths = diff_dumps(&a, &b)
+            .into_iter()
+            .map(|difference| difference.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "execution_info.execute_call_info.execution.events.len",
+                "execution_info.execute_call_info.execution.events[0].data[0]",
+                r#"state_maps.storage[["0x1","0x3"]]"#,
+                r#"state_maps.storage[["0x2","0x1"]]"#,
+            ]
+        );
+
+        let failed = json!({ "reverted": "out of gas" });
+        assert_eq!(diff_dumps(&a, &failed).len(), 1);
+        assert!(diff_dumps(&failed, &json!({ "reverted": "Out of gas." })).is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+//! Schema of the state dumps, shared by the code that writes them and the
+//! tools that read them.
+//!
+//! Dumps are written as canonical JSON: object keys are sorted and sets are
+//! written as sorted lists, so dumps of the same execution are identical
+//! byte for byte. Every dump has a `schema_version`, which must be bumped
+//! whenever its fields change, so that dumps of different replay versions
+//! aren't compared by mistake. Dumps written before the version was added
+//! are read as version 0.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{ensure, Context};
+use blockifier::{
+    execution::{
+        call_info::{CallExecution, CallInfo},
+        entry_point::{CallEntryPoint, CallType},
+    },
+    fee::{
+        receipt::TransactionReceipt,
+        resources::{ComputationResources, StarknetResources, TransactionResources},
+    },
+    state::cached_state::{StateMaps, StorageEntry},
+    transaction::objects::TransactionExecutionInfo,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::serde_as;
+use starknet_api::{
+    contract_class::EntryPointType,
+    core::{ClassHash, CompiledClassHash, ContractAddress, EntryPointSelector, Nonce},
+    execution_resources::{GasAmount, GasVector},
+    state::StorageKey,
+    transaction::fields::{Calldata, Fee},
+};
+use starknet_types_core::felt::Felt;
+
+/// Version of the dumps written by this replay
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Serializes the dump as canonical JSON
+pub fn to_canonical(dump: &impl Serialize) -> anyhow::Result<Value> {
+    // Without the `preserve_order` feature, JSON objects are sorted maps
+    Ok(serde_json::to_value(dump)?)
+}
+
+/// Reads a dump, checking that this replay understands its version
+pub fn read_dump(path: &Path) -> anyhow::Result<Value> {
+    let dump = fs::read_to_string(path)
+        .with_context(|| format!("failed to read state dump {}", path.display()))?;
+    let dump: Value = serde_json::from_str(&dump)
+        .with_context(|| format!("failed to parse state dump {}", path.display()))?;
+    check_version(&dump).with_context(|| format!("unsupported state dump {}", path.display()))?;
+    Ok(dump)
+}
+
+/// Returns the schema version of the dump
+pub fn schema_version(dump: &Value) -> u64 {
+    dump.get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or_default()
+}
+
+fn check_version(dump: &Value) -> anyhow::Result<()> {
+    let version = schema_version(dump);
+    ensure!(
+        version <= SCHEMA_VERSION as u64,
+        "schema version {version} is newer than the supported {SCHEMA_VERSION}"
+    );
+    Ok(())
+}
+
+// The error messages is different between CairoVM and Cairo Native. That is way
+// we must ignore them while comparing the state dumps. To make ignoring them
+// easier, we name the field that contains the error message as "reverted" in
+// `ErrorDump`. That way we can just filter out that line before comparing them
+
+/// Dump of a transaction that failed to execute
+#[derive(Serialize)]
+pub struct ErrorDump {
+    pub schema_version: u32,
+    pub reverted: String,
+}
+
+/// Dump of an executed transaction
+#[derive(Serialize)]
+pub struct StateDump {
+    pub schema_version: u32,
+    pub execution_info: SerializableExecutionInfo,
+    pub state_maps: SerializableStateMaps,
+}
+
+/// From `blockifier::state::cached_state::StateMaps`
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct SerializableStateMaps {
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub nonces: BTreeMap<ContractAddress, Nonce>,
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub class_hashes: BTreeMap<ContractAddress, ClassHash>,
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub storage: BTreeMap<StorageEntry, Felt>,
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub compiled_class_hashes: BTreeMap<ClassHash, CompiledClassHash>,
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub declared_contracts: BTreeMap<ClassHash, bool>,
+}
+
+impl From<StateMaps> for SerializableStateMaps {
+    fn from(value: StateMaps) -> Self {
+        Self {
+            nonces: value.nonces.into_iter().collect(),
+            class_hashes: value.class_hashes.into_iter().collect(),
+            storage: value.storage.into_iter().collect(),
+            compiled_class_hashes: value.compiled_class_hashes.into_iter().collect(),
+            declared_contracts: value.declared_contracts.into_iter().collect(),
+        }
+    }
+}
+
+/// From `blockifier::transaction::objects::TransactionExecutionInfo`
+#[derive(Serialize)]
+pub struct SerializableExecutionInfo {
+    pub validate_call_info: Option<SerializableCallInfo>,
+    pub execute_call_info: Option<SerializableCallInfo>,
+    pub fee_transfer_call_info: Option<SerializableCallInfo>,
+    pub revert_error: Option<String>,
+    pub receipt: SerializableTransactionReceipt,
+}
+
+impl SerializableExecutionInfo {
+    pub fn new(execution_info: TransactionExecutionInfo) -> Self {
+        let TransactionExecutionInfo {
+            validate_call_info,
+            execute_call_info,
+            fee_transfer_call_info,
+            revert_error,
+            receipt,
+        } = execution_info;
+
+        Self {
+            validate_call_info: validate_call_info.clone().map(From::<CallInfo>::from),
+            execute_call_info: execute_call_info.clone().map(From::<CallInfo>::from),
+            fee_transfer_call_info: fee_transfer_call_info.clone().map(From::<CallInfo>::from),
+            revert_error: revert_error.map(|x| x.to_string()),
+            receipt: SerializableTransactionReceipt::from(receipt),
+        }
+    }
+}
+
+/// From `blockifier::execution::call_info::CallInfo`
+#[derive(Serialize)]
+pub struct SerializableCallInfo {
+    pub call: SerializableCallEntryPoint,
+    pub execution: CallExecution,
+    pub inner_calls: Vec<SerializableCallInfo>,
+    pub storage_read_values: Vec<Felt>,
+
+    // Convert HashSet to vector to avoid random order
+    pub accessed_storage_keys: Vec<StorageKey>,
+    pub read_class_hash_values: Vec<ClassHash>,
+    // Convert HashSet to vector to avoid random order
+    pub accessed_contract_addresses: Vec<ContractAddress>,
+}
+
+impl From<CallInfo> for SerializableCallInfo {
+    fn from(value: CallInfo) -> Self {
+        let CallInfo {
+            call,
+            execution,
+            inner_calls,
+            storage_read_values,
+            accessed_storage_keys,
+            read_class_hash_values,
+            accessed_contract_addresses,
+            resources: _resources,
+            tracked_resource: _tracked_resource,
+            time: _time,
+        } = value;
+
+        let mut accessed_storage_keys = accessed_storage_keys.into_iter().collect::<Vec<_>>();
+        accessed_storage_keys.sort();
+
+        let mut accessed_contract_addresses =
+            accessed_contract_addresses.into_iter().collect::<Vec<_>>();
+        accessed_contract_addresses.sort();
+
+        Self {
+            call: SerializableCallEntryPoint::from(call),
+            execution,
+            inner_calls: inner_calls
+                .into_iter()
+                .map(From::<CallInfo>::from)
+                .collect(),
+            storage_read_values,
+            accessed_storage_keys,
+            read_class_hash_values,
+            accessed_contract_addresses,
+        }
+    }
+}
+
+/// From `blockifier::execution::entry_point::CallEntryPoint`
+#[derive(Serialize)]
+pub struct SerializableCallEntryPoint {
+    pub class_hash: Option<ClassHash>,
+    pub code_address: Option<ContractAddress>,
+    pub entry_point_type: EntryPointType,
+    pub entry_point_selector: EntryPointSelector,
+    pub calldata: Calldata,
+    pub storage_address: ContractAddress,
+    pub caller_address: ContractAddress,
+    pub call_type: CallType,
+    pub initial_gas: u64,
+}
+impl From<CallEntryPoint> for SerializableCallEntryPoint {
+    fn from(value: CallEntryPoint) -> Self {
+        let CallEntryPoint {
+            class_hash,
+            code_address,
+            entry_point_type,
+            entry_point_selector,
+            calldata,
+            storage_address,
+            caller_address,
+            call_type,
+            initial_gas,
+        } = value;
+        Self {
+            class_hash,
+            code_address,
+            entry_point_type,
+            entry_point_selector,
+            calldata,
+            storage_address,
+            caller_address,
+            call_type,
+            initial_gas,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SerializableTransactionReceipt {
+    pub fee: Fee,
+    pub gas: GasVector,
+    pub da_gas: GasVector,
+    pub resources: SerializableTransactionResources,
+}
+
+#[derive(Serialize)]
+pub struct SerializableTransactionResources {
+    pub starknet_resources: StarknetResources,
+    pub computation: SerializableComputationResources,
+}
+
+#[derive(Serialize)]
+pub struct SerializableComputationResources {
+    pub n_reverted_steps: usize,
+    pub sierra_gas: GasAmount,
+    pub reverted_sierra_gas: GasAmount,
+}
+
+impl From<TransactionReceipt> for SerializableTransactionReceipt {
+    fn from(value: TransactionReceipt) -> Self {
+        let TransactionReceipt {
+            fee,
+            gas,
+            da_gas,
+            resources:
+                TransactionResources {
+                    starknet_resources,
+                    computation:
+                        ComputationResources {
+                            vm_resources: _vm_resources,
+                            n_reverted_steps,
+                            sierra_gas,
+                            reverted_sierra_gas,
+                        },
+                },
+        } = value;
+        Self {
+            fee,
+            gas,
+            da_gas,
+            resources: SerializableTransactionResources {
+                starknet_resources,
+                computation: SerializableComputationResources {
+                    n_reverted_steps,
+                    sierra_gas,
+                    reverted_sierra_gas,
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_schema_version() {
+        let dump = ErrorDump {
+            schema_version: SCHEMA_VERSION,
+            reverted: "out of gas".to_string(),
+        };
+        let dump = to_canonical(&dump).unwrap();
+        assert_eq!(
+            serde_json::to_string(&dump).unwrap(),
+            r#"{"reverted":"out of gas","schema_version":1}"#
+        );
+        assert!(check_version(&dump).is_ok());
+
+        assert_eq!(schema_version(&json!({ "reverted": "out of gas" })), 0);
+        assert!(check_version(&json!({ "schema_version": SCHEMA_VERSION + 1 })).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Entry point coverage of the classes executed by a replay, to judge how
+//! representative a replay corpus is, e.g. before validating a new Cairo
+//! Native release.
+//!
+//! Neither Cairo Native nor the Cairo VM report which Sierra statements were
+//! reached, so coverage is measured on entry points instead: the share of the
+//! entry points of each executed class that were called at least once. The
+//! entry points of a class are read the first time it's executed.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use blockifier::{execution::call_info::CallInfo, transaction::objects::TransactionExecutionInfo};
+use rpc_state_reader::reader::{entry_point_selectors, StateReader};
+use serde::Serialize;
+use starknet_api::core::{ClassHash, EntryPointSelector};
+use tracing::warn;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CLASSES: Mutex<BTreeMap<ClassHash, ClassCoverage>> = Mutex::new(BTreeMap::new());
+
+#[derive(Default)]
+struct ClassCoverage {
+    /// Every entry point of the class, or `None` if it couldn't be read
+    entry_points: Option<BTreeSet<EntryPointSelector>>,
+    reached: BTreeSet<EntryPointSelector>,
+    calls: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClassReport {
+    pub class_hash: ClassHash,
+    pub entry_points: Option<usize>,
+    pub reached: usize,
+    pub calls: u64,
+    /// Percentage of the entry points that were reached
+    pub coverage: Option<f64>,
+    pub unreached: Vec<EntryPointSelector>,
+}
+
+/// Starts recording the entry points reached by every execution
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Records the entry points called by the execution, reading the entry
+/// points of the classes executed for the first time from the reader.
+pub fn record(reader: &impl StateReader, execution_info: &TransactionExecutionInfo) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut calls = Vec::new();
+    for call_info in execution_info.non_optional_call_infos() {
+        collect_calls(call_info, &mut calls);
+    }
+
+    for (class_hash, selector) in calls {
+        // The class is read without holding the lock, as it may be fetched from the node
+        let known = CLASSES.lock().unwrap().contains_key(&class_hash);
+        let entry_points = (!known).then(|| read_entry_points(reader, class_hash));
+
+        let mut classes = CLASSES.lock().unwrap();
+        let class = classes.entry(class_hash).or_insert_with(|| ClassCoverage {
+            entry_points: entry_points.flatten(),
+            ..Default::default()
+        });
+        class.reached.insert(selector);
+        class.calls += 1;
+    }
+}
+
+/// Returns the coverage of every executed class, the most called first
+pub fn report() -> Vec<ClassReport> {
+    let mut reports = CLASSES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(class_hash, class)| ClassReport {
+            class_hash: *class_hash,
+            entry_points: class.entry_points.as_ref().map(BTreeSet::len),
+            reached: class.reached.len(),
+            calls: class.calls,
+            coverage: class
+                .entry_points
+                .as_ref()
+                .and_then(|entry_points| percentage(&class.reached, entry_points)),
+            unreached: class
+                .entry_points
+                .iter()
+                .flatten()
+                .filter(|selector| !class.reached.contains(selector))
+                .copied()
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+    reports.sort_by(|a, b| b.calls.cmp(&a.calls));
+    reports
+}
+
+fn collect_calls(call_info: &CallInfo, calls: &mut Vec<(ClassHash, EntryPointSelector)>) {
+    if let Some(class_hash) = call_info.call.class_hash {
+        calls.push((class_hash, call_info.call.entry_point_selector));
+    }
+    for inner_call in &call_info.inner_calls {
+        collect_calls(inner_call, calls);
+    }
+}
+
+fn read_entry_points(
+    reader: &impl StateReader,
+    class_hash: ClassHash,
+) -> Option<BTreeSet<EntryPointSelector>> {
+    match reader.get_contract_class(&class_hash) {
+        Ok(class) => Some(entry_point_selectors(&class).into_iter().collect()),
+        Err(err) => {
+            warn!(
+                class_hash = class_hash.to_hex_string(),
+                "failed to read the entry points of the class: {err}"
+            );
+            None
+        }
+    }
+}
+
+/// Percentage of the entry points that were reached, ignoring reached
+/// selectors that aren't entry points of the class (e.g. legacy `__default__`
+/// calls). Classes without entry points have no coverage.
+fn percentage(
+    reached: &BTreeSet<EntryPointSelector>,
+    entry_points: &BTreeSet<EntryPointSelector>,
+) -> Option<f64> {
+    if entry_points.is_empty() {
+        return None;
+    }
+    let reached = entry_points.intersection(reached).count();
+    Some(reached as f64 * 100.0 / entry_points.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::hash::StarkHash;
+
+    use super::*;
+
+    fn selectors(values: &[u8]) -> BTreeSet<EntryPointSelector> {
+        values
+            .iter()
+            .map(|value| EntryPointSelector(StarkHash::from(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_percentage() {
+        let entry_points = selectors(&[1, 2, 3, 4]);
+        assert_eq!(percentage(&selectors(&[1, 3]), &entry_points), Some(50.0));
+        assert_eq!(percentage(&selectors(&[1, 5]), &entry_points), Some(25.0));
+        assert_eq!(percentage(&selectors(&[]), &entry_points), Some(0.0));
+        assert_eq!(percentage(&selectors(&[1]), &selectors(&[])), None);
+    }
+}
@@ -0,0 +1,202 @@
+use blockifier::{
+    context::BlockContext,
+    execution::{contract_class::RunnableCompiledClass, errors::EntryPointExecutionError},
+    state::{
+        cached_state::{CachedState, StateMaps},
+        state_api::{State, StateReader as BlockifierStateReader, StateResult},
+    },
+    transaction::{
+        errors::TransactionExecutionError, objects::TransactionExecutionInfo,
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use starknet_api::{
+    core::{ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    hash::StarkHash,
+    state::StorageKey,
+};
+use tracing::warn;
+
+use crate::{
+    audit::Outcome,
+    run_report::{self, Fallback},
+};
+
+/// Executes the transaction, retrying it with the Cairo VM if the Native
+/// execution fails. Returns whether it was retried along with the result.
+///
+/// Only internal errors of Cairo Native are retried, as other failures (e.g.
+/// a state error) would fail with the VM too. In that case, the transaction
+/// is executed again with every Sierra class compiled to CASM and, if it
+/// succeeds, its state changes are applied to the given state so that the
+/// block can continue. Every fallback is recorded in the run report.
+pub fn execute_with_vm_fallback<S: BlockifierStateReader>(
+    tx: &BlockiTransaction,
+    state: &mut CachedState<S>,
+    context: &BlockContext,
+) -> (
+    Result<TransactionExecutionInfo, TransactionExecutionError>,
+    bool,
+) {
+    let native_err = match tx.execute(state, context) {
+        Err(err) if is_native_error(&err) => err,
+        result => return (result, false),
+    };
+
+    warn!(
+        native_error = native_err.to_string(),
+        "native execution failed, falling back to vm"
+    );
+
+    let vm_result = execute_with_vm(tx, state, context);
+
+    let (vm_outcome, vm_error) = match &vm_result {
+        Ok(execution_info) if execution_info.is_reverted() => (Outcome::Reverted, None),
+        Ok(_) => (Outcome::Success, None),
+        Err(err) => (Outcome::Failed, Some(err.to_string())),
+    };
+    run_report::record_fallback(Fallback {
+        block_number: context.block_info().block_number.0,
+        tx_hash: tx.tx_hash().0.to_hex_string(),
+        class_hash: failed_class_hash(&native_err).map(|class_hash| class_hash.to_hex_string()),
+        native_error: native_err.to_string(),
+        vm_outcome,
+        vm_error,
+    });
+
+    (vm_result, true)
+}
+
+/// Executes the transaction with every Sierra class compiled to CASM, and
+/// applies its state changes to the given state if it succeeds.
+fn execute_with_vm<S: BlockifierStateReader>(
+    tx: &BlockiTransaction,
+    state: &mut CachedState<S>,
+    context: &BlockContext,
+) -> Result<TransactionExecutionInfo, TransactionExecutionError> {
+    let (execution_info, state_maps, declared_classes) = {
+        let mut vm_state = CachedState::new(VmStateReader(&*state));
+        let execution_info = tx.execute(&mut vm_state, context).inspect_err(|vm_err| {
+            warn!(
+                vm_error = vm_err.to_string(),
+                "vm fallback execution failed"
+            )
+        })?;
+        let mut state_maps = vm_state.to_state_diff()?.state_maps;
+
+        // declared classes only live in the fallback state, so we must
+        // take them before dropping it
+        let declared_classes = state_maps
+            .declared_contracts
+            .keys()
+            .map(|class_hash| Ok((*class_hash, vm_state.get_compiled_class(*class_hash)?)))
+            .collect::<StateResult<Vec<_>>>()?;
+
+        state_maps.declared_contracts.clear();
+
+        (execution_info, state_maps, declared_classes)
+    };
+
+    apply_state_maps(state, state_maps, declared_classes)?;
+
+    warn!(
+        reverted = execution_info.is_reverted(),
+        "vm fallback execution finished"
+    );
+
+    Ok(execution_info)
+}
+
+/// Returns whether the execution failed because of Cairo Native itself
+fn is_native_error(err: &TransactionExecutionError) -> bool {
+    match err {
+        TransactionExecutionError::ExecutionError { error, .. }
+        | TransactionExecutionError::ValidateTransactionError { error, .. } => {
+            is_native_entry_point_error(error)
+        }
+        _ => false,
+    }
+}
+
+fn is_native_entry_point_error(error: &EntryPointExecutionError) -> bool {
+    matches!(
+        error,
+        EntryPointExecutionError::NativeUnexpectedError(_)
+            | EntryPointExecutionError::NativeUnrecoverableError(_)
+            | EntryPointExecutionError::InternalError(_)
+    )
+}
+
+/// Returns the class of the entry point that failed, if any
+fn failed_class_hash(err: &TransactionExecutionError) -> Option<ClassHash> {
+    match err {
+        TransactionExecutionError::ExecutionError { class_hash, .. }
+        | TransactionExecutionError::ValidateTransactionError { class_hash, .. } => {
+            Some(*class_hash)
+        }
+        _ => None,
+    }
+}
+
+/// Writes the state changes of an execution made on a different state into the given state.
+pub fn apply_state_maps<S: BlockifierStateReader>(
+    state: &mut CachedState<S>,
+    state_maps: StateMaps,
+    declared_classes: Vec<(ClassHash, RunnableCompiledClass)>,
+) -> StateResult<()> {
+    for ((contract_address, key), value) in state_maps.storage {
+        state.set_storage_at(contract_address, key, value)?;
+    }
+    for (contract_address, nonce) in state_maps.nonces {
+        while state.get_nonce_at(contract_address)? < nonce {
+            state.increment_nonce(contract_address)?;
+        }
+    }
+    for (contract_address, class_hash) in state_maps.class_hashes {
+        state.set_class_hash_at(contract_address, class_hash)?;
+    }
+    for (class_hash, compiled_class_hash) in state_maps.compiled_class_hashes {
+        state.set_compiled_class_hash(class_hash, compiled_class_hash)?;
+    }
+    for (class_hash, class) in declared_classes {
+        state.set_contract_class(class_hash, class)?;
+    }
+
+    Ok(())
+}
+
+/// A state reader that reads from an inner state, but returns the CASM
+/// version of every Native compiled class.
+pub struct VmStateReader<'a, S: BlockifierStateReader>(pub &'a S);
+
+impl<S: BlockifierStateReader> BlockifierStateReader for VmStateReader<'_, S> {
+    fn get_storage_at(
+        &self,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> StateResult<StarkHash> {
+        self.0.get_storage_at(contract_address, key)
+    }
+
+    fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
+        self.0.get_nonce_at(contract_address)
+    }
+
+    fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        self.0.get_class_hash_at(contract_address)
+    }
+
+    fn get_compiled_class(&self, class_hash: ClassHash) -> StateResult<RunnableCompiledClass> {
+        Ok(match self.0.get_compiled_class(class_hash)? {
+            RunnableCompiledClass::V1Native(native_class) => {
+                RunnableCompiledClass::V1(native_class.casm())
+            }
+            class => class,
+        })
+    }
+
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.0.get_compiled_class_hash(class_hash)
+    }
+}
@@ -0,0 +1,183 @@
+//! Comparison of the fee of a transaction estimated by the replay against the
+//! one estimated by the node with `starknet_estimateFee`, to validate that
+//! the fee model of the replay matches the one of production nodes.
+//!
+//! Both estimates skip validation and run on top of the state of the
+//! previous block. The node's estimate of RPC v0.7 has no L2 gas, so it's
+//! reported as zero.
+
+use anyhow::{bail, Context};
+use blockifier::transaction::objects::TransactionExecutionInfo;
+use rpc_state_reader::reader::RpcStateReader;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use starknet_api::transaction::TransactionHash;
+
+/// Gas consumed by the transaction, deserialized from the node's estimate
+/// with the names of RPC v0.7 (e.g. `gas_consumed`) and v0.8 (e.g.
+/// `l1_gas_consumed`)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    #[serde(
+        alias = "gas_consumed",
+        alias = "l1_gas_consumed",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub l1_gas: u128,
+    #[serde(
+        alias = "data_gas_consumed",
+        alias = "l1_data_gas_consumed",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub l1_data_gas: u128,
+    #[serde(
+        alias = "l2_gas_consumed",
+        default,
+        deserialize_with = "deserialize_amount"
+    )]
+    pub l2_gas: u128,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub overall_fee: u128,
+}
+
+/// Deltas of the replay's estimate with respect to the node's
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FeeDelta {
+    pub l1_gas: i128,
+    pub l1_data_gas: i128,
+    pub l2_gas: i128,
+    pub overall_fee: i128,
+}
+
+#[derive(Serialize)]
+pub struct FeeComparison {
+    pub tx_hash: String,
+    pub replay: FeeEstimate,
+    pub node: FeeEstimate,
+    pub delta: FeeDelta,
+}
+
+impl FeeComparison {
+    pub fn new(tx_hash: String, replay: FeeEstimate, node: FeeEstimate) -> Self {
+        let delta = |replay: u128, node: u128| replay as i128 - node as i128;
+        Self {
+            tx_hash,
+            delta: FeeDelta {
+                l1_gas: delta(replay.l1_gas, node.l1_gas),
+                l1_data_gas: delta(replay.l1_data_gas, node.l1_data_gas),
+                l2_gas: delta(replay.l2_gas, node.l2_gas),
+                overall_fee: delta(replay.overall_fee, node.overall_fee),
+            },
+            replay,
+            node,
+        }
+    }
+
+    pub fn matches(&self) -> bool {
+        self.delta == FeeDelta::default()
+    }
+}
+
+/// Reads an amount written either as a hex string or as a number
+fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(hex) => {
+            u128::from_str_radix(hex.trim_start_matches("0x"), 16).map_err(serde::de::Error::custom)
+        }
+        Value::Number(number) => number
+            .as_u64()
+            .map(u128::from)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid amount {number}"))),
+        value => Err(serde::de::Error::custom(format!("invalid amount {value}"))),
+    }
+}
+
+impl From<&TransactionExecutionInfo> for FeeEstimate {
+    fn from(execution_info: &TransactionExecutionInfo) -> Self {
+        let gas = &execution_info.receipt.gas;
+        Self {
+            l1_gas: gas.l1_gas.0.into(),
+            l1_data_gas: gas.l1_data_gas.0.into(),
+            l2_gas: gas.l2_gas.0.into(),
+            overall_fee: execution_info.receipt.fee.0,
+        }
+    }
+}
+
+/// Asks the node to estimate the fee of the transaction, on top of the state
+/// of the block read by the reader
+pub fn estimate_fee_on_node(
+    reader: &RpcStateReader,
+    tx_hash: TransactionHash,
+) -> anyhow::Result<FeeEstimate> {
+    let mut transaction =
+        reader.send_rpc_request_with_retry("starknet_getTransactionByHash", json!([tx_hash]))?;
+    if let Some(transaction) = transaction.as_object_mut() {
+        transaction.remove("transaction_hash");
+    }
+
+    let estimates = reader.send_rpc_request_with_retry(
+        "starknet_estimateFee",
+        json!({
+            "request": [transaction],
+            "simulation_flags": ["SKIP_VALIDATE"],
+            "block_id": reader.block_id(),
+        }),
+    )?;
+    parse_estimate(estimates)
+}
+
+fn parse_estimate(estimates: Value) -> anyhow::Result<FeeEstimate> {
+    let Value::Array(mut estimates) = estimates else {
+        bail!("invalid fee estimate {estimates}");
+    };
+    if estimates.len() != 1 {
+        bail!("expected a single fee estimate, got {}", estimates.len());
+    }
+    serde_json::from_value(estimates.remove(0)).context("invalid fee estimate")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_estimate() {
+        let v0_7 = parse_estimate(json!([{
+            "gas_consumed": "0x10",
+            "gas_price": "0x1",
+            "data_gas_consumed": "0x80",
+            "data_gas_price": "0x1",
+            "overall_fee": "0x90",
+            "unit": "FRI",
+        }]))
+        .unwrap();
+        assert_eq!(
+            v0_7,
+            FeeEstimate {
+                l1_gas: 0x10,
+                l1_data_gas: 0x80,
+                l2_gas: 0,
+                overall_fee: 0x90,
+            }
+        );
+
+        let v0_8 = parse_estimate(json!([{
+            "l1_gas_consumed": "0x0",
+            "l1_gas_price": "0x1",
+            "l2_gas_consumed": "0x100",
+            "l2_gas_price": "0x1",
+            "l1_data_gas_consumed": "0x80",
+            "l1_data_gas_price": "0x1",
+            "overall_fee": "0x180",
+            "unit": "FRI",
+        }]))
+        .unwrap();
+        assert_eq!(v0_8.l2_gas, 0x100);
+
+        let comparison = FeeComparison::new("0x1".to_string(), v0_8, v0_7);
+        assert!(!comparison.matches());
+        assert_eq!(comparison.delta.l1_gas, -0x10);
+        assert_eq!(comparison.delta.overall_fee, 0xf0);
+    }
+}
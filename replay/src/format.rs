@@ -0,0 +1,245 @@
+//! Serialization of the data written by the replay in the supported formats.
+//!
+//! JSON and MessagePack keep the whole structure of the data. CSV and
+//! Parquet are tabular: each record is a row, where nested objects are
+//! flattened into dotted columns (e.g. `time.secs`) and arrays are written
+//! as JSON strings.
+//!
+//! Each format but JSON needs the feature of the same name (`csv`, `parquet`
+//! and `msgpack`), so that only the users of a format compile its crates.
+
+use std::{io::Write, path::Path};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::output;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Json,
+    Csv,
+    Parquet,
+    Msgpack,
+}
+
+impl Format {
+    /// Extension of the files written in this format, available to output
+    /// templates as `{ext}`
+    pub fn ext(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+            Format::Msgpack => "msgpack",
+        }
+    }
+}
+
+/// Writes the records atomically to the given path.
+pub fn write_records<R: Serialize>(
+    path: &Path,
+    format: Format,
+    records: &[R],
+) -> anyhow::Result<()> {
+    write(path, format, &records, records)
+}
+
+/// Writes the value atomically to the given path. As tabular formats can't
+/// represent the whole value, they only contain the given records.
+pub fn write<R: Serialize>(
+    path: &Path,
+    format: Format,
+    value: &impl Serialize,
+    records: &[R],
+) -> anyhow::Result<()> {
+    output::write_atomic(path, |writer| match format {
+        Format::Json => Ok(serde_json::to_writer_pretty(writer, value)?),
+        Format::Msgpack => write_msgpack(writer, value),
+        Format::Csv => write_csv(writer, &to_table(records)?),
+        Format::Parquet => write_parquet(writer, &to_table(records)?),
+    })
+}
+
+/// Records flattened into rows, with the columns in order of first appearance
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Map<String, Value>>,
+}
+
+fn to_table<R: Serialize>(records: &[R]) -> anyhow::Result<Table> {
+    let mut columns = Vec::new();
+    let mut rows = Vec::new();
+
+    for record in records {
+        let mut row = Map::new();
+        flatten(
+            None,
+            serde_json::to_value(record).context("failed to serialize record")?,
+            &mut row,
+        );
+        for column in row.keys() {
+            if !columns.contains(column) {
+                columns.push(column.clone());
+            }
+        }
+        rows.push(row);
+    }
+
+    Ok(Table { columns, rows })
+}
+
+fn flatten(prefix: Option<&str>, value: Value, row: &mut Map<String, Value>) {
+    let column = prefix.unwrap_or("value").to_string();
+
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let column = match prefix {
+                    Some(prefix) => format!("{prefix}.{key}"),
+                    None => key,
+                };
+                flatten(Some(&column), value, row);
+            }
+        }
+        Value::Array(_) => {
+            row.insert(column, Value::String(value.to_string()));
+        }
+        value => {
+            row.insert(column, value);
+        }
+    }
+}
+
+/// Returns the value as a cell of a text column
+fn to_cell(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(string) => Some(string.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+#[cfg(not(feature = "msgpack"))]
+fn write_msgpack(_writer: impl Write, _value: &impl Serialize) -> anyhow::Result<()> {
+    anyhow::bail!("built without msgpack support, rebuild with `--features msgpack`")
+}
+
+#[cfg(feature = "msgpack")]
+fn write_msgpack(mut writer: impl Write, value: &impl Serialize) -> anyhow::Result<()> {
+    Ok(rmp_serde::encode::write_named(&mut writer, value)?)
+}
+
+#[cfg(not(feature = "csv"))]
+fn write_csv(_writer: impl Write, _table: &Table) -> anyhow::Result<()> {
+    anyhow::bail!("built without csv support, rebuild with `--features csv`")
+}
+
+#[cfg(feature = "csv")]
+fn write_csv(writer: impl Write, table: &Table) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(&table.columns)?;
+    for row in &table.rows {
+        writer.write_record(
+            table
+                .columns
+                .iter()
+                .map(|column| row.get(column).and_then(to_cell).unwrap_or_default()),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_writer: impl Write + Send, _table: &Table) -> anyhow::Result<()> {
+    anyhow::bail!("built without parquet support, rebuild with `--features parquet`")
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(writer: impl Write + Send, table: &Table) -> anyhow::Result<()> {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
+        datatypes::{Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use parquet::arrow::ArrowWriter;
+
+    let mut fields = Vec::new();
+    let mut arrays: Vec<ArrayRef> = Vec::new();
+
+    for column in &table.columns {
+        let values = table
+            .rows
+            .iter()
+            .map(|row| row.get(column).unwrap_or(&Value::Null))
+            .collect::<Vec<_>>();
+        let non_null = || values.iter().filter(|value| !value.is_null());
+
+        let array: ArrayRef = if non_null().all(|value| value.is_boolean()) {
+            Arc::new(BooleanArray::from_iter(values.iter().map(|v| v.as_bool())))
+        } else if non_null().all(|value| value.is_i64()) {
+            Arc::new(Int64Array::from_iter(values.iter().map(|v| v.as_i64())))
+        } else if non_null().all(|value| value.is_number()) {
+            Arc::new(Float64Array::from_iter(values.iter().map(|v| v.as_f64())))
+        } else {
+            Arc::new(StringArray::from_iter(values.iter().copied().map(to_cell)))
+        };
+
+        fields.push(Field::new(column, array.data_type().clone(), true));
+        arrays.push(array);
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+    let mut writer = ArrowWriter::try_new(writer, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_to_table() {
+        let records = [
+            json!({"hash": "0x1", "time": {"secs": 1, "nanos": 2}, "calls": [1, 2]}),
+            json!({"hash": "0x2", "error": "failed"}),
+        ];
+
+        let table = to_table(&records).unwrap();
+        assert_eq!(
+            table.columns,
+            ["calls", "hash", "time.nanos", "time.secs", "error"]
+        );
+        assert_eq!(table.rows[0]["calls"], json!("[1,2]"));
+        assert_eq!(table.rows[0]["time.secs"], json!(1));
+        assert!(!table.rows[1].contains_key("time.secs"));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_write_csv() {
+        let records = [
+            json!({"hash": "0x1", "time": {"secs": 1, "nanos": 2}, "calls": [1, 2]}),
+            json!({"hash": "0x2", "error": "failed"}),
+        ];
+        let table = to_table(&records).unwrap();
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &table).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "calls,hash,time.nanos,time.secs,error\n\"[1,2]\",0x1,2,1,\n,0x2,,,failed\n"
+        );
+    }
+}
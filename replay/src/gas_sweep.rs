@@ -0,0 +1,199 @@
+//! Re-execution of a transaction under decreasing fee limits, to find the
+//! minimum at which it still succeeds.
+//!
+//! The limit is the max fee of v1 invoke transactions, and the maximum
+//! amount of L2 gas (or L1 gas, for transactions that only bound it) of v3
+//! ones. As changing it invalidates the signature, the transaction is
+//! executed without validation, always charging fees.
+
+use anyhow::{bail, ensure};
+use blockifier::{
+    context::BlockContext,
+    state::{cached_state::CachedState, state_api::StateReader as BlockifierStateReader},
+    transaction::{
+        account_transaction::ExecutionFlags,
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use serde::Serialize;
+use starknet_api::{
+    execution_resources::GasAmount,
+    transaction::{
+        fields::{AllResourceBounds, Fee, ResourceBounds, ValidResourceBounds},
+        InvokeTransaction, InvokeTransactionV1, InvokeTransactionV3, Transaction as SNTransaction,
+        TransactionHash,
+    },
+};
+
+use crate::audit::Outcome;
+
+#[derive(Serialize)]
+pub struct SweepPoint {
+    pub limit: u128,
+    pub outcome: Outcome,
+    /// Revert reason, or error of a failed execution
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct GasSweep {
+    /// Resource whose limit is swept, e.g. `l2_gas`
+    pub resource: &'static str,
+    pub original_limit: u128,
+    /// Executions at evenly spaced limits, from the original one down to
+    /// (about) zero
+    pub points: Vec<SweepPoint>,
+    /// Minimum limit at which the transaction succeeds, if it succeeds
+    /// with the original one
+    pub minimum: Option<u128>,
+    /// Execution just below the minimum, showing how the transaction fails
+    pub below_minimum: Option<SweepPoint>,
+}
+
+/// Executes the transaction at `steps` limits between the original one and
+/// zero, and searches for the minimum limit at which it succeeds, assuming
+/// that it succeeds with every limit above it.
+///
+/// Fees are charged and validation is skipped regardless of the flags, as the
+/// limits only apply when charging fees and changing them invalidates the
+/// signature.
+pub fn sweep_transaction<S: BlockifierStateReader>(
+    transaction: &SNTransaction,
+    tx_hash: TransactionHash,
+    steps: u128,
+    flags: ExecutionFlags,
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+) -> anyhow::Result<GasSweep> {
+    let SNTransaction::Invoke(invoke) = transaction else {
+        bail!("only invoke transactions can be swept");
+    };
+    ensure!(steps > 0, "at least one step is needed");
+    let (resource, original_limit) = limit(invoke)?;
+
+    let mut execute = |limit: u128| -> anyhow::Result<SweepPoint> {
+        let tx = with_limit(invoke, limit, tx_hash, flags.clone())?;
+        let mut transactional_state = CachedState::create_transactional(state);
+
+        let (outcome, error) = match tx.execute(&mut transactional_state, block_context) {
+            Ok(execution_info) => match execution_info.revert_error {
+                Some(revert_error) => (Outcome::Reverted, Some(revert_error.to_string())),
+                None => (Outcome::Success, None),
+            },
+            Err(err) => (Outcome::Failed, Some(err.to_string())),
+        };
+
+        Ok(SweepPoint {
+            limit,
+            outcome,
+            error,
+        })
+    };
+    let succeeds = |point: &SweepPoint| matches!(point.outcome, Outcome::Success);
+
+    let points = (0..=steps)
+        .map(|step| execute(original_limit - original_limit / steps * step))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if !succeeds(&points[0]) {
+        return Ok(GasSweep {
+            resource,
+            original_limit,
+            points,
+            minimum: None,
+            below_minimum: None,
+        });
+    }
+
+    let mut low = 0;
+    let mut high = original_limit;
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if succeeds(&execute(middle)?) {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+
+    let below_minimum = match high {
+        0 => None,
+        minimum => Some(execute(minimum - 1)?),
+    };
+
+    Ok(GasSweep {
+        resource,
+        original_limit,
+        points,
+        minimum: Some(high),
+        below_minimum,
+    })
+}
+
+/// Returns the swept resource and its limit in the transaction
+fn limit(invoke: &InvokeTransaction) -> anyhow::Result<(&'static str, u128)> {
+    Ok(match invoke {
+        InvokeTransaction::V0(_) => bail!("invoke v0 transactions have no fee limit"),
+        InvokeTransaction::V1(tx) => ("max_fee", tx.max_fee.0),
+        InvokeTransaction::V3(tx) => match tx.resource_bounds {
+            ValidResourceBounds::L1Gas(bounds) => ("l1_gas", bounds.max_amount.0.into()),
+            ValidResourceBounds::AllResources(bounds) => {
+                ("l2_gas", bounds.l2_gas.max_amount.0.into())
+            }
+        },
+    })
+}
+
+/// Builds the transaction with the given limit, without validation
+fn with_limit(
+    invoke: &InvokeTransaction,
+    limit: u128,
+    tx_hash: TransactionHash,
+    flags: ExecutionFlags,
+) -> anyhow::Result<BlockiTransaction> {
+    let invoke = match invoke {
+        InvokeTransaction::V0(_) => bail!("invoke v0 transactions have no fee limit"),
+        InvokeTransaction::V1(tx) => InvokeTransaction::V1(InvokeTransactionV1 {
+            max_fee: Fee(limit),
+            ..tx.clone()
+        }),
+        InvokeTransaction::V3(tx) => {
+            let max_amount = GasAmount(limit.try_into()?);
+            let resource_bounds = match tx.resource_bounds {
+                ValidResourceBounds::L1Gas(bounds) => ValidResourceBounds::L1Gas(ResourceBounds {
+                    max_amount,
+                    ..bounds
+                }),
+                ValidResourceBounds::AllResources(bounds) => {
+                    ValidResourceBounds::AllResources(AllResourceBounds {
+                        l2_gas: ResourceBounds {
+                            max_amount,
+                            ..bounds.l2_gas
+                        },
+                        ..bounds
+                    })
+                }
+            };
+            InvokeTransaction::V3(InvokeTransactionV3 {
+                resource_bounds,
+                ..tx.clone()
+            })
+        }
+    };
+
+    let flags = ExecutionFlags {
+        charge_fee: true,
+        validate: false,
+        ..flags
+    };
+
+    Ok(BlockiTransaction::from_api(
+        SNTransaction::Invoke(invoke),
+        tx_hash,
+        None,
+        None,
+        None,
+        flags,
+    )?)
+}
@@ -0,0 +1,131 @@
+//! Utilities to replay every invocation of a single contract.
+
+use blockifier::{execution::call_info::CallInfo, transaction::objects::TransactionExecutionInfo};
+use rpc_state_reader::reader::StateReader;
+use serde::Serialize;
+use starknet_api::{
+    core::{ContractAddress, EntryPointSelector},
+    hash::StarkHash,
+    transaction::TransactionHash,
+};
+use tracing::error;
+
+/// Returns the hashes of the transactions in the reader's block whose trace
+/// contains a call to the given contract, in the order they were executed.
+pub fn find_invocations(
+    reader: &impl StateReader,
+    contract_address: ContractAddress,
+) -> anyhow::Result<Vec<TransactionHash>> {
+    let transaction_hashes = reader.get_block_with_tx_hashes()?.transactions;
+
+    let mut invocations = Vec::new();
+    for tx_hash in transaction_hashes {
+        let trace = match reader.get_transaction_trace(&tx_hash) {
+            Ok(trace) => trace,
+            Err(err) => {
+                error!(
+                    hash = tx_hash.0.to_hex_string(),
+                    "failed to fetch transaction trace: {err}"
+                );
+                continue;
+            }
+        };
+
+        if trace
+            .calls()
+            .any(|call| call.contract_address == Some(contract_address))
+        {
+            invocations.push(tx_hash);
+        }
+    }
+
+    Ok(invocations)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvocationStatus {
+    Succeeded,
+    Reverted,
+    Failed,
+}
+
+#[derive(Serialize)]
+pub struct InvocationReport {
+    pub block_number: u64,
+    pub tx_hash: TransactionHash,
+    pub status: InvocationStatus,
+    pub revert_error: Option<String>,
+    /// Every call made to the contract during the transaction
+    pub calls: Vec<ContractCall>,
+}
+
+#[derive(Serialize)]
+pub struct ContractCall {
+    pub selector: EntryPointSelector,
+    pub caller_address: ContractAddress,
+    pub calldata: Vec<StarkHash>,
+    pub retdata: Vec<StarkHash>,
+    pub failed: bool,
+}
+
+impl InvocationReport {
+    pub fn new(
+        block_number: u64,
+        tx_hash: TransactionHash,
+        contract_address: ContractAddress,
+        execution_info: Option<&TransactionExecutionInfo>,
+    ) -> Self {
+        let Some(execution_info) = execution_info else {
+            return Self {
+                block_number,
+                tx_hash,
+                status: InvocationStatus::Failed,
+                revert_error: None,
+                calls: Vec::new(),
+            };
+        };
+
+        let status = if execution_info.is_reverted() {
+            InvocationStatus::Reverted
+        } else {
+            InvocationStatus::Succeeded
+        };
+
+        let mut calls = Vec::new();
+        for call in execution_info.non_optional_call_infos() {
+            collect_contract_calls(call, contract_address, &mut calls);
+        }
+
+        Self {
+            block_number,
+            tx_hash,
+            status,
+            revert_error: execution_info
+                .revert_error
+                .as_ref()
+                .map(|err| err.to_string()),
+            calls,
+        }
+    }
+}
+
+fn collect_contract_calls(
+    call: &CallInfo,
+    contract_address: ContractAddress,
+    calls: &mut Vec<ContractCall>,
+) {
+    if call.call.storage_address == contract_address {
+        calls.push(ContractCall {
+            selector: call.call.entry_point_selector,
+            caller_address: call.call.caller_address,
+            calldata: call.call.calldata.0.to_vec(),
+            retdata: call.execution.retdata.0.clone(),
+            failed: call.execution.failed,
+        });
+    }
+
+    for inner_call in &call.inner_calls {
+        collect_contract_calls(inner_call, contract_address, calls);
+    }
+}
@@ -0,0 +1,90 @@
+//! Transactions read from a file and executed within a replayed block, to
+//! study how a hypothetical transaction would have behaved with the real
+//! state and ordering of the block.
+//!
+//! The file holds a single transaction in the format of
+//! `starknet_getTransactionByHash` (invoke, declare or deploy account). Its
+//! `transaction_hash` is optional, as it's only used to identify it in logs.
+//! Declared classes must be known by the node.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context};
+use rpc_state_reader::objects::deser::transaction_from_json;
+use serde_json::Value;
+use starknet_api::{
+    felt,
+    transaction::{Transaction as SNTransaction, TransactionHash},
+};
+
+pub struct InjectedTx {
+    /// Number of transactions of the block executed before it
+    pub index: usize,
+    pub hash: TransactionHash,
+    pub transaction: SNTransaction,
+}
+
+pub fn load_transaction(path: &Path, index: usize) -> anyhow::Result<InjectedTx> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("failed to read transaction {}", path.display()))?;
+    parse_transaction(serde_json::from_str(&json)?, index)
+        .with_context(|| format!("failed to parse transaction {}", path.display()))
+}
+
+fn parse_transaction(json: Value, index: usize) -> anyhow::Result<InjectedTx> {
+    let hash = match json.get("transaction_hash").and_then(Value::as_str) {
+        Some(hash) => TransactionHash(felt!(hash)),
+        None => TransactionHash::default(),
+    };
+
+    let transaction = transaction_from_json(json)?;
+    if !matches!(
+        transaction,
+        SNTransaction::Invoke(_) | SNTransaction::Declare(_) | SNTransaction::DeployAccount(_)
+    ) {
+        bail!("only invoke, declare and deploy account transactions can be injected");
+    }
+
+    Ok(InjectedTx {
+        index,
+        hash,
+        transaction,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_transaction() {
+        let injected = parse_transaction(
+            json!({
+                "type": "INVOKE",
+                "version": "0x1",
+                "max_fee": "0x100",
+                "signature": [],
+                "nonce": "0x2",
+                "sender_address": "0x3",
+                "calldata": ["0x0"],
+            }),
+            4,
+        )
+        .unwrap();
+        assert_eq!(injected.index, 4);
+        assert_eq!(injected.hash, TransactionHash::default());
+        assert!(matches!(injected.transaction, SNTransaction::Invoke(_)));
+
+        let l1_handler = json!({
+            "type": "L1_HANDLER",
+            "version": "0x0",
+            "nonce": "0x1",
+            "contract_address": "0x2",
+            "entry_point_selector": "0x3",
+            "calldata": [],
+        });
+        assert!(parse_transaction(l1_handler, 0).is_err());
+    }
+}
@@ -1,175 +1,2663 @@
-use blockifier::state::cached_state::CachedState;
+use anyhow::{ensure, Context};
+use blockifier::context::BlockContext;
+use blockifier::state::cached_state::{CachedState, StateMaps};
+use blockifier::state::state_api::StateReader as BlockifierStateReader;
 use blockifier::transaction::account_transaction::ExecutionFlags;
+use blockifier::transaction::errors::TransactionExecutionError;
 use blockifier::transaction::objects::{RevertError, TransactionExecutionInfo};
+use blockifier::transaction::transaction_execution::Transaction as BlockiTransaction;
 use blockifier::transaction::transactions::ExecutableTransaction;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
-use rpc_state_reader::cache::RpcCachedStateReader;
-use rpc_state_reader::execution::fetch_transaction_with_state;
+use checkpoint::Checkpoint;
+use format::Format;
+use output::OutputVars;
+use parallel::SpeculativeExecution;
+use progress::Progress;
+use replay_core::{BlockReplay, Replayer};
+#[cfg(feature = "results_store")]
+use results_store::ResultsStore;
+use rpc_state_reader::cache::{thread_cache_misses, RpcCachedStateReader};
+use rpc_state_reader::disk_cache::{self, DiskCache};
+use rpc_state_reader::execution::{
+    fetch_block, fetch_block_context, fetch_blockifier_transaction, fetch_transaction_with_state,
+    to_blockifier_transaction,
+};
 use rpc_state_reader::objects::RpcTransactionReceipt;
-use rpc_state_reader::reader::{RpcStateReader, StateReader};
+use rpc_state_reader::reader::{self, Executor, RpcStateReader, StateReader};
+use rpc_state_reader::{
+    chains,
+    pathfinder::{self, PathfinderDatabase},
+    provider,
+};
+use starknet_api::abi::abi_utils::selector_from_name;
 use starknet_api::block::BlockNumber;
-use starknet_api::core::ChainId;
+use starknet_api::core::{ChainId, ContractAddress};
 use starknet_api::felt;
+use starknet_api::state::StorageKey;
 use starknet_api::transaction::{TransactionExecutionStatus, TransactionHash};
-use tracing::{debug, error, info, info_span};
+use state_access::StateAccessConfig;
+use tracing::{debug, error, info, info_span, warn};
 use tracing_subscriber::{util::SubscriberInitExt, EnvFilter};
 
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
 #[cfg(feature = "benchmark")]
-use {
-    crate::benchmark::{
-        aggregate_executions, execute_block_range, fetch_block_range_data, fetch_transaction_data,
-        BenchmarkingData,
-    },
-    std::path::PathBuf,
-    std::time::Instant,
+use crate::benchmark::{
+    aggregate_dual_executions, aggregate_executions, aggregate_transactions, execute_block_range,
+    execute_block_range_dual, fetch_block_range_data, fetch_transaction_data, measure_first_call,
+    BenchmarkingData, ClassExecutionInfo, RunData,
 };
 
 #[cfg(feature = "profiling")]
-use {std::thread, std::time::Duration};
+use std::thread;
+
+mod audit;
+#[cfg(feature = "benchmark")]
+mod bench_compare;
+#[cfg(feature = "benchmark")]
+mod benchmark;
+mod block_composition;
+#[cfg(feature = "benchmark")]
+mod budget;
+mod call_raw;
+mod call_trace_diff;
+mod capabilities;
+#[cfg(feature = "state_dump")]
+mod central;
+mod checkpoint;
+mod divergence;
+#[cfg(feature = "state_dump")]
+mod dump_diff;
+#[cfg(feature = "state_dump")]
+mod dump_schema;
+mod entrypoint_coverage;
+mod fallback;
+mod fee_estimate;
+mod format;
+mod gas_sweep;
+mod history;
+mod inject;
+#[cfg(feature = "benchmark")]
+mod memory;
+mod output;
+mod parallel;
+mod precompile;
+mod progress;
+mod reorder;
+mod report_diff;
+mod repro;
+#[cfg(feature = "results_store")]
+mod results_store;
+mod revenue;
+mod rpc_trace;
+mod run_report;
+mod scenario;
+#[cfg(feature = "serve")]
+mod serve;
+mod shrink;
+mod simulate;
+mod spot_check;
+mod state_access;
+#[cfg(feature = "state_dump")]
+mod state_dump;
+mod state_update;
+mod stress;
+#[cfg(feature = "tui")]
+mod tui;
+mod tx_report;
+mod verify_cache;
+mod workers;
+
+/// Counts the allocations of each benchmark run
+#[cfg(feature = "benchmark")]
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
+
+/// Amount of entry points logged after benchmarking a block range
+#[cfg(feature = "benchmark")]
+const HOT_ENTRYPOINTS: usize = 10;
+
+#[derive(Debug, Parser)]
+#[command(about = "Replay is a tool for executing Starknet transactions.", long_about = None)]
+struct ReplayCLI {
+    #[command(subcommand)]
+    subcommand: ReplayExecute,
+    #[arg(
+        long,
+        global = true,
+        default_value = ".",
+        help = "Directory where every output file is written to. Output paths may contain
+the placeholders {chain}, {block_start}, {block_end}, {tx}, {ext} and {date}"
+    )]
+    out_dir: PathBuf,
+    #[arg(
+        long,
+        global = true,
+        default_value = "audit_log.jsonl",
+        help = "Path of the append-only log of executed transactions"
+    )]
+    audit_log: PathBuf,
+    #[arg(
+        long,
+        global = true,
+        default_value = "results.sqlite",
+        help = "Path of the database where replay results are stored"
+    )]
+    results_store: PathBuf,
+    #[arg(
+        long,
+        global = true,
+        help = "Fail when an RPC response has unknown fields or misses expected ones, instead of only logging it"
+    )]
+    strict_rpc: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "MiB",
+        help = "Maximum memory used by the in-memory rpc cache of each block, evicting the least recently used entries"
+    )]
+    cache_memory_limit: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        env = "NATIVE_OPT_LEVEL",
+        default_value_t = rpc_state_reader::utils::DEFAULT_NATIVE_OPT_LEVEL,
+        value_parser = clap::value_parser!(u8).range(0..=3),
+        help = "Optimization level of the native compilations, from 0 (fastest to compile) to 3 (fastest to execute)"
+    )]
+    native_opt_level: u8,
+    #[arg(
+        long,
+        global = true,
+        env = "NATIVE_STACK_SIZE",
+        value_name = "MiB",
+        help = "Stack size of the threads executing native code, e.g. for deeply recursive contracts"
+    )]
+    native_stack_size: Option<usize>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "CLASSES",
+        help = "Maximum amount of native classes kept loaded, unloading the least recently used ones"
+    )]
+    native_executor_limit: Option<usize>,
+    #[arg(
+        long,
+        global = true,
+        default_value = "native",
+        help = "Executor of the Sierra classes: native, vm or sierra-emu"
+    )]
+    executor: Executor,
+    #[arg(
+        long,
+        global = true,
+        help = "Fail on rpc cache misses instead of fetching the missing data from the node"
+    )]
+    offline: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "RPC provider profile to use, as defined by the RPC_PROVIDER_<NAME>_* env vars"
+    )]
+    provider: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Read the state from a local pathfinder database instead of over rpc"
+    )]
+    pathfinder_db: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "VERSION",
+        help = "Use the versioned constants of the given starknet version (e.g. 0.13.2) for every block, instead of the ones of each block's version"
+    )]
+    versioned_constants: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = "run-report.json",
+        help = "Write a JSON report with the executed blocks, the outcome of the transactions, the divergences from the node and the total time"
+    )]
+    run_report: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = "entrypoint-coverage.json",
+        help = "Write the share of the entry points of each executed class that were reached, to judge how representative the replayed transactions are. Sierra statement coverage is not measured"
+    )]
+    entrypoint_coverage: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ReplayExecute {
+    #[clap(about = "Execute a single transaction given a transaction hash.")]
+    Tx {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+        #[command(flatten)]
+        prefix: PrefixArgs,
+        #[arg(
+            long,
+            help = "Also execute the transaction with the Cairo VM on the same state, reporting the first point of divergence"
+        )]
+        compare_vm: bool,
+    },
+    #[clap(
+        about = "Execute a single transaction both with Cairo Native and with the Cairo VM,
+printing every divergence between them"
+    )]
+    DiffTx {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[command(flatten)]
+        flags: FlagsArgs,
+        #[command(flatten)]
+        prefix: PrefixArgs,
+    },
+    #[clap(
+        about = "Execute a single transaction both with Cairo Native and with the Sierra emulator,
+writing the call trace of each execution with the same file name to compare them"
+    )]
+    CallTraceDiff {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[command(flatten)]
+        flags: FlagsArgs,
+        #[command(flatten)]
+        prefix: PrefixArgs,
+        #[arg(
+            short,
+            long,
+            default_value = "call_traces/{chain}",
+            help = "Directory of the call traces, with a subdirectory per executor"
+        )]
+        output_dir: String,
+    },
+    #[clap(
+        about = "Find the minimal set of calls of a multicall transaction for which Cairo Native
+and the Cairo VM still diverge"
+    )]
+    ShrinkTx {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[command(flatten)]
+        flags: FlagsArgs,
+    },
+    #[clap(
+        about = "Re-execute a transaction with decreasing fee limits, reporting the minimum at
+which it still succeeds and how it fails below it"
+    )]
+    SweepGas {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[arg(
+            long,
+            default_value_t = 10,
+            help = "Number of evenly spaced limits to execute"
+        )]
+        steps: u128,
+        #[command(flatten)]
+        flags: FlagsArgs,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "gas-sweep-{chain}-{tx}.{ext}")]
+        output: String,
+    },
+    #[clap(
+        about = "Estimate the fee of a single transaction without validation, comparing it against the
+estimate of the node"
+    )]
+    Estimate {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+    },
+    #[clap(
+        about = "Execute a single transaction on top of the state of the previous block with the given overrides,
+printing its outcome"
+    )]
+    Simulate {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[arg(help = "JSON file with the storage, nonce, balance and class hash overrides")]
+        overrides: String,
+        #[command(flatten)]
+        flags: FlagsArgs,
+    },
+    #[clap(about = "Execute all the transactions in a given block.")]
+    Block {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+        #[command(flatten)]
+        index: IndexArgs,
+        #[command(flatten)]
+        block: BlockArgs,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Execute the transaction of the given JSON file within the block, without validation"
+        )]
+        inject_tx: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "N",
+            requires = "inject_tx",
+            help = "Number of transactions of the block to execute before the injected one, all of them by default"
+        )]
+        inject_at: Option<usize>,
+    },
+    #[clap(about = "Execute all the transactions in a given range of blocks.")]
+    BlockRange {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+        #[command(flatten)]
+        index: IndexArgs,
+        #[command(flatten)]
+        block: BlockArgs,
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            num_args = 0..=1,
+            default_missing_value = "checkpoint-{chain}-{block_start}-{block_end}.json",
+            help = "Save the progress after each block, resuming from it if the file already exists"
+        )]
+        checkpoint: Option<String>,
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            num_args = 0..=1,
+            default_missing_value = "selectors-{chain}-{block_start}-{block_end}.csv",
+            help = "Write the invocation count, total gas and mean calldata size of each selector called in the range, as CSV"
+        )]
+        selector_stats: Option<String>,
+        #[arg(
+            long,
+            default_value_t = 1,
+            conflicts_with_all = ["index_events", "index_messages"],
+            help = "Number of blocks to execute concurrently"
+        )]
+        jobs: usize,
+    },
+    #[clap(
+        about = "Re-execute only the failed transactions (and blocks) listed in the run report of a previous run,
+each on the state of its block right before it"
+    )]
+    RetryFailed {
+        #[arg(help = "Run report written with --run-report")]
+        report: PathBuf,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+    },
+    #[clap(
+        about = "Fetch and cache everything needed to replay the given range of blocks, without executing them."
+    )]
+    FetchBlock {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_start: u64,
+        #[arg(help = "Last block to fetch, defaults to the first one")]
+        block_end: Option<u64>,
+    },
+    #[cfg(feature = "results_store")]
+    #[clap(about = "Query the events stored in the results store.")]
+    Events {
+        #[command(subcommand)]
+        command: EventsCommand,
+    },
+    #[cfg(feature = "results_store")]
+    #[clap(about = "Export the L2 to L1 messages stored in the results store.")]
+    Messages {
+        #[command(subcommand)]
+        command: MessagesCommand,
+    },
+    #[clap(about = "Compare the transaction reports of replays.")]
+    Report {
+        #[command(subcommand)]
+        command: ReportCommand,
+    },
+    #[clap(about = "Inspect the compiled classes executed in a block range.")]
+    Classes {
+        #[command(subcommand)]
+        command: ClassesCommand,
+    },
+    #[clap(about = "Inspect the rpc cache database.")]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    #[clap(about = "Export and run self-contained reproduction bundles of a transaction.")]
+    Repro {
+        #[command(subcommand)]
+        command: ReproCommand,
+    },
+    #[clap(
+        about = "Compute the fees collected by the sequencer in each block of a range,
+reconciling them against the on-chain fee transfers"
+    )]
+    Revenue {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[arg(long, help = "Retry with the Cairo VM if a Native execution fails")]
+        vm_fallback: bool,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "revenue-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Replay a block range under a scenario overriding gas prices and timestamps,
+reporting which transactions would have reverted, failed or paid drastically different fees"
+    )]
+    Scenario {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[arg(help = "JSON file describing the scenario")]
+        scenario: String,
+        #[arg(
+            long,
+            default_value_t = 2.0,
+            help = "Factor by which a fee must change to be considered drastic"
+        )]
+        fee_threshold: f64,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "scenario-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Replay the transactions of a block in a custom or shuffled order,
+reporting which orders change the final state"
+    )]
+    Reorder {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Indexes of the block transactions, in the order to execute them"
+        )]
+        order: Option<Vec<usize>>,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Number of shuffled orders to replay"
+        )]
+        shuffles: usize,
+        #[arg(long, default_value_t = 0, help = "Seed of the shuffled orders")]
+        seed: u64,
+        #[command(flatten)]
+        flags: FlagsArgs,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "reorder-{chain}-{block_start}.{ext}")]
+        output: String,
+    },
+    #[clap(
+        about = "Execute invocations with extreme calldata shapes from the given account,
+with both Cairo Native and the Cairo VM, reporting every divergence"
+    )]
+    Stress {
+        account: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[arg(
+            long,
+            default_value = "0x049d36570d4e46f48e99674bd3fcc84644ddd6b96f7c741b1562b82f9e004dc7",
+            help = "Contract called by the generated multicalls"
+        )]
+        target: String,
+        #[arg(
+            long,
+            default_value = "balanceOf",
+            help = "Entry point called by the generated multicalls"
+        )]
+        entry_point: String,
+        #[command(flatten)]
+        flags: FlagsArgs,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "stress-{chain}-{block_start}.{ext}")]
+        output: String,
+    },
+    #[clap(
+        about = "Execute an entry point on the state of the previous block, from an existing account,
+printing its result"
+    )]
+    CallRaw {
+        account: String,
+        contract_address: String,
+        #[arg(help = "Name of the entry point (e.g. balanceOf), or its selector")]
+        entry_point: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[arg(long, value_delimiter = ',', help = "Comma separated felts")]
+        calldata: Vec<String>,
+        #[command(flatten)]
+        flags: FlagsArgs,
+    },
+    #[clap(
+        about = "Compile every class executed in a range of blocks to CASM and Cairo Native ahead of time.
+Saves a report with the compilation times and failures of each class"
+    )]
+    Precompile {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "precompile-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Compile every class executed in a range of blocks to CASM and to Cairo Native at several optimization levels.
+Saves a report with the compilation time and artifact size of each class per target, without caching the artifacts"
+    )]
+    BenchCompilation {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "0,1,2,3",
+            value_parser = clap::value_parser!(u8).range(0..=3),
+            help = "Native optimization levels to compile with"
+        )]
+        opt_levels: Vec<u8>,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "bench-compilation-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Report every value taken by a storage slot in a range of blocks, reading mostly from the rpc cache"
+    )]
+    StorageHistory {
+        contract_address: String,
+        key: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_start: u64,
+        block_end: u64,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "storage-history-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Replay every transaction that invoked the given contract in a range of blocks.
+Saves a report with every invocation of the contract"
+    )]
+    History {
+        contract_address: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_start: u64,
+        block_end: u64,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "history-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+    #[cfg(feature = "benchmark")]
+    #[clap(
+        about = "Measures the time it takes to run all transactions in a given range of blocks.
+Caches all rpc data before the benchmark runs to provide accurate results"
+    )]
+    BenchBlockRange {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        number_of_runs: usize,
+        #[arg(
+            long,
+            help = "Report the timings of the first execution after filling the caches, separately"
+        )]
+        first_call: bool,
+        #[command(flatten)]
+        budgets: BudgetArgs,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "bench-{chain}-{block_start}-{block_end}-{date}.{ext}"
+        )]
+        output: String,
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            num_args = 0..=1,
+            default_missing_value = "entrypoints-{chain}-{block_start}-{block_end}.{ext}",
+            help = "Write the entry points ranked by their execution time"
+        )]
+        entrypoints: Option<String>,
+    },
+    #[cfg(feature = "benchmark")]
+    #[clap(about = "Measures the time it takes to run a single transaction.
+        Caches all rpc data before the benchmark runs to provide accurate results.
+        It only works if the transaction doesn't depend on another transaction in the same block")]
+    BenchTx {
+        tx: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block: u64,
+        number_of_runs: usize,
+        #[arg(
+            long,
+            help = "Report the timings of the first execution after filling the caches, separately"
+        )]
+        first_call: bool,
+        #[command(flatten)]
+        budgets: BudgetArgs,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "bench-{chain}-{tx}-{date}.{ext}")]
+        output: String,
+    },
+    #[cfg(feature = "benchmark")]
+    #[clap(
+        about = "Compare two benchmark outputs of bench-block-range or bench-tx, reporting the speedup and the regressed transactions"
+    )]
+    BenchCompare {
+        before: PathBuf,
+        after: PathBuf,
+        #[arg(
+            long,
+            default_value_t = 0.05,
+            help = "Minimum relative change of a transaction to report it as a regression or improvement"
+        )]
+        threshold: f64,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "bench-compare.{ext}")]
+        output: String,
+    },
+    #[cfg(feature = "benchmark")]
+    #[clap(
+        about = "Measures the time it takes to run all transactions in a given range of blocks,
+both with Cairo Native and with the Cairo VM, in the same process.
+Caches all rpc data before the benchmark runs to provide accurate results"
+    )]
+    BenchDual {
+        block_start: u64,
+        block_end: u64,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        number_of_runs: usize,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "bench-dual-{chain}-{block_start}-{block_end}-{date}.{ext}"
+        )]
+        output: String,
+    },
+    #[cfg(feature = "state_dump")]
+    #[clap(
+        about = "Compare the execution infos of a central blob written by the sequencer
+against the state dumps of the replayed block"
+    )]
+    CompareCentral {
+        blob: PathBuf,
+        #[arg(
+            long,
+            default_value = "native",
+            help = "Executor whose state dumps are compared (native, vm or sierra-emu)"
+        )]
+        executor: Executor,
+    },
+    #[cfg(feature = "state_dump")]
+    #[clap(about = "Inspect the state dumps of replayed transactions.")]
+    StateDump {
+        #[command(subcommand)]
+        command: StateDumpCommand,
+    },
+    #[cfg(feature = "serve")]
+    #[clap(
+        about = "Serve a JSON-RPC endpoint that traces and simulates transactions with the replay engine"
+    )]
+    Serve {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    #[cfg(feature = "tui")]
+    #[clap(
+        about = "Browse the results of a finished replay: executed blocks and transactions,
+call trees (from state dumps) and cache stats"
+    )]
+    Tui,
+}
+
+/// Flags passed to the blockifier when executing each transaction
+#[derive(Args, Debug, Default)]
+struct FlagsArgs {
+    #[arg(short, long)]
+    charge_fee: bool,
+    #[arg(
+        long,
+        help = "Execute the transactions as queries (e.g. as starknet_estimateFee does), with the query version"
+    )]
+    only_query: bool,
+    #[arg(long, help = "Skip the validation of the account transactions")]
+    skip_validate: bool,
+}
+
+impl FlagsArgs {
+    fn flags(&self) -> ExecutionFlags {
+        ExecutionFlags {
+            only_query: self.only_query,
+            charge_fee: self.charge_fee,
+            validate: !self.skip_validate,
+        }
+    }
+}
+
+impl From<repro::Flags> for FlagsArgs {
+    fn from(flags: repro::Flags) -> Self {
+        Self {
+            charge_fee: flags.charge_fee,
+            only_query: flags.only_query,
+            skip_validate: !flags.validate,
+        }
+    }
+}
+
+/// Options that control how each transaction is executed
+#[derive(Args, Debug)]
+struct ExecutionArgs {
+    #[command(flatten)]
+    flags: FlagsArgs,
+    #[arg(long, help = "Retry with the Cairo VM if a Native execution fails")]
+    vm_fallback: bool,
+    // With fees charged, every transaction writes the balance of the
+    // sequencer, so every speculative execution would conflict
+    #[arg(
+        long,
+        conflicts_with = "charge_fee",
+        help = "Execute the transactions of each block in parallel, continuing sequentially after the first conflict. Only used when executing whole blocks, without charging fees"
+    )]
+    parallel: bool,
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = "traces/{chain}/block{block_start}/{tx}.json",
+        help = "Write the trace of each executed transaction, in the format of starknet_traceTransaction"
+    )]
+    export_trace: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = state_access::parse_config,
+        help = "Count and time the state reads of the execution, injecting the state errors of the given JSON config. Not applied to speculative executions"
+    )]
+    state_access_config: Option<StateAccessConfig>,
+}
+
+impl ExecutionArgs {
+    fn flags(&self) -> ExecutionFlags {
+        self.flags.flags()
+    }
+}
+
+/// Options that only apply when executing whole blocks
+#[derive(Args, Debug, Default)]
+struct BlockArgs {
+    #[arg(
+        long,
+        value_name = "K",
+        help = "After each block, compare K of the written storage keys (sampled by hash) against the node"
+    )]
+    spot_check: Option<usize>,
+    #[arg(
+        long = "skip-tx",
+        value_name = "HASH",
+        help = "Don't execute the given transaction, accepting the resulting state drift. Can be repeated"
+    )]
+    skip_txs: Vec<String>,
+    #[arg(
+        long,
+        help = "After each block, compare its state changes against the state update reported by the node"
+    )]
+    check_state_diff: bool,
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = "tx-reports/{chain}/block{block_start}.jsonl",
+        help = "Write the wall time, resources, outcome and cache misses of each transaction of the block, as JSON Lines"
+    )]
+    tx_report: Option<String>,
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = "composition/{chain}/block{block_start}.{ext}",
+        help = "Write the call graph of the entry points called in the block"
+    )]
+    composition: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Format::Json,
+        help = "Format of the block composition. Tabular formats only have a row per entry point"
+    )]
+    composition_format: Format,
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = "composition/{chain}/block{block_start}.dot",
+        help = "Write the call graph of the entry points called in the block, as DOT"
+    )]
+    composition_dot: Option<String>,
+    #[arg(
+        long,
+        help = "Record the transactions (or blocks) that panic as failures of the run report, continuing with the rest of the block and range"
+    )]
+    continue_on_error: bool,
+}
+
+impl BlockArgs {
+    fn skipped_txs(&self) -> Vec<TransactionHash> {
+        self.skip_txs
+            .iter()
+            .map(|hash| TransactionHash(felt!(hash.as_str())))
+            .collect()
+    }
+}
+
+/// Transactions of the block to execute before the requested one, so that it
+/// runs on an intermediate state of the block instead of the previous block's
+#[derive(Args, Debug, Default)]
+struct PrefixArgs {
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "after_tx",
+        help = "Execute the first N transactions of the block first"
+    )]
+    at_tx_index: Option<usize>,
+    #[arg(
+        long,
+        value_name = "HASH",
+        help = "Execute the transactions of the block up to and including the given one first"
+    )]
+    after_tx: Option<String>,
+}
+
+impl PrefixArgs {
+    /// Returns the hashes of the transactions to execute before the requested
+    /// one, which must not be part of them
+    fn prefix(
+        &self,
+        reader: &impl StateReader,
+        target: &TransactionHash,
+    ) -> anyhow::Result<Vec<TransactionHash>> {
+        if self.at_tx_index.is_none() && self.after_tx.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let transaction_hashes = reader.get_block_with_tx_hashes()?.transactions;
+
+        let len = match (self.at_tx_index, &self.after_tx) {
+            (Some(index), _) => {
+                ensure!(
+                    index <= transaction_hashes.len(),
+                    "the block only has {} transactions",
+                    transaction_hashes.len()
+                );
+                index
+            }
+            (None, Some(hash)) => {
+                let hash = TransactionHash(felt!(hash.as_str()));
+                transaction_hashes
+                    .iter()
+                    .position(|tx_hash| *tx_hash == hash)
+                    .context("transaction not found in the block")?
+                    + 1
+            }
+            (None, None) => unreachable!(),
+        };
+
+        // Otherwise, the requested transaction would be executed twice
+        if let Some(target_index) = transaction_hashes.iter().position(|hash| hash == target) {
+            ensure!(
+                len <= target_index,
+                "the prefix must end before the requested transaction, at index {target_index}"
+            );
+        }
+
+        Ok(transaction_hashes[..len].to_vec())
+    }
+}
+
+/// Executes the transactions on the state, without reporting them.
+/// Failed transactions are logged and skipped.
+fn execute_prefix(
+    state: &mut CachedState<impl StateReader>,
+    reader: &impl StateReader,
+    prefix: &[TransactionHash],
+    flags: ExecutionFlags,
+) -> anyhow::Result<()> {
+    if prefix.is_empty() {
+        return Ok(());
+    }
+    info!(transactions = prefix.len(), "executing block prefix");
+
+    let context = fetch_block_context(reader)?;
+    for tx_hash in prefix {
+        let tx = fetch_blockifier_transaction(reader, flags.clone(), *tx_hash)?;
+        if let Err(err) = tx.execute(state, &context) {
+            warn!(
+                hash = tx_hash.0.to_hex_string(),
+                "prefix transaction failed: {err}"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes the transaction on top of the state of the previous block
+fn retry_transaction(
+    chain: &str,
+    block_number: u64,
+    tx_hash: &str,
+    execution: &ExecutionArgs,
+) -> anyhow::Result<()> {
+    let mut state = build_cached_state(chain, block_number - 1);
+    let reader = build_reader(chain, block_number);
+
+    execute_in_block(&mut state, &reader, chain, block_number, tx_hash, execution)?;
+    Ok(())
+}
+
+/// Executes the transaction after the ones preceding it in its block, as
+/// when the whole block is executed
+fn execute_in_block(
+    state: &mut CachedState<impl StateReader>,
+    reader: &impl StateReader,
+    chain: &str,
+    block_number: u64,
+    tx_hash: &str,
+    execution: &ExecutionArgs,
+) -> anyhow::Result<Option<TransactionExecutionInfo>> {
+    let hash = TransactionHash(felt!(tx_hash));
+    let transaction_hashes = reader.get_block_with_tx_hashes()?.transactions;
+    let position = transaction_hashes
+        .iter()
+        .position(|tx_hash| *tx_hash == hash)
+        .context("transaction not found in the block")?;
+    execute_prefix(
+        state,
+        reader,
+        &transaction_hashes[..position],
+        execution.flags(),
+    )?;
+
+    Ok(show_execution_data(
+        state,
+        reader,
+        tx_hash.to_string(),
+        chain,
+        block_number,
+        execution,
+    ))
+}
+
+/// Options of a transaction executed with the given flags only
+fn flags_execution(flags: FlagsArgs) -> ExecutionArgs {
+    ExecutionArgs {
+        flags,
+        vm_fallback: false,
+        parallel: false,
+        export_trace: None,
+        state_access_config: None,
+    }
+}
+
+/// Data to store into the results store after executing each transaction
+#[derive(Args, Debug, Default)]
+struct IndexArgs {
+    #[arg(long, help = "Index the emitted events into the results store")]
+    index_events: bool,
+    #[arg(long, help = "Index the sent L2 to L1 messages into the results store")]
+    index_messages: bool,
+}
+
+impl IndexArgs {
+    fn any(&self) -> bool {
+        self.index_events || self.index_messages
+    }
+}
+
+/// Performance budgets to check the benchmark against
+#[cfg(feature = "benchmark")]
+#[derive(Args, Debug)]
+struct BudgetArgs {
+    #[arg(
+        long,
+        help = "JSON file mapping class hashes to their maximum execution time, in nanoseconds, per unit of sierra gas"
+    )]
+    budgets: Option<PathBuf>,
+    #[arg(
+        long,
+        requires = "budgets",
+        help = "Only warn about classes over budget, instead of failing"
+    )]
+    warn_over_budget: bool,
+}
+
+#[cfg(feature = "benchmark")]
+impl BudgetArgs {
+    /// Checks the class executions against the budgets, failing if any
+    /// class exceeds it unless `--warn-over-budget` is given.
+    fn enforce(&self, class_executions: &[ClassExecutionInfo]) -> anyhow::Result<()> {
+        let Some(path) = &self.budgets else {
+            return Ok(());
+        };
+        let budgets = budget::load_budgets(path).context("failed to load budgets")?;
+
+        let violations = budget::check_budgets(&budgets, class_executions);
+        for violation in &violations {
+            let class_hash = violation.class_hash.to_hex_string();
+            if self.warn_over_budget {
+                warn!(
+                    class_hash,
+                    budget = violation.budget,
+                    actual = violation.actual,
+                    "class execution is over budget"
+                );
+            } else {
+                error!(
+                    class_hash,
+                    budget = violation.budget,
+                    actual = violation.actual,
+                    "class execution is over budget"
+                );
+            }
+        }
+
+        info!(
+            classes = budgets.len(),
+            over_budget = violations.len(),
+            "checked performance budgets"
+        );
+        ensure!(
+            self.warn_over_budget || violations.is_empty(),
+            "{} classes exceeded their performance budget",
+            violations.len()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(feature = "results_store")]
+#[derive(Subcommand, Debug)]
+enum EventsCommand {
+    #[clap(about = "Print the indexed events that match the given filters.")]
+    Query {
+        #[arg(long)]
+        address: Option<String>,
+        #[arg(long)]
+        key: Option<String>,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    #[clap(
+        about = "Fetch a sample of the cached storage values, nonces, class hashes and classes again, reporting every entry that differs."
+    )]
+    Verify {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        #[arg(long, default_value_t = 100, help = "Entries to verify of each kind")]
+        samples: usize,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "cache-verify-{chain}.{ext}")]
+        output: String,
+    },
+    #[clap(about = "Remove the JSON caches of the given range of blocks.")]
+    Prune {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_start: u64,
+        block_end: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ReproCommand {
+    #[clap(
+        about = "Execute a transaction after the ones preceding it in its block, packaging every entry it reads into a bundle."
+    )]
+    Export {
+        tx_hash: String,
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_number: u64,
+        #[command(flatten)]
+        flags: FlagsArgs,
+        #[arg(short, long, default_value = "repro-{chain}-{tx}.json.zst")]
+        output: String,
+    },
+    #[clap(
+        about = "Execute the transaction of a bundle offline, with the flags it was exported with."
+    )]
+    Run { bundle: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+enum ReportCommand {
+    #[clap(
+        about = "Compare two transaction reports of the same range, summarizing the changes in outcome, gas, fees and timing."
+    )]
+    Diff {
+        run_a: PathBuf,
+        run_b: PathBuf,
+        #[arg(long, help = "Only report the transactions that regressed")]
+        only_regressions: bool,
+        #[arg(
+            long,
+            default_value_t = 0.1,
+            help = "Minimum relative change of the wall time to report a transaction as slower or faster"
+        )]
+        time_threshold: f64,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(short, long, default_value = "report-diff.{ext}")]
+        output: String,
+    },
+}
+
+#[cfg(feature = "state_dump")]
+#[derive(Subcommand, Debug)]
+enum StateDumpCommand {
+    #[clap(
+        about = "Compare two state dumps of a transaction, printing only the differing storage entries, events and call info fields."
+    )]
+    Diff { a: PathBuf, b: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+enum ClassesCommand {
+    #[clap(
+        about = "Load the classes executed in the given block range, reporting the ones holding the most memory."
+    )]
+    TopMem {
+        #[arg(value_parser = parse_chain_name)]
+        chain: String,
+        block_start: u64,
+        block_end: u64,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "classes-top-mem-{chain}-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+}
+
+#[cfg(feature = "results_store")]
+#[derive(Subcommand, Debug)]
+enum MessagesCommand {
+    #[clap(
+        about = "Export the messages sent in the given block range, reconciling their count against the receipts."
+    )]
+    Export {
+        block_start: u64,
+        block_end: u64,
+        #[arg(short, long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        #[arg(
+            short,
+            long,
+            default_value = "messages-{block_start}-{block_end}.{ext}"
+        )]
+        output: String,
+    },
+}
+
+fn main() {
+    let run_instant = Instant::now();
+    dotenvy::dotenv().ok();
+    set_global_subscriber();
+
+    let cli = ReplayCLI::parse();
+
+    // The stack size of the main thread can't be changed, so the replay runs
+    // in a thread of its own
+    let Some(stack_size) = cli.native_stack_size.map(|size| size << 20) else {
+        return run(cli, run_instant);
+    };
+    // Read by the standard library when spawning any other thread
+    std::env::set_var("RUST_MIN_STACK", stack_size.to_string());
+    let replay = std::thread::Builder::new()
+        .name("replay".to_string())
+        .stack_size(stack_size)
+        .spawn(move || run(cli, run_instant))
+        .expect("failed to spawn the replay thread");
+    if let Err(panic) = replay.join() {
+        panic::resume_unwind(panic);
+    }
+}
+
+fn run(cli: ReplayCLI, run_instant: Instant) {
+    output::init(cli.out_dir);
+    if let Err(err) = reader::select_executor(cli.executor) {
+        error!("{err:#}");
+        std::process::exit(1);
+    }
+    rpc_state_reader::utils::set_native_opt_level(cli.native_opt_level);
+    rpc_state_reader::utils::set_native_executor_limit(cli.native_executor_limit);
+    rpc_state_reader::schema::set_strict(cli.strict_rpc);
+    rpc_state_reader::cache::set_memory_limit(cli.cache_memory_limit.map(|limit| limit << 20));
+    rpc_state_reader::cache::set_offline(cli.offline);
+    if cli.entrypoint_coverage.is_some() {
+        entrypoint_coverage::enable();
+    }
+    if let Some(name) = &cli.provider {
+        let provider = provider::load(name).expect("failed to load provider profile");
+        info!(
+            provider = provider.name,
+            url = provider.url,
+            "using rpc provider profile"
+        );
+        if let Err(err) = provider::select(provider) {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+    }
+    if let Some(version) = &cli.versioned_constants {
+        rpc_state_reader::execution::override_versioned_constants(version)
+            .expect("invalid versioned constants version");
+        info!(version, "overriding versioned constants");
+    }
+    if let Some(path) = &cli.pathfinder_db {
+        let database = PathfinderDatabase::open(path).expect("failed to open pathfinder database");
+        info!(
+            path = %path.display(),
+            latest_block = database.latest_block(),
+            "reading state from pathfinder database"
+        );
+        pathfinder::select(database);
+    }
+
+    if let Err(err) = audit::init(&output::resolve(&cli.audit_log)) {
+        error!("failed to open audit log: {err}");
+    }
+
+    match cli.subcommand {
+        ReplayExecute::Tx {
+            tx_hash,
+            chain,
+            block_number,
+            execution,
+            prefix,
+            compare_vm,
+        } => {
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let reader = build_reader(&chain, block_number);
+
+            let prefix = prefix
+                .prefix(&reader, &TransactionHash(felt!(tx_hash.as_str())))
+                .expect("invalid block prefix");
+            execute_prefix(&mut state, &reader, &prefix, execution.flags())
+                .expect("failed to execute block prefix");
+
+            // Compared before executing, as the execution commits its changes
+            if compare_vm {
+                let (tx, context) = fetch_transaction_with_state(
+                    &reader,
+                    &TransactionHash(felt!(tx_hash.as_str())),
+                    execution.flags(),
+                )
+                .expect("failed to fetch transaction");
+                let divergences = divergence::diff_transaction(&tx, &mut state, &context)
+                    .expect("failed to execute transaction");
+
+                match divergence::first_divergence(&divergences) {
+                    None => info!("native and vm executions match"),
+                    Some(first) => error!(
+                        divergences = divergences.len(),
+                        path = first.path,
+                        native = %first.native,
+                        vm = %first.vm,
+                        "native and vm executions diverged"
+                    ),
+                }
+            }
+
+            show_execution_data(
+                &mut state,
+                &reader,
+                tx_hash,
+                &chain,
+                block_number,
+                &execution,
+            );
+        }
+        ReplayExecute::DiffTx {
+            tx_hash,
+            chain,
+            block_number,
+            flags,
+            prefix,
+        } => {
+            let _transaction_span = info_span!("transaction", hash = tx_hash).entered();
+
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let flags = flags.flags();
+            let (tx, context) = fetch_transaction_with_state(
+                &reader,
+                &TransactionHash(felt!(tx_hash.as_str())),
+                flags.clone(),
+            )
+            .expect("failed to fetch transaction");
+
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let prefix = prefix
+                .prefix(&reader, &TransactionHash(felt!(tx_hash.as_str())))
+                .expect("invalid block prefix");
+            execute_prefix(&mut state, &reader, &prefix, flags)
+                .expect("failed to execute block prefix");
+
+            let divergences = divergence::diff_transaction(&tx, &mut state, &context)
+                .expect("failed to execute transaction");
+
+            if divergences.is_empty() {
+                info!("native and vm executions match");
+            } else {
+                error!(
+                    divergences = divergences.len(),
+                    "native and vm executions diverged"
+                );
+            }
+
+            serde_json::to_writer_pretty(std::io::stdout(), &divergences).unwrap();
+        }
+        ReplayExecute::CallTraceDiff {
+            tx_hash,
+            chain,
+            block_number,
+            flags,
+            prefix,
+            output_dir,
+        } => {
+            let _transaction_span = info_span!("transaction", hash = tx_hash).entered();
+
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let flags = flags.flags();
+            let hash = TransactionHash(felt!(tx_hash.as_str()));
+            let (tx, context) = fetch_transaction_with_state(&reader, &hash, flags.clone())
+                .expect("failed to fetch transaction");
+            let receipt = reader
+                .get_transaction_receipt(&hash)
+                .expect("failed to fetch transaction receipt");
+
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let prefix = prefix.prefix(&reader, &hash).expect("invalid block prefix");
+            execute_prefix(&mut state, &reader, &prefix, flags)
+                .expect("failed to execute block prefix");
+
+            let dir = output::path(
+                &output_dir,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_number),
+                    tx: Some(&tx_hash),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output directory");
+            let (paths, equal) = call_trace_diff::write_call_traces(
+                &tx,
+                &mut state,
+                &reader,
+                &context,
+                &receipt.tx_type,
+                &tx_hash,
+                &dir,
+            )
+            .expect("failed to write call traces");
+
+            for path in paths {
+                info!("saved call trace to {}", path.display());
+            }
+            if equal {
+                info!("call traces are equal");
+            } else {
+                warn!("call traces differ");
+            }
+        }
+        ReplayExecute::ShrinkTx {
+            tx_hash,
+            chain,
+            block_number,
+            flags,
+        } => {
+            let _transaction_span = info_span!("transaction", hash = tx_hash).entered();
+
+            let tx_hash = TransactionHash(felt!(tx_hash.as_str()));
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let transaction = reader
+                .get_transaction(&tx_hash)
+                .expect("failed to fetch transaction");
+            let block_context =
+                fetch_block_context(&reader).expect("failed to fetch block context");
+
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let result = shrink::shrink_transaction(
+                &transaction,
+                tx_hash,
+                flags.flags(),
+                &mut state,
+                &block_context,
+            )
+            .expect("failed to shrink transaction");
+
+            match &result {
+                Some(result) => info!(
+                    original_calls = result.original_calls,
+                    calls = result.calls.len(),
+                    "shrunk diverging transaction"
+                ),
+                None => info!("native and vm executions match"),
+            }
+
+            serde_json::to_writer_pretty(std::io::stdout(), &result).unwrap();
+        }
+        ReplayExecute::Estimate {
+            tx_hash,
+            chain,
+            block_number,
+        } => {
+            let _transaction_span = info_span!("transaction", hash = tx_hash).entered();
+            let hash = TransactionHash(felt!(tx_hash.as_str()));
+
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let flags = ExecutionFlags {
+                only_query: true,
+                charge_fee: false,
+                validate: false,
+            };
+            let (tx, context) = fetch_transaction_with_state(&reader, &hash, flags)
+                .expect("failed to fetch transaction");
+
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let execution_info = tx
+                .execute(&mut state, &context)
+                .expect("failed to execute transaction");
+
+            let node_estimate = fee_estimate::estimate_fee_on_node(
+                &RpcStateReader::new(parse_network(&chain), BlockNumber(block_number - 1)),
+                hash,
+            )
+            .expect("failed to estimate fee on node");
+
+            let comparison = fee_estimate::FeeComparison::new(
+                tx_hash,
+                fee_estimate::FeeEstimate::from(&execution_info),
+                node_estimate,
+            );
+            if comparison.matches() {
+                info!("fee estimate matches the node");
+            } else {
+                warn!(
+                    l1_gas = comparison.delta.l1_gas,
+                    l1_data_gas = comparison.delta.l1_data_gas,
+                    l2_gas = comparison.delta.l2_gas,
+                    overall_fee = comparison.delta.overall_fee,
+                    "fee estimate differs from the node"
+                );
+            }
+
+            serde_json::to_writer_pretty(std::io::stdout(), &comparison).unwrap();
+        }
+        ReplayExecute::Simulate {
+            tx_hash,
+            chain,
+            block_number,
+            overrides,
+            flags,
+        } => {
+            let _transaction_span = info_span!("transaction", hash = tx_hash).entered();
+
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let flags = flags.flags();
+            let (tx, context) = fetch_transaction_with_state(
+                &reader,
+                &TransactionHash(felt!(tx_hash.as_str())),
+                flags,
+            )
+            .expect("failed to fetch transaction");
+
+            let overrides = simulate::load_overrides(&overrides)
+                .and_then(|overrides| {
+                    overrides.to_state_maps(&context.chain_info().fee_token_addresses)
+                })
+                .expect("failed to load overrides");
+            let mut state = CachedState::new(simulate::OverrideStateReader {
+                inner: build_reader(&chain, block_number - 1),
+                overrides,
+            });
+
+            let simulation = simulate::simulate(&tx_hash, &tx, &mut state, &context);
+            match &simulation.error {
+                Some(error) => warn!(error, "simulated transaction didn't succeed"),
+                None => info!("simulated transaction succeeded"),
+            }
+
+            serde_json::to_writer_pretty(std::io::stdout(), &simulation).unwrap();
+        }
+        ReplayExecute::SweepGas {
+            tx_hash,
+            chain,
+            block_number,
+            steps,
+            flags,
+            format,
+            output,
+        } => {
+            let _transaction_span = info_span!("transaction", hash = tx_hash).entered();
+
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    tx: Some(&tx_hash),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let tx_hash = TransactionHash(felt!(tx_hash.as_str()));
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let transaction = reader
+                .get_transaction(&tx_hash)
+                .expect("failed to fetch transaction");
+            let block_context =
+                fetch_block_context(&reader).expect("failed to fetch block context");
+
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let sweep = gas_sweep::sweep_transaction(
+                &transaction,
+                tx_hash,
+                steps,
+                flags.flags(),
+                &mut state,
+                &block_context,
+            )
+            .expect("failed to sweep transaction");
+
+            match sweep.minimum {
+                Some(minimum) => info!(
+                    resource = sweep.resource,
+                    original_limit = sweep.original_limit,
+                    minimum,
+                    "found minimum fee limit"
+                ),
+                None => warn!("transaction doesn't succeed with its original fee limit"),
+            }
+
+            if let Err(err) = format::write(&path, format, &sweep, &sweep.points) {
+                error!("failed to save gas sweep: {err:#}");
+            }
+        }
+        ReplayExecute::Block {
+            block_number,
+            chain,
+            execution,
+            index,
+            block,
+            inject_tx,
+            inject_at,
+        } => {
+            let mut results_store = index.any().then(|| open_results_store(&cli.results_store));
+
+            let injected = inject_tx.map(|path| {
+                inject::load_transaction(&path, inject_at.unwrap_or(usize::MAX))
+                    .expect("failed to load injected transaction")
+            });
+
+            execute_block(
+                &chain,
+                block_number,
+                &execution,
+                &index,
+                &block,
+                injected.as_ref(),
+                results_store.as_mut(),
+            );
+        }
+        ReplayExecute::BlockRange {
+            block_start,
+            block_end,
+            chain,
+            execution,
+            index,
+            block,
+            checkpoint,
+            selector_stats,
+            jobs,
+        } => {
+            info!("executing block range: {} - {}", block_start, block_end);
+
+            let mut results_store = index.any().then(|| open_results_store(&cli.results_store));
+
+            let checkpoint_path = checkpoint.map(|template| {
+                output::path(
+                    &template,
+                    &OutputVars {
+                        chain: Some(&chain),
+                        block_start: Some(block_start),
+                        block_end: Some(block_end),
+                        ..Default::default()
+                    },
+                )
+                .expect("invalid checkpoint path")
+            });
+            let mut checkpoint = match &checkpoint_path {
+                Some(path) => Checkpoint::load_or_new(path, &chain, block_start, block_end)
+                    .expect("failed to load checkpoint"),
+                None => Checkpoint::new(&chain, block_start, block_end),
+            };
+            if checkpoint.next_block() > block_start {
+                info!(
+                    block = checkpoint.next_block(),
+                    failed_txs = checkpoint.failed_txs.len(),
+                    "resuming from checkpoint"
+                );
+            }
+
+            let mut progress = Progress::new(block_end + 1 - checkpoint.next_block());
+            let mut selector_summary = block_composition::SelectorSummary::default();
+
+            let blocks = checkpoint.next_block()..=block_end;
+            let mut record_block = |block_number: u64, executions: BlockExecutions| {
+                progress.advance(1, executions.len());
+
+                if selector_stats.is_some() {
+                    selector_summary.add(&block_composition::compose(
+                        block_number,
+                        executions
+                            .iter()
+                            .filter_map(|(_, execution_info)| execution_info.as_ref()),
+                    ));
+                }
+
+                let failed_txs = executions
+                    .into_iter()
+                    .filter(|(_, execution_info)| execution_info.is_none())
+                    .map(|(tx_hash, _)| tx_hash)
+                    .collect();
+                checkpoint.record_block(block_number, failed_txs);
+
+                if let Some(path) = &checkpoint_path {
+                    if let Err(err) = output::write_json(path, &checkpoint) {
+                        error!("failed to save checkpoint: {err:#}");
+                    }
+                }
+            };
+
+            if jobs > 1 {
+                workers::execute_in_order(
+                    jobs,
+                    blocks,
+                    |block_number| {
+                        execute_range_block(&chain, block_number, &execution, &index, &block, None)
+                    },
+                    &mut record_block,
+                );
+            } else {
+                for block_number in blocks {
+                    let executions = execute_range_block(
+                        &chain,
+                        block_number,
+                        &execution,
+                        &index,
+                        &block,
+                        results_store.as_mut(),
+                    );
+                    record_block(block_number, executions);
+                }
+            }
+
+            progress.finish();
+
+            if let Some(template) = selector_stats {
+                output::path(
+                    &template,
+                    &OutputVars {
+                        chain: Some(&chain),
+                        block_start: Some(block_start),
+                        block_end: Some(block_end),
+                        ..Default::default()
+                    },
+                )
+                .and_then(|path| {
+                    format::write_records(&path, Format::Csv, &selector_summary.stats())
+                })
+                .inspect_err(|err| error!("failed to write selector stats: {err:#}"))
+                .ok();
+            }
+        }
+        ReplayExecute::RetryFailed {
+            report,
+            chain,
+            execution,
+        } => {
+            let failures = run_report::load_failures(&report).expect("failed to load run report");
+            info!(failures = failures.len(), "retrying failures");
+
+            for failure in failures {
+                match &failure.tx_hash {
+                    Some(tx_hash) => {
+                        retry_transaction(&chain, failure.block_number, tx_hash, &execution)
+                            .inspect_err(|err| error!(hash = tx_hash, "failed to retry: {err:#}"))
+                            .ok();
+                    }
+                    None => {
+                        execute_block(
+                            &chain,
+                            failure.block_number,
+                            &execution,
+                            &IndexArgs::default(),
+                            &BlockArgs::default(),
+                            None,
+                            None,
+                        );
+                    }
+                }
+            }
+        }
+        ReplayExecute::FetchBlock {
+            chain,
+            block_start,
+            block_end,
+        } => {
+            for block_number in block_start..=block_end.unwrap_or(block_start) {
+                let _block_span = info_span!("block", number = block_number).entered();
+
+                let reader = build_reader(&chain, block_number);
+                let previous_reader = build_reader(&chain, block_number - 1);
+
+                match fetch_block(&reader, &previous_reader) {
+                    Ok(fetched) => info!(
+                        transactions = fetched.transactions,
+                        classes = fetched.classes,
+                        "fetched block"
+                    ),
+                    Err(err) => error!("failed to fetch block: {err:#}"),
+                }
+            }
+        }
+        #[cfg(feature = "results_store")]
+        ReplayExecute::Events { command } => match command {
+            EventsCommand::Query {
+                address,
+                key,
+                limit,
+            } => {
+                let results_store = open_results_store(&cli.results_store);
+
+                let address = address.map(|address| {
+                    ContractAddress::try_from(felt!(address.as_str()))
+                        .expect("invalid contract address")
+                });
+                let key = key.map(|key| felt!(key.as_str()));
+
+                let events = results_store
+                    .query_events(address, key, limit)
+                    .expect("failed to query events");
+
+                serde_json::to_writer_pretty(std::io::stdout(), &events).unwrap();
+            }
+        },
+        #[cfg(feature = "results_store")]
+        ReplayExecute::Messages { command } => match command {
+            MessagesCommand::Export {
+                block_start,
+                block_end,
+                format,
+                output,
+            } => {
+                let results_store = open_results_store(&cli.results_store);
+
+                let messages = results_store
+                    .query_messages(block_start, block_end)
+                    .expect("failed to query messages");
+
+                let mismatches = results_store
+                    .message_count_mismatches(block_start, block_end)
+                    .expect("failed to query message counts");
+                for mismatch in &mismatches {
+                    error!(
+                        block = mismatch.block_number,
+                        hash = mismatch.tx_hash.0.to_hex_string(),
+                        executed_count = mismatch.executed_count,
+                        rpc_count = mismatch.rpc_count,
+                        "message count diverged from receipt"
+                    );
+                }
+
+                let path = output::path(
+                    &output,
+                    &OutputVars {
+                        block_start: Some(block_start),
+                        block_end: Some(block_end),
+                        ext: Some(format.ext()),
+                        ..Default::default()
+                    },
+                )
+                .expect("invalid output path");
+
+                if let Err(err) = format::write_records(&path, format, &messages) {
+                    error!("failed to export messages: {err:#}");
+                }
+
+                info!(
+                    messages = messages.len(),
+                    mismatches = mismatches.len(),
+                    "exported messages"
+                );
+            }
+        },
+        ReplayExecute::Report { command } => match command {
+            ReportCommand::Diff {
+                run_a,
+                run_b,
+                only_regressions,
+                time_threshold,
+                format,
+                output,
+            } => {
+                let path = output::path(
+                    &output,
+                    &OutputVars {
+                        ext: Some(format.ext()),
+                        ..Default::default()
+                    },
+                )
+                .expect("invalid output path");
+
+                let run_a = report_diff::load_report(&run_a).expect("failed to load report");
+                let run_b = report_diff::load_report(&run_b).expect("failed to load report");
+                let diff = report_diff::diff(run_a, run_b, time_threshold, only_regressions);
+
+                let summary = &diff.summary;
+                info!(
+                    aligned = summary.aligned,
+                    only_in_a = summary.only_in_a,
+                    only_in_b = summary.only_in_b,
+                    outcome_changes = summary.outcome_changes,
+                    gas_changes = summary.gas_changes,
+                    fee_changes = summary.fee_changes,
+                    slower = summary.slower,
+                    faster = summary.faster,
+                    regressions = summary.regressions,
+                    "report diff"
+                );
+
+                if let Err(err) = format::write(&path, format, &diff, &diff.transactions) {
+                    error!("failed to save report diff: {err:#}");
+                }
+            }
+        },
+        ReplayExecute::Classes { command } => match command {
+            ClassesCommand::TopMem {
+                chain,
+                block_start,
+                block_end,
+                limit,
+                format,
+                output,
+            } => {
+                let path = output::path(
+                    &output,
+                    &OutputVars {
+                        chain: Some(&chain),
+                        block_start: Some(block_start),
+                        block_end: Some(block_end),
+                        ext: Some(format.ext()),
+                        ..Default::default()
+                    },
+                )
+                .expect("invalid output path");
+
+                let classes =
+                    precompile::collect_classes(&parse_network(&chain), block_start, block_end)
+                        .expect("failed to collect executed classes");
+                info!(classes = classes.len(), "loading executed classes");
+                precompile::compile_classes(classes);
+
+                let classes = precompile::top_memory(limit);
+                for class in &classes {
+                    info!(
+                        class_hash = class.class_hash.to_hex_string(),
+                        casm_bytes = class.footprint.casm_bytes,
+                        native_bytes = class.footprint.native_bytes,
+                        "class memory"
+                    );
+                }
+
+                if let Err(err) = format::write_records(&path, format, &classes) {
+                    error!("failed to save class memory report: {err:#}");
+                }
+            }
+        },
+        ReplayExecute::Cache { command } => match command {
+            CacheCommand::Verify {
+                chain,
+                samples,
+                format,
+                output,
+            } => {
+                let path = output::path(
+                    &output,
+                    &OutputVars {
+                        chain: Some(&chain),
+                        ext: Some(format.ext()),
+                        ..Default::default()
+                    },
+                )
+                .expect("invalid output path");
+
+                let chain = parse_network(&chain);
+                let disk_cache = DiskCache::get(&chain)
+                    .expect("the rpc cache database is used by another process");
+                let mismatches = verify_cache::verify_cache(disk_cache, &chain, samples)
+                    .expect("failed to verify cache");
+
+                if mismatches.is_empty() {
+                    info!("cached entries match the node");
+                } else {
+                    error!(
+                        mismatches = mismatches.len(),
+                        "cached entries differ from the node"
+                    );
+                }
+
+                if let Err(err) = format::write_records(&path, format, &mismatches) {
+                    error!("failed to save cache verification report: {err:#}");
+                }
+            }
+            CacheCommand::Prune {
+                chain,
+                block_start,
+                block_end,
+            } => {
+                match disk_cache::prune_json_caches(&parse_network(&chain), block_start..=block_end)
+                {
+                    Ok(pruned) => info!(pruned, "pruned json caches"),
+                    Err(err) => error!("failed to prune json caches: {err:#}"),
+                }
+            }
+        },
+        ReplayExecute::Repro { command } => match command {
+            ReproCommand::Export {
+                tx_hash,
+                chain,
+                block_number,
+                flags,
+                output,
+            } => {
+                let path = output::path(
+                    &output,
+                    &OutputVars {
+                        chain: Some(&chain),
+                        tx: Some(&tx_hash),
+                        ..Default::default()
+                    },
+                )
+                .expect("invalid output path");
+                // Evicted entries would be missing from the bundle
+                rpc_state_reader::cache::set_memory_limit(None);
+
+                let mut state = build_cached_state(&chain, block_number - 1);
+                let reader = build_reader(&chain, block_number);
+                let bundle_flags = repro::Flags::from(flags.flags());
+                execute_in_block(
+                    &mut state,
+                    &reader,
+                    &chain,
+                    block_number,
+                    &tx_hash,
+                    &flags_execution(flags),
+                )
+                .expect("failed to execute transaction");
+
+                let bundle = repro::Bundle {
+                    bundle_version: repro::BUNDLE_VERSION,
+                    replay_version: repro::replay_version(),
+                    chain,
+                    block_number,
+                    tx_hash,
+                    flags: bundle_flags,
+                    block: reader.snapshot(),
+                    state: state.state.snapshot(),
+                };
+                match bundle.write(&path) {
+                    Ok(()) => info!(path = %path.display(), "exported reproduction bundle"),
+                    Err(err) => error!("failed to export reproduction bundle: {err:#}"),
+                }
+            }
+            ReproCommand::Run { bundle } => {
+                let bundle = repro::Bundle::read(&bundle).expect("failed to read bundle");
+                if bundle.replay_version != repro::replay_version() {
+                    warn!(
+                        exported_with = bundle.replay_version,
+                        "bundle was exported with another version of the replay"
+                    );
+                }
+                rpc_state_reader::cache::set_offline(true);
+
+                let chain = match chains::parse_chain(&bundle.chain) {
+                    Ok(chain) => chain,
+                    Err(err) => {
+                        error!("invalid chain of the bundle: {err:#}");
+                        std::process::exit(1);
+                    }
+                };
+                let mut state = CachedState::new(RpcCachedStateReader::from_snapshot(
+                    RpcStateReader::without_node(
+                        chain.clone(),
+                        BlockNumber(bundle.block_number - 1),
+                    ),
+                    bundle.state,
+                ));
+                let reader = RpcCachedStateReader::from_snapshot(
+                    RpcStateReader::without_node(chain, BlockNumber(bundle.block_number)),
+                    bundle.block,
+                );
+                execute_in_block(
+                    &mut state,
+                    &reader,
+                    &bundle.chain,
+                    bundle.block_number,
+                    &bundle.tx_hash,
+                    &flags_execution(bundle.flags.into()),
+                )
+                .expect("failed to execute bundle");
+            }
+        },
+        ReplayExecute::Scenario {
+            block_start,
+            block_end,
+            chain,
+            scenario,
+            fee_threshold,
+            format,
+            output,
+        } => {
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let scenario = scenario::load_scenario(&scenario).expect("failed to load scenario");
+
+            let mut transactions = Vec::new();
+            for block_number in block_start..=block_end {
+                let _block_span = info_span!("block", number = block_number).entered();
+
+                let reader = build_reader(&chain, block_number);
+                capabilities::check(&reader);
+                let mut state = build_cached_state(&chain, block_number - 1);
+
+                match scenario::execute_block(&reader, &scenario, &mut state) {
+                    Ok(block_transactions) => transactions.extend(block_transactions),
+                    Err(err) => error!("failed to execute block under scenario: {err:#}"),
+                }
+            }
+
+            let summary = scenario::summarize(&transactions, fee_threshold);
+            info!(
+                transactions = summary.transactions,
+                newly_reverted = summary.newly_reverted,
+                newly_failed = summary.newly_failed,
+                drastic_fee_changes = summary.drastic_fee_changes,
+                "scenario replay finished"
+            );
+
+            let report = scenario::ScenarioReport {
+                summary,
+                transactions,
+            };
+            if let Err(err) = format::write(&path, format, &report, &report.transactions) {
+                error!("failed to save scenario report: {err:#}");
+            }
+        }
+        ReplayExecute::Revenue {
+            block_start,
+            block_end,
+            chain,
+            vm_fallback,
+            format,
+            output,
+        } => {
+            info!(
+                "computing revenue of block range: {} - {}",
+                block_start, block_end
+            );
+
+            // Fees must be charged so that the fee transfers are executed
+            let execution = ExecutionArgs {
+                flags: FlagsArgs {
+                    charge_fee: true,
+                    ..Default::default()
+                },
+                vm_fallback,
+                parallel: false,
+                export_trace: None,
+                state_access_config: None,
+            };
+            let index = IndexArgs::default();
+
+            let mut report = Vec::new();
+
+            for block_number in block_start..=block_end {
+                let executions = execute_block(
+                    &chain,
+                    block_number,
+                    &execution,
+                    &index,
+                    &BlockArgs::default(),
+                    None,
+                    None,
+                );
+
+                let reader = build_reader(&chain, block_number);
+                let block_context =
+                    fetch_block_context(&reader).expect("failed to fetch block context");
+
+                let block_revenue =
+                    revenue::compute_block_revenue(&reader, &block_context, &executions);
+
+                if !block_revenue.matches {
+                    error!(block = block_number, "sequencer revenue diverged");
+                }
+
+                report.push(block_revenue);
+            }
+
+            let mismatches = report.iter().filter(|block| !block.matches).count();
+            info!(blocks = report.len(), mismatches, "saving revenue report");
+
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            if let Err(err) = format::write_records(&path, format, &report) {
+                error!("failed to save revenue report: {err:#}");
+            }
+        }
+        ReplayExecute::Reorder {
+            chain,
+            block_number,
+            order,
+            shuffles,
+            seed,
+            flags,
+            format,
+            output,
+        } => {
+            let reader = build_reader(&chain, block_number);
+            let block_context =
+                fetch_block_context(&reader).expect("failed to fetch block context");
+
+            let flags = flags.flags();
+            let transactions = reader
+                .get_block_with_tx_hashes()
+                .expect("Unable to fetch the transaction hashes.")
+                .transactions
+                .into_iter()
+                .map(|hash| {
+                    fetch_blockifier_transaction(&reader, flags.clone(), hash)
+                        .expect("failed to fetch transaction")
+                })
+                .collect::<Vec<_>>();
+
+            let mut orderings = Vec::new();
+            if let Some(order) = order {
+                reorder::validate_order(&order, transactions.len()).expect("invalid order");
+                orderings.push(order);
+            }
+            for i in 0..shuffles {
+                let seed = seed.wrapping_add(i as u64);
+                orderings.push(reorder::shuffled_order(transactions.len(), seed));
+            }
 
-#[cfg(feature = "benchmark")]
-mod benchmark;
-#[cfg(feature = "state_dump")]
-mod state_dump;
+            info!(
+                block_number,
+                transactions = transactions.len(),
+                orderings = orderings.len(),
+                "replaying block with custom orders"
+            );
 
-#[derive(Debug, Parser)]
-#[command(about = "Replay is a tool for executing Starknet transactions.", long_about = None)]
-struct ReplayCLI {
-    #[command(subcommand)]
-    subcommand: ReplayExecute,
-}
+            let mut state = build_cached_state(&chain, block_number - 1);
+            let results =
+                reorder::replay_orderings(&mut state, &block_context, &transactions, orderings)
+                    .expect("failed to replay orderings");
 
-#[derive(Subcommand, Debug)]
-enum ReplayExecute {
-    #[clap(about = "Execute a single transaction given a transaction hash.")]
-    Tx {
-        tx_hash: String,
-        chain: String,
-        block_number: u64,
-        #[arg(short, long)]
-        charge_fee: bool,
-    },
-    #[clap(about = "Execute all the transactions in a given block.")]
-    Block {
-        chain: String,
-        block_number: u64,
-        #[arg(short, long)]
-        charge_fee: bool,
-    },
-    #[clap(about = "Execute all the transactions in a given range of blocks.")]
-    BlockRange {
-        block_start: u64,
-        block_end: u64,
-        chain: String,
-        #[arg(short, long)]
-        charge_fee: bool,
-    },
-    #[cfg(feature = "benchmark")]
-    #[clap(
-        about = "Measures the time it takes to run all transactions in a given range of blocks.
-Caches all rpc data before the benchmark runs to provide accurate results"
-    )]
-    BenchBlockRange {
-        block_start: u64,
-        block_end: u64,
-        chain: String,
-        number_of_runs: usize,
-        #[arg(short, long, default_value=PathBuf::from("data").into_os_string())]
-        output: PathBuf,
-    },
-    #[cfg(feature = "benchmark")]
-    #[clap(about = "Measures the time it takes to run a single transaction.
-        Caches all rpc data before the benchmark runs to provide accurate results.
-        It only works if the transaction doesn't depend on another transaction in the same block")]
-    BenchTx {
-        tx: String,
-        chain: String,
-        block: u64,
-        number_of_runs: usize,
-        #[arg(short, long, default_value=PathBuf::from("data").into_os_string())]
-        output: PathBuf,
-    },
-}
+            let state_changing = results.iter().filter(|r| r.changes_state()).count();
+            info!(state_changing, "replayed block with custom orders");
 
-fn main() {
-    dotenvy::dotenv().ok();
-    set_global_subscriber();
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_number),
+                    block_end: Some(block_number),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
 
-    let cli = ReplayCLI::parse();
-    match cli.subcommand {
-        ReplayExecute::Tx {
-            tx_hash,
+            if let Err(err) = format::write_records(&path, format, &results) {
+                error!("failed to save reorder report: {err:#}");
+            }
+        }
+        ReplayExecute::CallRaw {
+            account,
+            contract_address,
+            entry_point,
             chain,
             block_number,
-            charge_fee,
+            calldata,
+            flags,
         } => {
+            let account = ContractAddress::try_from(felt!(account.as_str()))
+                .expect("invalid account address");
+            let call = shrink::Call {
+                to: felt!(contract_address.as_str()),
+                selector: call_raw::parse_selector(&entry_point).0,
+                calldata: calldata.iter().map(|felt| felt!(felt.as_str())).collect(),
+            };
+
+            let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let block_context =
+                fetch_block_context(&reader).expect("failed to fetch block context");
             let mut state = build_cached_state(&chain, block_number - 1);
+
+            let result =
+                call_raw::execute_call(call, account, flags.flags(), &mut state, &block_context)
+                    .expect("failed to execute call");
+            if result.failed {
+                warn!("call failed");
+            }
+
+            serde_json::to_writer_pretty(std::io::stdout(), &result).unwrap();
+        }
+        ReplayExecute::Stress {
+            account,
+            chain,
+            block_number,
+            target,
+            entry_point,
+            flags,
+            format,
+            output,
+        } => {
+            let account = ContractAddress::try_from(felt!(account.as_str()))
+                .expect("invalid account address");
+            let target =
+                ContractAddress::try_from(felt!(target.as_str())).expect("invalid target address");
+
+            let corpus = stress::generate_corpus(target, selector_from_name(&entry_point));
+            info!(cases = corpus.len(), "executing stress corpus");
+
             let reader = build_reader(&chain, block_number);
+            capabilities::check(&reader);
+            let block_context =
+                fetch_block_context(&reader).expect("failed to fetch block context");
+            let mut state = build_cached_state(&chain, block_number - 1);
 
-            show_execution_data(
-                &mut state,
-                &reader,
-                tx_hash,
-                &chain,
-                block_number,
-                charge_fee,
+            let results =
+                stress::run_corpus(corpus, account, flags.flags(), &mut state, &block_context)
+                    .expect("failed to execute stress corpus");
+
+            let diverging = results
+                .iter()
+                .filter(|result| !result.divergences.is_empty())
+                .count();
+            if diverging == 0 {
+                info!("native and vm executions match");
+            } else {
+                error!(diverging, "native and vm executions diverged");
+            }
+
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_number),
+                    block_end: Some(block_number),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            if let Err(err) = format::write_records(&path, format, &results) {
+                error!("failed to save stress report: {err:#}");
+            }
+        }
+        ReplayExecute::Precompile {
+            block_start,
+            block_end,
+            chain,
+            format,
+            output,
+        } => {
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let classes =
+                precompile::collect_classes(&parse_network(&chain), block_start, block_end)
+                    .expect("failed to collect executed classes");
+            info!(classes = classes.len(), "compiling executed classes");
+
+            let compilations = precompile::compile_classes(classes);
+
+            let failed = compilations
+                .iter()
+                .filter(|compilation| !compilation.errors.is_empty())
+                .count();
+            let casm_time_ms: u128 = compilations.iter().filter_map(|c| c.casm_time_ms).sum();
+            let native_time_ms: u128 = compilations.iter().filter_map(|c| c.native_time_ms).sum();
+            info!(
+                classes = compilations.len(),
+                failed, casm_time_ms, native_time_ms, "finished compiling classes"
             );
+
+            if let Err(err) = format::write_records(&path, format, &compilations) {
+                error!("failed to save precompilation report: {err:#}");
+            }
         }
-        ReplayExecute::Block {
-            block_number,
+        ReplayExecute::BenchCompilation {
+            block_start,
+            block_end,
             chain,
-            charge_fee,
+            opt_levels,
+            format,
+            output,
         } => {
-            let _block_span = info_span!("block", number = block_number).entered();
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
 
-            let mut state = build_cached_state(&chain, block_number - 1);
-            let reader = build_reader(&chain, block_number);
+            let classes =
+                precompile::collect_classes(&parse_network(&chain), block_start, block_end)
+                    .expect("failed to collect executed classes");
+            info!(classes = classes.len(), "compiling executed classes");
 
-            let transaction_hashes = reader
-                .get_block_with_tx_hashes()
-                .expect("Unable to fetch the transaction hashes.")
-                .transactions;
-            for tx_hash in transaction_hashes {
-                show_execution_data(
-                    &mut state,
-                    &reader,
-                    tx_hash.0.to_hex_string(),
-                    &chain,
-                    block_number,
-                    charge_fee,
+            let matrix = precompile::compile_matrix(classes, &opt_levels);
+
+            for target in &matrix.targets {
+                info!(
+                    classes = target.classes,
+                    failed = target.failed,
+                    time_ms = target.time_ms,
+                    size_bytes = target.size_bytes,
+                    "compiled classes to {}",
+                    target.target
                 );
             }
+
+            if let Err(err) = format::write(&path, format, &matrix, &matrix.compilations) {
+                error!("failed to save compilation benchmark: {err:#}");
+            }
         }
-        ReplayExecute::BlockRange {
+        ReplayExecute::StorageHistory {
+            contract_address,
+            key,
+            chain,
             block_start,
             block_end,
+            format,
+            output,
+        } => {
+            let contract_address = ContractAddress::try_from(felt!(contract_address.as_str()))
+                .expect("invalid contract address");
+            let key = StorageKey::try_from(felt!(key.as_str())).expect("invalid storage key");
+
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let history = rpc_state_reader::history::get_storage_history(
+                &parse_network(&chain),
+                contract_address,
+                key,
+                block_start,
+                block_end,
+            )
+            .expect("failed to query storage history");
+
+            info!(
+                initial_value = history.initial_value.to_hex_string(),
+                changes = history.changes.len(),
+                "queried storage history"
+            );
+
+            if let Err(err) = format::write(&path, format, &history, &history.changes) {
+                error!("failed to save storage history: {err:#}");
+            }
+        }
+        ReplayExecute::History {
+            contract_address,
             chain,
-            charge_fee,
+            block_start,
+            block_end,
+            execution,
+            format,
+            output,
         } => {
-            info!("executing block range: {} - {}", block_start, block_end);
+            let contract_address = ContractAddress::try_from(felt!(contract_address.as_str()))
+                .expect("invalid contract address");
+
+            info!(
+                "replaying contract history: {} - {}",
+                block_start, block_end
+            );
+
+            let mut reports = Vec::new();
 
             for block_number in block_start..=block_end {
                 let _block_span = info_span!("block", number = block_number).entered();
 
-                let mut state = build_cached_state(&chain, block_number - 1);
                 let reader = build_reader(&chain, block_number);
 
+                let invocations = history::find_invocations(&reader, contract_address)
+                    .expect("Unable to fetch the transaction hashes.");
+                let Some(last_invocation) = invocations.last() else {
+                    continue;
+                };
+
+                let mut state = build_cached_state(&chain, block_number - 1);
+
                 let transaction_hashes = reader
                     .get_block_with_tx_hashes()
                     .expect("Unable to fetch the transaction hashes.")
                     .transactions;
+
+                // Previous transactions of the block must be executed too,
+                // as the invocations may depend on them
                 for tx_hash in transaction_hashes {
-                    show_execution_data(
+                    let execution_info = show_execution_data(
                         &mut state,
                         &reader,
                         tx_hash.0.to_hex_string(),
                         &chain,
                         block_number,
-                        charge_fee,
+                        &execution,
                     );
+
+                    if invocations.contains(&tx_hash) {
+                        reports.push(history::InvocationReport::new(
+                            block_number,
+                            tx_hash,
+                            contract_address,
+                            execution_info.as_ref(),
+                        ));
+                    }
+
+                    if &tx_hash == last_invocation {
+                        break;
+                    }
                 }
             }
+
+            info!(invocations = reports.len(), "saving contract history");
+
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            if let Err(err) = format::write_records(&path, format, &reports) {
+                error!("failed to save contract history: {err:#}");
+            }
         }
         #[cfg(feature = "benchmark")]
         ReplayExecute::BenchBlockRange {
@@ -177,19 +2665,36 @@ fn main() {
             block_end,
             chain,
             number_of_runs,
+            first_call,
+            budgets,
+            format,
             output,
+            entrypoints,
         } => {
+            let output_vars = |ext| OutputVars {
+                chain: Some(&chain),
+                block_start: Some(block_start),
+                block_end: Some(block_end),
+                ext: Some(ext),
+                ..Default::default()
+            };
+            let output =
+                output::path(&output, &output_vars(format.ext())).expect("invalid output path");
+            let entrypoints = entrypoints.map(|template| {
+                output::path(&template, &output_vars(format.ext())).expect("invalid output path")
+            });
+
             let block_start = BlockNumber(block_start);
             let block_end = BlockNumber(block_end);
             let chain = parse_network(&chain);
 
-            let mut block_range_data = {
+            let (mut block_range_data, first_call) = {
                 let _caching_span = info_span!("caching block range").entered();
 
                 info!("fetching block range data");
                 let mut block_range_data = fetch_block_range_data(block_start, block_end, chain);
 
-                // We must execute the block range once first to ensure that all data required by blockifier is cached
+                // We must execute the block range once first to ensure that all data required by blockifier is cached.
                 info!("filling up execution cache");
                 execute_block_range(&mut block_range_data);
 
@@ -199,7 +2704,14 @@ fn main() {
                     cached_state.state.disable();
                 }
 
-                block_range_data
+                // The first execution after filling the caches calls every cached
+                // executor once, so it measures their first call overhead
+                let first_call = first_call.then(|| {
+                    info!("measuring first calls");
+                    measure_first_call(&mut block_range_data)
+                });
+
+                (block_range_data, first_call)
             };
 
             // We pause the main thread to differentiate
@@ -211,28 +2723,69 @@ fn main() {
                 let _benchmark_span = info_span!("benchmarking block range").entered();
 
                 let mut executions = Vec::new();
+                let mut tx_times = Vec::new();
+                let mut runs = Vec::new();
 
                 info!("executing block range");
+                let block_count = block_range_data.len() as u64;
+                let mut progress = Progress::new(block_count * number_of_runs as u64);
                 let before_execution = Instant::now();
                 for _ in 0..number_of_runs {
-                    executions.push(execute_block_range(&mut block_range_data));
+                    let before_run = Instant::now();
+                    let execution = execute_block_range(&mut block_range_data);
+                    runs.push(RunData::new(
+                        before_run.elapsed(),
+                        execution.state_read_time,
+                        execution.memory,
+                    ));
+                    progress.advance(block_count, execution.executions.len());
+                    tx_times.extend(execution.tx_hashes.into_iter().zip(execution.tx_times));
+                    executions.push(execution.executions);
                 }
                 let execution_time = before_execution.elapsed();
+                progress.finish();
 
                 info!("saving execution info");
 
                 let executions = executions.into_iter().flatten().collect::<Vec<_>>();
                 let class_executions = aggregate_executions(executions);
 
-                let average_time = execution_time.div_f32(number_of_runs as f32);
-
-                let benchmarking_data = BenchmarkingData {
-                    average_time,
+                let benchmarking_data = BenchmarkingData::new(
+                    runs,
                     class_executions,
-                };
+                    aggregate_transactions(tx_times),
+                    first_call,
+                );
+                let average_time = benchmarking_data.average_time;
+
+                if let Err(err) = format::write(
+                    &output,
+                    format,
+                    &benchmarking_data,
+                    &benchmarking_data.class_executions,
+                ) {
+                    error!("failed to save benchmark data: {err:#}");
+                }
+
+                if let Some(path) = &entrypoints {
+                    if let Err(err) =
+                        format::write_records(path, format, &benchmarking_data.entrypoints)
+                    {
+                        error!("failed to save entry points: {err:#}");
+                    }
+                }
 
-                let file = std::fs::File::create(output).unwrap();
-                serde_json::to_writer_pretty(file, &benchmarking_data).unwrap();
+                for entrypoint in benchmarking_data.entrypoints.iter().take(HOT_ENTRYPOINTS) {
+                    info!(
+                        class_hash = entrypoint.class_hash.to_hex_string(),
+                        selector = entrypoint.selector.0.to_hex_string(),
+                        calls = entrypoint.calls,
+                        total_time = entrypoint.total_time.as_secs_f64(),
+                        mean_time = entrypoint.mean_time.as_secs_f64(),
+                        share = entrypoint.share,
+                        "hot entry point"
+                    );
+                }
 
                 info!(
                     block_start = block_start.0,
@@ -240,8 +2793,49 @@ fn main() {
                     number_of_runs,
                     total_run_time = execution_time.as_secs_f64(),
                     average_run_time = average_time.as_secs_f64(),
+                    average_state_read_time =
+                        benchmarking_data.average_state_read_time.as_secs_f64(),
+                    average_allocations = benchmarking_data.average_allocations,
+                    peak_rss = benchmarking_data.peak_rss,
                     "benchmark finished",
                 );
+
+                if let Some(first_call) = &benchmarking_data.first_call {
+                    info!(
+                        first_call_time = first_call.run.execution_time.as_secs_f64(),
+                        steady_execution_time = (average_time
+                            - benchmarking_data.average_state_read_time)
+                            .as_secs_f64(),
+                        "first call compared to steady state"
+                    );
+                }
+
+                if let Err(err) = budgets.enforce(&benchmarking_data.class_executions) {
+                    error!("{err:#}");
+                    std::process::exit(1);
+                }
+
+                // As all data was cached before benchmarking, every state read
+                // should be an in-memory lookup
+                let mut cold_runs = 0;
+                for (run, data) in benchmarking_data.runs.iter().enumerate() {
+                    if !data.is_warm() {
+                        error!(
+                            run,
+                            total_time = data.total_time.as_secs_f64(),
+                            state_read_time = data.state_read_time.as_secs_f64(),
+                            "cold benchmark run"
+                        );
+                        cold_runs += 1;
+                    }
+                }
+                if cold_runs > 0 {
+                    error!(
+                        cold_runs,
+                        "benchmark runs spent a significant time reading state, results are not reliable"
+                    );
+                    std::process::exit(1);
+                }
             }
         }
         #[cfg(feature = "benchmark")]
@@ -250,23 +2844,236 @@ fn main() {
             block,
             chain,
             number_of_runs,
+            first_call,
+            budgets,
+            format,
+            output,
+        } => {
+            let output = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    tx: Some(&tx),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let chain = parse_network(&chain);
+            let block = BlockNumber(block);
+
+            let (mut block_range_data, first_call) = {
+                let _caching_span = info_span!("caching block range").entered();
+
+                info!("fetching transaction data");
+                let transaction_data = fetch_transaction_data(&tx, block, chain);
+
+                // We insert it into a vector so that we can reuse `execute_block_range`
+                let mut block_range_data = vec![transaction_data];
+
+                // We must execute the block range once first to ensure that all data required by blockifier is chached.
+                info!("filling up execution cache");
+                execute_block_range(&mut block_range_data);
+
+                // Benchmark run should make no api requests as all data is cached
+                // To ensure this, we disable the inner StateReader
+                for (cached_state, ..) in &mut block_range_data {
+                    cached_state.state.disable();
+                }
+
+                // The first execution after filling the caches calls every cached
+                // executor once, so it measures their first call overhead
+                let first_call = first_call.then(|| {
+                    info!("measuring first calls");
+                    measure_first_call(&mut block_range_data)
+                });
+
+                (block_range_data, first_call)
+            };
+
+            // We pause the main thread to differentiate
+            // caching from benchmarking from within a profiler
+            #[cfg(feature = "profiling")]
+            thread::sleep(Duration::from_secs(1));
+
+            {
+                let _benchmark_span = info_span!("benchmarking block range").entered();
+
+                let mut executions = Vec::new();
+                let mut tx_times = Vec::new();
+                let mut runs = Vec::new();
+
+                info!("executing block range");
+                let before_execution = Instant::now();
+                for _ in 0..number_of_runs {
+                    let before_run = Instant::now();
+                    let execution = execute_block_range(&mut block_range_data);
+                    runs.push(RunData::new(
+                        before_run.elapsed(),
+                        execution.state_read_time,
+                        execution.memory,
+                    ));
+                    tx_times.extend(execution.tx_hashes.into_iter().zip(execution.tx_times));
+                    executions.push(execution.executions);
+                }
+                let execution_time = before_execution.elapsed();
+
+                info!("saving execution info");
+
+                let executions = executions.into_iter().flatten().collect::<Vec<_>>();
+                let class_executions = aggregate_executions(executions);
+
+                let benchmarking_data = BenchmarkingData::new(
+                    runs,
+                    class_executions,
+                    aggregate_transactions(tx_times),
+                    first_call,
+                );
+                let average_time = benchmarking_data.average_time;
+
+                if let Err(err) = format::write(
+                    &output,
+                    format,
+                    &benchmarking_data,
+                    &benchmarking_data.class_executions,
+                ) {
+                    error!("failed to save benchmark data: {err:#}");
+                }
+
+                info!(
+                    tx = tx,
+                    block = block.0,
+                    number_of_runs,
+                    total_run_time = execution_time.as_secs_f64(),
+                    average_run_time = average_time.as_secs_f64(),
+                    average_state_read_time =
+                        benchmarking_data.average_state_read_time.as_secs_f64(),
+                    average_allocations = benchmarking_data.average_allocations,
+                    peak_rss = benchmarking_data.peak_rss,
+                    "benchmark finished",
+                );
+
+                if let Some(first_call) = &benchmarking_data.first_call {
+                    info!(
+                        first_call_time = first_call.run.execution_time.as_secs_f64(),
+                        steady_execution_time = (average_time
+                            - benchmarking_data.average_state_read_time)
+                            .as_secs_f64(),
+                        "first call compared to steady state"
+                    );
+                }
+
+                if let Err(err) = budgets.enforce(&benchmarking_data.class_executions) {
+                    error!("{err:#}");
+                    std::process::exit(1);
+                }
+
+                // As all data was cached before benchmarking, every state read
+                // should be an in-memory lookup
+                let mut cold_runs = 0;
+                for (run, data) in benchmarking_data.runs.iter().enumerate() {
+                    if !data.is_warm() {
+                        error!(
+                            run,
+                            total_time = data.total_time.as_secs_f64(),
+                            state_read_time = data.state_read_time.as_secs_f64(),
+                            "cold benchmark run"
+                        );
+                        cold_runs += 1;
+                    }
+                }
+                if cold_runs > 0 {
+                    error!(
+                        cold_runs,
+                        "benchmark runs spent a significant time reading state, results are not reliable"
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        #[cfg(feature = "benchmark")]
+        ReplayExecute::BenchCompare {
+            before,
+            after,
+            threshold,
+            format,
+            output,
+        } => {
+            let path = output::path(
+                &output,
+                &OutputVars {
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let before = bench_compare::load_benchmark(&before).expect("failed to load benchmark");
+            let after = bench_compare::load_benchmark(&after).expect("failed to load benchmark");
+            let comparison = bench_compare::compare(&before, &after, threshold);
+
+            info!(
+                speedup = comparison.speedup,
+                change = comparison.change.change,
+                change_low = comparison.change.low,
+                change_high = comparison.change.high,
+                verdict = ?comparison.verdict,
+                regressions = comparison.regressions,
+                improvements = comparison.improvements,
+                "benchmark comparison"
+            );
+            for transaction in comparison
+                .transactions
+                .iter()
+                .filter(|transaction| transaction.verdict == bench_compare::Verdict::Regression)
+            {
+                warn!(
+                    tx_hash = transaction.tx_hash.0.to_hex_string(),
+                    speedup = transaction.speedup,
+                    change = transaction.change.change,
+                    "transaction regressed"
+                );
+            }
+
+            if let Err(err) = format::write(&path, format, &comparison, &comparison.transactions) {
+                error!("failed to save benchmark comparison: {err:#}");
+            }
+        }
+        #[cfg(feature = "benchmark")]
+        ReplayExecute::BenchDual {
+            block_start,
+            block_end,
+            chain,
+            number_of_runs,
+            format,
             output,
         } => {
+            let output = output::path(
+                &output,
+                &OutputVars {
+                    chain: Some(&chain),
+                    block_start: Some(block_start),
+                    block_end: Some(block_end),
+                    ext: Some(format.ext()),
+                    ..Default::default()
+                },
+            )
+            .expect("invalid output path");
+
+            let block_start = BlockNumber(block_start);
+            let block_end = BlockNumber(block_end);
             let chain = parse_network(&chain);
-            let block = BlockNumber(block);
 
             let mut block_range_data = {
                 let _caching_span = info_span!("caching block range").entered();
 
-                info!("fetching transaction data");
-                let transaction_data = fetch_transaction_data(&tx, block, chain);
-
-                // We insert it into a vector so that we can reuse `execute_block_range`
-                let mut block_range_data = vec![transaction_data];
+                info!("fetching block range data");
+                let mut block_range_data = fetch_block_range_data(block_start, block_end, chain);
 
-                // We must execute the block range once first to ensure that all data required by blockifier is chached
+                // We must execute the block range once first to ensure that all data required by blockifier is cached
                 info!("filling up execution cache");
-                execute_block_range(&mut block_range_data);
+                execute_block_range_dual(&mut block_range_data);
 
                 // Benchmark run should make no api requests as all data is cached
                 // To ensure this, we disable the inner StateReader
@@ -288,46 +3095,138 @@ fn main() {
                 let mut executions = Vec::new();
 
                 info!("executing block range");
-                let before_execution = Instant::now();
                 for _ in 0..number_of_runs {
-                    executions.push(execute_block_range(&mut block_range_data));
+                    executions.push(execute_block_range_dual(&mut block_range_data));
                 }
-                let execution_time = before_execution.elapsed();
 
                 info!("saving execution info");
 
-                let executions = executions.into_iter().flatten().collect::<Vec<_>>();
-                let class_executions = aggregate_executions(executions);
-
-                let average_time = execution_time.div_f32(number_of_runs as f32);
+                let benchmarking_data = aggregate_dual_executions(executions);
 
-                let benchmarking_data = BenchmarkingData {
-                    average_time,
-                    class_executions,
-                };
-
-                let file = std::fs::File::create(output).unwrap();
-                serde_json::to_writer_pretty(file, &benchmarking_data).unwrap();
+                if let Err(err) = format::write(
+                    &output,
+                    format,
+                    &benchmarking_data,
+                    &benchmarking_data.transactions,
+                ) {
+                    error!("failed to save benchmark data: {err:#}");
+                }
 
                 info!(
-                    tx = tx,
-                    block = block.0,
+                    block_start = block_start.0,
+                    block_end = block_end.0,
                     number_of_runs,
-                    total_run_time = execution_time.as_secs_f64(),
-                    average_run_time = average_time.as_secs_f64(),
+                    native_average_run_time = benchmarking_data.native_average_time.as_secs_f64(),
+                    vm_average_run_time = benchmarking_data.vm_average_time.as_secs_f64(),
+                    speedup = benchmarking_data.speedup,
                     "benchmark finished",
                 );
             }
         }
+        #[cfg(feature = "state_dump")]
+        ReplayExecute::CompareCentral { blob, executor } => {
+            let block = central::import_central_blob(&blob).expect("failed to import central blob");
+
+            let dumps_root = output::resolve(&state_dumps_root(executor));
+            let comparisons = central::compare_block(&block, &dumps_root);
+
+            let count = |f: fn(&central::ComparisonStatus) -> bool| {
+                comparisons.iter().filter(|c| f(&c.status)).count()
+            };
+            info!(
+                block_number = block.block_number,
+                matching = count(|status| matches!(status, central::ComparisonStatus::Match)),
+                differing =
+                    count(|status| matches!(status, central::ComparisonStatus::Differ { .. })),
+                missing = count(|status| matches!(status, central::ComparisonStatus::Missing)),
+                "compared central blob"
+            );
+
+            serde_json::to_writer_pretty(std::io::stdout(), &comparisons).unwrap();
+        }
+        #[cfg(feature = "state_dump")]
+        ReplayExecute::StateDump { command } => match command {
+            StateDumpCommand::Diff { a, b } => {
+                let dump_a = dump_schema::read_dump(&a).expect("failed to load state dump");
+                let dump_b = dump_schema::read_dump(&b).expect("failed to load state dump");
+                let differences = dump_diff::diff_dumps(&dump_a, &dump_b);
+
+                info!(
+                    a = %a.display(),
+                    b = %b.display(),
+                    differences = differences.len(),
+                    "compared state dumps"
+                );
+
+                serde_json::to_writer_pretty(std::io::stdout(), &differences).unwrap();
+            }
+        },
+        #[cfg(feature = "serve")]
+        ReplayExecute::Serve { chain, address } => {
+            if let Err(err) = serve::serve(&address, parse_network(&chain)) {
+                error!("failed to run the server: {err:#}");
+            }
+        }
+        #[cfg(feature = "tui")]
+        ReplayExecute::Tui => {
+            let audit_log = output::resolve(&cli.audit_log);
+            let results_store = output::resolve(&cli.results_store);
+
+            if let Err(err) = tui::run(&audit_log, &results_store) {
+                error!("failed to run the interface: {err:#}");
+            }
+        }
+    }
+
+    let report = run_report::report(run_instant.elapsed());
+    if report.transactions() > 0 {
+        info!(
+            blocks = report.blocks,
+            succeeded = report.succeeded,
+            reverted = report.reverted,
+            failed = report.failed,
+            divergences = report.divergences,
+            fallbacks = report.fallbacks.len(),
+            "run finished"
+        );
+    }
+    if let Some(path) = &cli.run_report {
+        if let Err(err) = output::write_json(&output::resolve(path), &report) {
+            error!("failed to write run report: {err:#}");
+        }
+    }
+    if let Some(path) = &cli.entrypoint_coverage {
+        let coverage = entrypoint_coverage::report();
+        info!(classes = coverage.len(), "entry point coverage recorded");
+        if let Err(err) = output::write_json(&output::resolve(path), &coverage) {
+            error!("failed to write entry point coverage: {err:#}");
+        }
+    }
+
+    // Flushes the spans that are still being exported
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+
+    if !report.is_success() {
+        std::process::exit(1);
     }
 }
 
+/// Directory of the state dumps written by the executor
+#[cfg(feature = "state_dump")]
+fn state_dumps_root(executor: Executor) -> PathBuf {
+    PathBuf::from("state_dumps").join(executor.name())
+}
+
+/// Checks that the chain is defined, keeping its name for the output paths
+fn parse_chain_name(name: &str) -> Result<String, String> {
+    chains::parse_chain(name)
+        .map(|_| name.to_string())
+        .map_err(|err| format!("{err:#}"))
+}
+
 fn parse_network(network: &str) -> ChainId {
-    match network.to_lowercase().as_str() {
-        "mainnet" => ChainId::Mainnet,
-        "testnet" => ChainId::Sepolia,
-        _ => panic!("Invalid network name, it should be one of: mainnet, testnet"),
-    }
+    chains::parse_chain(network).expect("chains are validated when parsing the arguments")
 }
 
 fn build_cached_state(network: &str, block_number: u64) -> CachedState<RpcCachedStateReader> {
@@ -341,14 +3240,364 @@ fn build_reader(network: &str, block_number: u64) -> RpcCachedStateReader {
     RpcCachedStateReader::new(RpcStateReader::new(rpc_chain, block_number))
 }
 
+/// Hash of each executed transaction of a block, with its execution info
+/// unless it failed
+type BlockExecutions = Vec<(TransactionHash, Option<TransactionExecutionInfo>)>;
+
+/// Executes a block of a range. With `--continue-on-error`, a panic is
+/// recorded as a failure of the block instead of aborting the range.
+fn execute_range_block(
+    chain: &str,
+    block_number: u64,
+    execution: &ExecutionArgs,
+    index: &IndexArgs,
+    block: &BlockArgs,
+    results_store: Option<&mut ResultsStore>,
+) -> BlockExecutions {
+    let execute = || {
+        execute_block(
+            chain,
+            block_number,
+            execution,
+            index,
+            block,
+            None,
+            results_store,
+        )
+    };
+    if !block.continue_on_error {
+        return execute();
+    }
+
+    panic::catch_unwind(AssertUnwindSafe(execute)).unwrap_or_else(|payload| {
+        let error = precompile::panic_message(payload);
+        error!(block = block_number, "block panicked: {error}");
+        run_report::record_block_failure(block_number, error);
+        Vec::new()
+    })
+}
+
+#[cfg(feature = "results_store")]
+fn open_results_store(path: &Path) -> ResultsStore {
+    ResultsStore::open(&output::resolve(path)).expect("failed to open results store")
+}
+
+#[cfg(not(feature = "results_store"))]
+fn open_results_store(_path: &Path) -> ResultsStore {
+    error!("built without results store support, rebuild with `--features results_store`");
+    std::process::exit(1);
+}
+
+/// Stand-in for the results store when built without the `results_store`
+/// feature. It can't be opened, so nothing is ever indexed into it.
+#[cfg(not(feature = "results_store"))]
+enum ResultsStore {}
+
+#[cfg(not(feature = "results_store"))]
+impl ResultsStore {
+    fn index_events(
+        &mut self,
+        _block_number: u64,
+        _tx_hash: TransactionHash,
+        _execution_info: &TransactionExecutionInfo,
+    ) -> anyhow::Result<()> {
+        match *self {}
+    }
+
+    fn index_messages(
+        &mut self,
+        _block_number: u64,
+        _tx_hash: TransactionHash,
+        _execution_info: &TransactionExecutionInfo,
+        _rpc_count: Option<usize>,
+    ) -> anyhow::Result<()> {
+        match *self {}
+    }
+}
+
+/// Executes all the transactions in the given block, starting from the state of the previous block
+fn execute_block(
+    chain: &str,
+    block_number: u64,
+    execution: &ExecutionArgs,
+    index: &IndexArgs,
+    block: &BlockArgs,
+    injected: Option<&inject::InjectedTx>,
+    mut results_store: Option<&mut ResultsStore>,
+) -> BlockExecutions {
+    let _block_span = info_span!("block", number = block_number).entered();
+    run_report::record_block();
+
+    let mut replay = Replayer::new(parse_network(chain))
+        .open_block(BlockNumber(block_number))
+        .expect("failed to open block");
+    capabilities::check(&replay.reader);
+
+    let skipped_txs = block.skipped_txs();
+    replay.tx_hashes.retain(|tx_hash| {
+        let skipped = skipped_txs.contains(tx_hash);
+        if skipped {
+            warn!(hash = tx_hash.0.to_hex_string(), "skipping transaction");
+        }
+        !skipped
+    });
+
+    {
+        let _warmup_span = info_span!("warming up block").entered();
+        if let Err(err) = replay.prefetch() {
+            warn!(
+                "failed to prefetch block state: {:#}",
+                anyhow::Error::from(err)
+            );
+        }
+        if let Err(err) = replay.precompile() {
+            warn!(
+                "failed to precompile block classes: {:#}",
+                anyhow::Error::from(err)
+            );
+        }
+    }
+    let transaction_hashes = replay.tx_hashes.clone();
+
+    // Speculative executions wouldn't see the state changes of the injected transaction
+    if execution.parallel && injected.is_some() {
+        warn!("executing sequentially, as a transaction is injected");
+    }
+    let speculative_executions = if execution.parallel && injected.is_none() {
+        parallel::execute_speculatively(
+            &parse_network(chain),
+            BlockNumber(block_number),
+            &transaction_hashes,
+            &execution.flags(),
+        )
+    } else {
+        Vec::new()
+    };
+    let mut speculative_executions = speculative_executions.into_iter();
+
+    // State changes of the transactions committed from speculative executions
+    let mut written = StateMaps::default();
+    let mut sequential = !execution.parallel || injected.is_some();
+
+    let mut executions = Vec::new();
+    let mut tx_reports = Vec::new();
+    let transaction_count = transaction_hashes.len();
+    let execution_span = info_span!("executing transactions", count = transaction_count).entered();
+    for (position, tx_hash) in transaction_hashes.into_iter().enumerate() {
+        if let Some(injected) = injected.filter(|injected| injected.index == position) {
+            execute_injected(
+                &mut replay.state,
+                &replay.reader,
+                injected,
+                execution.flags(),
+            );
+        }
+
+        let (execution_info, cost) = match speculative_executions.next().flatten() {
+            Some(speculative_execution)
+                if !sequential && !speculative_execution.reads.conflicts_with(&written) =>
+            {
+                written.extend(&speculative_execution.writes);
+                commit_speculative_execution(
+                    &mut replay.state,
+                    &replay.reader,
+                    tx_hash,
+                    chain,
+                    block_number,
+                    speculative_execution,
+                    execution.export_trace.as_deref(),
+                )
+            }
+            _ => {
+                if !sequential {
+                    info!(
+                        hash = tx_hash.0.to_hex_string(),
+                        "speculative execution discarded, continuing sequentially"
+                    );
+                    sequential = true;
+                }
+
+                let mut execute =
+                    || execute_block_transaction(&mut replay, tx_hash, chain, execution);
+                if block.continue_on_error {
+                    // Blockifier executes on a transactional state, so a
+                    // panic leaves the block state untouched
+                    panic::catch_unwind(AssertUnwindSafe(execute)).unwrap_or_else(|payload| {
+                        let error = precompile::panic_message(payload);
+                        error!(
+                            hash = tx_hash.0.to_hex_string(),
+                            "transaction panicked: {error}"
+                        );
+                        run_report::record_outcome(
+                            block_number,
+                            &tx_hash.0.to_hex_string(),
+                            &audit::Outcome::Failed,
+                            Some(&error),
+                        );
+                        (None, Default::default())
+                    })
+                } else {
+                    execute()
+                }
+            }
+        };
+
+        if let (Some(results_store), Some(execution_info)) =
+            (results_store.as_deref_mut(), &execution_info)
+        {
+            if index.index_events {
+                results_store
+                    .index_events(block_number, tx_hash, execution_info)
+                    .inspect_err(|err| error!("failed to index events: {err}"))
+                    .ok();
+            }
+            if index.index_messages {
+                let rpc_count = replay
+                    .reader
+                    .get_transaction_receipt(&tx_hash)
+                    .ok()
+                    .map(|receipt| receipt.messages_sent.len());
+                results_store
+                    .index_messages(block_number, tx_hash, execution_info, rpc_count)
+                    .inspect_err(|err| error!("failed to index messages: {err}"))
+                    .ok();
+            }
+        }
+
+        if block.tx_report.is_some() {
+            tx_reports.push(tx_report::TxReport::new(
+                tx_hash,
+                cost,
+                execution_info.as_ref(),
+            ));
+        }
+
+        executions.push((tx_hash, execution_info));
+    }
+    if let Some(injected) = injected.filter(|injected| injected.index >= transaction_count) {
+        execute_injected(
+            &mut replay.state,
+            &replay.reader,
+            injected,
+            execution.flags(),
+        );
+    }
+    drop(execution_span);
+
+    if let Some(template) = &block.tx_report {
+        output::path(
+            template,
+            &OutputVars {
+                chain: Some(chain),
+                block_start: Some(block_number),
+                ..Default::default()
+            },
+        )
+        .and_then(|path| tx_report::write(&path, &tx_reports))
+        .inspect_err(|err| error!("failed to write transaction report: {err:#}"))
+        .ok();
+    }
+
+    if block.composition.is_some() || block.composition_dot.is_some() {
+        let composition = block_composition::compose(
+            block_number,
+            executions
+                .iter()
+                .filter_map(|(_, execution_info)| execution_info.as_ref()),
+        );
+        let vars = OutputVars {
+            chain: Some(chain),
+            block_start: Some(block_number),
+            ext: Some(block.composition_format.ext()),
+            ..Default::default()
+        };
+        if let Some(template) = &block.composition {
+            output::path(template, &vars)
+                .and_then(|path| {
+                    format::write(
+                        &path,
+                        block.composition_format,
+                        &composition,
+                        &composition.records(),
+                    )
+                })
+                .inspect_err(|err| error!("failed to write block composition: {err:#}"))
+                .ok();
+        }
+        if let Some(template) = &block.composition_dot {
+            output::path(template, &vars)
+                .and_then(|path| block_composition::write_dot(&path, &composition))
+                .inspect_err(|err| error!("failed to write block composition graph: {err:#}"))
+                .ok();
+        }
+    }
+
+    if let Some(sample_size) = block.spot_check {
+        match spot_check::spot_check_storage(
+            &mut replay.state,
+            &replay.reader,
+            block_number,
+            sample_size,
+        ) {
+            Ok(spot_check) => {
+                for mismatch in &spot_check.mismatches {
+                    error!(
+                        contract_address = mismatch.contract_address.0.key().to_hex_string(),
+                        key = mismatch.key.0.key().to_hex_string(),
+                        executed = mismatch.executed.to_hex_string(),
+                        onchain = mismatch.onchain.to_hex_string(),
+                        "storage value diverged from node"
+                    );
+                }
+                info!(
+                    written = spot_check.written,
+                    checked = spot_check.checked,
+                    mismatches = spot_check.mismatches.len(),
+                    "storage spot check finished"
+                );
+            }
+            Err(err) => error!("failed to spot check storage: {err}"),
+        }
+    }
+
+    if block.check_state_diff {
+        let mismatches = replay
+            .reader
+            .reader
+            .get_state_update()
+            .map_err(anyhow::Error::from)
+            .and_then(|state_update| {
+                state_update::compare_state_diff(&mut replay.state, &state_update.state_diff)
+            });
+        match mismatches {
+            Ok(mismatches) => {
+                for mismatch in &mismatches {
+                    error!(
+                        kind = mismatch.kind,
+                        contract_address = mismatch.contract_address.to_hex_string(),
+                        key = mismatch.key.map(|key| key.to_hex_string()),
+                        executed = mismatch.executed.to_hex_string(),
+                        onchain = mismatch.onchain.map(|value| value.to_hex_string()),
+                        "state diff diverged from node"
+                    );
+                }
+                info!(mismatches = mismatches.len(), "state diff check finished");
+            }
+            Err(err) => error!("failed to check state diff: {err}"),
+        }
+    }
+
+    executions
+}
+
 fn show_execution_data(
     state: &mut CachedState<impl StateReader>,
     reader: &impl StateReader,
     tx_hash_str: String,
     chain_str: &str,
     block_number: u64,
-    charge_fee: bool,
-) {
+    execution: &ExecutionArgs,
+) -> Option<TransactionExecutionInfo> {
     let _transaction_execution_span = info_span!(
         "transaction",
         hash = tx_hash_str,
@@ -357,39 +3606,279 @@ fn show_execution_data(
     )
     .entered();
     info!("starting execution");
+    capabilities::check(reader);
 
     let tx_hash = TransactionHash(felt!(tx_hash_str.as_str()));
-    let flags = ExecutionFlags {
-        only_query: false,
-        charge_fee,
-        validate: true,
-    };
 
-    let (tx, context) = match fetch_transaction_with_state(reader, &tx_hash, flags) {
+    let (tx, context) = match fetch_transaction_with_state(reader, &tx_hash, execution.flags()) {
         Ok(x) => x,
         Err(err) => {
-            return error!("failed to fetch transaction: {err}");
+            record_fetch_failure(
+                &tx_hash_str,
+                chain_str,
+                block_number,
+                format!("failed to fetch transaction: {err}"),
+            );
+            return None;
+        }
+    };
+
+    let tx_execution = execute_with_options(&tx, state, &context, execution);
+
+    report_execution(
+        state,
+        reader,
+        &tx_hash_str,
+        chain_str,
+        block_number,
+        tx_execution,
+        execution.export_trace.as_deref(),
+    )
+}
+
+/// Executes a transaction of the block being replayed, on its state
+fn execute_block_transaction(
+    replay: &mut BlockReplay,
+    tx_hash: TransactionHash,
+    chain_str: &str,
+    execution: &ExecutionArgs,
+) -> (Option<TransactionExecutionInfo>, tx_report::ExecutionCost) {
+    let tx_hash_str = tx_hash.0.to_hex_string();
+    let block_number = replay.block_number.0;
+
+    let _transaction_execution_span = info_span!(
+        "transaction",
+        hash = tx_hash_str,
+        chain = chain_str,
+        block = block_number
+    )
+    .entered();
+    info!("starting execution");
+
+    let tx = match replay.fetch_transaction(tx_hash, execution.flags()) {
+        Ok(tx) => tx,
+        Err(err) => {
+            let error = format!("{:#}", anyhow::Error::from(err));
+            record_fetch_failure(&tx_hash_str, chain_str, block_number, error);
+            return (None, Default::default());
         }
     };
 
-    let execution_info_result = tx.execute(state, &context);
+    let mut cost = tx_report::ExecutionCost::default();
+    let mut fallback = false;
+    let result = replay.execute_with(&tx, |tx, state, context| {
+        let tx_execution = execute_with_options(tx, state, context, execution);
+        cost = tx_execution.cost;
+        fallback = tx_execution.fallback;
+        tx_execution.result
+    });
+
+    let execution_info = report_execution(
+        &mut replay.state,
+        &replay.reader,
+        &tx_hash_str,
+        chain_str,
+        block_number,
+        TxExecution {
+            result,
+            cost,
+            fallback,
+        },
+        execution.export_trace.as_deref(),
+    );
+
+    (execution_info, cost)
+}
+
+/// Result of a transaction executed with the configured options
+struct TxExecution {
+    result: Result<TransactionExecutionInfo, TransactionExecutionError>,
+    /// Time and node fetches of the execution alone
+    cost: tx_report::ExecutionCost,
+    /// Whether the transaction was executed again with the Cairo VM
+    fallback: bool,
+}
+
+/// Executes the transaction as configured: instrumenting its state reads,
+/// falling back to the VM, or with the selected executor only.
+fn execute_with_options(
+    tx: &BlockiTransaction,
+    state: &mut CachedState<impl BlockifierStateReader>,
+    context: &BlockContext,
+    execution: &ExecutionArgs,
+) -> TxExecution {
+    let pre_execution_cache_misses = thread_cache_misses();
+    let pre_execution_instant = Instant::now();
+    let (result, fallback) = if let Some(config) = &execution.state_access_config {
+        (
+            state_access::execute_instrumented(tx, state, context, config),
+            false,
+        )
+    } else if execution.vm_fallback && reader::executor() != Executor::Vm {
+        fallback::execute_with_vm_fallback(tx, state, context)
+    } else {
+        (tx.execute(state, context), false)
+    };
+
+    TxExecution {
+        result,
+        cost: tx_report::ExecutionCost {
+            wall_time: pre_execution_instant.elapsed(),
+            cache_misses: thread_cache_misses() - pre_execution_cache_misses,
+        },
+        fallback,
+    }
+}
+
+/// Records a transaction that couldn't be fetched as failed
+fn record_fetch_failure(tx_hash_str: &str, chain_str: &str, block_number: u64, error: String) {
+    error!("{error}");
+    run_report::record_outcome(
+        block_number,
+        tx_hash_str,
+        &audit::Outcome::Failed,
+        Some(&error),
+    );
+    audit::record(
+        tx_hash_str,
+        chain_str,
+        block_number,
+        audit::Outcome::Failed,
+        Default::default(),
+        &audit::UsedExecutor::new(None, false),
+        Some(error),
+    );
+}
+
+/// Executes a transaction that isn't part of the block, without validation,
+/// keeping its state changes for the rest of the block
+fn execute_injected(
+    state: &mut CachedState<impl StateReader>,
+    reader: &impl StateReader,
+    injected: &inject::InjectedTx,
+    mut flags: ExecutionFlags,
+) {
+    let _transaction_span = info_span!(
+        "injected transaction",
+        hash = injected.hash.0.to_hex_string(),
+        index = injected.index
+    )
+    .entered();
+    flags.validate = false;
+
+    let result = fetch_block_context(reader).and_then(|context| {
+        let tx =
+            to_blockifier_transaction(reader, injected.transaction.clone(), injected.hash, flags)?;
+        Ok(tx.execute(state, &context)?)
+    });
+
+    match result {
+        Ok(execution_info) => match &execution_info.revert_error {
+            Some(revert_error) => warn!(
+                revert_error = revert_error.to_string(),
+                fee = execution_info.receipt.fee.0,
+                "injected transaction reverted"
+            ),
+            None => info!(
+                fee = execution_info.receipt.fee.0,
+                "injected transaction succeeded"
+            ),
+        },
+        Err(err) => error!("injected transaction failed: {err:#}"),
+    }
+}
+
+/// Applies the state changes of a speculative execution and reports it
+fn commit_speculative_execution(
+    state: &mut CachedState<impl StateReader>,
+    reader: &impl StateReader,
+    tx_hash: TransactionHash,
+    chain_str: &str,
+    block_number: u64,
+    speculative_execution: SpeculativeExecution,
+    export_trace: Option<&str>,
+) -> (Option<TransactionExecutionInfo>, tx_report::ExecutionCost) {
+    let tx_hash_str = tx_hash.0.to_hex_string();
+
+    let _transaction_execution_span = info_span!(
+        "transaction",
+        hash = tx_hash_str,
+        chain = chain_str,
+        block = block_number
+    )
+    .entered();
+    info!("committing speculative execution");
+
+    // The execution ran before, so its cost is taken from it
+    let cost = tx_report::ExecutionCost {
+        wall_time: speculative_execution.execution_time,
+        cache_misses: speculative_execution.cache_misses,
+    };
+
+    if let Err(err) = fallback::apply_state_maps(state, speculative_execution.writes, Vec::new()) {
+        error!("failed to apply speculative state changes: {err}");
+        return (None, cost);
+    }
+
+    let execution_info = report_execution(
+        state,
+        reader,
+        &tx_hash_str,
+        chain_str,
+        block_number,
+        TxExecution {
+            result: Ok(speculative_execution.execution_info),
+            cost,
+            fallback: false,
+        },
+        export_trace,
+    );
+
+    (execution_info, cost)
+}
+
+/// Records the execution in the audit log and the state dumps, comparing it
+/// with the transaction receipt.
+fn report_execution(
+    state: &mut CachedState<impl StateReader>,
+    reader: &impl StateReader,
+    tx_hash_str: &str,
+    chain_str: &str,
+    block_number: u64,
+    tx_execution: TxExecution,
+    export_trace: Option<&str>,
+) -> Option<TransactionExecutionInfo> {
+    let tx_hash = TransactionHash(felt!(tx_hash_str));
+    let execution_info_result = tx_execution.result;
+
+    let (outcome, error) = match &execution_info_result {
+        Ok(execution_info) if execution_info.is_reverted() => (audit::Outcome::Reverted, None),
+        Ok(_) => (audit::Outcome::Success, None),
+        Err(err) => (audit::Outcome::Failed, Some(err.to_string())),
+    };
+    run_report::record_outcome(block_number, tx_hash_str, &outcome, error.as_deref());
+    if let Ok(execution_info) = &execution_info_result {
+        entrypoint_coverage::record(reader, execution_info);
+    }
+    let used_executor =
+        audit::UsedExecutor::new(execution_info_result.as_ref().ok(), tx_execution.fallback);
+    audit::record(
+        tx_hash_str,
+        chain_str,
+        block_number,
+        outcome,
+        tx_execution.cost.wall_time,
+        &used_executor,
+        error,
+    );
 
+    // Dumped under the executor that produced the execution, as in the audit log
     #[cfg(feature = "state_dump")]
     {
-        use std::path::Path;
-
-        let root = if cfg!(feature = "only_cairo_vm") {
-            Path::new("state_dumps/vm")
-        } else if cfg!(feature = "with-sierra-emu") {
-            Path::new("state_dumps/emu")
-        } else {
-            Path::new("state_dumps/native")
-        };
+        let root = output::resolve(&state_dumps_root(used_executor.executor));
         let root = root.join(format!("block{}", block_number));
 
-        std::fs::create_dir_all(&root).ok();
-
-        let mut path = root.join(&tx_hash_str);
+        let mut path = root.join(tx_hash_str);
         path.set_extension("json");
 
         match &execution_info_result {
@@ -412,22 +3901,64 @@ fn show_execution_data(
         Ok(x) => x,
         Err(err) => {
             error!("execution failed: {}", err);
-            return;
+            return None;
         }
     };
 
+    if let Some(template) = export_trace {
+        write_trace(
+            reader,
+            template,
+            chain_str,
+            block_number,
+            tx_hash_str,
+            &execution_info,
+        )
+        .inspect_err(|err| error!("failed to export trace: {err:#}"))
+        .ok();
+    }
+
     match reader.get_transaction_receipt(&tx_hash) {
         Ok(rpc_receipt) => {
-            compare_execution(execution_info, rpc_receipt);
+            if !compare_execution(&execution_info, rpc_receipt) {
+                run_report::record_divergence();
+            }
         }
         Err(_) => {
             error!("failed to get transaction receipt, could not compare to rpc");
         }
     };
+
+    Some(execution_info)
+}
+
+/// Writes the trace of the execution in the format of `starknet_traceTransaction`
+fn write_trace(
+    reader: &impl StateReader,
+    template: &str,
+    chain_str: &str,
+    block_number: u64,
+    tx_hash_str: &str,
+    execution_info: &TransactionExecutionInfo,
+) -> anyhow::Result<()> {
+    // The receipt gives the RPC transaction type, which determines the shape of the trace
+    let receipt = reader.get_transaction_receipt(&TransactionHash(felt!(tx_hash_str)))?;
+    let trace = rpc_trace::transaction_trace(&receipt.tx_type, execution_info);
+
+    let path = output::path(
+        template,
+        &OutputVars {
+            chain: Some(chain_str),
+            block_start: Some(block_number),
+            tx: Some(tx_hash_str),
+            ..Default::default()
+        },
+    )?;
+    output::write_json(&path, &trace)
 }
 
 fn compare_execution(
-    execution: TransactionExecutionInfo,
+    execution: &TransactionExecutionInfo,
     rpc_receipt: RpcTransactionReceipt,
 ) -> bool {
     let reverted = execution.is_reverted();
@@ -466,7 +3997,7 @@ fn compare_execution(
 
     let events_msgs_match = events_match && msgs_match;
 
-    let state_changes = exec_rsc.state.state_changes_for_fee;
+    let state_changes = &exec_rsc.state.state_changes_for_fee;
     let state_changes_for_fee_str = format!(
         "{{ n_class_hash_updates: {}, n_compiled_class_hash_updates: {}, n_modified_contracts: {}, n_storage_updates: {} }}",
         state_changes.state_changes_count.n_class_hash_updates,
@@ -479,7 +4010,7 @@ fn compare_execution(
     let rpc_gas = rpc_receipt.actual_fee;
     debug!(?execution_gas, ?rpc_gas, "execution actual fee");
 
-    let revert_error = execution.revert_error.map(|err| match err {
+    let revert_error = execution.revert_error.as_ref().map(|err| match err {
         RevertError::Execution(e) => e.to_string(),
         RevertError::PostExecution(p) => p.to_string(),
     });
@@ -546,5 +4077,38 @@ fn set_global_subscriber() {
     #[cfg(feature = "structured_logging")]
     let subscriber = subscriber.json();
 
+    #[cfg(not(feature = "otlp"))]
     subscriber.finish().init();
+    #[cfg(feature = "otlp")]
+    {
+        use tracing_subscriber::layer::SubscriberExt;
+        subscriber.finish().with(otlp_layer()).init();
+    }
+}
+
+/// Exports the spans to the OTLP endpoint set in `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// (e.g. Jaeger or Tempo), over HTTP
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>() -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::{trace::TracerProvider as _, KeyValue};
+    use opentelemetry_sdk::{trace::TracerProvider, Resource};
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .expect("failed to build otlp exporter");
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            "starknet-replay",
+        )]))
+        .build();
+    let tracer = provider.tracer("replay");
+    opentelemetry::global::set_tracer_provider(provider);
+
+    tracing_opentelemetry::layer().with_tracer(tracer)
 }
@@ -0,0 +1,118 @@
+//! Memory usage of benchmark runs.
+//!
+//! Allocations are counted by a global allocator wrapping the system one,
+//! installed by the benchmark builds. The peak resident set size is read
+//! from `/proc/self/status`, so it's only available on Linux, where it's
+//! reset before each run.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::Serialize;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting every allocation
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(
+            new_size.saturating_sub(layout.size()) as u64,
+            Ordering::Relaxed,
+        );
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MemoryStats {
+    /// Allocations (and reallocations) performed
+    pub allocations: u64,
+    /// Bytes allocated, without subtracting the freed ones
+    pub allocated_bytes: u64,
+    /// Peak resident set size, in bytes
+    pub peak_rss: Option<u64>,
+}
+
+/// Measures the memory used from its creation until `finish` is called
+pub struct MemoryTracker {
+    allocations: u64,
+    allocated_bytes: u64,
+}
+
+impl MemoryTracker {
+    pub fn start() -> Self {
+        reset_peak_rss();
+        Self {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn finish(self) -> MemoryStats {
+        MemoryStats {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed) - self.allocations,
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed) - self.allocated_bytes,
+            peak_rss: peak_rss(),
+        }
+    }
+}
+
+/// Resets the peak resident set size to the current one
+fn reset_peak_rss() {
+    if cfg!(target_os = "linux") {
+        let _ = fs::write("/proc/self/clear_refs", "5");
+    }
+}
+
+fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    parse_peak_rss(&status)
+}
+
+/// Parses the `VmHWM` line of the process status, in kB
+fn parse_peak_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peak_rss() {
+        let status = "VmPeak:\t  123456 kB\nVmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\n";
+        assert_eq!(parse_peak_rss(status), Some(2048 * 1024));
+        assert_eq!(parse_peak_rss("VmRSS:\t1024 kB"), None);
+    }
+}
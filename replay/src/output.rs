@@ -0,0 +1,191 @@
+//! Resolution of the paths of every file written by the replay.
+//!
+//! Output paths are templates relative to the output directory, where
+//! placeholders like `{chain}` or `{block_start}` are replaced with the
+//! values of the current command.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+static OUT_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory where every output file is written to.
+pub fn init(out_dir: PathBuf) {
+    OUT_DIR.get_or_init(|| out_dir);
+}
+
+/// Values available to output templates
+#[derive(Default)]
+pub struct OutputVars<'a> {
+    pub chain: Option<&'a str>,
+    pub block_start: Option<u64>,
+    pub block_end: Option<u64>,
+    pub tx: Option<&'a str>,
+    pub ext: Option<&'a str>,
+}
+
+/// Renders the template and resolves it against the output directory.
+pub fn path(template: &str, vars: &OutputVars) -> anyhow::Result<PathBuf> {
+    Ok(resolve(Path::new(&render(template, vars, &today())?)))
+}
+
+/// Resolves the path against the output directory. Absolute paths are kept as is.
+pub fn resolve(path: &Path) -> PathBuf {
+    match OUT_DIR.get() {
+        Some(out_dir) => out_dir.join(path),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Writes a file atomically, creating its parent directories.
+///
+/// The content is written to a temporary file in the same directory, which
+/// is then renamed to the given path, so that an interrupted run never
+/// leaves a truncated file behind.
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create directory {}", parent.display()))?;
+
+    let Some(file_name) = path.file_name() else {
+        bail!("invalid output path: {}", path.display());
+    };
+    let mut tmp_file_name = file_name.to_os_string();
+    tmp_file_name.push(".tmp");
+    let tmp_path = parent.join(tmp_file_name);
+
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    let result = write(&mut writer).and_then(|_| {
+        writer.flush()?;
+        Ok(())
+    });
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err.context(format!("failed to write {}", path.display())));
+    }
+
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to move output file into {}", path.display()))
+}
+
+/// Serializes the value as pretty JSON and writes it atomically to the given path.
+pub fn write_json(path: &Path, value: &impl Serialize) -> anyhow::Result<()> {
+    write_atomic(path, |writer| {
+        Ok(serde_json::to_writer_pretty(writer, value)?)
+    })
+}
+
+fn render(template: &str, vars: &OutputVars, date: &str) -> anyhow::Result<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+
+        let Some(end) = rest[start..].find('}') else {
+            bail!("unclosed placeholder in output template: {template}");
+        };
+        let name = &rest[start + 1..start + end];
+
+        let value = match name {
+            "chain" => vars.chain.map(str::to_string),
+            "block_start" => vars.block_start.map(|block| block.to_string()),
+            "block_end" => vars.block_end.map(|block| block.to_string()),
+            "tx" => vars.tx.map(str::to_string),
+            "ext" => vars.ext.map(str::to_string),
+            "date" => Some(date.to_string()),
+            _ => bail!("unknown placeholder in output template: {{{name}}}"),
+        };
+        let Some(value) = value else {
+            bail!("placeholder {{{name}}} is not available for this command");
+        };
+
+        rendered.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Returns the current UTC date, formatted as `YYYY-MM-DD`
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Converts days since the unix epoch into a (year, month, day) date.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = OutputVars {
+            chain: Some("mainnet"),
+            block_start: Some(90000),
+            block_end: Some(90002),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render(
+                "{chain}-{block_start}-{block_end}-{date}.json",
+                &vars,
+                "2025-01-31"
+            )
+            .unwrap(),
+            "mainnet-90000-90002-2025-01-31.json"
+        );
+        assert_eq!(render("data", &vars, "").unwrap(), "data");
+        assert!(render("{tx}.json", &vars, "").is_err());
+        assert!(render("{unknown}.json", &vars, "").is_err());
+        assert!(render("{chain.json", &vars, "").is_err());
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20119), (2025, 1, 31));
+    }
+}
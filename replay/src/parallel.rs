@@ -0,0 +1,223 @@
+//! Speculative parallel execution of the transactions of a block.
+//!
+//! Every transaction is executed in parallel on top of the state of the
+//! previous block, recording the keys it reads. Then, in block order, each
+//! execution is only valid if none of its reads were written by a previous
+//! transaction of the block. After the first conflict, the rest of the block
+//! must be executed sequentially.
+
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    num::NonZeroUsize,
+    thread,
+    time::{Duration, Instant},
+};
+
+use blockifier::{
+    execution::contract_class::RunnableCompiledClass,
+    state::{
+        cached_state::{CachedState, StateMaps, StorageEntry},
+        state_api::{StateReader as BlockifierStateReader, StateResult},
+    },
+    transaction::{
+        account_transaction::ExecutionFlags, objects::TransactionExecutionInfo,
+        transactions::ExecutableTransaction,
+    },
+};
+use rpc_state_reader::{
+    cache::{thread_cache_misses, RpcCachedStateReader},
+    execution::fetch_transaction_with_state,
+    reader::RpcStateReader,
+};
+use starknet_api::{
+    block::BlockNumber,
+    core::{ChainId, ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    hash::StarkHash,
+    state::StorageKey,
+    transaction::TransactionHash,
+};
+use tracing::debug;
+
+/// Keys read by a transaction from the state of the previous block
+#[derive(Default)]
+pub struct ReadSet {
+    storage: HashSet<StorageEntry>,
+    nonces: HashSet<ContractAddress>,
+    class_hashes: HashSet<ContractAddress>,
+    compiled_classes: HashSet<ClassHash>,
+    compiled_class_hashes: HashSet<ClassHash>,
+}
+
+impl ReadSet {
+    /// Returns whether any of the read keys was written
+    pub fn conflicts_with(&self, writes: &StateMaps) -> bool {
+        self.storage
+            .iter()
+            .any(|entry| writes.storage.contains_key(entry))
+            || self
+                .nonces
+                .iter()
+                .any(|address| writes.nonces.contains_key(address))
+            || self
+                .class_hashes
+                .iter()
+                .any(|address| writes.class_hashes.contains_key(address))
+            || self
+                .compiled_classes
+                .iter()
+                .any(|class_hash| writes.declared_contracts.contains_key(class_hash))
+            || self
+                .compiled_class_hashes
+                .iter()
+                .any(|class_hash| writes.compiled_class_hashes.contains_key(class_hash))
+    }
+}
+
+pub struct SpeculativeExecution {
+    pub execution_info: TransactionExecutionInfo,
+    pub execution_time: Duration,
+    /// Reads fetched from the node while executing the transaction
+    pub cache_misses: u64,
+    pub reads: ReadSet,
+    pub writes: StateMaps,
+}
+
+/// Executes every transaction in parallel, on top of the state of the previous block.
+///
+/// Returns `None` for the transactions that can't be committed from a
+/// speculative execution: the ones that failed and the ones that declared a
+/// class, as declared classes only live in the speculative state.
+pub fn execute_speculatively(
+    chain: &ChainId,
+    block_number: BlockNumber,
+    tx_hashes: &[TransactionHash],
+    flags: &ExecutionFlags,
+) -> Vec<Option<SpeculativeExecution>> {
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(tx_hashes.len())
+        .max(1);
+    let chunk_size = tx_hashes.len().div_ceil(workers).max(1);
+
+    thread::scope(|scope| {
+        let handles = tx_hashes
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    // Readers are not thread safe, so each worker builds its own
+                    let reader =
+                        RpcCachedStateReader::new(RpcStateReader::new(chain.clone(), block_number));
+                    let previous_block_number = block_number.prev().unwrap();
+                    let state = CachedState::new(RpcCachedStateReader::new(RpcStateReader::new(
+                        chain.clone(),
+                        previous_block_number,
+                    )));
+
+                    chunk
+                        .iter()
+                        .map(|tx_hash| execute_transaction(&reader, &state, tx_hash, flags))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("speculative execution panicked"))
+            .collect()
+    })
+}
+
+fn execute_transaction(
+    reader: &RpcCachedStateReader,
+    state: &CachedState<RpcCachedStateReader>,
+    tx_hash: &TransactionHash,
+    flags: &ExecutionFlags,
+) -> Option<SpeculativeExecution> {
+    let (tx, context) = fetch_transaction_with_state(reader, tx_hash, flags.clone()).ok()?;
+
+    let mut speculative_state = CachedState::new(RecordingStateReader::new(state));
+
+    let pre_execution_cache_misses = thread_cache_misses();
+    let pre_execution_instant = Instant::now();
+    let execution_info = tx
+        .execute(&mut speculative_state, &context)
+        .inspect_err(|err| {
+            debug!(
+                hash = tx_hash.0.to_hex_string(),
+                "speculative execution failed: {err}"
+            )
+        })
+        .ok()?;
+    let execution_time = pre_execution_instant.elapsed();
+    let cache_misses = thread_cache_misses() - pre_execution_cache_misses;
+
+    let writes = speculative_state.to_state_diff().ok()?.state_maps;
+    if !writes.declared_contracts.is_empty() {
+        return None;
+    }
+
+    Some(SpeculativeExecution {
+        execution_info,
+        execution_time,
+        cache_misses,
+        reads: speculative_state.state.reads.into_inner(),
+        writes,
+    })
+}
+
+/// An implementation of StateReader that records every key read from the inner state
+pub struct RecordingStateReader<'a, S: BlockifierStateReader> {
+    state_reader: &'a S,
+    reads: RefCell<ReadSet>,
+}
+
+impl<'a, S: BlockifierStateReader> RecordingStateReader<'a, S> {
+    pub fn new(state_reader: &'a S) -> Self {
+        Self {
+            state_reader,
+            reads: RefCell::default(),
+        }
+    }
+}
+
+impl<S: BlockifierStateReader> BlockifierStateReader for RecordingStateReader<'_, S> {
+    fn get_storage_at(
+        &self,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> StateResult<StarkHash> {
+        self.reads
+            .borrow_mut()
+            .storage
+            .insert((contract_address, key));
+        self.state_reader.get_storage_at(contract_address, key)
+    }
+
+    fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
+        self.reads.borrow_mut().nonces.insert(contract_address);
+        self.state_reader.get_nonce_at(contract_address)
+    }
+
+    fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        self.reads
+            .borrow_mut()
+            .class_hashes
+            .insert(contract_address);
+        self.state_reader.get_class_hash_at(contract_address)
+    }
+
+    fn get_compiled_class(&self, class_hash: ClassHash) -> StateResult<RunnableCompiledClass> {
+        self.reads.borrow_mut().compiled_classes.insert(class_hash);
+        self.state_reader.get_compiled_class(class_hash)
+    }
+
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.reads
+            .borrow_mut()
+            .compiled_class_hashes
+            .insert(class_hash);
+        self.state_reader.get_compiled_class_hash(class_hash)
+    }
+}
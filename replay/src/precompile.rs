@@ -0,0 +1,317 @@
+//! Ahead of time compilation of the classes executed in a range of blocks,
+//! so that the compilation cost doesn't count towards later replays.
+//!
+//! Native artifacts are saved to `compiled_programs`, where they are loaded
+//! from by every other command. Classes with an existing artifact are only
+//! loaded.
+//!
+//! The compilation matrix instead compiles each class to CASM and to native
+//! at several optimization levels, without saving the artifacts, to compare
+//! their compilation time and size.
+//!
+//! Loading the classes also reports their approximate memory footprint, to
+//! explain the memory growth of long replays and guide eviction tuning.
+
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    panic::{self, AssertUnwindSafe},
+    time::{Duration, Instant},
+};
+
+use cairo_lang_starknet_classes::contract_class::ContractClass;
+use rpc_state_reader::{
+    cache::RpcCachedStateReader,
+    execution::called_classes,
+    reader::{executor, sierra_contract_class, Executor, RpcStateReader, StateReader},
+    utils::{
+        class_footprints, get_casm_compiled_class, get_native_executor, measure_casm_compilation,
+        measure_native_compilation, ClassFootprint,
+    },
+};
+use serde::Serialize;
+use starknet_api::{
+    block::BlockNumber,
+    core::{ChainId, ClassHash},
+};
+use tracing::{info, info_span};
+
+#[derive(Serialize)]
+pub struct ClassCompilation {
+    pub class_hash: ClassHash,
+    /// Time to compile the class to CASM, if it succeeded
+    pub casm_time_ms: Option<u128>,
+    /// Time to compile (or load) the class to native, if it succeeded
+    pub native_time_ms: Option<u128>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ClassMemory {
+    pub class_hash: ClassHash,
+    #[serde(flatten)]
+    pub footprint: ClassFootprint,
+    pub total_bytes: u64,
+}
+
+/// Returns the classes loaded by this process holding the most memory
+pub fn top_memory(limit: usize) -> Vec<ClassMemory> {
+    class_footprints()
+        .into_iter()
+        .take(limit)
+        .map(|(class_hash, footprint)| ClassMemory {
+            class_hash,
+            footprint,
+            total_bytes: footprint.total_bytes(),
+        })
+        .collect()
+}
+
+/// Returns every Sierra class executed in the range of blocks, as declared
+/// at the start of the block that first executed it.
+pub fn collect_classes(
+    chain: &ChainId,
+    block_start: u64,
+    block_end: u64,
+) -> anyhow::Result<Vec<(ClassHash, ContractClass)>> {
+    let mut seen = HashSet::new();
+    let mut classes = Vec::new();
+
+    for block_number in block_start..=block_end {
+        let reader = RpcCachedStateReader::new(RpcStateReader::new(
+            chain.clone(),
+            BlockNumber(block_number),
+        ));
+        let previous_reader = RpcCachedStateReader::new(RpcStateReader::new(
+            chain.clone(),
+            BlockNumber(block_number - 1),
+        ));
+
+        let tx_hashes = reader.get_block_with_tx_hashes()?.transactions;
+        for class_hash in called_classes(&reader, &previous_reader, &tx_hashes)? {
+            if !seen.insert(class_hash) {
+                continue;
+            }
+            let class = previous_reader.get_contract_class(&class_hash)?;
+            if let Some(sierra_cc) = sierra_contract_class(class) {
+                classes.push((class_hash, sierra_cc));
+            }
+        }
+
+        info!(
+            block_number,
+            classes = classes.len(),
+            "collected block classes"
+        );
+    }
+
+    Ok(classes)
+}
+
+/// Compiles every class to CASM and native, one at a time, so that the
+/// measured times aren't affected by each other.
+pub fn compile_classes(classes: Vec<(ClassHash, ContractClass)>) -> Vec<ClassCompilation> {
+    classes
+        .into_iter()
+        .map(|(class_hash, sierra_cc)| {
+            let _span =
+                info_span!("precompilation", class_hash = class_hash.to_hex_string()).entered();
+
+            let mut errors = Vec::new();
+
+            let casm_time_ms = timed(|| get_casm_compiled_class(sierra_cc.clone(), class_hash))
+                .map_err(|err| errors.push(format!("casm compilation failed: {err}")))
+                .ok();
+
+            let native_time_ms = if executor() == Executor::Vm {
+                None
+            } else {
+                timed(|| get_native_executor(&sierra_cc, class_hash))
+                    .map_err(|err| errors.push(format!("native compilation failed: {err}")))
+                    .ok()
+            };
+
+            ClassCompilation {
+                class_hash,
+                casm_time_ms,
+                native_time_ms,
+                errors,
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct TargetCompilation {
+    pub class_hash: ClassHash,
+    /// Either `casm` or a native optimization level, e.g. `native-O2`
+    pub target: String,
+    pub time_ms: Option<u128>,
+    /// Size of the library, or of the CASM bytecode as felts
+    pub size_bytes: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TargetSummary {
+    pub target: String,
+    pub classes: usize,
+    pub failed: usize,
+    /// Totals of the classes that compiled
+    pub time_ms: u128,
+    pub size_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct CompilationMatrix {
+    pub targets: Vec<TargetSummary>,
+    pub compilations: Vec<TargetCompilation>,
+}
+
+/// Compiles every class to CASM and to native at each optimization level,
+/// one at a time, without caching the results.
+pub fn compile_matrix(
+    classes: Vec<(ClassHash, ContractClass)>,
+    opt_levels: &[u8],
+) -> CompilationMatrix {
+    let mut compilations = Vec::new();
+    for (class_hash, sierra_cc) in classes {
+        let _span = info_span!(
+            "compilation matrix",
+            class_hash = class_hash.to_hex_string()
+        )
+        .entered();
+
+        compilations.push(measured(class_hash, "casm".to_string(), || {
+            measure_casm_compilation(sierra_cc.clone())
+        }));
+
+        if executor() == Executor::Vm {
+            continue;
+        }
+        for &opt_level in opt_levels {
+            compilations.push(measured(class_hash, format!("native-O{opt_level}"), || {
+                measure_native_compilation(&sierra_cc, class_hash, opt_level)
+            }));
+        }
+    }
+
+    CompilationMatrix {
+        targets: summarize(&compilations),
+        compilations,
+    }
+}
+
+fn measured(
+    class_hash: ClassHash,
+    target: String,
+    compile: impl FnOnce() -> (Duration, u64),
+) -> TargetCompilation {
+    let (time_ms, size_bytes, error) = match panic::catch_unwind(AssertUnwindSafe(compile)) {
+        Ok((time, size)) => (Some(time.as_millis()), Some(size), None),
+        Err(payload) => (None, None, Some(panic_message(payload))),
+    };
+    info!(time_ms, size_bytes, "measured {target} compilation");
+
+    TargetCompilation {
+        class_hash,
+        target,
+        time_ms,
+        size_bytes,
+        error,
+    }
+}
+
+/// Totals the compilations of each target, in order of first appearance
+fn summarize(compilations: &[TargetCompilation]) -> Vec<TargetSummary> {
+    let mut targets: Vec<TargetSummary> = Vec::new();
+    let mut indexes = HashMap::new();
+    for compilation in compilations {
+        let index = *indexes
+            .entry(compilation.target.as_str())
+            .or_insert_with(|| {
+                targets.push(TargetSummary {
+                    target: compilation.target.clone(),
+                    classes: 0,
+                    failed: 0,
+                    time_ms: 0,
+                    size_bytes: 0,
+                });
+                targets.len() - 1
+            });
+        let summary = &mut targets[index];
+        summary.classes += 1;
+        summary.failed += compilation.error.is_some() as usize;
+        summary.time_ms += compilation.time_ms.unwrap_or_default();
+        summary.size_bytes += compilation.size_bytes.unwrap_or_default();
+    }
+    targets
+}
+
+/// Runs the compilation, returning its time in milliseconds. The compilers
+/// panic on failure, so the panic message is returned as the error.
+fn timed<T>(compile: impl FnOnce() -> T) -> Result<u128, String> {
+    let start = Instant::now();
+    panic::catch_unwind(AssertUnwindSafe(compile))
+        .map(|_| start.elapsed().as_millis())
+        .map_err(panic_message)
+}
+
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timed() {
+        assert!(timed(|| 1).is_ok());
+        assert_eq!(timed(|| panic!("boom")), Err("boom".to_string()));
+        assert_eq!(timed(|| panic!("{}", 1)), Err("1".to_string()));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let compilation = |target: &str, time_ms: Option<u128>| TargetCompilation {
+            class_hash: ClassHash::default(),
+            target: target.to_string(),
+            time_ms,
+            size_bytes: time_ms.map(|time_ms| time_ms as u64 * 2),
+            error: time_ms.is_none().then(|| "boom".to_string()),
+        };
+        let compilations = [
+            compilation("casm", Some(1)),
+            compilation("native-O0", Some(10)),
+            compilation("casm", Some(2)),
+            compilation("native-O0", None),
+        ];
+
+        assert_eq!(
+            summarize(&compilations),
+            [
+                TargetSummary {
+                    target: "casm".to_string(),
+                    classes: 2,
+                    failed: 0,
+                    time_ms: 3,
+                    size_bytes: 6,
+                },
+                TargetSummary {
+                    target: "native-O0".to_string(),
+                    classes: 2,
+                    failed: 1,
+                    time_ms: 10,
+                    size_bytes: 20,
+                },
+            ]
+        );
+    }
+}
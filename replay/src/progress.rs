@@ -0,0 +1,78 @@
+//! Progress reporting for long replays: blocks and transactions per second,
+//! cache hit rate and estimated time remaining.
+//!
+//! The progress is drawn as a bar on stderr. When stderr is not a terminal,
+//! it is logged after every step instead.
+
+use std::time::Instant;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rpc_state_reader::cache::{cache_stats, log_cache_statistics, CacheStats};
+use tracing::info;
+
+pub struct Progress {
+    bar: ProgressBar,
+    start: Instant,
+    blocks: u64,
+    transactions: u64,
+    initial_cache_stats: CacheStats,
+}
+
+impl Progress {
+    pub fn new(total_blocks: u64) -> Self {
+        let bar = ProgressBar::new(total_blocks);
+        bar.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_bar} {pos}/{len} blocks, ETA {eta} | {msg}",
+            )
+            .expect("hard-coded progress template should be valid"),
+        );
+
+        Self {
+            bar,
+            start: Instant::now(),
+            blocks: 0,
+            transactions: 0,
+            initial_cache_stats: cache_stats(),
+        }
+    }
+
+    /// Records that the given number of blocks, with the given number of
+    /// transactions, were executed.
+    pub fn advance(&mut self, blocks: u64, transactions: usize) {
+        self.blocks += blocks;
+        self.transactions += transactions as u64;
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let blocks_per_sec = self.blocks as f64 / elapsed;
+        let txs_per_sec = self.transactions as f64 / elapsed;
+        let cache_stats = cache_stats().since(&self.initial_cache_stats);
+        let cache_hit_rate = cache_stats
+            .hit_rate()
+            .map_or("-".to_string(), |rate| format!("{:.1}%", rate * 100.0));
+
+        self.bar.inc(blocks);
+
+        if self.bar.is_hidden() {
+            info!(
+                blocks = self.blocks,
+                total_blocks = self.bar.length(),
+                blocks_per_sec = format!("{blocks_per_sec:.2}"),
+                txs_per_sec = format!("{txs_per_sec:.2}"),
+                cache_hit_rate,
+                cache_evictions = cache_stats.evictions,
+                eta_secs = self.bar.eta().as_secs(),
+                "progress"
+            );
+        } else {
+            self.bar.set_message(format!(
+                "{blocks_per_sec:.2} blocks/s, {txs_per_sec:.2} tx/s, cache hits {cache_hit_rate}"
+            ));
+        }
+    }
+
+    pub fn finish(self) {
+        self.bar.finish();
+        log_cache_statistics();
+    }
+}
@@ -0,0 +1,172 @@
+//! Replay of the transactions of a block in a different order than the one
+//! they were sequenced in, to study how much the final state depends on it.
+//!
+//! Every order is executed on top of the state of the previous block, and its
+//! final state is compared against the one obtained with the block order.
+
+use std::{collections::HashMap, hash::Hash};
+
+use anyhow::ensure;
+use blockifier::{
+    context::BlockContext,
+    state::{
+        cached_state::{CachedState, StateMaps},
+        state_api::StateReader as BlockifierStateReader,
+    },
+    transaction::{
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct OrderingResult {
+    /// Indexes of the transactions in the block, in execution order
+    pub order: Vec<usize>,
+    /// Number of transactions that failed to execute
+    pub failed: usize,
+    /// Number of state entries (storage, nonces, class hashes, declared classes)
+    /// whose final value differs from the one obtained with the block order
+    pub differing_entries: usize,
+}
+
+impl OrderingResult {
+    pub fn changes_state(&self) -> bool {
+        self.differing_entries > 0
+    }
+}
+
+/// Executes the transactions in the block order and in each of the given orders,
+/// discarding the state changes applied to `state`.
+///
+/// The first result always corresponds to the block order.
+pub fn replay_orderings<S: BlockifierStateReader>(
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+    transactions: &[BlockiTransaction],
+    orderings: Vec<Vec<usize>>,
+) -> anyhow::Result<Vec<OrderingResult>> {
+    let block_order = (0..transactions.len()).collect::<Vec<_>>();
+    let (reference, failed) = execute_in_order(state, block_context, transactions, &block_order)?;
+
+    let mut results = vec![OrderingResult {
+        order: block_order,
+        failed,
+        differing_entries: 0,
+    }];
+
+    for order in orderings {
+        let (writes, failed) = execute_in_order(state, block_context, transactions, &order)?;
+
+        results.push(OrderingResult {
+            order,
+            failed,
+            differing_entries: count_differing_entries(&reference, &writes),
+        });
+    }
+
+    Ok(results)
+}
+
+/// Returns the state changes of executing the transactions in the given order,
+/// and the number of transactions that failed.
+fn execute_in_order<S: BlockifierStateReader>(
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+    transactions: &[BlockiTransaction],
+    order: &[usize],
+) -> anyhow::Result<(StateMaps, usize)> {
+    let mut transactional_state = CachedState::create_transactional(state);
+
+    let mut failed = 0;
+    for &index in order {
+        if transactions[index]
+            .execute(&mut transactional_state, block_context)
+            .is_err()
+        {
+            failed += 1;
+        }
+    }
+
+    let writes = transactional_state.to_state_diff()?.state_maps;
+
+    Ok((writes, failed))
+}
+
+fn count_differing_entries(reference: &StateMaps, other: &StateMaps) -> usize {
+    count_differing(&reference.storage, &other.storage)
+        + count_differing(&reference.nonces, &other.nonces)
+        + count_differing(&reference.class_hashes, &other.class_hashes)
+        + count_differing(
+            &reference.compiled_class_hashes,
+            &other.compiled_class_hashes,
+        )
+        + count_differing(&reference.declared_contracts, &other.declared_contracts)
+}
+
+fn count_differing<K: Eq + Hash, V: PartialEq>(a: &HashMap<K, V>, b: &HashMap<K, V>) -> usize {
+    let changed = a.iter().filter(|(key, value)| b.get(key) != Some(value));
+    let added = b.keys().filter(|key| !a.contains_key(key));
+
+    changed.count() + added.count()
+}
+
+/// Checks that the order contains every transaction of the block exactly once
+pub fn validate_order(order: &[usize], transaction_count: usize) -> anyhow::Result<()> {
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+
+    ensure!(
+        sorted.into_iter().eq(0..transaction_count),
+        "the order must contain every transaction index from 0 to {} exactly once",
+        transaction_count.saturating_sub(1)
+    );
+
+    Ok(())
+}
+
+/// Returns a random permutation of the transaction indexes.
+///
+/// Uses a Fisher-Yates shuffle driven by SplitMix64, so that the same seed
+/// always produces the same order.
+pub fn shuffled_order(transaction_count: usize, seed: u64) -> Vec<usize> {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    let mut order = (0..transaction_count).collect::<Vec<_>>();
+    for i in (1..transaction_count).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffled_order() {
+        let order = shuffled_order(50, 7);
+        validate_order(&order, 50).unwrap();
+
+        assert_eq!(order, shuffled_order(50, 7));
+        assert_ne!(order, shuffled_order(50, 8));
+    }
+
+    #[test]
+    fn test_validate_order() {
+        assert!(validate_order(&[2, 0, 1], 3).is_ok());
+        assert!(validate_order(&[0, 1], 3).is_err());
+        assert!(validate_order(&[0, 1, 1], 3).is_err());
+        assert!(validate_order(&[0, 1, 3], 3).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Comparison of the transaction reports of two replays of the same range,
+//! e.g. before and after updating the blockifier.
+//!
+//! Transactions are aligned by hash. A transaction regressed if its outcome
+//! got worse (e.g. it succeeded and now reverts), if it consumes more gas or
+//! pays a higher fee, or if it got slower by more than the time threshold.
+
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::Outcome;
+
+/// The fields of a transaction report needed to compare it
+#[derive(Deserialize)]
+pub struct TxRecord {
+    pub tx_hash: String,
+    pub wall_time: Duration,
+    pub outcome: Outcome,
+    pub gas_consumed: Option<u64>,
+    #[serde(default)]
+    pub fee: Option<u128>,
+}
+
+/// Reads a transaction report written with `--tx-report`
+pub fn load_report(path: &Path) -> anyhow::Result<Vec<TxRecord>> {
+    let report = fs::read_to_string(path)
+        .with_context(|| format!("failed to read report {}", path.display()))?;
+    report
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line).with_context(|| {
+                format!("invalid record at line {} of {}", index + 1, path.display())
+            })
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct TxDiff {
+    pub tx_hash: String,
+    pub outcome_a: Outcome,
+    pub outcome_b: Outcome,
+    pub gas_a: Option<u64>,
+    pub gas_b: Option<u64>,
+    pub gas_delta: Option<i128>,
+    pub fee_a: Option<u128>,
+    pub fee_b: Option<u128>,
+    pub fee_delta: Option<i128>,
+    pub wall_time_a: Duration,
+    pub wall_time_b: Duration,
+    /// Wall time of the second run divided by the first one
+    pub time_ratio: f64,
+    pub regression: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct DiffSummary {
+    /// Transactions present in both reports
+    pub aligned: usize,
+    pub only_in_a: usize,
+    pub only_in_b: usize,
+    pub outcome_changes: usize,
+    pub gas_changes: usize,
+    pub fee_changes: usize,
+    /// Transactions slower by more than the time threshold
+    pub slower: usize,
+    /// Transactions faster by more than the time threshold
+    pub faster: usize,
+    pub regressions: usize,
+}
+
+#[derive(Serialize)]
+pub struct ReportDiff {
+    pub summary: DiffSummary,
+    /// Changed transactions, in the order of the first report
+    pub transactions: Vec<TxDiff>,
+}
+
+/// Ranks outcomes from best to worst
+fn rank(outcome: &Outcome) -> u8 {
+    match outcome {
+        Outcome::Success => 0,
+        Outcome::Reverted => 1,
+        Outcome::Failed => 2,
+    }
+}
+
+fn delta(a: Option<u128>, b: Option<u128>) -> Option<i128> {
+    Some(b? as i128 - a? as i128)
+}
+
+/// Compares the reports, keeping only the regressed transactions if
+/// `only_regressions` is set. The time threshold is relative, e.g. 0.1
+/// reports transactions that got 10% slower or faster.
+pub fn diff(
+    a: Vec<TxRecord>,
+    b: Vec<TxRecord>,
+    time_threshold: f64,
+    only_regressions: bool,
+) -> ReportDiff {
+    let mut summary = DiffSummary::default();
+
+    let mut b = b
+        .into_iter()
+        .map(|record| (record.tx_hash.clone(), record))
+        .collect::<HashMap<_, _>>();
+
+    let mut transactions = Vec::new();
+    for record_a in a {
+        let Some(record_b) = b.remove(&record_a.tx_hash) else {
+            summary.only_in_a += 1;
+            continue;
+        };
+        summary.aligned += 1;
+
+        let gas_delta = delta(
+            record_a.gas_consumed.map(u128::from),
+            record_b.gas_consumed.map(u128::from),
+        );
+        let fee_delta = delta(record_a.fee, record_b.fee);
+        let time_ratio = record_b.wall_time.as_secs_f64() / record_a.wall_time.as_secs_f64();
+
+        let outcome_changed = rank(&record_a.outcome) != rank(&record_b.outcome);
+        let gas_changed = gas_delta.is_some_and(|delta| delta != 0);
+        let fee_changed = fee_delta.is_some_and(|delta| delta != 0);
+        let slower = time_ratio > 1.0 + time_threshold;
+        let faster = time_ratio < 1.0 - time_threshold;
+
+        summary.outcome_changes += outcome_changed as usize;
+        summary.gas_changes += gas_changed as usize;
+        summary.fee_changes += fee_changed as usize;
+        summary.slower += slower as usize;
+        summary.faster += faster as usize;
+
+        let regression = rank(&record_b.outcome) > rank(&record_a.outcome)
+            || gas_delta.is_some_and(|delta| delta > 0)
+            || fee_delta.is_some_and(|delta| delta > 0)
+            || slower;
+        summary.regressions += regression as usize;
+
+        let changed = outcome_changed || gas_changed || fee_changed || slower || faster;
+        if !changed || (only_regressions && !regression) {
+            continue;
+        }
+
+        transactions.push(TxDiff {
+            tx_hash: record_a.tx_hash,
+            outcome_a: record_a.outcome,
+            outcome_b: record_b.outcome,
+            gas_a: record_a.gas_consumed,
+            gas_b: record_b.gas_consumed,
+            gas_delta,
+            fee_a: record_a.fee,
+            fee_b: record_b.fee,
+            fee_delta,
+            wall_time_a: record_a.wall_time,
+            wall_time_b: record_b.wall_time,
+            time_ratio,
+            regression,
+        });
+    }
+    summary.only_in_b = b.len();
+
+    ReportDiff {
+        summary,
+        transactions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tx_hash: &str, millis: u64, outcome: Outcome, gas: u64) -> TxRecord {
+        TxRecord {
+            tx_hash: tx_hash.to_string(),
+            wall_time: Duration::from_millis(millis),
+            outcome,
+            gas_consumed: Some(gas),
+            fee: Some(gas * 10),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = vec![
+            record("0x1", 10, Outcome::Success, 100),
+            record("0x2", 10, Outcome::Success, 100),
+            record("0x3", 10, Outcome::Success, 100),
+            record("0x4", 10, Outcome::Success, 100),
+        ];
+        let b = vec![
+            record("0x1", 10, Outcome::Success, 100),
+            record("0x2", 10, Outcome::Reverted, 100),
+            record("0x3", 5, Outcome::Success, 90),
+            record("0x5", 10, Outcome::Success, 100),
+        ];
+
+        let report = diff(a, b, 0.1, false);
+        assert_eq!(report.summary.aligned, 3);
+        assert_eq!(report.summary.only_in_a, 1);
+        assert_eq!(report.summary.only_in_b, 1);
+        assert_eq!(report.summary.outcome_changes, 1);
+        assert_eq!(report.summary.gas_changes, 1);
+        assert_eq!(report.summary.faster, 1);
+        assert_eq!(report.summary.regressions, 1);
+        assert_eq!(report.transactions.len(), 2);
+        assert_eq!(report.transactions[1].gas_delta, Some(-10));
+
+        let a = vec![record("0x2", 10, Outcome::Success, 100)];
+        let b = vec![record("0x2", 10, Outcome::Failed, 100)];
+        let report = diff(a, b, 0.1, true);
+        assert_eq!(report.transactions.len(), 1);
+        assert!(report.transactions[0].regression);
+    }
+}
@@ -0,0 +1,86 @@
+//! Self-contained bundles reproducing the execution of a transaction without
+//! a node, e.g. to attach to an issue instead of instructions to warm a cache.
+//!
+//! A bundle holds every entry read while executing the transaction after the
+//! ones preceding it in its block: the block header, its transactions and
+//! receipts, and the state values and classes of the previous block. It's
+//! executed with the readers failing on anything missing from it.
+
+use std::{fs::File, path::Path};
+
+use anyhow::{ensure, Context};
+use blockifier::transaction::account_transaction::ExecutionFlags;
+use rpc_state_reader::cache::RpcCache;
+use serde::{Deserialize, Serialize};
+
+use crate::output;
+
+/// Version of the bundle format, increased on breaking changes
+pub const BUNDLE_VERSION: u32 = 1;
+
+const COMPRESSION_LEVEL: i32 = 19;
+
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub bundle_version: u32,
+    /// Version of the replay that exported the bundle
+    pub replay_version: String,
+    pub chain: String,
+    pub block_number: u64,
+    pub tx_hash: String,
+    /// Flags the transactions were executed with, as other flags may read
+    /// entries missing from the bundle
+    pub flags: Flags,
+    /// Entries read from the block of the transaction
+    pub block: RpcCache,
+    /// Entries read from the state of the previous block
+    pub state: RpcCache,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Flags {
+    pub only_query: bool,
+    pub charge_fee: bool,
+    pub validate: bool,
+}
+
+impl From<ExecutionFlags> for Flags {
+    fn from(flags: ExecutionFlags) -> Self {
+        Self {
+            only_query: flags.only_query,
+            charge_fee: flags.charge_fee,
+            validate: flags.validate,
+        }
+    }
+}
+
+/// Version of this replay, e.g. `0.1.0 (<commit>)`
+pub fn replay_version() -> String {
+    format!("{} ({})", env!("CARGO_PKG_VERSION"), env!("REPLAY_COMMIT"))
+}
+
+impl Bundle {
+    /// Writes the bundle as zstd compressed JSON
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        output::write_atomic(path, |writer| {
+            let mut encoder = zstd::Encoder::new(writer, COMPRESSION_LEVEL)?;
+            serde_json::to_writer(&mut encoder, self)?;
+            encoder.finish()?;
+            Ok(())
+        })
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let decoder = zstd::Decoder::new(file)?;
+        let bundle: Self = serde_json::from_reader(decoder)
+            .with_context(|| format!("invalid reproduction bundle {}", path.display()))?;
+        ensure!(
+            bundle.bundle_version == BUNDLE_VERSION,
+            "unsupported bundle version {}, expected {BUNDLE_VERSION}",
+            bundle.bundle_version
+        );
+        Ok(bundle)
+    }
+}
@@ -0,0 +1,441 @@
+//! A SQLite database that stores data extracted from replayed transactions,
+//! so that it can be queried after the replay finished.
+//!
+//! Addresses and event keys repeat massively across transactions, so they
+//! are stored once in the `strings` dictionary and referenced by their id.
+
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context};
+use blockifier::transaction::objects::TransactionExecutionInfo;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+use starknet_api::{core::ContractAddress, hash::StarkHash, transaction::TransactionHash};
+
+/// Version of the schema, stored as the `user_version` of the database
+const SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS strings (
+    id INTEGER PRIMARY KEY,
+    value TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS events (
+    block_number INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL,
+    event_index INTEGER NOT NULL,
+    from_address INTEGER NOT NULL REFERENCES strings (id),
+    data TEXT NOT NULL,
+    PRIMARY KEY (tx_hash, event_index)
+);
+CREATE INDEX IF NOT EXISTS events_by_address ON events (from_address, block_number);
+
+CREATE TABLE IF NOT EXISTS event_keys (
+    tx_hash TEXT NOT NULL,
+    event_index INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    key INTEGER NOT NULL REFERENCES strings (id),
+    PRIMARY KEY (tx_hash, event_index, position)
+);
+CREATE INDEX IF NOT EXISTS event_keys_by_key ON event_keys (key);
+
+CREATE TABLE IF NOT EXISTS messages (
+    block_number INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL,
+    message_index INTEGER NOT NULL,
+    from_address INTEGER NOT NULL REFERENCES strings (id),
+    to_address INTEGER NOT NULL REFERENCES strings (id),
+    payload TEXT NOT NULL,
+    PRIMARY KEY (tx_hash, message_index)
+);
+CREATE INDEX IF NOT EXISTS messages_by_block ON messages (block_number);
+
+CREATE TABLE IF NOT EXISTS message_counts (
+    tx_hash TEXT PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    executed_count INTEGER NOT NULL,
+    rpc_count INTEGER
+);
+";
+
+pub struct ResultsStore {
+    connection: Connection,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexedEvent {
+    pub block_number: u64,
+    pub tx_hash: TransactionHash,
+    pub event_index: usize,
+    pub from_address: ContractAddress,
+    pub keys: Vec<StarkHash>,
+    pub data: Vec<StarkHash>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexedMessage {
+    pub block_number: u64,
+    pub tx_hash: TransactionHash,
+    pub message_index: usize,
+    pub from_address: ContractAddress,
+    pub to_address: String,
+    pub payload: Vec<StarkHash>,
+}
+
+/// A transaction whose executed message count differs from its receipt
+#[derive(Debug, Serialize)]
+pub struct MessageCountMismatch {
+    pub block_number: u64,
+    pub tx_hash: TransactionHash,
+    pub executed_count: usize,
+    pub rpc_count: usize,
+}
+
+/// Number of rows stored in each table
+#[derive(Debug)]
+pub struct StoreCounts {
+    pub events: usize,
+    pub messages: usize,
+}
+
+impl ResultsStore {
+    /// Opens the store at the given path, creating it if it doesn't exist
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let connection = Connection::open(path)
+            .with_context(|| format!("failed to open results store at {}", path.display()))?;
+
+        let version: u32 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let has_tables: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+            [],
+            |row| row.get(0),
+        )?;
+        ensure!(
+            version == SCHEMA_VERSION || !has_tables,
+            "results store at {} was created with an older schema, remove it to index again",
+            path.display()
+        );
+
+        connection.execute_batch(SCHEMA)?;
+        connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        Ok(Self { connection })
+    }
+
+    /// Stores every event emitted by the transaction, replacing the ones
+    /// stored by a previous replay of the same transaction.
+    pub fn index_events(
+        &mut self,
+        block_number: u64,
+        tx_hash: TransactionHash,
+        execution_info: &TransactionExecutionInfo,
+    ) -> anyhow::Result<()> {
+        let tx_hash = tx_hash.0.to_hex_string();
+
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute("DELETE FROM events WHERE tx_hash = ?1", params![tx_hash])?;
+        transaction.execute(
+            "DELETE FROM event_keys WHERE tx_hash = ?1",
+            params![tx_hash],
+        )?;
+
+        // As in the RPC, events are ordered by emission within each top level
+        // call, which differs from the depth-first order of the calls when a
+        // call emits events after its inner calls
+        let events = execution_info
+            .non_optional_call_infos()
+            .flat_map(|call_info| {
+                let mut events =
+                    call_info
+                        .iter()
+                        .flat_map(|call_info| {
+                            call_info.execution.events.iter().map(|ordered_event| {
+                                (call_info.call.storage_address, ordered_event)
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                // Stable, so events with the same order keep the depth-first order
+                events.sort_by_key(|(_, ordered_event)| ordered_event.order);
+                events
+            })
+            .map(|(from_address, ordered_event)| (from_address, &ordered_event.event));
+
+        for (event_index, (from_address, event)) in events.enumerate() {
+            transaction.execute(
+                "INSERT INTO events (block_number, tx_hash, event_index, from_address, data)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    block_number,
+                    tx_hash,
+                    event_index,
+                    intern(&transaction, &from_address.0.key().to_hex_string())?,
+                    serde_json::to_string(&event.data.0)?,
+                ],
+            )?;
+
+            for (position, key) in event.keys.iter().enumerate() {
+                transaction.execute(
+                    "INSERT INTO event_keys (tx_hash, event_index, position, key)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        tx_hash,
+                        event_index,
+                        position,
+                        intern(&transaction, &key.0.to_hex_string())?
+                    ],
+                )?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Returns the indexed events, optionally filtering by emitter and key
+    pub fn query_events(
+        &self,
+        from_address: Option<ContractAddress>,
+        key: Option<StarkHash>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<IndexedEvent>> {
+        // Values missing from the dictionary were never indexed
+        let from_address = match from_address {
+            Some(address) => match self.string_id(&address.0.key().to_hex_string())? {
+                Some(id) => Some(id),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+        let key = match key {
+            Some(key) => match self.string_id(&key.to_hex_string())? {
+                Some(id) => Some(id),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+
+        let mut statement = self.connection.prepare(
+            "SELECT block_number, tx_hash, event_index, strings.value, data
+            FROM events
+            JOIN strings ON strings.id = events.from_address
+            WHERE (?1 IS NULL OR from_address = ?1)
+            AND (?2 IS NULL OR EXISTS (
+                SELECT 1 FROM event_keys
+                WHERE event_keys.tx_hash = events.tx_hash
+                AND event_keys.event_index = events.event_index
+                AND event_keys.key = ?2
+            ))
+            ORDER BY block_number, tx_hash, event_index
+            LIMIT ?3",
+        )?;
+        let mut keys_statement = self.connection.prepare(
+            "SELECT strings.value
+            FROM event_keys
+            JOIN strings ON strings.id = event_keys.key
+            WHERE tx_hash = ?1 AND event_index = ?2
+            ORDER BY position",
+        )?;
+
+        let rows = statement.query_map(params![from_address, key, limit], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, usize>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (block_number, tx_hash, event_index, from_address, data) = row?;
+            let keys = keys_statement
+                .query_map(params![tx_hash, event_index], |row| row.get::<_, String>(0))?
+                .map(|key| Ok(StarkHash::from_hex(&key?)?))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            Ok(IndexedEvent {
+                block_number,
+                tx_hash: TransactionHash(StarkHash::from_hex(&tx_hash)?),
+                event_index,
+                from_address: ContractAddress::try_from(StarkHash::from_hex(&from_address)?)?,
+                keys,
+                data: serde_json::from_str(&data)?,
+            })
+        })
+        .collect()
+    }
+
+    /// Stores every L2 to L1 message sent by the transaction, along with the
+    /// number of messages reported by its receipt, if available.
+    pub fn index_messages(
+        &mut self,
+        block_number: u64,
+        tx_hash: TransactionHash,
+        execution_info: &TransactionExecutionInfo,
+        rpc_count: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let tx_hash = tx_hash.0.to_hex_string();
+
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute("DELETE FROM messages WHERE tx_hash = ?1", params![tx_hash])?;
+
+        let messages = execution_info
+            .non_optional_call_infos()
+            .flat_map(|call_info| call_info.iter())
+            .flat_map(|call_info| {
+                call_info
+                    .execution
+                    .l2_to_l1_messages
+                    .iter()
+                    .map(|ordered_message| {
+                        (call_info.call.storage_address, &ordered_message.message)
+                    })
+            });
+
+        let mut executed_count = 0;
+        for (message_index, (from_address, message)) in messages.enumerate() {
+            transaction.execute(
+                "INSERT INTO messages (block_number, tx_hash, message_index, from_address, to_address, payload)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    block_number,
+                    tx_hash,
+                    message_index,
+                    intern(&transaction, &from_address.0.key().to_hex_string())?,
+                    intern(&transaction, &format!("{:#x}", message.to_address.0))?,
+                    serde_json::to_string(&message.payload.0)?,
+                ],
+            )?;
+            executed_count += 1;
+        }
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO message_counts (tx_hash, block_number, executed_count, rpc_count)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![tx_hash, block_number, executed_count, rpc_count],
+        )?;
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Returns the indexed messages sent in the given block range
+    pub fn query_messages(
+        &self,
+        block_start: u64,
+        block_end: u64,
+    ) -> anyhow::Result<Vec<IndexedMessage>> {
+        let mut statement = self.connection.prepare(
+            "SELECT block_number, tx_hash, message_index, from_strings.value, to_strings.value, payload
+            FROM messages
+            JOIN strings AS from_strings ON from_strings.id = messages.from_address
+            JOIN strings AS to_strings ON to_strings.id = messages.to_address
+            WHERE block_number BETWEEN ?1 AND ?2
+            ORDER BY block_number, tx_hash, message_index",
+        )?;
+
+        let rows = statement.query_map(params![block_start, block_end], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, usize>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (block_number, tx_hash, message_index, from_address, to_address, payload) = row?;
+            Ok(IndexedMessage {
+                block_number,
+                tx_hash: TransactionHash(StarkHash::from_hex(&tx_hash)?),
+                message_index,
+                from_address: ContractAddress::try_from(StarkHash::from_hex(&from_address)?)?,
+                to_address,
+                payload: serde_json::from_str(&payload)?,
+            })
+        })
+        .collect()
+    }
+
+    /// Returns the transactions in the given block range whose number of
+    /// executed messages differs from the one reported by their receipt
+    pub fn message_count_mismatches(
+        &self,
+        block_start: u64,
+        block_end: u64,
+    ) -> anyhow::Result<Vec<MessageCountMismatch>> {
+        let mut statement = self.connection.prepare(
+            "SELECT block_number, tx_hash, executed_count, rpc_count
+            FROM message_counts
+            WHERE block_number BETWEEN ?1 AND ?2
+            AND rpc_count IS NOT NULL
+            AND executed_count != rpc_count
+            ORDER BY block_number, tx_hash",
+        )?;
+
+        let rows = statement.query_map(params![block_start, block_end], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, usize>(2)?,
+                row.get::<_, usize>(3)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (block_number, tx_hash, executed_count, rpc_count) = row?;
+            Ok(MessageCountMismatch {
+                block_number,
+                tx_hash: TransactionHash(StarkHash::from_hex(&tx_hash)?),
+                executed_count,
+                rpc_count,
+            })
+        })
+        .collect()
+    }
+
+    /// Returns the id of the value in the dictionary, if it was ever stored
+    fn string_id(&self, value: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT id FROM strings WHERE value = ?1",
+                params![value],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Returns the number of indexed events and messages
+    pub fn counts(&self) -> anyhow::Result<StoreCounts> {
+        let events = self
+            .connection
+            .query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+        let messages = self
+            .connection
+            .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+
+        Ok(StoreCounts { events, messages })
+    }
+}
+
+/// Returns the id of the value in the dictionary, adding it if missing
+fn intern(transaction: &Transaction, value: &str) -> anyhow::Result<i64> {
+    transaction
+        .prepare_cached("INSERT INTO strings (value) VALUES (?1) ON CONFLICT (value) DO NOTHING")?
+        .execute(params![value])?;
+    Ok(transaction
+        .prepare_cached("SELECT id FROM strings WHERE value = ?1")?
+        .query_row(params![value], |row| row.get(0))?)
+}
@@ -0,0 +1,161 @@
+//! Reconciliation of the fees collected by the sequencer in each block.
+
+use std::collections::BTreeMap;
+
+use blockifier::{context::BlockContext, transaction::objects::TransactionExecutionInfo};
+use rpc_state_reader::{objects::RpcTransactionReceipt, reader::StateReader};
+use serde::Serialize;
+use starknet_api::{
+    core::ContractAddress,
+    felt,
+    hash::StarkHash,
+    transaction::{Event, TransactionHash},
+};
+use tracing::error;
+
+/// Selector of the ERC20 `Transfer` event
+const TRANSFER_EVENT_KEY: &str = "0x99cd8bde557814842a3121e8ddfd433a539b8c9f14bf31ebf108d12e6196e9";
+
+#[derive(Serialize)]
+pub struct BlockRevenue {
+    pub block_number: u64,
+    pub sequencer_address: ContractAddress,
+    pub fees: Vec<TokenRevenue>,
+    pub matches: bool,
+}
+
+#[derive(Serialize)]
+pub struct TokenRevenue {
+    pub fee_token: ContractAddress,
+    /// Sum of the fees of the replayed transactions
+    pub executed: u128,
+    /// Sum of the fee transfers to the sequencer emitted on chain
+    pub onchain: u128,
+}
+
+/// Computes the fees collected by the sequencer in a block, both from the
+/// replayed transactions and from the fee transfer events of the receipts.
+pub fn compute_block_revenue(
+    reader: &impl StateReader,
+    block_context: &BlockContext,
+    executions: &[(TransactionHash, Option<TransactionExecutionInfo>)],
+) -> BlockRevenue {
+    let block_info = block_context.block_info();
+    let fee_token_addresses = &block_context.chain_info().fee_token_addresses;
+    let fee_tokens = [
+        fee_token_addresses.eth_fee_token_address,
+        fee_token_addresses.strk_fee_token_address,
+    ];
+
+    let mut fees: BTreeMap<ContractAddress, TokenRevenue> = fee_tokens
+        .into_iter()
+        .map(|fee_token| {
+            (
+                fee_token,
+                TokenRevenue {
+                    fee_token,
+                    executed: 0,
+                    onchain: 0,
+                },
+            )
+        })
+        .collect();
+
+    for (tx_hash, execution_info) in executions {
+        let receipt = match reader.get_transaction_receipt(tx_hash) {
+            Ok(receipt) => Some(receipt),
+            Err(err) => {
+                error!(
+                    hash = tx_hash.0.to_hex_string(),
+                    "failed to get transaction receipt: {err}"
+                );
+                None
+            }
+        };
+
+        if let Some(execution_info) = execution_info {
+            let fee_token = execution_info
+                .fee_transfer_call_info
+                .as_ref()
+                .map(|call_info| call_info.call.storage_address)
+                .or_else(|| {
+                    receipt
+                        .as_ref()
+                        .map(|receipt| match receipt.actual_fee.unit.as_str() {
+                            "FRI" => fee_token_addresses.strk_fee_token_address,
+                            _ => fee_token_addresses.eth_fee_token_address,
+                        })
+                });
+
+            if let Some(revenue) = fee_token.and_then(|fee_token| fees.get_mut(&fee_token)) {
+                revenue.executed += execution_info.receipt.fee.0;
+            }
+        }
+
+        if let Some((fee_token, amount)) = receipt
+            .as_ref()
+            .and_then(|receipt| find_fee_transfer(receipt, block_info.sequencer_address))
+        {
+            if let Some(revenue) = fees.get_mut(&fee_token) {
+                revenue.onchain += amount;
+            }
+        }
+    }
+
+    let fees = fees.into_values().collect::<Vec<_>>();
+    let matches = fees.iter().all(|fee| fee.executed == fee.onchain);
+
+    BlockRevenue {
+        block_number: block_info.block_number.0,
+        sequencer_address: block_info.sequencer_address,
+        fees,
+        matches,
+    }
+}
+
+/// Returns the fee token and amount of the fee transfer to the sequencer
+///
+/// The fee transfer is always the last event emitted by a transaction.
+fn find_fee_transfer(
+    receipt: &RpcTransactionReceipt,
+    sequencer_address: ContractAddress,
+) -> Option<(ContractAddress, u128)> {
+    let event = receipt.events.last()?;
+    let (to, amount_low, amount_high) = parse_transfer(event)?;
+
+    if to != *sequencer_address.0.key() || amount_high != StarkHash::ZERO {
+        return None;
+    }
+
+    Some((event.from_address, felt_to_u128(amount_low)?))
+}
+
+/// Parses a `Transfer` event, returning the recipient and the amount as an
+/// u256 (low, high). Supports both the legacy layout, which has every field
+/// in the data, and the current one, which has the sender and the recipient
+/// as keys.
+fn parse_transfer(event: &Event) -> Option<(StarkHash, StarkHash, StarkHash)> {
+    let keys = &event.content.keys;
+    let data = &event.content.data.0;
+
+    if keys.first()?.0 != felt!(TRANSFER_EVENT_KEY) {
+        return None;
+    }
+
+    match (keys.len(), data.len()) {
+        (1, 4) => Some((data[1], data[2], data[3])),
+        (3, 2) => Some((keys[2].0, data[0], data[1])),
+        _ => None,
+    }
+}
+
+fn felt_to_u128(felt: StarkHash) -> Option<u128> {
+    let bytes = felt.to_bytes_be();
+    let (high, low) = bytes.split_at(16);
+
+    if high.iter().any(|byte| *byte != 0) {
+        return None;
+    }
+
+    Some(u128::from_be_bytes(low.try_into().ok()?))
+}
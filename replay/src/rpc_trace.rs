@@ -0,0 +1,94 @@
+//! Conversion of executions to the trace format of the RPC specification
+//! (`starknet_traceTransaction`), so that they can be compared with the
+//! traces of any node using generic JSON tooling.
+
+use blockifier::{
+    execution::{call_info::CallInfo, entry_point::CallType},
+    transaction::objects::TransactionExecutionInfo,
+};
+use serde_json::{json, Value};
+
+/// Builds the trace of a transaction of the given RPC type (`INVOKE`,
+/// `DECLARE`, `DEPLOY_ACCOUNT` or `L1_HANDLER`), without its state diff.
+pub fn transaction_trace(tx_type: &str, execution_info: &TransactionExecutionInfo) -> Value {
+    let mut trace = json!({
+        "type": tx_type,
+        "validate_invocation": execution_info.validate_call_info.as_ref().map(call_json),
+        "fee_transfer_invocation": execution_info.fee_transfer_call_info.as_ref().map(call_json),
+        "execution_resources": {
+            "l1_gas": execution_info.receipt.gas.l1_gas,
+            "l1_data_gas": execution_info.receipt.gas.l1_data_gas,
+            "l2_gas": execution_info.receipt.gas.l2_gas,
+        },
+    });
+
+    let execute_invocation = match &execution_info.revert_error {
+        Some(revert_error) => json!({ "revert_reason": revert_error.to_string() }),
+        None => json!(execution_info.execute_call_info.as_ref().map(call_json)),
+    };
+
+    let execute_field = match tx_type {
+        "DEPLOY_ACCOUNT" => Some("constructor_invocation"),
+        "INVOKE" => Some("execute_invocation"),
+        "L1_HANDLER" => Some("function_invocation"),
+        _ => None,
+    };
+    if let Some(execute_field) = execute_field {
+        trace[execute_field] = execute_invocation;
+    }
+
+    trace
+}
+
+fn call_json(call: &CallInfo) -> Value {
+    let call_type = match call.call.call_type {
+        CallType::Call => "CALL",
+        CallType::Delegate => "LIBRARY_CALL",
+    };
+
+    let events = call
+        .execution
+        .events
+        .iter()
+        .map(|event| {
+            json!({
+                "order": event.order,
+                "keys": event.event.keys,
+                "data": event.event.data.0,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let messages = call
+        .execution
+        .l2_to_l1_messages
+        .iter()
+        .map(|message| {
+            json!({
+                "order": message.order,
+                "from_address": call.call.storage_address,
+                "to_address": message.message.to_address,
+                "payload": message.message.payload.0,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "contract_address": call.call.storage_address,
+        "entry_point_selector": call.call.entry_point_selector,
+        "calldata": call.call.calldata.0.as_ref(),
+        "caller_address": call.call.caller_address,
+        "class_hash": call.call.class_hash,
+        "entry_point_type": call.call.entry_point_type,
+        "call_type": call_type,
+        "result": call.execution.retdata.0,
+        "calls": call.inner_calls.iter().map(call_json).collect::<Vec<_>>(),
+        "events": events,
+        "messages": messages,
+        // The call info has no L1 gas of its own, so it is omitted
+        "execution_resources": {
+            "l2_gas": call.execution.gas_consumed,
+        },
+        "is_reverted": call.execution.failed,
+    })
+}
@@ -0,0 +1,181 @@
+//! Summary of every transaction executed by the run, to gate CI on the
+//! results of a replay instead of grepping its logs.
+//!
+//! A run fails (exiting with a non-zero code) if any transaction failed to
+//! execute, or if its execution diverged from the node's receipt, as every
+//! replayed transaction was accepted on-chain. The error of each failure is
+//! included in the report, along with the transactions that fell back to the
+//! Cairo VM.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::Outcome;
+
+static BLOCKS: AtomicU64 = AtomicU64::new(0);
+static SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+static REVERTED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static DIVERGENCES: AtomicU64 = AtomicU64::new(0);
+static FAILURES: Mutex<Vec<Failure>> = Mutex::new(Vec::new());
+static FALLBACKS: Mutex<Vec<Fallback>> = Mutex::new(Vec::new());
+
+pub fn record_block() {
+    BLOCKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_outcome(block_number: u64, tx_hash: &str, outcome: &Outcome, error: Option<&str>) {
+    let counter = match outcome {
+        Outcome::Success => &SUCCEEDED,
+        Outcome::Reverted => &REVERTED,
+        Outcome::Failed => &FAILED,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+
+    if let Outcome::Failed = outcome {
+        record_failure(Failure {
+            block_number,
+            tx_hash: Some(tx_hash.to_string()),
+            error: error.unwrap_or_default().to_string(),
+        });
+    }
+}
+
+/// Records a block that couldn't be executed, e.g. because its
+/// transactions couldn't be fetched
+pub fn record_block_failure(block_number: u64, error: String) {
+    record_failure(Failure {
+        block_number,
+        tx_hash: None,
+        error,
+    });
+}
+
+fn record_failure(failure: Failure) {
+    FAILURES.lock().unwrap().push(failure);
+}
+
+/// Records a transaction that was executed again with the Cairo VM, after
+/// Cairo Native failed
+pub fn record_fallback(fallback: Fallback) {
+    FALLBACKS.lock().unwrap().push(fallback);
+}
+
+/// Records a transaction whose execution doesn't match the node's receipt
+pub fn record_divergence() {
+    DIVERGENCES.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub block_number: u64,
+    /// Hash of the failed transaction, or `None` if the whole block failed
+    pub tx_hash: Option<String>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fallback {
+    pub block_number: u64,
+    pub tx_hash: String,
+    /// Class of the entry point that failed with Cairo Native
+    pub class_hash: Option<String>,
+    pub native_error: String,
+    pub vm_outcome: Outcome,
+    pub vm_error: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub blocks: u64,
+    pub succeeded: u64,
+    pub reverted: u64,
+    pub failed: u64,
+    pub divergences: u64,
+    pub duration: Duration,
+    pub failures: Vec<Failure>,
+    #[serde(default)]
+    pub fallbacks: Vec<Fallback>,
+}
+
+impl RunReport {
+    pub fn transactions(&self) -> u64 {
+        self.succeeded + self.reverted + self.failed
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty() && self.divergences == 0
+    }
+}
+
+/// Returns the report of the transactions executed so far
+pub fn report(duration: Duration) -> RunReport {
+    RunReport {
+        blocks: BLOCKS.load(Ordering::Relaxed),
+        succeeded: SUCCEEDED.load(Ordering::Relaxed),
+        reverted: REVERTED.load(Ordering::Relaxed),
+        failed: FAILED.load(Ordering::Relaxed),
+        divergences: DIVERGENCES.load(Ordering::Relaxed),
+        duration,
+        failures: FAILURES.lock().unwrap().clone(),
+        fallbacks: FALLBACKS.lock().unwrap().clone(),
+    }
+}
+
+/// Reads the failures of the run report written by a previous run
+pub fn load_failures(path: &Path) -> anyhow::Result<Vec<Failure>> {
+    let report = fs::read_to_string(path)
+        .with_context(|| format!("failed to read run report {}", path.display()))?;
+    let report: RunReport = serde_json::from_str(&report)
+        .with_context(|| format!("failed to parse run report {}", path.display()))?;
+    Ok(report.failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_success() {
+        let report = RunReport {
+            succeeded: 3,
+            reverted: 1,
+            ..Default::default()
+        };
+        assert_eq!(report.transactions(), 4);
+        assert!(report.is_success());
+
+        let diverged = RunReport {
+            divergences: 1,
+            ..Default::default()
+        };
+        assert!(!diverged.is_success());
+
+        let failed_block = RunReport {
+            failures: vec![Failure {
+                block_number: 1,
+                tx_hash: None,
+                error: "failed to fetch block".to_string(),
+            }],
+            ..report
+        };
+        assert_eq!(failed_block.transactions(), 4);
+        assert!(!failed_block.is_success());
+
+        let json = serde_json::to_string(&failed_block).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RunReport>(&json).unwrap(),
+            failed_block
+        );
+    }
+}
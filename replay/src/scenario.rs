@@ -0,0 +1,249 @@
+//! Replay of a block range under overridden sequencer behavior, to find which
+//! historical transactions would have reverted, been rejected, or paid
+//! drastically different fees.
+//!
+//! Scenarios are JSON files describing curves over the block range:
+//!
+//! ```json
+//! {
+//!   "l1_gas_price": [{ "block": 90000, "multiplier": 1 }, { "block": 90010, "multiplier": 10 }],
+//!   "l1_data_gas_price": [{ "block": 90000, "multiplier": 2 }],
+//!   "timestamp_skew": 3600
+//! }
+//! ```
+//!
+//! Multipliers are linearly interpolated between the given blocks, and kept
+//! constant before the first one and after the last one. Each block starts
+//! from the state of the previous block on chain, but its transactions are
+//! executed on top of the ones before them under the scenario, so the state
+//! may drift from the one on chain within the block.
+
+use std::fs;
+
+use anyhow::Context;
+use blockifier::{
+    state::{cached_state::CachedState, state_api::StateReader as BlockifierStateReader},
+    transaction::{account_transaction::ExecutionFlags, transactions::ExecutableTransaction},
+};
+use rpc_state_reader::{
+    execution::{block_context_from_header, fetch_blockifier_transaction},
+    objects::{BlockHeader, ResourcePrice},
+    reader::StateReader,
+};
+use serde::{Deserialize, Serialize};
+use starknet_api::{
+    block::{BlockTimestamp, GasPrice},
+    transaction::{TransactionExecutionStatus, TransactionHash},
+};
+
+use crate::audit::Outcome;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct CurvePoint {
+    pub block: u64,
+    pub multiplier: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub l1_gas_price: Vec<CurvePoint>,
+    #[serde(default)]
+    pub l1_data_gas_price: Vec<CurvePoint>,
+    /// Seconds added to the timestamp of every block, may be negative
+    #[serde(default)]
+    pub timestamp_skew: i64,
+}
+
+pub fn load_scenario(path: &str) -> anyhow::Result<Scenario> {
+    let scenario =
+        fs::read_to_string(path).with_context(|| format!("failed to read scenario {path}"))?;
+    serde_json::from_str(&scenario).with_context(|| format!("failed to parse scenario {path}"))
+}
+
+impl Scenario {
+    /// Returns the block header with the scenario applied
+    pub fn apply(&self, mut header: BlockHeader) -> BlockHeader {
+        let block = header.block_number.0;
+
+        header.l1_gas_price = scale(header.l1_gas_price, multiplier(&self.l1_gas_price, block));
+        header.l1_data_gas_price = scale(
+            header.l1_data_gas_price,
+            multiplier(&self.l1_data_gas_price, block),
+        );
+        header.timestamp = BlockTimestamp(
+            header
+                .timestamp
+                .0
+                .saturating_add_signed(self.timestamp_skew),
+        );
+
+        header
+    }
+}
+
+/// Returns the multiplier of the curve at the given block
+fn multiplier(curve: &[CurvePoint], block: u64) -> f64 {
+    let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+        return 1.0;
+    };
+    if block <= first.block {
+        return first.multiplier;
+    }
+    if block >= last.block {
+        return last.multiplier;
+    }
+
+    let window = curve
+        .windows(2)
+        .find(|window| window[0].block <= block && block < window[1].block)
+        .expect("block should be within the curve");
+    let (start, end) = (window[0], window[1]);
+
+    let progress = (block - start.block) as f64 / (end.block - start.block) as f64;
+    start.multiplier + (end.multiplier - start.multiplier) * progress
+}
+
+fn scale(price: ResourcePrice, multiplier: f64) -> ResourcePrice {
+    let scale = |price: GasPrice| GasPrice((price.0 as f64 * multiplier) as u128);
+    ResourcePrice {
+        price_in_wei: scale(price.price_in_wei),
+        price_in_fri: scale(price.price_in_fri),
+    }
+}
+
+#[derive(Serialize)]
+pub struct ScenarioTransaction {
+    pub block_number: u64,
+    pub tx_hash: TransactionHash,
+    pub onchain_outcome: Outcome,
+    pub outcome: Outcome,
+    /// Error of a failed execution, or revert reason
+    pub error: Option<String>,
+    pub onchain_fee: u128,
+    pub fee: Option<u128>,
+    /// Fee under the scenario, divided by the fee paid on chain
+    pub fee_ratio: Option<f64>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ScenarioSummary {
+    pub transactions: usize,
+    /// Successful transactions on chain that reverted under the scenario
+    pub newly_reverted: usize,
+    /// Transactions on chain that failed under the scenario, e.g. because
+    /// their max fee didn't cover the new gas prices
+    pub newly_failed: usize,
+    /// Transactions whose fee was multiplied or divided by at least the
+    /// fee threshold
+    pub drastic_fee_changes: usize,
+}
+
+#[derive(Serialize)]
+pub struct ScenarioReport {
+    pub summary: ScenarioSummary,
+    pub transactions: Vec<ScenarioTransaction>,
+}
+
+/// Executes every transaction of the block under the scenario, charging fees
+pub fn execute_block<S: BlockifierStateReader>(
+    reader: &impl StateReader,
+    scenario: &Scenario,
+    state: &mut CachedState<S>,
+) -> anyhow::Result<Vec<ScenarioTransaction>> {
+    let block = reader.get_block_with_tx_hashes()?;
+    let block_number = block.header.block_number.0;
+    let block_context =
+        block_context_from_header(scenario.apply(block.header), reader.get_chain_id())?;
+
+    let flags = ExecutionFlags {
+        only_query: false,
+        charge_fee: true,
+        validate: true,
+    };
+
+    let mut transactions = Vec::new();
+    for tx_hash in block.transactions {
+        let receipt = reader.get_transaction_receipt(&tx_hash)?;
+        let tx = fetch_blockifier_transaction(reader, flags.clone(), tx_hash)?;
+
+        let onchain_outcome = match receipt.execution_status {
+            TransactionExecutionStatus::Succeeded => Outcome::Success,
+            TransactionExecutionStatus::Reverted(_) => Outcome::Reverted,
+        };
+        let onchain_fee = receipt.actual_fee.amount.0;
+
+        let (outcome, error, fee) = match tx.execute(state, &block_context) {
+            Ok(execution_info) => {
+                let fee = Some(execution_info.receipt.fee.0);
+                match execution_info.revert_error {
+                    Some(revert_error) => (Outcome::Reverted, Some(revert_error.to_string()), fee),
+                    None => (Outcome::Success, None, fee),
+                }
+            }
+            Err(err) => (Outcome::Failed, Some(err.to_string()), None),
+        };
+
+        transactions.push(ScenarioTransaction {
+            block_number,
+            tx_hash,
+            onchain_outcome,
+            outcome,
+            error,
+            onchain_fee,
+            fee,
+            fee_ratio: fee
+                .filter(|_| onchain_fee > 0)
+                .map(|fee| fee as f64 / onchain_fee as f64),
+        });
+    }
+
+    Ok(transactions)
+}
+
+pub fn summarize(transactions: &[ScenarioTransaction], fee_threshold: f64) -> ScenarioSummary {
+    let mut summary = ScenarioSummary {
+        transactions: transactions.len(),
+        ..Default::default()
+    };
+
+    for transaction in transactions {
+        match (&transaction.onchain_outcome, &transaction.outcome) {
+            (Outcome::Success, Outcome::Reverted) => summary.newly_reverted += 1,
+            (Outcome::Success | Outcome::Reverted, Outcome::Failed) => summary.newly_failed += 1,
+            _ => {}
+        }
+        if transaction
+            .fee_ratio
+            .is_some_and(|ratio| ratio >= fee_threshold || ratio <= 1.0 / fee_threshold)
+        {
+            summary.drastic_fee_changes += 1;
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiplier() {
+        let curve = [
+            CurvePoint {
+                block: 10,
+                multiplier: 1.0,
+            },
+            CurvePoint {
+                block: 20,
+                multiplier: 3.0,
+            },
+        ];
+
+        assert_eq!(multiplier(&[], 15), 1.0);
+        assert_eq!(multiplier(&curve, 5), 1.0);
+        assert_eq!(multiplier(&curve, 15), 2.0);
+        assert_eq!(multiplier(&curve, 25), 3.0);
+    }
+}
@@ -0,0 +1,341 @@
+//! JSON-RPC server that exposes the replay engine as a tracing backend.
+//!
+//! Supports `starknet_traceTransaction`, `starknet_traceBlockTransactions`
+//! and `starknet_simulateTransactions`. Traces are computed by re-executing
+//! the block on top of the state of the previous block, so they reflect this
+//! crate's execution rather than the node's.
+
+use std::{collections::BTreeMap, io::Read};
+
+use anyhow::{anyhow, bail, Context};
+use blockifier::{
+    context::BlockContext,
+    state::{
+        cached_state::{CachedState, StateMaps},
+        state_api::StateReader as BlockifierStateReader,
+    },
+    transaction::{
+        account_transaction::ExecutionFlags, objects::TransactionExecutionInfo,
+        transaction_execution::Transaction as BlockiTransaction,
+        transaction_types::TransactionType, transactions::ExecutableTransaction,
+    },
+};
+use rpc_state_reader::{
+    cache::RpcCachedStateReader,
+    execution::{fetch_block_context, fetch_blockifier_transaction},
+    objects::deser::transaction_from_json,
+    reader::{RpcStateReader, StateReader},
+};
+use serde_json::{json, Value};
+use starknet_api::{
+    block::BlockNumber,
+    core::{ChainId, ClassHash},
+    transaction::{Transaction as SNTransaction, TransactionHash, TransactionOptions},
+};
+use tiny_http::{Header, Response, Server};
+use tracing::{info, info_span, warn};
+
+use crate::rpc_trace;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Serves JSON-RPC requests on the given address until the process is stopped
+pub fn serve(address: &str, chain: ChainId) -> anyhow::Result<()> {
+    let server = Server::http(address).map_err(|err| anyhow!("failed to bind {address}: {err}"))?;
+    info!("listening on {address}");
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        let response = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => handle_body(&chain, &body),
+            Err(err) => error_response(Value::Null, PARSE_ERROR, &err.to_string()),
+        };
+
+        let response =
+            Response::from_string(response.to_string()).with_header(content_type.clone());
+        if let Err(err) = request.respond(response) {
+            warn!("failed to send response: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_body(chain: &ChainId, body: &str) -> Value {
+    match serde_json::from_str(body) {
+        Ok(Value::Array(requests)) => requests
+            .iter()
+            .map(|request| handle_request(chain, request))
+            .collect(),
+        Ok(request) => handle_request(chain, &request),
+        Err(err) => error_response(Value::Null, PARSE_ERROR, &err.to_string()),
+    }
+}
+
+fn handle_request(chain: &ChainId, request: &Value) -> Value {
+    let id = request["id"].clone();
+    let params = &request["params"];
+
+    let Some(method) = request["method"].as_str() else {
+        return error_response(id, METHOD_NOT_FOUND, "missing method");
+    };
+    let _request_span = info_span!("request", method).entered();
+
+    let result = match method {
+        "starknet_traceTransaction" => trace_transaction(chain, params),
+        "starknet_traceBlockTransactions" => trace_block_transactions(chain, params),
+        "starknet_simulateTransactions" => simulate_transactions(chain, params),
+        _ => return error_response(id, METHOD_NOT_FOUND, "method not found"),
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => error_response(id, INTERNAL_ERROR, &format!("{err:#}")),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Returns a parameter given either by position or by name
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> anyhow::Result<&'a Value> {
+    let value = match params {
+        Value::Array(params) => params.get(index),
+        params => params.get(name),
+    };
+    value.with_context(|| format!("missing parameter: {name}"))
+}
+
+/// Only block ids given by number are supported, as blocks are replayed by number
+fn parse_block_id(block_id: &Value) -> anyhow::Result<BlockNumber> {
+    block_id["block_number"]
+        .as_u64()
+        .map(BlockNumber)
+        .context("unsupported block id, expected a block number")
+}
+
+fn trace_transaction(chain: &ChainId, params: &Value) -> anyhow::Result<Value> {
+    let tx_hash: TransactionHash =
+        serde_json::from_value(param(params, 0, "transaction_hash")?.clone())?;
+
+    // Receipts don't depend on the block of the reader
+    let receipt =
+        RpcStateReader::new(chain.clone(), BlockNumber(0)).get_transaction_receipt(&tx_hash)?;
+
+    let traces = trace_block(chain, BlockNumber(receipt.block_number), Some(tx_hash))?;
+    let (_, trace) = traces
+        .into_iter()
+        .last()
+        .context("transaction not found in its block")?;
+
+    Ok(trace)
+}
+
+fn trace_block_transactions(chain: &ChainId, params: &Value) -> anyhow::Result<Value> {
+    let block_number = parse_block_id(param(params, 0, "block_id")?)?;
+
+    let traces = trace_block(chain, block_number, None)?
+        .into_iter()
+        .map(|(tx_hash, trace)| json!({ "transaction_hash": tx_hash, "trace_root": trace }))
+        .collect();
+
+    Ok(Value::Array(traces))
+}
+
+/// Executes the transactions of the block in order, returning their traces.
+/// If `until` is given, stops after executing that transaction.
+fn trace_block(
+    chain: &ChainId,
+    block_number: BlockNumber,
+    until: Option<TransactionHash>,
+) -> anyhow::Result<Vec<(TransactionHash, Value)>> {
+    let reader = RpcCachedStateReader::new(RpcStateReader::new(chain.clone(), block_number));
+    let block_context = fetch_block_context(&reader)?;
+
+    let previous_block_number = block_number
+        .prev()
+        .context("block number had no previous")?;
+    let mut state = CachedState::new(RpcCachedStateReader::new(RpcStateReader::new(
+        chain.clone(),
+        previous_block_number,
+    )));
+
+    let flags = ExecutionFlags {
+        only_query: false,
+        charge_fee: true,
+        validate: true,
+    };
+
+    let mut traces = Vec::new();
+    for tx_hash in reader.get_block_with_tx_hashes()?.transactions {
+        let tx = fetch_blockifier_transaction(&reader, flags.clone(), tx_hash)?;
+        let (_, trace) = execute_traced(&mut state, &tx, &block_context)?;
+        traces.push((tx_hash, trace));
+
+        if until == Some(tx_hash) {
+            break;
+        }
+    }
+
+    Ok(traces)
+}
+
+/// Simulates the transactions in order, on top of the state after the given
+/// block and with its block context. Declare transactions are not supported.
+fn simulate_transactions(chain: &ChainId, params: &Value) -> anyhow::Result<Value> {
+    let block_number = parse_block_id(param(params, 0, "block_id")?)?;
+    let transactions = param(params, 1, "transactions")?
+        .as_array()
+        .context("transactions must be an array")?;
+    let simulation_flags = param(params, 2, "simulation_flags")
+        .ok()
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    let has_flag = |flag: &str| simulation_flags.iter().any(|f| f.as_str() == Some(flag));
+    let flags = ExecutionFlags {
+        only_query: false,
+        charge_fee: !has_flag("SKIP_FEE_CHARGE"),
+        validate: !has_flag("SKIP_VALIDATE"),
+    };
+
+    let reader = RpcCachedStateReader::new(RpcStateReader::new(chain.clone(), block_number));
+    let block_context = fetch_block_context(&reader)?;
+    let mut state = CachedState::new(reader);
+
+    let mut simulations = Vec::new();
+    for transaction in transactions {
+        let fee_unit = if transaction["version"].as_str() == Some("0x3") {
+            "FRI"
+        } else {
+            "WEI"
+        };
+
+        let transaction = transaction_from_json(transaction.clone())?;
+        if let SNTransaction::Declare(_) = transaction {
+            bail!("declare transactions can't be simulated");
+        }
+        let tx_hash = transaction
+            .calculate_transaction_hash(chain, &TransactionOptions { only_query: false })?;
+        let tx =
+            BlockiTransaction::from_api(transaction, tx_hash, None, None, None, flags.clone())?;
+
+        let (execution_info, trace) = execute_traced(&mut state, &tx, &block_context)?;
+        let gas = execution_info.receipt.gas;
+
+        simulations.push(json!({
+            "transaction_trace": trace,
+            "fee_estimation": {
+                "l1_gas_consumed": gas.l1_gas,
+                "l1_data_gas_consumed": gas.l1_data_gas,
+                "l2_gas_consumed": gas.l2_gas,
+                "overall_fee": execution_info.receipt.fee,
+                "unit": fee_unit,
+            },
+        }));
+    }
+
+    Ok(Value::Array(simulations))
+}
+
+/// Executes the transaction, applying its state changes, and returns its trace
+/// in the format of the RPC specification.
+fn execute_traced(
+    state: &mut CachedState<RpcCachedStateReader>,
+    tx: &BlockiTransaction,
+    block_context: &BlockContext,
+) -> anyhow::Result<(TransactionExecutionInfo, Value)> {
+    let mut transactional_state = CachedState::create_transactional(state);
+    let execution_info = tx.execute(&mut transactional_state, block_context)?;
+    let writes = transactional_state.to_state_diff()?.state_maps;
+    transactional_state.commit();
+
+    let tx_type = match tx {
+        BlockiTransaction::L1Handler(_) => "L1_HANDLER",
+        BlockiTransaction::Account(tx) => match tx.tx_type() {
+            TransactionType::Declare => "DECLARE",
+            TransactionType::DeployAccount => "DEPLOY_ACCOUNT",
+            TransactionType::InvokeFunction => "INVOKE",
+            TransactionType::L1Handler => "L1_HANDLER",
+        },
+    };
+
+    let mut trace = rpc_trace::transaction_trace(tx_type, &execution_info);
+    trace["state_diff"] = state_diff_json(&state.state, writes)?;
+
+    Ok((execution_info, trace))
+}
+
+/// Builds the state diff of a transaction.
+///
+/// Contracts whose class hash changed are reported as replaced if they
+/// already existed in the previous block, and as deployed otherwise.
+fn state_diff_json(
+    previous_block: &RpcCachedStateReader,
+    writes: StateMaps,
+) -> anyhow::Result<Value> {
+    let mut storage = BTreeMap::<_, Vec<Value>>::new();
+    for ((contract_address, key), value) in writes.storage {
+        storage
+            .entry(contract_address)
+            .or_default()
+            .push(json!({ "key": key, "value": value }));
+    }
+    let storage_diffs = storage
+        .into_iter()
+        .map(|(address, storage_entries)| {
+            json!({ "address": address, "storage_entries": storage_entries })
+        })
+        .collect::<Vec<_>>();
+
+    let nonces = writes
+        .nonces
+        .into_iter()
+        .map(|(contract_address, nonce)| {
+            json!({ "contract_address": contract_address, "nonce": nonce })
+        })
+        .collect::<Vec<_>>();
+
+    let mut deployed_contracts = Vec::new();
+    let mut replaced_classes = Vec::new();
+    for (address, class_hash) in writes.class_hashes {
+        if previous_block.get_class_hash_at(address)? == ClassHash::default() {
+            deployed_contracts.push(json!({ "address": address, "class_hash": class_hash }));
+        } else {
+            replaced_classes.push(json!({ "contract_address": address, "class_hash": class_hash }));
+        }
+    }
+
+    let declared_classes = writes
+        .compiled_class_hashes
+        .iter()
+        .map(|(class_hash, compiled_class_hash)| {
+            json!({ "class_hash": class_hash, "compiled_class_hash": compiled_class_hash })
+        })
+        .collect::<Vec<_>>();
+    let deprecated_declared_classes = writes
+        .declared_contracts
+        .keys()
+        .filter(|class_hash| !writes.compiled_class_hashes.contains_key(class_hash))
+        .collect::<Vec<_>>();
+
+    Ok(json!({
+        "storage_diffs": storage_diffs,
+        "nonces": nonces,
+        "deployed_contracts": deployed_contracts,
+        "replaced_classes": replaced_classes,
+        "declared_classes": declared_classes,
+        "deprecated_declared_classes": deprecated_declared_classes,
+    }))
+}
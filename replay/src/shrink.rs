@@ -0,0 +1,199 @@
+//! Reduction of a diverging multicall transaction to the calls that cause
+//! the divergence between Cairo Native and the Cairo VM.
+//!
+//! The transaction is re-executed without validation, removing one call at a
+//! time and keeping the removal whenever the remaining calls still diverge.
+//! Only the multicall format of Cairo 1 accounts is supported.
+
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context};
+use blockifier::{
+    context::BlockContext,
+    state::{cached_state::CachedState, state_api::StateReader as BlockifierStateReader},
+    transaction::{
+        account_transaction::ExecutionFlags,
+        transaction_execution::Transaction as BlockiTransaction,
+    },
+};
+use serde::Serialize;
+use starknet_api::{
+    hash::StarkHash,
+    transaction::{
+        fields::Calldata, InvokeTransaction, InvokeTransactionV1, InvokeTransactionV3,
+        Transaction as SNTransaction, TransactionHash,
+    },
+};
+
+use crate::divergence::{self, Divergence};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Call {
+    pub to: StarkHash,
+    pub selector: StarkHash,
+    pub calldata: Vec<StarkHash>,
+}
+
+#[derive(Serialize)]
+pub struct ShrinkResult {
+    /// Number of calls of the original transaction
+    pub original_calls: usize,
+    /// Minimal set of calls that still diverges, in their original order
+    pub calls: Vec<Call>,
+    /// Divergences of the minimal set of calls
+    pub divergences: Vec<Divergence>,
+}
+
+/// Returns the minimal set of calls of the transaction that still diverges,
+/// or `None` if the transaction doesn't diverge when executed without validation.
+///
+/// Validation is skipped regardless of the flags, as removing calls
+/// invalidates the signature.
+pub fn shrink_transaction<S: BlockifierStateReader>(
+    transaction: &SNTransaction,
+    tx_hash: TransactionHash,
+    flags: ExecutionFlags,
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+) -> anyhow::Result<Option<ShrinkResult>> {
+    let SNTransaction::Invoke(invoke) = transaction else {
+        bail!("only invoke transactions can be shrunk");
+    };
+    let calls = parse_multicall(&invoke.calldata().0)?;
+
+    let mut diff = |calls: &[Call]| -> anyhow::Result<Vec<Divergence>> {
+        let tx = with_calls(invoke, calls, tx_hash, flags.clone())?;
+        divergence::diff_transaction(&tx, state, block_context)
+    };
+
+    let mut divergences = diff(&calls)?;
+    if divergences.is_empty() {
+        return Ok(None);
+    }
+
+    let original_calls = calls.len();
+    let mut remaining = calls;
+    let mut index = 0;
+    while index < remaining.len() {
+        let mut candidate = remaining.clone();
+        candidate.remove(index);
+
+        let candidate_divergences = diff(&candidate)?;
+        if candidate_divergences.is_empty() {
+            // The call is needed to reproduce the divergence
+            index += 1;
+        } else {
+            remaining = candidate;
+            divergences = candidate_divergences;
+        }
+    }
+
+    Ok(Some(ShrinkResult {
+        original_calls,
+        calls: remaining,
+        divergences,
+    }))
+}
+
+/// Builds the transaction with the given calls, without validation
+fn with_calls(
+    invoke: &InvokeTransaction,
+    calls: &[Call],
+    tx_hash: TransactionHash,
+    flags: ExecutionFlags,
+) -> anyhow::Result<BlockiTransaction> {
+    let calldata = Calldata(Arc::new(encode_multicall(calls)));
+    let invoke = match invoke {
+        InvokeTransaction::V0(_) => bail!("invoke v0 transactions have no multicall"),
+        InvokeTransaction::V1(tx) => InvokeTransaction::V1(InvokeTransactionV1 {
+            calldata,
+            ..tx.clone()
+        }),
+        InvokeTransaction::V3(tx) => InvokeTransaction::V3(InvokeTransactionV3 {
+            calldata,
+            ..tx.clone()
+        }),
+    };
+
+    let flags = ExecutionFlags {
+        validate: false,
+        ..flags
+    };
+
+    Ok(BlockiTransaction::from_api(
+        SNTransaction::Invoke(invoke),
+        tx_hash,
+        None,
+        None,
+        None,
+        flags,
+    )?)
+}
+
+/// Parses the calldata of `__execute__`: the number of calls, followed by
+/// the address, selector, calldata length and calldata of each call.
+fn parse_multicall(calldata: &[StarkHash]) -> anyhow::Result<Vec<Call>> {
+    let (call_count, mut rest) = calldata
+        .split_first()
+        .context("multicall calldata is empty")?;
+
+    let mut calls = Vec::new();
+    for _ in 0..to_usize(call_count)? {
+        ensure!(rest.len() >= 3, "multicall calldata is truncated");
+        let len = to_usize(&rest[2])?;
+        ensure!(rest.len() - 3 >= len, "multicall calldata is truncated");
+
+        calls.push(Call {
+            to: rest[0],
+            selector: rest[1],
+            calldata: rest[3..3 + len].to_vec(),
+        });
+        rest = &rest[3 + len..];
+    }
+    ensure!(rest.is_empty(), "multicall calldata has trailing elements");
+
+    Ok(calls)
+}
+
+fn to_usize(felt: &StarkHash) -> anyhow::Result<usize> {
+    usize::try_from(felt.to_biguint()).context("multicall length is too big")
+}
+
+pub fn encode_multicall(calls: &[Call]) -> Vec<StarkHash> {
+    let mut calldata = vec![calls.len().into()];
+    for call in calls {
+        calldata.extend([call.to, call.selector, call.calldata.len().into()]);
+        calldata.extend(&call.calldata);
+    }
+    calldata
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::felt;
+
+    use super::*;
+
+    #[test]
+    fn test_multicall_roundtrip() {
+        let calls = vec![
+            Call {
+                to: felt!("0x1"),
+                selector: felt!("0x2"),
+                calldata: vec![felt!("0x3"), felt!("0x4")],
+            },
+            Call {
+                to: felt!("0x5"),
+                selector: felt!("0x6"),
+                calldata: Vec::new(),
+            },
+        ];
+
+        let calldata = encode_multicall(&calls);
+        assert_eq!(calldata.len(), 1 + 5 + 3);
+        assert_eq!(parse_multicall(&calldata).unwrap(), calls);
+
+        assert!(parse_multicall(&calldata[..calldata.len() - 1]).is_err());
+        assert!(parse_multicall(&[calldata.clone(), vec![felt!("0x7")]].concat()).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! What-if execution of a transaction on top of an overridden state, e.g. to
+//! check whether it would have succeeded with a higher allowance or balance.
+//!
+//! Overrides are JSON files, where every section is optional:
+//!
+//! ```json
+//! {
+//!   "storage": [{ "contract_address": "<address>", "key": "<key>", "value": "0x1" }],
+//!   "nonces": [{ "contract_address": "<address>", "nonce": "0x5" }],
+//!   "balances": [{ "contract_address": "<address>", "token": "strk", "amount": 1000000000000000000 }],
+//!   "class_hashes": [{ "contract_address": "<address>", "class_hash": "<class_hash>" }]
+//! }
+//! ```
+//!
+//! Balances are written to the storage of the fee token of the chain (STRK
+//! by default). Overridden values are returned instead of the ones of the
+//! historical state, so they can also lower a nonce or a balance.
+
+use std::fs;
+
+use anyhow::Context;
+use blockifier::{
+    context::{BlockContext, FeeTokenAddresses},
+    execution::contract_class::RunnableCompiledClass,
+    state::{
+        cached_state::{CachedState, StateMaps},
+        state_api::{StateReader as BlockifierStateReader, StateResult},
+    },
+    transaction::{
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use serde::{Deserialize, Serialize};
+use starknet_api::{
+    abi::abi_utils::get_fee_token_var_address,
+    core::{ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    hash::StarkHash,
+    state::StorageKey,
+};
+
+use crate::audit::Outcome;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageOverride {
+    pub contract_address: ContractAddress,
+    pub key: StorageKey,
+    pub value: StarkHash,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NonceOverride {
+    pub contract_address: ContractAddress,
+    pub nonce: Nonce,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeToken {
+    Eth,
+    #[default]
+    Strk,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalanceOverride {
+    pub contract_address: ContractAddress,
+    #[serde(default)]
+    pub token: FeeToken,
+    pub amount: u128,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClassHashOverride {
+    pub contract_address: ContractAddress,
+    pub class_hash: ClassHash,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateOverrides {
+    #[serde(default)]
+    pub storage: Vec<StorageOverride>,
+    #[serde(default)]
+    pub nonces: Vec<NonceOverride>,
+    #[serde(default)]
+    pub balances: Vec<BalanceOverride>,
+    #[serde(default)]
+    pub class_hashes: Vec<ClassHashOverride>,
+}
+
+pub fn load_overrides(path: &str) -> anyhow::Result<StateOverrides> {
+    let overrides =
+        fs::read_to_string(path).with_context(|| format!("failed to read overrides {path}"))?;
+    serde_json::from_str(&overrides).with_context(|| format!("failed to parse overrides {path}"))
+}
+
+impl StateOverrides {
+    /// Returns the overridden values, with balances written as the low and
+    /// high words of the fee token balance of each account
+    pub fn to_state_maps(&self, fee_tokens: &FeeTokenAddresses) -> anyhow::Result<StateMaps> {
+        let mut state_maps = StateMaps::default();
+
+        for storage in &self.storage {
+            state_maps
+                .storage
+                .insert((storage.contract_address, storage.key), storage.value);
+        }
+        for balance in &self.balances {
+            let token = match balance.token {
+                FeeToken::Eth => fee_tokens.eth_fee_token_address,
+                FeeToken::Strk => fee_tokens.strk_fee_token_address,
+            };
+            let low_key = get_fee_token_var_address(balance.contract_address);
+            let high_key = low_key.next_storage_key()?;
+            state_maps
+                .storage
+                .insert((token, low_key), StarkHash::from(balance.amount));
+            state_maps
+                .storage
+                .insert((token, high_key), StarkHash::ZERO);
+        }
+        for nonce in &self.nonces {
+            state_maps
+                .nonces
+                .insert(nonce.contract_address, nonce.nonce);
+        }
+        for class_hash in &self.class_hashes {
+            state_maps
+                .class_hashes
+                .insert(class_hash.contract_address, class_hash.class_hash);
+        }
+
+        Ok(state_maps)
+    }
+}
+
+/// A state reader that returns the overridden values instead of the ones of
+/// the inner state.
+pub struct OverrideStateReader<S: BlockifierStateReader> {
+    pub inner: S,
+    pub overrides: StateMaps,
+}
+
+impl<S: BlockifierStateReader> BlockifierStateReader for OverrideStateReader<S> {
+    fn get_storage_at(
+        &self,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> StateResult<StarkHash> {
+        match self.overrides.storage.get(&(contract_address, key)) {
+            Some(value) => Ok(*value),
+            None => self.inner.get_storage_at(contract_address, key),
+        }
+    }
+
+    fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
+        match self.overrides.nonces.get(&contract_address) {
+            Some(nonce) => Ok(*nonce),
+            None => self.inner.get_nonce_at(contract_address),
+        }
+    }
+
+    fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        match self.overrides.class_hashes.get(&contract_address) {
+            Some(class_hash) => Ok(*class_hash),
+            None => self.inner.get_class_hash_at(contract_address),
+        }
+    }
+
+    fn get_compiled_class(&self, class_hash: ClassHash) -> StateResult<RunnableCompiledClass> {
+        self.inner.get_compiled_class(class_hash)
+    }
+
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.inner.get_compiled_class_hash(class_hash)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Simulation {
+    pub tx_hash: String,
+    pub outcome: Outcome,
+    /// Revert reason, or the error if the transaction failed
+    pub error: Option<String>,
+    pub fee: Option<u128>,
+    /// Sierra gas consumed by the calls of the transaction
+    pub gas_consumed: Option<u64>,
+}
+
+/// Executes the transaction on top of the overridden state
+pub fn simulate<S: BlockifierStateReader>(
+    tx_hash: &str,
+    tx: &BlockiTransaction,
+    state: &mut CachedState<OverrideStateReader<S>>,
+    block_context: &BlockContext,
+) -> Simulation {
+    let (outcome, error, execution_info) = match tx.execute(state, block_context) {
+        Ok(execution_info) if execution_info.is_reverted() => (
+            Outcome::Reverted,
+            execution_info
+                .revert_error
+                .as_ref()
+                .map(ToString::to_string),
+            Some(execution_info),
+        ),
+        Ok(execution_info) => (Outcome::Success, None, Some(execution_info)),
+        Err(err) => (Outcome::Failed, Some(err.to_string()), None),
+    };
+
+    Simulation {
+        tx_hash: tx_hash.to_string(),
+        outcome,
+        error,
+        fee: execution_info
+            .as_ref()
+            .map(|execution_info| execution_info.receipt.fee.0),
+        gas_consumed: execution_info.as_ref().map(|execution_info| {
+            execution_info
+                .non_optional_call_infos()
+                .map(|call_info| call_info.execution.gas_consumed)
+                .sum()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_state_maps() {
+        let overrides: StateOverrides = serde_json::from_str(
+            r#"{
+                "storage": [{ "contract_address": "0x1", "key": "0x2", "value": "0x3" }],
+                "balances": [{ "contract_address": "0x1", "amount": 10 }],
+                "nonces": [{ "contract_address": "0x1", "nonce": "0x5" }]
+            }"#,
+        )
+        .unwrap();
+        let fee_tokens = FeeTokenAddresses {
+            eth_fee_token_address: ContractAddress::from(10u128),
+            strk_fee_token_address: ContractAddress::from(11u128),
+        };
+
+        let state_maps = overrides.to_state_maps(&fee_tokens).unwrap();
+        assert_eq!(state_maps.storage.len(), 3);
+        let balance_key = get_fee_token_var_address(ContractAddress::from(1u128));
+        assert_eq!(
+            state_maps.storage[&(fee_tokens.strk_fee_token_address, balance_key)],
+            StarkHash::from(10u128)
+        );
+        assert_eq!(
+            state_maps.nonces[&ContractAddress::from(1u128)],
+            Nonce(StarkHash::from(5u64))
+        );
+
+        assert!(serde_json::from_str::<StateOverrides>(r#"{ "balance": [] }"#).is_err());
+    }
+}
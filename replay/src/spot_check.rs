@@ -0,0 +1,130 @@
+//! Cheap validation of a replayed block, comparing a sample of the written
+//! storage keys against the values reported by the node after the block.
+//!
+//! Keys are sampled deterministically by hash, so that replaying the same
+//! block always checks the same keys.
+
+use blockifier::state::{
+    cached_state::{CachedState, StorageEntry},
+    state_api::StateReader as BlockifierStateReader,
+};
+use starknet_api::{core::ContractAddress, hash::StarkHash, state::StorageKey};
+
+pub struct StorageMismatch {
+    pub contract_address: ContractAddress,
+    pub key: StorageKey,
+    pub executed: StarkHash,
+    pub onchain: StarkHash,
+}
+
+pub struct SpotCheck {
+    /// Number of storage keys written by the block
+    pub written: usize,
+    /// Number of storage keys checked against the node
+    pub checked: usize,
+    pub mismatches: Vec<StorageMismatch>,
+}
+
+/// Compares up to `sample_size` storage keys written by the executed block
+/// against their values at the end of the block, as reported by `reader`.
+pub fn spot_check_storage<S: BlockifierStateReader>(
+    state: &mut CachedState<S>,
+    reader: &impl BlockifierStateReader,
+    block_number: u64,
+    sample_size: usize,
+) -> anyhow::Result<SpotCheck> {
+    let storage = state.to_state_diff()?.state_maps.storage;
+
+    let sample = sample_storage_keys(block_number, storage.keys().copied(), sample_size);
+
+    let mut mismatches = Vec::new();
+    for (contract_address, key) in &sample {
+        let executed = storage[&(*contract_address, *key)];
+        let onchain = reader.get_storage_at(*contract_address, *key)?;
+
+        if executed != onchain {
+            mismatches.push(StorageMismatch {
+                contract_address: *contract_address,
+                key: *key,
+                executed,
+                onchain,
+            });
+        }
+    }
+
+    Ok(SpotCheck {
+        written: storage.len(),
+        checked: sample.len(),
+        mismatches,
+    })
+}
+
+/// Returns the `sample_size` entries with the lowest hash
+fn sample_storage_keys(
+    block_number: u64,
+    entries: impl Iterator<Item = StorageEntry>,
+    sample_size: usize,
+) -> Vec<StorageEntry> {
+    let mut entries = entries
+        .map(|entry| (hash_entry(block_number, &entry), entry))
+        .collect::<Vec<_>>();
+    entries.sort_unstable();
+
+    entries
+        .into_iter()
+        .take(sample_size)
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+/// 64-bit FNV-1a hash of the block number and the storage entry.
+///
+/// The standard library hashers are not guaranteed to be stable
+/// across releases, so we can't rely on them for reproducible samples.
+fn hash_entry(block_number: u64, (contract_address, key): &StorageEntry) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    block_number
+        .to_be_bytes()
+        .into_iter()
+        .chain(contract_address.0.key().to_bytes_be())
+        .chain(key.0.key().to_bytes_be())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::{core::PatriciaKey, felt};
+
+    use super::*;
+
+    fn entry(address: &str, key: &str) -> StorageEntry {
+        (
+            ContractAddress::try_from(felt!(address)).unwrap(),
+            StorageKey(PatriciaKey::try_from(felt!(key)).unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_sample_storage_keys() {
+        let entries = [
+            entry("0x1", "0x10"),
+            entry("0x1", "0x11"),
+            entry("0x2", "0x10"),
+            entry("0x3", "0x12"),
+        ];
+
+        let sample = sample_storage_keys(90000, entries.iter().copied(), 2);
+        assert_eq!(sample.len(), 2);
+
+        // The sample doesn't depend on the order of the entries
+        let reversed_sample = sample_storage_keys(90000, entries.iter().rev().copied(), 2);
+        assert_eq!(sample, reversed_sample);
+
+        let full_sample = sample_storage_keys(90000, entries.iter().copied(), 10);
+        assert_eq!(full_sample.len(), entries.len());
+    }
+}
@@ -0,0 +1,282 @@
+//! Instrumentation of the state reads made during execution.
+//!
+//! This is not syscall instrumentation: the syscall handlers are built by the
+//! blockifier, so they can't be wrapped, and syscalls that don't read the
+//! state (e.g. `emit_event` or `send_message_to_l1`) are neither measured nor
+//! faulted. Out of gas faults can't be injected either. Instead, the
+//! transaction is executed on top of a state reader that counts and times
+//! every read reaching it, and that can fail specific reads with a state
+//! error, as configured in a JSON file:
+//!
+//! ```json
+//! {
+//!   "faults": [
+//!     { "access": "storage_read", "target": "<contract_address>", "after": 2 },
+//!     { "access": "compiled_class", "target": "<class_hash>" }
+//!   ]
+//! }
+//! ```
+//!
+//! Each access is triggered by the following syscalls:
+//! - `storage_read`: `storage_read`.
+//! - `class_hash_at`: `call_contract`, `deploy` and `get_class_hash_at`.
+//! - `compiled_class`: `call_contract`, `library_call`, `deploy` and `replace_class`.
+//! - `nonce_at`: no syscall, read when validating the transaction.
+//!
+//! As the state caches its reads, only the first access to each value in a
+//! transaction reaches the instrumented reader.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt, fs,
+    time::{Duration, Instant},
+};
+
+use blockifier::{
+    context::BlockContext,
+    execution::contract_class::RunnableCompiledClass,
+    state::{
+        cached_state::CachedState,
+        errors::StateError,
+        state_api::{StateReader as BlockifierStateReader, StateResult},
+    },
+    transaction::{
+        errors::TransactionExecutionError, objects::TransactionExecutionInfo,
+        transaction_execution::Transaction as BlockiTransaction,
+        transactions::ExecutableTransaction,
+    },
+};
+use serde::Deserialize;
+use starknet_api::{
+    core::{ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    hash::StarkHash,
+    state::StorageKey,
+};
+use tracing::{info, warn};
+
+use crate::fallback;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    StorageRead,
+    ClassHashAt,
+    CompiledClass,
+    NonceAt,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::StorageRead => write!(f, "storage_read"),
+            Access::ClassHashAt => write!(f, "class_hash_at"),
+            Access::CompiledClass => write!(f, "compiled_class"),
+            Access::NonceAt => write!(f, "nonce_at"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Fault {
+    pub access: Access,
+    /// Contract address (or class hash, for `compiled_class`) whose accesses
+    /// fail. If missing, every access of the given kind fails.
+    pub target: Option<StarkHash>,
+    /// Amount of matching accesses to let through before failing
+    #[serde(default)]
+    pub after: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StateAccessConfig {
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+}
+
+impl StateAccessConfig {
+    /// Returns whether the access must fail, counting it towards the
+    /// matching accesses of each fault.
+    fn inject_fault(&self, matches: &mut [u64], access: Access, target: StarkHash) -> bool {
+        let mut inject = false;
+        for (fault, matches) in self.faults.iter().zip(matches.iter_mut()) {
+            if fault.access != access || fault.target.is_some_and(|t| t != target) {
+                continue;
+            }
+            *matches += 1;
+            inject |= *matches > fault.after;
+        }
+        inject
+    }
+}
+
+/// Parses the config file given through the CLI.
+pub fn parse_config(path: &str) -> Result<StateAccessConfig, String> {
+    let config = fs::read_to_string(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+    serde_json::from_str(&config).map_err(|err| format!("failed to parse {path}: {err}"))
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AccessStats {
+    pub count: u64,
+    pub time: Duration,
+    pub faults: u64,
+}
+
+/// A state reader that measures every read, failing those matching a fault.
+pub struct InstrumentedStateReader<'a, S: BlockifierStateReader> {
+    state_reader: &'a S,
+    config: &'a StateAccessConfig,
+    stats: RefCell<BTreeMap<Access, AccessStats>>,
+    /// Matching accesses of each fault so far
+    matches: RefCell<Vec<u64>>,
+}
+
+impl<'a, S: BlockifierStateReader> InstrumentedStateReader<'a, S> {
+    pub fn new(state_reader: &'a S, config: &'a StateAccessConfig) -> Self {
+        Self {
+            state_reader,
+            config,
+            stats: RefCell::default(),
+            matches: RefCell::new(vec![0; config.faults.len()]),
+        }
+    }
+
+    pub fn stats(&self) -> BTreeMap<Access, AccessStats> {
+        self.stats.borrow().clone()
+    }
+
+    fn instrument<T>(
+        &self,
+        access: Access,
+        target: StarkHash,
+        read: impl FnOnce(&S) -> StateResult<T>,
+    ) -> StateResult<T> {
+        let inject = self
+            .config
+            .inject_fault(&mut self.matches.borrow_mut(), access, target);
+
+        let pre_read_instant = Instant::now();
+        let result = if inject {
+            Err(StateError::StateReadError(format!(
+                "injected {access} fault for {}",
+                target.to_hex_string()
+            )))
+        } else {
+            read(self.state_reader)
+        };
+
+        let mut stats = self.stats.borrow_mut();
+        let stats = stats.entry(access).or_default();
+        stats.count += 1;
+        stats.time += pre_read_instant.elapsed();
+        stats.faults += inject as u64;
+
+        result
+    }
+}
+
+impl<S: BlockifierStateReader> BlockifierStateReader for InstrumentedStateReader<'_, S> {
+    fn get_storage_at(
+        &self,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> StateResult<StarkHash> {
+        self.instrument(Access::StorageRead, *contract_address.0.key(), |state| {
+            state.get_storage_at(contract_address, key)
+        })
+    }
+
+    fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
+        self.instrument(Access::NonceAt, *contract_address.0.key(), |state| {
+            state.get_nonce_at(contract_address)
+        })
+    }
+
+    fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        self.instrument(Access::ClassHashAt, *contract_address.0.key(), |state| {
+            state.get_class_hash_at(contract_address)
+        })
+    }
+
+    fn get_compiled_class(&self, class_hash: ClassHash) -> StateResult<RunnableCompiledClass> {
+        self.instrument(Access::CompiledClass, class_hash.0, |state| {
+            state.get_compiled_class(class_hash)
+        })
+    }
+
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.state_reader.get_compiled_class_hash(class_hash)
+    }
+}
+
+/// Executes the transaction on top of an instrumented reader, logging the
+/// statistics of each access. The state changes are only applied to the
+/// given state if the execution succeeds.
+pub fn execute_instrumented<S: BlockifierStateReader>(
+    tx: &BlockiTransaction,
+    state: &mut CachedState<S>,
+    context: &BlockContext,
+    config: &StateAccessConfig,
+) -> Result<TransactionExecutionInfo, TransactionExecutionError> {
+    let (execution_info, state_maps, declared_classes) = {
+        let mut instrumented_state =
+            CachedState::new(InstrumentedStateReader::new(&*state, config));
+        let execution_info = tx.execute(&mut instrumented_state, context);
+
+        for (access, stats) in instrumented_state.state.stats() {
+            info!(
+                %access,
+                count = stats.count,
+                time_ms = stats.time.as_millis(),
+                faults = stats.faults,
+                "state accesses"
+            );
+        }
+
+        let execution_info = execution_info
+            .inspect_err(|err| warn!(error = err.to_string(), "instrumented execution failed"))?;
+        let mut state_maps = instrumented_state.to_state_diff()?.state_maps;
+
+        let declared_classes = state_maps
+            .declared_contracts
+            .keys()
+            .map(|class_hash| {
+                Ok((
+                    *class_hash,
+                    instrumented_state.get_compiled_class(*class_hash)?,
+                ))
+            })
+            .collect::<StateResult<Vec<_>>>()?;
+
+        state_maps.declared_contracts.clear();
+
+        (execution_info, state_maps, declared_classes)
+    };
+
+    fallback::apply_state_maps(state, state_maps, declared_classes)?;
+
+    Ok(execution_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::felt;
+
+    use super::*;
+
+    #[test]
+    fn test_inject_fault() {
+        let config: StateAccessConfig = serde_json::from_str(
+            r#"{ "faults": [{ "access": "storage_read", "target": "0x1", "after": 1 }] }"#,
+        )
+        .unwrap();
+        let mut matches = vec![0; config.faults.len()];
+        let mut inject = |access, target| config.inject_fault(&mut matches, access, target);
+
+        assert!(!inject(Access::StorageRead, felt!("0x1")));
+        assert!(!inject(Access::StorageRead, felt!("0x2")));
+        assert!(!inject(Access::ClassHashAt, felt!("0x1")));
+        assert!(inject(Access::StorageRead, felt!("0x1")));
+    }
+}
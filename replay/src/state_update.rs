@@ -0,0 +1,132 @@
+//! End-to-end validation of a replayed block, comparing the state changes of
+//! the execution against the state update reported by the node.
+//!
+//! Entries present in the state update are checked against the final value
+//! in the executed state, so writes that leave a value unchanged are not
+//! reported. Entries changed by the execution but missing from the state
+//! update are always reported.
+
+use std::collections::HashSet;
+
+use blockifier::state::{
+    cached_state::CachedState, state_api::StateReader as BlockifierStateReader,
+};
+use rpc_state_reader::objects::RpcStateDiff;
+use starknet_api::hash::StarkHash;
+
+pub struct StateDiffMismatch {
+    /// Kind of state entry: `storage`, `nonce` or `class_hash`
+    pub kind: &'static str,
+    pub contract_address: StarkHash,
+    /// Storage key, only set for storage entries
+    pub key: Option<StarkHash>,
+    pub executed: StarkHash,
+    /// Value reported by the node, or `None` if the entry is not in the state update
+    pub onchain: Option<StarkHash>,
+}
+
+/// Compares the state changes applied to `state` against the state diff of the block
+pub fn compare_state_diff<S: BlockifierStateReader>(
+    state: &mut CachedState<S>,
+    state_diff: &RpcStateDiff,
+) -> anyhow::Result<Vec<StateDiffMismatch>> {
+    let executed = state.to_state_diff()?.state_maps;
+
+    let mut mismatches = Vec::new();
+
+    let mut onchain_storage = HashSet::new();
+    for storage_diff in &state_diff.storage_diffs {
+        for entry in &storage_diff.storage_entries {
+            onchain_storage.insert((storage_diff.address, entry.key));
+
+            let value = state.get_storage_at(storage_diff.address, entry.key)?;
+            if value != entry.value {
+                mismatches.push(StateDiffMismatch {
+                    kind: "storage",
+                    contract_address: *storage_diff.address.0.key(),
+                    key: Some(*entry.key.0.key()),
+                    executed: value,
+                    onchain: Some(entry.value),
+                });
+            }
+        }
+    }
+    for ((contract_address, key), value) in &executed.storage {
+        if !onchain_storage.contains(&(*contract_address, *key)) {
+            mismatches.push(StateDiffMismatch {
+                kind: "storage",
+                contract_address: *contract_address.0.key(),
+                key: Some(*key.0.key()),
+                executed: *value,
+                onchain: None,
+            });
+        }
+    }
+
+    let mut onchain_nonces = HashSet::new();
+    for update in &state_diff.nonces {
+        onchain_nonces.insert(update.contract_address);
+
+        let nonce = state.get_nonce_at(update.contract_address)?;
+        if nonce != update.nonce {
+            mismatches.push(StateDiffMismatch {
+                kind: "nonce",
+                contract_address: *update.contract_address.0.key(),
+                key: None,
+                executed: nonce.0,
+                onchain: Some(update.nonce.0),
+            });
+        }
+    }
+    for (contract_address, nonce) in &executed.nonces {
+        if !onchain_nonces.contains(contract_address) {
+            mismatches.push(StateDiffMismatch {
+                kind: "nonce",
+                contract_address: *contract_address.0.key(),
+                key: None,
+                executed: nonce.0,
+                onchain: None,
+            });
+        }
+    }
+
+    let onchain_class_hashes = state_diff
+        .deployed_contracts
+        .iter()
+        .map(|deployed| (deployed.address, deployed.class_hash))
+        .chain(
+            state_diff
+                .replaced_classes
+                .iter()
+                .map(|replaced| (replaced.contract_address, replaced.class_hash)),
+        )
+        .collect::<Vec<_>>();
+    for (contract_address, onchain_class_hash) in &onchain_class_hashes {
+        let class_hash = state.get_class_hash_at(*contract_address)?;
+        if class_hash != *onchain_class_hash {
+            mismatches.push(StateDiffMismatch {
+                kind: "class_hash",
+                contract_address: *contract_address.0.key(),
+                key: None,
+                executed: class_hash.0,
+                onchain: Some(onchain_class_hash.0),
+            });
+        }
+    }
+    for (contract_address, class_hash) in &executed.class_hashes {
+        if !onchain_class_hashes
+            .iter()
+            .any(|(address, _)| address == contract_address)
+        {
+            mismatches.push(StateDiffMismatch {
+                kind: "class_hash",
+                contract_address: *contract_address.0.key(),
+                key: None,
+                executed: class_hash.0,
+                onchain: None,
+            });
+        }
+    }
+
+    Ok(mismatches)
+}
@@ -0,0 +1,215 @@
+//! Corpus of invocations with extreme calldata shapes, executed with both
+//! Cairo Native and the Cairo VM to find divergences in how each deserializes
+//! its inputs.
+//!
+//! Every case is an unsigned invoke transaction from an existing account,
+//! whose `__execute__` receives a multicall with the generated shape. As
+//! transactions are not validated, the account needs no private key.
+
+use std::sync::Arc;
+
+use blockifier::{
+    context::BlockContext,
+    state::{cached_state::CachedState, state_api::StateReader as BlockifierStateReader},
+    transaction::{
+        account_transaction::ExecutionFlags,
+        transaction_execution::Transaction as BlockiTransaction,
+    },
+};
+use serde::Serialize;
+use starknet_api::{
+    core::{ContractAddress, EntryPointSelector},
+    felt,
+    hash::StarkHash,
+    transaction::{
+        fields::{Calldata, Fee, TransactionSignature},
+        InvokeTransaction, InvokeTransactionV1, Transaction as SNTransaction, TransactionHash,
+    },
+};
+
+use crate::divergence::{self, Divergence};
+
+/// Felts at the boundaries of the integer types that calldata is usually
+/// deserialized into, and of the field itself
+const BOUNDARY_FELTS: &[&str] = &[
+    "0x0",
+    "0x1",
+    "0xff",
+    "0x100",
+    "0xffffffff",
+    "0x100000000",
+    "0xffffffffffffffff",
+    "0x10000000000000000",
+    "0xffffffffffffffffffffffffffffffff",
+    "0x100000000000000000000000000000000",
+    "0x800000000000000000000000000000000000000000000000000000000000000",
+    // P - 1, the largest felt, as P itself reduces to 0
+    "0x0800000000000011000000000000000000000000000000000000000000000000",
+];
+
+pub struct StressCase {
+    pub name: String,
+    /// Calldata of the account's `__execute__`
+    pub calldata: Vec<StarkHash>,
+}
+
+#[derive(Serialize)]
+pub struct StressResult {
+    pub case: String,
+    pub calldata_len: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Generates the corpus of multicalls to `target`, calling `selector`
+pub fn generate_corpus(target: ContractAddress, selector: EntryPointSelector) -> Vec<StressCase> {
+    let call = |calldata: Vec<StarkHash>| {
+        let mut call = vec![*target.0.key(), selector.0, calldata.len().into()];
+        call.extend(calldata);
+        call
+    };
+    let multicall = |calls: Vec<Vec<StarkHash>>| {
+        let mut multicall = vec![calls.len().into()];
+        multicall.extend(calls.into_iter().flatten());
+        multicall
+    };
+
+    let mut corpus = Vec::new();
+
+    for len in [1_000, 10_000, 100_000] {
+        corpus.push(StressCase {
+            name: format!("huge_calldata_{len}"),
+            calldata: multicall(vec![call(vec![felt!("0x1"); len])]),
+        });
+    }
+
+    for calls in [100, 1_000] {
+        corpus.push(StressCase {
+            name: format!("many_calls_{calls}"),
+            calldata: multicall(vec![call(Vec::new()); calls]),
+        });
+    }
+
+    for depth in [16, 256, 4_096] {
+        corpus.push(StressCase {
+            name: format!("nested_arrays_{depth}"),
+            calldata: multicall(vec![call(nested_array(depth))]),
+        });
+    }
+
+    let boundary_felts = BOUNDARY_FELTS
+        .iter()
+        .map(|felt| StarkHash::from_hex(felt).expect("hard-coded felt should be valid"))
+        .collect::<Vec<_>>();
+    corpus.push(StressCase {
+        name: "boundary_felts".to_string(),
+        calldata: multicall(vec![call(boundary_felts.clone())]),
+    });
+
+    // Lengths that don't match the actual amount of elements
+    for (index, length) in boundary_felts.iter().enumerate().skip(2) {
+        let mut calldata = multicall(vec![call(vec![felt!("0x1"); 4])]);
+        // Overwrite the length of the calldata of the call
+        calldata[3] = *length;
+        corpus.push(StressCase {
+            name: format!("calldata_len_boundary_{index}"),
+            calldata,
+        });
+
+        let mut calldata = multicall(vec![call(Vec::new())]);
+        // Overwrite the number of calls
+        calldata[0] = *length;
+        corpus.push(StressCase {
+            name: format!("calls_len_boundary_{index}"),
+            calldata,
+        });
+    }
+
+    corpus
+}
+
+/// Serializes an array nested `depth` times, with a single element per level
+fn nested_array(depth: usize) -> Vec<StarkHash> {
+    // Every level is an array of length one, and the innermost array is empty
+    let mut array = vec![felt!("0x1"); depth];
+    array.push(felt!("0x0"));
+    array
+}
+
+/// Executes every case from `account` with both executors, discarding the state changes
+///
+/// Validation is skipped regardless of the flags, as the cases are unsigned.
+pub fn run_corpus<S: BlockifierStateReader>(
+    corpus: Vec<StressCase>,
+    account: ContractAddress,
+    flags: ExecutionFlags,
+    state: &mut CachedState<S>,
+    block_context: &BlockContext,
+) -> anyhow::Result<Vec<StressResult>> {
+    let nonce = state.get_nonce_at(account)?;
+
+    let flags = ExecutionFlags {
+        validate: false,
+        ..flags
+    };
+
+    let mut results = Vec::new();
+    for (index, case) in corpus.into_iter().enumerate() {
+        let calldata_len = case.calldata.len();
+        let transaction = SNTransaction::Invoke(InvokeTransaction::V1(InvokeTransactionV1 {
+            max_fee: Fee(u128::MAX),
+            signature: TransactionSignature::default(),
+            nonce,
+            sender_address: account,
+            calldata: Calldata(Arc::new(case.calldata)),
+        }));
+        // Transactions are never committed, so the hash only needs to be unique
+        let tx_hash = TransactionHash(index.into());
+        let tx =
+            BlockiTransaction::from_api(transaction, tx_hash, None, None, None, flags.clone())?;
+
+        let divergences = divergence::diff_transaction(&tx, state, block_context)?;
+        results.push(StressResult {
+            case: case.name,
+            calldata_len,
+            divergences,
+        });
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_array() {
+        assert_eq!(nested_array(0), [felt!("0x0")]);
+        assert_eq!(nested_array(2), [felt!("0x1"), felt!("0x1"), felt!("0x0")]);
+    }
+
+    #[test]
+    fn test_generate_corpus() {
+        let target = ContractAddress::from(2u128);
+        let selector = EntryPointSelector(felt!("0x3"));
+
+        let corpus = generate_corpus(target, selector);
+        let boundary = corpus
+            .iter()
+            .find(|case| case.name == "boundary_felts")
+            .unwrap();
+
+        assert_eq!(boundary.calldata[0], felt!("0x1"));
+        assert_eq!(boundary.calldata[1], felt!("0x2"));
+        assert_eq!(boundary.calldata[2], felt!("0x3"));
+        assert_eq!(boundary.calldata[3], BOUNDARY_FELTS.len().into());
+        assert_eq!(boundary.calldata.len(), 4 + BOUNDARY_FELTS.len());
+    }
+
+    #[test]
+    fn test_boundary_felts() {
+        let largest = StarkHash::from_hex(BOUNDARY_FELTS.last().unwrap()).unwrap();
+        assert_eq!(largest, StarkHash::MAX);
+        assert_ne!(largest, StarkHash::ZERO);
+    }
+}
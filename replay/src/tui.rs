@@ -0,0 +1,412 @@
+//! Terminal interface to explore the results of a finished replay.
+//!
+//! Reads the audit log for the list of executed blocks and transactions,
+//! the state dumps (if available) for the call tree of each transaction,
+//! and the results store and on-disk caches for the stats panel.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListState, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{output, results_store::ResultsStore};
+
+/// An entry of the audit log, as written by `audit::record`
+#[derive(Deserialize)]
+struct AuditRecord {
+    tx_hash: String,
+    block_number: u64,
+    outcome: String,
+    duration: Duration,
+    executor: String,
+    error: Option<String>,
+}
+
+struct BlockSummary {
+    block_number: u64,
+    transactions: Vec<AuditRecord>,
+}
+
+impl BlockSummary {
+    fn total_time(&self) -> Duration {
+        self.transactions.iter().map(|tx| tx.duration).sum()
+    }
+
+    fn failures(&self) -> usize {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.outcome != "success")
+            .count()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Blocks,
+    Transactions,
+    CallTree,
+}
+
+struct App {
+    blocks: Vec<BlockSummary>,
+    stats: Vec<String>,
+    focus: Focus,
+    block_state: ListState,
+    tx_state: TableState,
+    call_tree: Vec<String>,
+    call_tree_scroll: u16,
+}
+
+/// Opens the interface, blocking until the user quits it.
+pub fn run(audit_log: &Path, results_store: &Path) -> anyhow::Result<()> {
+    let blocks = load_audit_log(audit_log)?;
+    let stats = load_stats(results_store);
+
+    let mut app = App {
+        blocks,
+        stats,
+        focus: Focus::Blocks,
+        block_state: ListState::default().with_selected(Some(0)),
+        tx_state: TableState::default().with_selected(Some(0)),
+        call_tree: Vec::new(),
+        call_tree_scroll: 0,
+    };
+    app.load_call_tree();
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
+
+/// Groups the audit log entries by block. If a transaction was executed more
+/// than once, only its last execution is kept.
+fn load_audit_log(path: &Path) -> anyhow::Result<Vec<BlockSummary>> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open audit log at {}", path.display()))?;
+
+    let mut blocks: BTreeMap<u64, Vec<AuditRecord>> = BTreeMap::new();
+    for line in BufReader::new(file).lines() {
+        let record: AuditRecord = serde_json::from_str(&line?)?;
+
+        let transactions = blocks.entry(record.block_number).or_default();
+        transactions.retain(|tx| tx.tx_hash != record.tx_hash);
+        transactions.push(record);
+    }
+
+    Ok(blocks
+        .into_iter()
+        .map(|(block_number, transactions)| BlockSummary {
+            block_number,
+            transactions,
+        })
+        .collect())
+}
+
+fn load_stats(results_store: &Path) -> Vec<String> {
+    let mut stats = Vec::new();
+
+    for (name, dir) in [
+        ("rpc caches", Path::new("rpc_cache")),
+        ("compiled programs", Path::new("compiled_programs")),
+    ] {
+        let (files, bytes) = dir_stats(dir);
+        stats.push(format!(
+            "{name}: {files} files, {:.1} MiB",
+            bytes as f64 / (1024.0 * 1024.0)
+        ));
+    }
+
+    if results_store.exists() {
+        match ResultsStore::open(results_store).and_then(|store| store.counts()) {
+            Ok(counts) => {
+                stats.push(format!("indexed events: {}", counts.events));
+                stats.push(format!("indexed messages: {}", counts.messages));
+            }
+            Err(err) => stats.push(format!("results store: {err}")),
+        }
+    }
+
+    stats
+}
+
+/// Returns the number of files and their total size
+fn dir_stats(dir: &Path) -> (usize, u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return (0, 0);
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .fold((0, 0), |(files, bytes), metadata| {
+            (files + 1, bytes + metadata.len())
+        })
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Tab => {
+                    self.focus = match self.focus {
+                        Focus::Blocks => Focus::Transactions,
+                        Focus::Transactions => Focus::CallTree,
+                        Focus::CallTree => Focus::Blocks,
+                    }
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                _ => {}
+            }
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        match self.focus {
+            Focus::Blocks => {
+                let selected = move_index(self.block_state.selected(), self.blocks.len(), delta);
+                self.block_state.select(Some(selected));
+                self.tx_state.select(Some(0));
+                self.load_call_tree();
+            }
+            Focus::Transactions => {
+                let len = self
+                    .selected_block()
+                    .map_or(0, |block| block.transactions.len());
+                let selected = move_index(self.tx_state.selected(), len, delta);
+                self.tx_state.select(Some(selected));
+                self.load_call_tree();
+            }
+            Focus::CallTree => {
+                self.call_tree_scroll = self.call_tree_scroll.saturating_add_signed(delta as i16);
+            }
+        }
+    }
+
+    fn selected_block(&self) -> Option<&BlockSummary> {
+        self.blocks.get(self.block_state.selected()?)
+    }
+
+    fn selected_tx(&self) -> Option<(u64, &AuditRecord)> {
+        let block = self.selected_block()?;
+        let tx = block.transactions.get(self.tx_state.selected()?)?;
+        Some((block.block_number, tx))
+    }
+
+    /// Loads the call tree of the selected transaction from its state dump
+    fn load_call_tree(&mut self) {
+        self.call_tree_scroll = 0;
+
+        let Some((block_number, tx)) = self.selected_tx() else {
+            self.call_tree = Vec::new();
+            return;
+        };
+
+        let mut path = output::resolve(&PathBuf::from(format!(
+            "state_dumps/{}/block{}/{}",
+            tx.executor, block_number, tx.tx_hash
+        )));
+        path.set_extension("json");
+
+        let mut lines = Vec::new();
+        if let Some(error) = &tx.error {
+            lines.push(format!("error: {error}"));
+        }
+
+        match fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|dump| Ok(serde_json::from_str::<Value>(&dump)?))
+        {
+            Ok(dump) => {
+                let execution_info = &dump["execution_info"];
+                for name in ["validate", "execute", "fee_transfer"] {
+                    let call = &execution_info[format!("{name}_call_info")];
+                    if !call.is_null() {
+                        lines.push(format!("{name}:"));
+                        push_call_lines(call, 1, &mut lines);
+                    }
+                }
+                if let Some(revert_error) = execution_info["revert_error"].as_str() {
+                    lines.push(format!("reverted: {revert_error}"));
+                }
+            }
+            Err(_) => lines.push(format!(
+                "no state dump found at {} (replay with the state_dump feature)",
+                path.display()
+            )),
+        }
+
+        self.call_tree = lines;
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, bottom] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(frame.area());
+        let [blocks_area, txs_area] =
+            Layout::horizontal([Constraint::Length(32), Constraint::Min(0)]).areas(top);
+        let [call_tree_area, stats_area] =
+            Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)])
+                .areas(bottom);
+
+        self.draw_blocks(frame, blocks_area);
+        self.draw_transactions(frame, txs_area);
+        self.draw_call_tree(frame, call_tree_area);
+
+        let stats = Paragraph::new(
+            self.stats
+                .iter()
+                .map(|stat| Line::from(stat.as_str()))
+                .collect::<Vec<_>>(),
+        )
+        .block(Block::bordered().title("Stats"));
+        frame.render_widget(stats, stats_area);
+    }
+
+    fn draw_blocks(&mut self, frame: &mut Frame, area: Rect) {
+        let items = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let line = format!(
+                    "{} ({} txs, {:.2?})",
+                    block.block_number,
+                    block.transactions.len(),
+                    block.total_time()
+                );
+                if block.failures() > 0 {
+                    Line::from(line).fg(Color::Red)
+                } else {
+                    Line::from(line)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let list = List::new(items)
+            .block(self.panel("Blocks", Focus::Blocks))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, area, &mut self.block_state);
+    }
+
+    fn draw_transactions(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self
+            .selected_block()
+            .map(|block| {
+                block
+                    .transactions
+                    .iter()
+                    .map(|tx| {
+                        let row = Row::new([
+                            tx.tx_hash.clone(),
+                            tx.outcome.clone(),
+                            format!("{:.2?}", tx.duration),
+                            tx.executor.clone(),
+                        ]);
+                        match tx.outcome.as_str() {
+                            "success" => row,
+                            "reverted" => row.fg(Color::Yellow),
+                            _ => row.fg(Color::Red),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(68),
+                Constraint::Length(10),
+                Constraint::Length(12),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(["hash", "outcome", "time", "executor"]).bold())
+        .block(self.panel("Transactions", Focus::Transactions))
+        .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, area, &mut self.tx_state);
+    }
+
+    fn draw_call_tree(&self, frame: &mut Frame, area: Rect) {
+        let lines = self
+            .call_tree
+            .iter()
+            .map(|line| Line::from(line.as_str()))
+            .collect::<Vec<_>>();
+
+        let call_tree = Paragraph::new(lines)
+            .block(self.panel("Call tree", Focus::CallTree))
+            .scroll((self.call_tree_scroll, 0));
+        frame.render_widget(call_tree, area);
+    }
+
+    fn panel(&self, title: &'static str, focus: Focus) -> Block<'static> {
+        let block = Block::bordered().title(title);
+        if self.focus == focus {
+            block.border_style(Style::new().fg(Color::Cyan))
+        } else {
+            block
+        }
+    }
+}
+
+/// Moves the selected index by delta, keeping it within the list bounds
+fn move_index(selected: Option<usize>, len: usize, delta: isize) -> usize {
+    let selected = selected.unwrap_or(0) as isize + delta;
+    selected.clamp(0, len.saturating_sub(1) as isize) as usize
+}
+
+/// Appends a line for the call and each of its inner calls, indented by depth
+fn push_call_lines(call: &Value, depth: usize, lines: &mut Vec<String>) {
+    let entry_point = &call["call"];
+    let execution = &call["execution"];
+
+    let failed = if execution["failed"].as_bool() == Some(true) {
+        " FAILED"
+    } else {
+        ""
+    };
+
+    lines.push(format!(
+        "{}{} {} gas={}{}",
+        "  ".repeat(depth),
+        entry_point["storage_address"].as_str().unwrap_or("?"),
+        entry_point["entry_point_selector"].as_str().unwrap_or("?"),
+        execution["gas_consumed"],
+        failed,
+    ));
+
+    if let Some(inner_calls) = call["inner_calls"].as_array() {
+        for inner_call in inner_calls {
+            push_call_lines(inner_call, depth + 1, lines);
+        }
+    }
+}
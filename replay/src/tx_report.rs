@@ -0,0 +1,86 @@
+//! Per-transaction report of the executed blocks, written as JSON Lines.
+//!
+//! It allows finding slow or misbehaving transactions in a range without a
+//! profiler, e.g. sorting the lines by wall time or cache misses.
+
+use std::{io::Write, path::Path, time::Duration};
+
+use blockifier::transaction::objects::TransactionExecutionInfo;
+use serde::Serialize;
+use starknet_api::transaction::TransactionHash;
+
+use crate::{audit::Outcome, output};
+
+/// Cost of executing a transaction, without fetching and reporting it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionCost {
+    pub wall_time: Duration,
+    /// Reads fetched from the node while executing the transaction
+    pub cache_misses: u64,
+}
+
+#[derive(Serialize)]
+pub struct TxReport {
+    pub tx_hash: String,
+    pub wall_time: Duration,
+    pub outcome: Outcome,
+    /// Cairo steps, zero when every call was executed with Cairo Native
+    pub steps: Option<usize>,
+    /// Sierra gas consumed by the calls of the transaction
+    pub gas_consumed: Option<u64>,
+    pub fee: Option<u128>,
+    pub revert_reason: Option<String>,
+    /// Reads fetched from the node while executing the transaction
+    pub cache_misses: u64,
+}
+
+impl TxReport {
+    /// Builds the report of an execution, which is missing if it failed
+    pub fn new(
+        tx_hash: TransactionHash,
+        cost: ExecutionCost,
+        execution_info: Option<&TransactionExecutionInfo>,
+    ) -> Self {
+        let outcome = match execution_info {
+            Some(execution_info) if execution_info.is_reverted() => Outcome::Reverted,
+            Some(_) => Outcome::Success,
+            None => Outcome::Failed,
+        };
+
+        Self {
+            tx_hash: tx_hash.0.to_hex_string(),
+            wall_time: cost.wall_time,
+            outcome,
+            steps: execution_info.map(|execution_info| {
+                execution_info
+                    .receipt
+                    .resources
+                    .computation
+                    .vm_resources
+                    .n_steps
+            }),
+            gas_consumed: execution_info.map(|execution_info| {
+                execution_info
+                    .non_optional_call_infos()
+                    .map(|call_info| call_info.execution.gas_consumed)
+                    .sum()
+            }),
+            fee: execution_info.map(|execution_info| execution_info.receipt.fee.0),
+            revert_reason: execution_info
+                .and_then(|execution_info| execution_info.revert_error.as_ref())
+                .map(ToString::to_string),
+            cache_misses: cost.cache_misses,
+        }
+    }
+}
+
+/// Writes the reports atomically to the given path, one JSON per line.
+pub fn write(path: &Path, reports: &[TxReport]) -> anyhow::Result<()> {
+    output::write_atomic(path, |writer| {
+        for report in reports {
+            serde_json::to_writer(&mut *writer, report)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    })
+}
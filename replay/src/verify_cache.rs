@@ -0,0 +1,125 @@
+//! Verification of the disk cache against the node.
+//!
+//! A sample of the cached storage values, nonces, class hashes, compiled
+//! class hashes and classes is fetched again, reporting every entry that differs. A corrupted or stale
+//! cache would otherwise only show up as execution divergences.
+
+use blockifier::state::state_api::StateReader as BlockifierStateReader;
+use rpc_state_reader::{
+    disk_cache::{self, DiskCache},
+    reader::{RpcStateReader, StateReader},
+};
+use serde::Serialize;
+use serde_json::Value;
+use starknet_api::{block::BlockNumber, core::ChainId};
+use tracing::info;
+
+#[derive(Serialize)]
+pub struct CacheMismatch {
+    pub tree: &'static str,
+    /// Description of the entry, e.g. its block, contract address and key
+    pub entry: String,
+    pub cached: Value,
+    /// The value returned by the node, or the error if it couldn't be fetched
+    pub fetched: Result<Value, String>,
+}
+
+/// Verifies up to `samples` entries of each verified tree of the disk cache,
+/// which must be the one of the given chain.
+pub fn verify_cache(
+    disk_cache: &DiskCache,
+    chain: &ChainId,
+    samples: usize,
+) -> anyhow::Result<Vec<CacheMismatch>> {
+    let mut mismatches = Vec::new();
+
+    let mut verify = |tree: &'static str,
+                      fetch: &dyn Fn(&[u8]) -> Option<(String, anyhow::Result<Value>)>|
+     -> anyhow::Result<()> {
+        let entries = disk_cache.sample(tree, samples)?;
+        info!(tree, entries = entries.len(), "verifying cache entries");
+
+        for (key, cached) in entries {
+            let Some((entry, fetched)) = fetch(&key) else {
+                anyhow::bail!("invalid key in {tree}: {}", hex(&key));
+            };
+            let fetched = fetched.map_err(|err| format!("{err:#}"));
+            if fetched.as_ref() != Ok(&cached) {
+                mismatches.push(CacheMismatch {
+                    tree,
+                    entry,
+                    cached,
+                    fetched,
+                });
+            }
+        }
+        Ok(())
+    };
+
+    verify(disk_cache::STORAGE, &|key| {
+        let (block_number, contract_address, storage_key) = disk_cache::parse_storage_key(key)?;
+        let reader = RpcStateReader::new(chain.clone(), block_number);
+        Some((
+            format!(
+                "block {block_number}, contract {}, key {}",
+                contract_address.0.key().to_hex_string(),
+                storage_key.0.key().to_hex_string()
+            ),
+            to_value(reader.get_storage_at(contract_address, storage_key)),
+        ))
+    })?;
+    verify(disk_cache::NONCES, &|key| {
+        let (block_number, contract_address) = disk_cache::parse_address_key(key)?;
+        let reader = RpcStateReader::new(chain.clone(), block_number);
+        Some((
+            format!(
+                "block {block_number}, contract {}",
+                contract_address.0.key().to_hex_string()
+            ),
+            to_value(reader.get_nonce_at(contract_address)),
+        ))
+    })?;
+    verify(disk_cache::CLASS_HASHES, &|key| {
+        let (block_number, contract_address) = disk_cache::parse_address_key(key)?;
+        let reader = RpcStateReader::new(chain.clone(), block_number);
+        Some((
+            format!(
+                "block {block_number}, contract {}",
+                contract_address.0.key().to_hex_string()
+            ),
+            to_value(reader.get_class_hash_at(contract_address)),
+        ))
+    })?;
+    verify(disk_cache::COMPILED_CLASS_HASHES, &|key| {
+        let (block_number, class_hash) = disk_cache::parse_compiled_class_hash_key(key)?;
+        let reader = RpcStateReader::new(chain.clone(), block_number);
+        Some((
+            format!("block {block_number}, class {}", class_hash.to_hex_string()),
+            to_value(reader.get_compiled_class_hash(class_hash)),
+        ))
+    })?;
+
+    // Classes are shared by every block, so they are fetched at the latest one
+    let latest_block =
+        RpcStateReader::new(chain.clone(), BlockNumber(0)).get_latest_block_number()?;
+    let reader = RpcStateReader::new(chain.clone(), latest_block);
+    verify(disk_cache::CONTRACT_CLASSES, &|key| {
+        let class_hash = disk_cache::parse_class_key(key)?;
+        Some((
+            format!("class {}", class_hash.to_hex_string()),
+            to_value(reader.get_contract_class(&class_hash)),
+        ))
+    })?;
+
+    Ok(mismatches)
+}
+
+fn to_value<T: Serialize, E: std::error::Error + Send + Sync + 'static>(
+    result: Result<T, E>,
+) -> anyhow::Result<Value> {
+    Ok(serde_json::to_value(result?)?)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,88 @@
+//! Concurrent execution of the blocks of a range with a bounded pool of
+//! workers.
+//!
+//! Each block starts from the state of the previous block as read from the
+//! node, so blocks don't depend on each other. Readers are not thread safe,
+//! so each block builds its own, sharing the disk cache. Results are handed
+//! back in block order, so that checkpoints only advance over contiguous
+//! blocks.
+
+use std::{
+    collections::BTreeMap,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+/// Executes the blocks with `workers` threads, recording each result in
+/// block order from the calling thread
+pub fn execute_in_order<T: Send>(
+    workers: usize,
+    blocks: RangeInclusive<u64>,
+    execute: impl Fn(u64) -> T + Sync,
+    mut record: impl FnMut(u64, T),
+) {
+    let (first_block, last_block) = blocks.into_inner();
+    let next_block = AtomicU64::new(first_block);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (next_block, execute) = (&next_block, &execute);
+            scope.spawn(move || loop {
+                let block_number = next_block.fetch_add(1, Ordering::Relaxed);
+                if block_number > last_block {
+                    break;
+                }
+                if sender.send((block_number, execute(block_number))).is_err() {
+                    break;
+                }
+            });
+        }
+        // Otherwise the receiver would wait forever
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut expected_block = first_block;
+        for (block_number, result) in receiver {
+            pending.insert(block_number, result);
+            while let Some(result) = pending.remove(&expected_block) {
+                record(expected_block, result);
+                expected_block += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_execute_in_order() {
+        let mut recorded = Vec::new();
+        execute_in_order(
+            4,
+            1..=20,
+            |block_number| {
+                // Earlier blocks finish later
+                thread::sleep(Duration::from_millis(20 - block_number));
+                block_number * 2
+            },
+            |block_number, result| recorded.push((block_number, result)),
+        );
+
+        assert_eq!(
+            recorded,
+            (1..=20)
+                .map(|block_number| (block_number, block_number * 2))
+                .collect::<Vec<_>>()
+        );
+    }
+}
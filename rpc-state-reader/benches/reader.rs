@@ -0,0 +1,114 @@
+//! Microbenchmarks of the reader layer, independent of transaction execution.
+//!
+//! The benchmarked entries are fetched from the node on the first run, and
+//! then read from the local caches (`rpc_cache/SN_MAIN/db` and `compiled_programs`).
+
+use std::path::PathBuf;
+
+use blockifier::state::state_api::StateReader as BlockifierStateReader;
+use cairo_native::executor::AotContractExecutor;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rpc_state_reader::{
+    cache::RpcCachedStateReader,
+    disk_cache::{self, DiskCache},
+    reader::{RpcStateReader, StateReader},
+};
+use serde_json::Value;
+use starknet::core::types::ContractClass;
+use starknet_api::{
+    block::BlockNumber,
+    class_hash,
+    core::{ChainId, ContractAddress},
+    patricia_key,
+    state::StorageKey,
+};
+
+const BLOCK_NUMBER: BlockNumber = BlockNumber(700000);
+
+fn reader() -> RpcCachedStateReader {
+    RpcCachedStateReader::new(RpcStateReader::new(ChainId::Mainnet, BLOCK_NUMBER))
+}
+
+fn disk_cache() -> &'static DiskCache {
+    DiskCache::get(&ChainId::Mainnet)
+        .expect("the rpc cache database must not be in use by another process")
+}
+
+fn bench_cache_hit(c: &mut Criterion) {
+    let address = ContractAddress(patricia_key!(
+        "0x00b081f7ba1efc6fe98770b09a827ae373ef2baa6116b3d2a0bf5154136573a9"
+    ));
+    let key = StorageKey(patricia_key!(0u128));
+
+    let reader = reader();
+    reader.get_storage_at(address, key).unwrap();
+
+    c.bench_function("cache hit lookup", |b| {
+        b.iter(|| reader.get_storage_at(black_box(address), black_box(key)))
+    });
+}
+
+fn bench_disk_load(c: &mut Criterion) {
+    let address = ContractAddress(patricia_key!(
+        "0x00b081f7ba1efc6fe98770b09a827ae373ef2baa6116b3d2a0bf5154136573a9"
+    ));
+    let key = StorageKey(patricia_key!(0u128));
+
+    reader().get_storage_at(address, key).unwrap();
+    let disk_key = disk_cache::storage_key(BLOCK_NUMBER, &address, &key);
+
+    c.bench_function("disk load", |b| {
+        b.iter(|| {
+            disk_cache()
+                .read::<Value>(disk_cache::STORAGE, black_box(&disk_key))
+                .unwrap()
+        })
+    });
+}
+
+fn bench_class_deserialize(c: &mut Criterion) {
+    let class_hash =
+        class_hash!("0x0298e56befa6d1446b86ed5b900a9ba51fd2faa683cd6f50e8f833c0fb847216");
+
+    StateReader::get_contract_class(&reader(), &class_hash).unwrap();
+    let class = disk_cache()
+        .read::<Value>(
+            disk_cache::CONTRACT_CLASSES,
+            &disk_cache::class_key(&class_hash),
+        )
+        .unwrap();
+
+    c.bench_function("class deserialize", |b| {
+        b.iter(|| serde_json::from_value::<ContractClass>(black_box(class.clone())).unwrap())
+    });
+}
+
+fn bench_native_load(c: &mut Criterion) {
+    let class_hash =
+        class_hash!("0x0298e56befa6d1446b86ed5b900a9ba51fd2faa683cd6f50e8f833c0fb847216");
+
+    // Compiles the class and saves its artifact, if it doesn't exist yet
+    reader().get_compiled_class(class_hash).unwrap();
+    let extension = if cfg!(target_os = "macos") {
+        "dylib"
+    } else {
+        "so"
+    };
+    let path = PathBuf::from(format!(
+        "compiled_programs/{}.{extension}",
+        class_hash.to_hex_string()
+    ));
+
+    c.bench_function("native artifact load", |b| {
+        b.iter(|| AotContractExecutor::load(black_box(&path)).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_cache_hit,
+    bench_disk_load,
+    bench_class_deserialize,
+    bench_native_load
+);
+criterion_main!(benches);
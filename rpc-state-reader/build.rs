@@ -0,0 +1,36 @@
+use std::fs;
+
+fn main() {
+    // Saved along with each native library, to recompile the libraries built
+    // by other versions of cairo-native
+    let version = fs::read_to_string("../Cargo.lock")
+        .ok()
+        .and_then(|lock| cairo_native_version(&lock))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=CAIRO_NATIVE_VERSION={version}");
+    println!("cargo:rerun-if-changed=../Cargo.lock");
+}
+
+/// Reads the version of cairo-native from the lock file, including the
+/// commit it's built from when it's a git dependency
+fn cairo_native_version(lock: &str) -> Option<String> {
+    lock.split("[[package]]").find_map(|package| {
+        let field = |key: &str| {
+            package.lines().find_map(|line| {
+                line.strip_prefix(key)?
+                    .strip_prefix(" = \"")?
+                    .strip_suffix('"')
+            })
+        };
+        if field("name")? != "cairo-native" {
+            return None;
+        }
+
+        let version = field("version")?;
+        Some(match field("source") {
+            Some(source) => format!("{version} ({source})"),
+            None => version.to_string(),
+        })
+    })
+}
@@ -0,0 +1,77 @@
+//! Shadow execution of a mainnet block through the sequencer's state reader
+//! traits.
+//!
+//! The state reader is created by the `StateReaderFactory` given to the
+//! sequencer's gateway, so the same wiring can be used to run a local
+//! sequencer on top of mainnet state, sharing the replay's caches.
+//!
+//! ```bash
+//! cargo run -p rpc-state-reader --example shadow_execution 90000
+//! ```
+
+use std::env;
+
+use anyhow::Context;
+use blockifier::{
+    state::cached_state::CachedState,
+    transaction::{account_transaction::ExecutionFlags, transactions::ExecutableTransaction},
+};
+use rpc_state_reader::{
+    cache::RpcCachedStateReader,
+    execution::{fetch_block_context, fetch_blockifier_transaction},
+    reader::{RpcStateReader, StateReader},
+    sequencer::SequencerStateReaderFactory,
+};
+use starknet_api::{block::BlockNumber, core::ChainId};
+use starknet_gateway::state_reader::StateReaderFactory;
+
+fn main() -> anyhow::Result<()> {
+    let block_number = BlockNumber(
+        env::args()
+            .nth(1)
+            .context("usage: shadow_execution <block_number>")?
+            .parse()?,
+    );
+    let chain = ChainId::Mainnet;
+
+    let factory = SequencerStateReaderFactory {
+        chain: chain.clone(),
+    };
+
+    // Transactions are executed on top of the state of the previous block
+    let state_reader = factory.get_state_reader(block_number.prev().context("no previous block")?);
+    let mut state = CachedState::new(state_reader);
+
+    let reader = RpcCachedStateReader::new(RpcStateReader::new(chain, block_number));
+    let block_context = fetch_block_context(&reader)?;
+    let flags = ExecutionFlags {
+        only_query: false,
+        charge_fee: true,
+        validate: true,
+    };
+
+    for tx_hash in reader.get_block_with_tx_hashes()?.transactions {
+        let tx = fetch_blockifier_transaction(&reader, flags.clone(), tx_hash)?;
+        let receipt = reader.get_transaction_receipt(&tx_hash)?;
+
+        let mut transactional_state = CachedState::create_transactional(&mut state);
+        match tx.execute(&mut transactional_state, &block_context) {
+            Ok(execution_info) => {
+                transactional_state.commit();
+
+                let fee_matches = execution_info.receipt.fee == receipt.actual_fee.amount;
+                println!(
+                    "{}: reverted: {}, fee matches: {fee_matches}",
+                    tx_hash.0.to_hex_string(),
+                    execution_info.is_reverted(),
+                );
+            }
+            Err(err) => {
+                transactional_state.abort();
+                println!("{}: failed: {err}", tx_hash.0.to_hex_string());
+            }
+        }
+    }
+
+    Ok(())
+}
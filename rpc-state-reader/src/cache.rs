@@ -1,32 +1,41 @@
 use std::{
-    cell::RefCell,
-    collections::{hash_map::Entry, HashMap},
-    fs::{self, File},
-    io::Seek,
-    path::PathBuf,
+    cell::{Cell, RefCell},
+    collections::{hash_map::Entry, HashMap, HashSet},
+    hash::Hash,
+    mem,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
 };
 
-use blockifier::state::state_api::{StateReader as BlockifierStateReader, StateResult};
+use blockifier::state::{
+    errors::StateError,
+    state_api::{StateReader as BlockifierStateReader, StateResult},
+};
 use cairo_vm::Felt252;
-use fs2::FileExt;
-use serde::{Deserialize, Serialize};
+use lru::LruCache;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use serde_with::serde_as;
 use starknet::core::types::ContractClass;
 use starknet_api::{
-    core::{ChainId, ClassHash, ContractAddress, Nonce},
+    core::{ChainId, ClassHash, CompiledClassHash, ContractAddress, Nonce},
     state::StorageKey,
     transaction::{Transaction, TransactionHash},
 };
-use tracing::warn;
+use starknet_gateway::rpc_objects::{GetClassHashAtParams, GetNonceParams, GetStorageAtParams};
+use tracing::{info, warn};
+use ureq::json;
 
 use crate::{
+    disk_cache::{self, read_json_cache, write_json_cache, DiskCache},
     objects::{BlockWithTxHahes, RpcTransactionReceipt, RpcTransactionTrace},
+    pathfinder,
     reader::{compile_contract_class, RpcStateReader, StateReader},
+    utils::{class_footprints, native_executor_unloads},
 };
 
 /// The RpcCache stores the result of RPC calls to memory (and disk)
 #[serde_as]
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct RpcCache {
     pub block: Option<BlockWithTxHahes>,
     // we need to serialize it as a vector to allow non string key types
@@ -44,71 +53,600 @@ pub struct RpcCache {
     pub transaction_receipts: HashMap<TransactionHash, RpcTransactionReceipt>,
     #[serde_as(as = "Vec<(_, _)>")]
     pub transaction_traces: HashMap<TransactionHash, RpcTransactionTrace>,
+    // missing from the caches written before it was cached
+    #[serde(default)]
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub compiled_class_hashes: HashMap<ClassHash, CompiledClassHash>,
+}
+
+static DISK_HITS: AtomicU64 = AtomicU64::new(0);
+static NODE_FETCHES: AtomicU64 = AtomicU64::new(0);
+static CATEGORY_COUNTERS: [CategoryCounters; CacheCategory::ALL.len()] =
+    [CategoryCounters::NEW; CacheCategory::ALL.len()];
+static EVICTIONS: AtomicU64 = AtomicU64::new(0);
+/// Estimated size of the entries in the memory caches of every reader, only
+/// tracked with a memory limit
+static MEMORY_USAGE: AtomicU64 = AtomicU64::new(0);
+/// Maximum memory usage of the memory cache of each reader, in bytes. Zero
+/// means unlimited.
+static MEMORY_LIMIT: AtomicU64 = AtomicU64::new(0);
+/// Whether it was logged that the memory limit doesn't apply to the JSON cache
+static MEMORY_LIMIT_WARNED: AtomicBool = AtomicBool::new(false);
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Reads fetched from the node by the current thread
+    static THREAD_NODE_FETCHES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Limits the estimated memory used by the memory cache of each reader,
+/// evicting its least recently used entries when exceeded.
+///
+/// Entries are only evicted if they are persisted in the disk cache database,
+/// as they would otherwise be missing from the JSON cache. Readers falling
+/// back to the JSON cache log a warning and ignore the limit.
+pub fn set_memory_limit(limit: Option<u64>) {
+    MEMORY_LIMIT.store(limit.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Makes the cached readers fail on a cache miss, instead of fetching the
+/// entry from the node
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// Number of reads that missed the memory cache, across every reader
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    /// Reads served by the disk cache
+    pub hits: u64,
+    /// Reads fetched from the node
+    pub misses: u64,
+    /// Entries evicted from the memory caches
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+
+    /// Returns the reads performed since `previous`
+    pub fn since(&self, previous: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits - previous.hits,
+            misses: self.misses - previous.misses,
+            evictions: self.evictions - previous.evictions,
+        }
+    }
+}
+
+/// Returns the reads fetched from the node by the current thread, to
+/// attribute them to what it executes while other threads also read
+pub fn thread_cache_misses() -> u64 {
+    THREAD_NODE_FETCHES.get()
+}
+
+pub fn cache_stats() -> CacheStats {
+    CacheStats {
+        hits: DISK_HITS.load(Ordering::Relaxed),
+        misses: NODE_FETCHES.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+    }
+}
+
+/// Kind of data read through the caches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCategory {
+    Block,
+    Transaction,
+    TransactionReceipt,
+    TransactionTrace,
+    ContractClass,
+    /// Native artifacts, loaded from `compiled_programs` or compiled
+    CompiledClass,
+    Storage,
+    Nonce,
+    ClassHash,
+    CompiledClassHash,
+}
+
+impl CacheCategory {
+    pub const ALL: [CacheCategory; 10] = [
+        CacheCategory::Block,
+        CacheCategory::Transaction,
+        CacheCategory::TransactionReceipt,
+        CacheCategory::TransactionTrace,
+        CacheCategory::ContractClass,
+        CacheCategory::CompiledClass,
+        CacheCategory::Storage,
+        CacheCategory::Nonce,
+        CacheCategory::ClassHash,
+        CacheCategory::CompiledClassHash,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CacheCategory::Block => "block",
+            CacheCategory::Transaction => "transaction",
+            CacheCategory::TransactionReceipt => "receipt",
+            CacheCategory::TransactionTrace => "trace",
+            CacheCategory::ContractClass => "contract class",
+            CacheCategory::CompiledClass => "compiled class",
+            CacheCategory::Storage => "storage",
+            CacheCategory::Nonce => "nonce",
+            CacheCategory::ClassHash => "class hash",
+            CacheCategory::CompiledClassHash => "compiled class hash",
+        }
+    }
+}
+
+struct CategoryCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CategoryCounters {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: CategoryCounters = CategoryCounters {
+        hits: AtomicU64::new(0),
+        misses: AtomicU64::new(0),
+    };
+}
+
+/// Records reads of the given kind, either served by the disk (hits) or
+/// fetched from the node (misses). For compiled classes, misses are
+/// compilations.
+pub fn record_reads(category: CacheCategory, hits: u64, misses: u64) {
+    let counters = &CATEGORY_COUNTERS[category as usize];
+    counters.hits.fetch_add(hits, Ordering::Relaxed);
+    counters.misses.fetch_add(misses, Ordering::Relaxed);
+
+    if category != CacheCategory::CompiledClass {
+        DISK_HITS.fetch_add(hits, Ordering::Relaxed);
+        NODE_FETCHES.fetch_add(misses, Ordering::Relaxed);
+        THREAD_NODE_FETCHES.set(THREAD_NODE_FETCHES.get() + misses);
+    }
+}
+
+/// Returns the reads of each kind, without evictions as they are not
+/// tracked per kind
+pub fn category_stats() -> Vec<(CacheCategory, CacheStats)> {
+    CacheCategory::ALL
+        .into_iter()
+        .map(|category| {
+            let counters = &CATEGORY_COUNTERS[category as usize];
+            let stats = CacheStats {
+                hits: counters.hits.load(Ordering::Relaxed),
+                misses: counters.misses.load(Ordering::Relaxed),
+                evictions: 0,
+            };
+            (category, stats)
+        })
+        .collect()
+}
+
+pub fn log_cache_statistics() {
+    let stats = cache_stats();
+    info!(
+        disk_hits = stats.hits,
+        node_fetches = stats.misses,
+        evictions = stats.evictions,
+        memory_usage = MEMORY_USAGE.load(Ordering::Relaxed),
+        class_memory = class_footprints()
+            .iter()
+            .map(|(_, footprint)| footprint.total_bytes())
+            .sum::<u64>(),
+        executor_unloads = native_executor_unloads(),
+        "cache statistics"
+    );
+
+    for (category, stats) in category_stats() {
+        if stats.hits + stats.misses == 0 {
+            continue;
+        }
+        info!(
+            category = category.name(),
+            disk_hits = stats.hits,
+            node_fetches = stats.misses,
+            hit_rate = stats.hit_rate(),
+            "cache statistics by category"
+        );
+    }
+}
+
+/// Entry of the memory cache, tracked to evict the least recently used ones
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Transaction(TransactionHash),
+    ContractClass(ClassHash),
+    Storage(ContractAddress, StorageKey),
+    Nonce(ContractAddress),
+    ClassHash(ContractAddress),
+    TransactionReceipt(TransactionHash),
+    TransactionTrace(TransactionHash),
+    CompiledClassHash(ClassHash),
+}
+
+/// Estimates the memory used by a cached value from its serialized size
+fn estimate_size(value: &impl Serialize) -> u64 {
+    serde_json::to_vec(value).map_or(0, |value| value.len() as u64)
 }
 
 /// A wrapper around `RpcStateReader` that caches all rpc calls.
 ///
-/// On drop, the cache is saved to disk at `rpc_cache/{block_number}.json`.
-/// It's not safe to use multiple instances of this struct at the same time,
-/// as there is no mechanism for file locking.
+/// Each call is cached to memory and to the disk cache database, which
+/// is read and written per entry. If the chain's database can't be opened (i.e.
+/// because another process is using it), it falls back to the JSON cache
+/// at `rpc_cache/{chain}/{block_number}.json.zst`, which is saved on drop.
 pub struct RpcCachedStateReader {
     pub reader: RpcStateReader,
     state: RefCell<RpcCache>,
+    disk_cache: Option<&'static DiskCache>,
+    /// Estimated size of each memory cache entry, by last use
+    lru: RefCell<LruCache<CacheKey, u64>>,
+    /// Estimated size of the memory cache, tracked with a memory limit
+    memory_usage: Cell<u64>,
+    /// Whether the memory cache is written to the JSON cache when dropped
+    persist: bool,
 }
 
 impl Drop for RpcCachedStateReader {
     fn drop(&mut self) {
-        let path = PathBuf::from(format!("rpc_cache/{}.json", self.reader.block_number));
-        let parent = path.parent().unwrap();
-        fs::create_dir_all(parent).unwrap();
-
-        let mut file = File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(path)
-            .unwrap();
-        file.lock_exclusive().unwrap();
-
-        // try to read old cache, and merge it with the current one
-        if let Ok(old_state) = serde_json::from_reader::<_, RpcCache>(&file) {
-            merge_cache(self.state.get_mut(), old_state);
-        }
+        MEMORY_USAGE.fetch_sub(self.memory_usage.get(), Ordering::Relaxed);
 
-        // overwrite the file with the new cache
-        file.set_len(0).unwrap();
-        file.seek(std::io::SeekFrom::Start(0)).unwrap();
+        if !self.persist {
+            return;
+        }
+        if let Some(disk_cache) = self.disk_cache {
+            disk_cache.flush();
+            return;
+        }
 
-        serde_json::to_writer_pretty(&file, &self.state).unwrap();
-        fs2::FileExt::unlock(&file).unwrap();
+        if let Err(err) = write_json_cache(
+            &self.reader.get_chain_id(),
+            self.reader.block_number,
+            self.state.get_mut(),
+        ) {
+            warn!(
+                "Failed to write cache for block {}: {err}",
+                self.reader.block_number
+            );
+        }
     }
 }
 
 impl RpcCachedStateReader {
     pub fn new(reader: RpcStateReader) -> Self {
-        let state = {
-            let path = PathBuf::from(format!("rpc_cache/{}.json", reader.block_number));
-
-            match File::open(path) {
-                Ok(file) => {
-                    fs2::FileExt::lock_shared(&file).unwrap();
-                    let state = serde_json::from_reader(&file).unwrap();
-                    fs2::FileExt::unlock(&file).unwrap();
-                    state
+        let disk_cache = DiskCache::get(&reader.get_chain_id());
+
+        let state = match disk_cache {
+            Some(disk_cache) => {
+                if let Err(err) =
+                    disk_cache.migrate_json_cache(&reader.get_chain_id(), reader.block_number)
+                {
+                    warn!(
+                        "Failed to migrate json cache for block {}: {err}",
+                        reader.block_number
+                    );
                 }
-                Err(_) => {
+                RpcCache::default()
+            }
+            None => {
+                if MEMORY_LIMIT.load(Ordering::Relaxed) != 0
+                    && !MEMORY_LIMIT_WARNED.swap(true, Ordering::Relaxed)
+                {
+                    warn!("the cache memory limit is ignored without the disk cache database, as evicted entries would be missing from the JSON cache");
+                }
+                read_json_cache(&reader.get_chain_id(), reader.block_number).unwrap_or_else(|| {
                     warn!("Failed to read cache for block {}", reader.block_number);
                     RpcCache::default()
-                }
+                })
             }
         };
 
         Self {
             reader,
             state: RefCell::new(state),
+            disk_cache,
+            lru: RefCell::new(LruCache::unbounded()),
+            memory_usage: Cell::new(0),
+            persist: true,
+        }
+    }
+
+    /// Builds a reader serving the given cache, e.g. the one of a
+    /// reproduction bundle. Nothing is written back to the caches.
+    pub fn from_snapshot(reader: RpcStateReader, state: RpcCache) -> Self {
+        Self {
+            reader,
+            state: RefCell::new(state),
+            disk_cache: None,
+            lru: RefCell::new(LruCache::unbounded()),
+            memory_usage: Cell::new(0),
+            persist: false,
+        }
+    }
+
+    /// Returns a copy of the memory cache, with every entry read so far
+    /// unless evicted
+    pub fn snapshot(&self) -> RpcCache {
+        self.state.borrow().clone()
+    }
+
+    /// Marks the memory cache entry as used, tracking its size if it's new,
+    /// and evicts the least recently used entries if the memory cache of the
+    /// reader exceeds the memory limit.
+    fn touch(&self, key: CacheKey, size: impl FnOnce() -> u64) {
+        // Without a limit nothing is evicted, so sizes aren't estimated.
+        // Without the disk cache, evicted entries would be lost.
+        let limit = MEMORY_LIMIT.load(Ordering::Relaxed);
+        if limit == 0 || self.disk_cache.is_none() {
+            return;
+        }
+
+        let mut lru = self.lru.borrow_mut();
+        if lru.get(&key).is_none() {
+            let size = mem::size_of::<CacheKey>() as u64 + size();
+            lru.put(key, size);
+            self.memory_usage.set(self.memory_usage.get() + size);
+            MEMORY_USAGE.fetch_add(size, Ordering::Relaxed);
+        }
+
+        let mut state = self.state.borrow_mut();
+        while self.memory_usage.get() > limit {
+            let Some((key, size)) = lru.pop_lru() else {
+                break;
+            };
+            match key {
+                CacheKey::Transaction(hash) => {
+                    state.transactions.remove(&hash);
+                }
+                CacheKey::ContractClass(class_hash) => {
+                    state.contract_classes.remove(&class_hash);
+                }
+                CacheKey::Storage(contract_address, key) => {
+                    state.storage.remove(&(contract_address, key));
+                }
+                CacheKey::Nonce(contract_address) => {
+                    state.nonces.remove(&contract_address);
+                }
+                CacheKey::ClassHash(contract_address) => {
+                    state.class_hashes.remove(&contract_address);
+                }
+                CacheKey::TransactionReceipt(hash) => {
+                    state.transaction_receipts.remove(&hash);
+                }
+                CacheKey::TransactionTrace(hash) => {
+                    state.transaction_traces.remove(&hash);
+                }
+                CacheKey::CompiledClassHash(class_hash) => {
+                    state.compiled_class_hashes.remove(&class_hash);
+                }
+            }
+            self.memory_usage.set(self.memory_usage.get() - size);
+            MEMORY_USAGE.fetch_sub(size, Ordering::Relaxed);
+            EVICTIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Reads the value from the disk cache database or, if it's not
+    /// there, fetches it and stores it into the database.
+    fn read_through<T: Serialize + DeserializeOwned>(
+        &self,
+        category: CacheCategory,
+        tree: &str,
+        key: &[u8],
+        fetch: impl FnOnce() -> StateResult<T>,
+    ) -> StateResult<T> {
+        if let Some(value) = self
+            .disk_cache
+            .and_then(|disk_cache| disk_cache.read(tree, key))
+        {
+            record_reads(category, 1, 0);
+            return Ok(value);
+        }
+
+        if OFFLINE.load(Ordering::Relaxed) {
+            return Err(StateError::StateReadError(format!(
+                "{} is not cached, and the replay is offline",
+                disk_cache::describe_key(tree, key)
+            )));
+        }
+
+        record_reads(category, 0, 1);
+        let Some(disk_cache) = self.disk_cache else {
+            return fetch();
+        };
+        let value = fetch()?;
+        disk_cache.write(tree, key, &value);
+
+        Ok(value)
+    }
+
+    /// Fetches the keys that are not cached yet in JSON-RPC batches, storing
+    /// them into memory and into the disk cache database.
+    ///
+    /// Keys whose request fails are skipped, they will be fetched
+    /// individually when read.
+    #[allow(clippy::too_many_arguments)]
+    fn prefetch<K: Eq + Hash + Clone, V: Serialize + DeserializeOwned>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        memory: fn(&mut RpcCache) -> &mut HashMap<K, V>,
+        cache_key: fn(&K) -> CacheKey,
+        category: CacheCategory,
+        tree: &str,
+        disk_key: impl Fn(&K) -> Vec<u8>,
+        request: impl Fn(&K) -> (&'static str, Value),
+    ) -> anyhow::Result<()> {
+        let keys = keys.into_iter().collect::<HashSet<_>>();
+
+        let mut state = self.state.borrow_mut();
+        let cache = memory(&mut state);
+
+        let mut hits = 0;
+        let mut inserted = Vec::new();
+        let mut missing = Vec::new();
+        for key in keys {
+            if cache.contains_key(&key) {
+                continue;
+            }
+            match self
+                .disk_cache
+                .and_then(|disk_cache| disk_cache.read(tree, &disk_key(&key)))
+            {
+                Some(value) => {
+                    hits += 1;
+                    inserted.push(key.clone());
+                    cache.insert(key, value);
+                }
+                None => missing.push(key),
+            }
+        }
+        // Missing entries fail when read instead
+        if OFFLINE.load(Ordering::Relaxed) {
+            missing.clear();
         }
+        record_reads(category, hits, missing.len() as u64);
+
+        let requests = missing.iter().map(&request).collect::<Vec<_>>();
+        let results = self.reader.send_rpc_batch(&requests)?;
+
+        for (key, result) in missing.into_iter().zip(results) {
+            let Some(value) = result.and_then(|value| serde_json::from_value(value).ok()) else {
+                continue;
+            };
+            if let Some(disk_cache) = self.disk_cache {
+                disk_cache.write(tree, &disk_key(&key), &value);
+            }
+            inserted.push(key.clone());
+            cache.insert(key, value);
+        }
+        drop(state);
+
+        // Sizes are estimated when touched, so only with a memory limit
+        for key in inserted {
+            self.touch(cache_key(&key), || {
+                memory(&mut self.state.borrow_mut())
+                    .get(&key)
+                    .map_or(0, |value| estimate_size(value))
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Warms up the caches for executing the given transactions.
+///
+/// Fetches in JSON-RPC batches the transaction traces from `reader`, and
+/// the storage, nonces and class hashes modified by the transactions from
+/// `previous_reader`, which must read the previous block. Most of the reads
+/// of the execution are then served from the cache, instead of sending a
+/// request each.
+///
+/// Only the entries known ahead of the execution are batched: the reads the
+/// execution makes beyond them are still sent individually, as the state
+/// readers are blocking.
+pub fn prefetch_block(
+    reader: &RpcCachedStateReader,
+    previous_reader: &RpcCachedStateReader,
+    tx_hashes: &[TransactionHash],
+) -> anyhow::Result<()> {
+    reader.prefetch(
+        tx_hashes.iter().copied(),
+        |cache| &mut cache.transaction_traces,
+        |hash| CacheKey::TransactionTrace(*hash),
+        CacheCategory::TransactionTrace,
+        disk_cache::TRANSACTION_TRACES,
+        disk_cache::hash_key,
+        |hash| ("starknet_traceTransaction", json!([hash])),
+    )?;
+
+    // State reads are served by the pathfinder database instead
+    if pathfinder::selected().is_some() {
+        return Ok(());
     }
+
+    let state_diffs = {
+        let state = reader.state.borrow();
+        tx_hashes
+            .iter()
+            .filter_map(|hash| state.transaction_traces.get(hash)?.state_diff.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let storage = state_diffs.iter().flat_map(|state_diff| {
+        state_diff.storage_diffs.iter().flat_map(|storage_diff| {
+            storage_diff
+                .storage_entries
+                .iter()
+                .map(|entry| (storage_diff.address, entry.key))
+        })
+    });
+    let contracts = state_diffs.iter().flat_map(|state_diff| {
+        state_diff
+            .storage_diffs
+            .iter()
+            .map(|storage_diff| storage_diff.address)
+            .chain(state_diff.nonces.iter().map(|nonce| nonce.contract_address))
+    });
+
+    let block_number = previous_reader.reader.block_number;
+    let block_id = previous_reader.reader.block_id();
+
+    previous_reader.prefetch(
+        storage,
+        |cache| &mut cache.storage,
+        |(contract_address, key)| CacheKey::Storage(*contract_address, *key),
+        CacheCategory::Storage,
+        disk_cache::STORAGE,
+        |(contract_address, key)| disk_cache::storage_key(block_number, contract_address, key),
+        |(contract_address, key)| {
+            let params = GetStorageAtParams {
+                block_id,
+                contract_address: *contract_address,
+                key: *key,
+            };
+            ("starknet_getStorageAt", json!(params))
+        },
+    )?;
+    previous_reader.prefetch(
+        contracts.clone(),
+        |cache| &mut cache.nonces,
+        |contract_address| CacheKey::Nonce(*contract_address),
+        CacheCategory::Nonce,
+        disk_cache::NONCES,
+        |contract_address| disk_cache::address_key(block_number, contract_address),
+        |contract_address| {
+            let params = GetNonceParams {
+                block_id,
+                contract_address: *contract_address,
+            };
+            ("starknet_getNonce", json!(params))
+        },
+    )?;
+    // Contracts deployed by the block don't exist yet in the previous
+    // block, so their requests fail and are skipped
+    previous_reader.prefetch(
+        contracts,
+        |cache| &mut cache.class_hashes,
+        |contract_address| CacheKey::ClassHash(*contract_address),
+        CacheCategory::ClassHash,
+        disk_cache::CLASS_HASHES,
+        |contract_address| disk_cache::address_key(block_number, contract_address),
+        |contract_address| {
+            let params = GetClassHashAtParams {
+                block_id,
+                contract_address: *contract_address,
+            };
+            ("starknet_getClassHashAt", json!(params))
+        },
+    )?;
+
+    Ok(())
 }
 
 impl StateReader for RpcCachedStateReader {
@@ -117,7 +655,12 @@ impl StateReader for RpcCachedStateReader {
             return Ok(block.clone());
         }
 
-        let result = self.reader.get_block_with_tx_hashes()?;
+        let result = self.read_through(
+            CacheCategory::Block,
+            disk_cache::BLOCKS,
+            &disk_cache::block_key(self.reader.block_number),
+            || self.reader.get_block_with_tx_hashes(),
+        )?;
 
         self.state.borrow_mut().block = Some(result.clone());
 
@@ -125,56 +668,86 @@ impl StateReader for RpcCachedStateReader {
     }
 
     fn get_transaction(&self, hash: &TransactionHash) -> StateResult<Transaction> {
-        Ok(match self.state.borrow_mut().transactions.entry(*hash) {
+        let result = match self.state.borrow_mut().transactions.entry(*hash) {
             Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
             Entry::Vacant(vacant_entry) => {
-                let result = self.reader.get_transaction(hash)?;
+                let result = self.read_through(
+                    CacheCategory::Transaction,
+                    disk_cache::TRANSACTIONS,
+                    &disk_cache::hash_key(hash),
+                    || self.reader.get_transaction(hash),
+                )?;
                 vacant_entry.insert(result.clone());
                 result
             }
-        })
+        };
+        self.touch(CacheKey::Transaction(*hash), || estimate_size(&result));
+
+        Ok(result)
     }
 
     fn get_contract_class(&self, class_hash: &ClassHash) -> StateResult<ContractClass> {
-        Ok(
-            match self.state.borrow_mut().contract_classes.entry(*class_hash) {
-                Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
-                Entry::Vacant(vacant_entry) => {
-                    let result = self.reader.get_contract_class(class_hash)?;
-                    vacant_entry.insert(result.clone());
-                    result
-                }
-            },
-        )
+        let result = match self.state.borrow_mut().contract_classes.entry(*class_hash) {
+            Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::ContractClass,
+                    disk_cache::CONTRACT_CLASSES,
+                    &disk_cache::class_key(class_hash),
+                    || self.reader.get_contract_class(class_hash),
+                )?;
+                vacant_entry.insert(result.clone());
+                result
+            }
+        };
+        self.touch(CacheKey::ContractClass(*class_hash), || {
+            estimate_size(&result)
+        });
+
+        Ok(result)
     }
 
     fn get_transaction_trace(&self, hash: &TransactionHash) -> StateResult<RpcTransactionTrace> {
-        Ok(
-            match self.state.borrow_mut().transaction_traces.entry(*hash) {
-                Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
-                Entry::Vacant(vacant_entry) => {
-                    let result = self.reader.get_transaction_trace(hash)?;
-                    vacant_entry.insert(result.clone());
-                    result
-                }
-            },
-        )
+        let result = match self.state.borrow_mut().transaction_traces.entry(*hash) {
+            Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::TransactionTrace,
+                    disk_cache::TRANSACTION_TRACES,
+                    &disk_cache::hash_key(hash),
+                    || self.reader.get_transaction_trace(hash),
+                )?;
+                vacant_entry.insert(result.clone());
+                result
+            }
+        };
+        self.touch(CacheKey::TransactionTrace(*hash), || estimate_size(&result));
+
+        Ok(result)
     }
 
     fn get_transaction_receipt(
         &self,
         hash: &TransactionHash,
     ) -> StateResult<RpcTransactionReceipt> {
-        Ok(
-            match self.state.borrow_mut().transaction_receipts.entry(*hash) {
-                Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
-                Entry::Vacant(vacant_entry) => {
-                    let result = self.reader.get_transaction_receipt(hash)?;
-                    vacant_entry.insert(result.clone());
-                    result
-                }
-            },
-        )
+        let result = match self.state.borrow_mut().transaction_receipts.entry(*hash) {
+            Entry::Occupied(occupied_entry) => occupied_entry.get().clone(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::TransactionReceipt,
+                    disk_cache::TRANSACTION_RECEIPTS,
+                    &disk_cache::hash_key(hash),
+                    || self.reader.get_transaction_receipt(hash),
+                )?;
+                vacant_entry.insert(result.clone());
+                result
+            }
+        };
+        self.touch(CacheKey::TransactionReceipt(*hash), || {
+            estimate_size(&result)
+        });
+
+        Ok(result)
     }
 
     fn get_chain_id(&self) -> ChainId {
@@ -188,47 +761,71 @@ impl BlockifierStateReader for RpcCachedStateReader {
         contract_address: ContractAddress,
         key: StorageKey,
     ) -> StateResult<Felt252> {
-        Ok(
-            match self
-                .state
-                .borrow_mut()
-                .storage
-                .entry((contract_address, key))
-            {
-                Entry::Occupied(occupied_entry) => *occupied_entry.get(),
-                Entry::Vacant(vacant_entry) => {
-                    let result = self.reader.get_storage_at(contract_address, key)?;
-                    vacant_entry.insert(result);
-                    result
-                }
-            },
-        )
+        let result = match self
+            .state
+            .borrow_mut()
+            .storage
+            .entry((contract_address, key))
+        {
+            Entry::Occupied(occupied_entry) => *occupied_entry.get(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::Storage,
+                    disk_cache::STORAGE,
+                    &disk_cache::storage_key(self.reader.block_number, &contract_address, &key),
+                    || self.reader.get_storage_at(contract_address, key),
+                )?;
+                vacant_entry.insert(result);
+                result
+            }
+        };
+        self.touch(CacheKey::Storage(contract_address, key), || {
+            mem::size_of::<Felt252>() as u64
+        });
+
+        Ok(result)
     }
 
     fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
-        Ok(
-            match self.state.borrow_mut().nonces.entry(contract_address) {
-                Entry::Occupied(occupied_entry) => *occupied_entry.get(),
-                Entry::Vacant(vacant_entry) => {
-                    let result = self.reader.get_nonce_at(contract_address)?;
-                    vacant_entry.insert(result);
-                    result
-                }
-            },
-        )
+        let result = match self.state.borrow_mut().nonces.entry(contract_address) {
+            Entry::Occupied(occupied_entry) => *occupied_entry.get(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::Nonce,
+                    disk_cache::NONCES,
+                    &disk_cache::address_key(self.reader.block_number, &contract_address),
+                    || self.reader.get_nonce_at(contract_address),
+                )?;
+                vacant_entry.insert(result);
+                result
+            }
+        };
+        self.touch(CacheKey::Nonce(contract_address), || {
+            mem::size_of::<Nonce>() as u64
+        });
+
+        Ok(result)
     }
 
     fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
-        Ok(
-            match self.state.borrow_mut().class_hashes.entry(contract_address) {
-                Entry::Occupied(occupied_entry) => *occupied_entry.get(),
-                Entry::Vacant(vacant_entry) => {
-                    let result = self.reader.get_class_hash_at(contract_address)?;
-                    vacant_entry.insert(result);
-                    result
-                }
-            },
-        )
+        let result = match self.state.borrow_mut().class_hashes.entry(contract_address) {
+            Entry::Occupied(occupied_entry) => *occupied_entry.get(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::ClassHash,
+                    disk_cache::CLASS_HASHES,
+                    &disk_cache::address_key(self.reader.block_number, &contract_address),
+                    || self.reader.get_class_hash_at(contract_address),
+                )?;
+                vacant_entry.insert(result);
+                result
+            }
+        };
+        self.touch(CacheKey::ClassHash(contract_address), || {
+            mem::size_of::<ClassHash>() as u64
+        });
+
+        Ok(result)
     }
 
     fn get_compiled_class(
@@ -239,15 +836,34 @@ impl BlockifierStateReader for RpcCachedStateReader {
         Ok(compile_contract_class(class, class_hash))
     }
 
-    fn get_compiled_class_hash(
-        &self,
-        _class_hash: ClassHash,
-    ) -> StateResult<starknet_api::core::CompiledClassHash> {
-        todo!();
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        let result = match self
+            .state
+            .borrow_mut()
+            .compiled_class_hashes
+            .entry(class_hash)
+        {
+            Entry::Occupied(occupied_entry) => *occupied_entry.get(),
+            Entry::Vacant(vacant_entry) => {
+                let result = self.read_through(
+                    CacheCategory::CompiledClassHash,
+                    disk_cache::COMPILED_CLASS_HASHES,
+                    &disk_cache::compiled_class_hash_key(self.reader.block_number, &class_hash),
+                    || self.reader.get_compiled_class_hash(class_hash),
+                )?;
+                vacant_entry.insert(result);
+                result
+            }
+        };
+        self.touch(CacheKey::CompiledClassHash(class_hash), || {
+            mem::size_of::<CompiledClassHash>() as u64
+        });
+
+        Ok(result)
     }
 }
 
-fn merge_cache(cache: &mut RpcCache, other: RpcCache) {
+pub(crate) fn merge_cache(cache: &mut RpcCache, other: RpcCache) {
     if cache.block.is_none() {
         cache.block = other.block
     }
@@ -260,4 +876,7 @@ fn merge_cache(cache: &mut RpcCache, other: RpcCache) {
         .transaction_receipts
         .extend(other.transaction_receipts);
     cache.transaction_traces.extend(other.transaction_traces);
+    cache
+        .compiled_class_hashes
+        .extend(other.compiled_class_hashes);
 }
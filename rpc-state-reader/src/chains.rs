@@ -0,0 +1,156 @@
+//! Chains other than mainnet and testnet, e.g. appchains, devnets or
+//! integration networks, defined in `chains.toml` (or the file set in
+//! `RPC_CHAINS_CONFIG`):
+//!
+//! ```toml
+//! [my-appchain]
+//! chain_id = "MY_APPCHAIN"
+//! url = "http://localhost:9545/rpc/v0_7"
+//! # Optional, mainnet's by default
+//! eth_fee_token_address = "<address>"
+//! strk_fee_token_address = "<address>"
+//! ```
+//!
+//! Each chain is selected by its name, wherever a chain is expected.
+
+use std::{collections::BTreeMap, env, fs, path::Path, sync::OnceLock};
+
+use anyhow::{bail, Context};
+use blockifier::context::FeeTokenAddresses;
+use blockifier_reexecution::state_reader::utils::get_fee_token_addresses;
+use serde::Deserialize;
+use starknet_api::core::{ChainId, ContractAddress};
+
+/// Config file read when `RPC_CHAINS_CONFIG` isn't set
+const DEFAULT_CONFIG: &str = "chains.toml";
+
+static CHAINS: OnceLock<Vec<CustomChain>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct CustomChain {
+    pub name: String,
+    pub chain_id: ChainId,
+    pub url: String,
+    pub fee_token_addresses: FeeTokenAddresses,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainConfig {
+    chain_id: String,
+    url: String,
+    eth_fee_token_address: Option<ContractAddress>,
+    strk_fee_token_address: Option<ContractAddress>,
+}
+
+/// Parses the chains of a config file
+fn parse_chains(config: &str) -> anyhow::Result<Vec<CustomChain>> {
+    let configs: BTreeMap<String, ChainConfig> = toml::from_str(config)?;
+    let mainnet_tokens = get_fee_token_addresses(&ChainId::Mainnet);
+
+    Ok(configs
+        .into_iter()
+        .map(|(name, config)| CustomChain {
+            name,
+            chain_id: ChainId::from(config.chain_id),
+            url: config.url,
+            fee_token_addresses: FeeTokenAddresses {
+                eth_fee_token_address: config
+                    .eth_fee_token_address
+                    .unwrap_or(mainnet_tokens.eth_fee_token_address),
+                strk_fee_token_address: config
+                    .strk_fee_token_address
+                    .unwrap_or(mainnet_tokens.strk_fee_token_address),
+            },
+        })
+        .collect())
+}
+
+/// Returns the path of the config file
+pub(crate) fn config_path() -> String {
+    env::var("RPC_CHAINS_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_string())
+}
+
+fn load() -> anyhow::Result<Vec<CustomChain>> {
+    let path = config_path();
+    if env::var("RPC_CHAINS_CONFIG").is_err() && !Path::new(&path).exists() {
+        return Ok(Vec::new());
+    }
+    let config = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read chains config {path}"))?;
+    parse_chains(&config).with_context(|| format!("failed to parse chains config {path}"))
+}
+
+/// Returns the chains of the config file, read on first use
+pub fn custom_chains() -> &'static [CustomChain] {
+    CHAINS.get_or_init(|| load().expect("failed to load chains config"))
+}
+
+/// Returns the chain with the given name: either mainnet, testnet (or
+/// sepolia) or a custom chain.
+pub fn parse_chain(name: &str) -> anyhow::Result<ChainId> {
+    match name.to_lowercase().as_str() {
+        "mainnet" => return Ok(ChainId::Mainnet),
+        "testnet" | "sepolia" => return Ok(ChainId::Sepolia),
+        _ => {}
+    }
+
+    match custom_chains().iter().find(|chain| chain.name == name) {
+        Some(chain) => Ok(chain.chain_id.clone()),
+        None => bail!(
+            "invalid network name {name}, it should be one of: mainnet, testnet, or a chain of the chains config {}",
+            config_path()
+        ),
+    }
+}
+
+pub(crate) fn custom_chain(chain_id: &ChainId) -> Option<&'static CustomChain> {
+    custom_chains()
+        .iter()
+        .find(|chain| &chain.chain_id == chain_id)
+}
+
+/// Returns the fee token addresses of the chain, as defined by the chains
+/// config for custom chains
+pub fn fee_token_addresses(chain_id: &ChainId) -> FeeTokenAddresses {
+    match custom_chain(chain_id) {
+        Some(chain) => chain.fee_token_addresses.clone(),
+        None => get_fee_token_addresses(chain_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chains() {
+        let chains = parse_chains(
+            r#"
+            [devnet]
+            chain_id = "SN_DEVNET"
+            url = "http://localhost:5050/rpc"
+            strk_fee_token_address = "0x1"
+
+            [integration]
+            chain_id = "SN_INTEGRATION_SEPOLIA"
+            url = "http://localhost:9545/rpc"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(chains.len(), 2);
+        assert_eq!(chains[0].chain_id, ChainId::Other("SN_DEVNET".to_string()));
+        assert_eq!(
+            chains[0].fee_token_addresses.strk_fee_token_address,
+            ContractAddress::from(1u128)
+        );
+        assert_eq!(
+            chains[0].fee_token_addresses.eth_fee_token_address,
+            get_fee_token_addresses(&ChainId::Mainnet).eth_fee_token_address
+        );
+        assert_eq!(chains[1].chain_id, ChainId::IntegrationSepolia);
+
+        assert!(parse_chains("[devnet]\nurl = \"http://localhost:5050\"").is_err());
+    }
+}
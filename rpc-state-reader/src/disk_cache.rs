@@ -0,0 +1,502 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter},
+    ops::RangeInclusive,
+    path::PathBuf,
+    process,
+    sync::Mutex,
+};
+
+use fs2::FileExt;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use starknet_api::{
+    block::BlockNumber,
+    core::{ChainId, ClassHash, ContractAddress, PatriciaKey},
+    hash::StarkHash,
+    state::StorageKey,
+    transaction::TransactionHash,
+};
+use tracing::{info, warn};
+
+use crate::cache::{merge_cache, RpcCache};
+
+pub const BLOCKS: &str = "blocks";
+pub const TRANSACTIONS: &str = "transactions";
+pub const CONTRACT_CLASSES: &str = "contract_classes";
+pub const STORAGE: &str = "storage";
+pub const NONCES: &str = "nonces";
+pub const CLASS_HASHES: &str = "class_hashes";
+pub const TRANSACTION_RECEIPTS: &str = "transaction_receipts";
+pub const TRANSACTION_TRACES: &str = "transaction_traces";
+pub const COMPILED_CLASS_HASHES: &str = "compiled_class_hashes";
+/// Blocks whose JSON cache was already imported
+const MIGRATED_BLOCKS: &str = "migrated_blocks";
+
+/// zstd's default level, which is fast enough to not slow down the replay
+const COMPRESSION_LEVEL: i32 = 3;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Disk cache of each chain, opened on first use
+static DISK_CACHES: Mutex<Option<HashMap<String, Option<&'static DiskCache>>>> = Mutex::new(None);
+
+/// A key-value store that persists the result of RPC calls to disk,
+/// reading and writing each entry individually.
+///
+/// There is a database per chain (`rpc_cache/{chain}/db`), as the keys don't
+/// include the chain. It's shared by every cached reader of the process, and
+/// can only be opened by a single process at a time.
+pub struct DiskCache {
+    db: sled::Db,
+}
+
+impl DiskCache {
+    /// Returns the process' disk cache of the chain, or `None` if it could
+    /// not be opened (i.e. because another process is using it).
+    pub fn get(chain: &ChainId) -> Option<&'static DiskCache> {
+        let mut disk_caches = DISK_CACHES.lock().unwrap();
+        *disk_caches
+            .get_or_insert_with(HashMap::new)
+            .entry(chain.to_string())
+            .or_insert_with(|| match sled::open(database_path(chain)) {
+                // Opened once per chain for the lifetime of the process
+                Ok(db) => Some(Box::leak(Box::new(DiskCache { db }))),
+                Err(err) => {
+                    warn!("failed to open rpc cache database, falling back to json cache: {err}");
+                    None
+                }
+            })
+    }
+
+    pub fn read<T: DeserializeOwned>(&self, tree: &str, key: &[u8]) -> Option<T> {
+        let value = self
+            .db
+            .open_tree(tree)
+            .and_then(|tree| tree.get(key))
+            .inspect_err(|err| warn!("failed to read from rpc cache database: {err}"))
+            .ok()??;
+
+        decompress(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| Ok(serde_json::from_slice(&value)?))
+            .inspect_err(|err| warn!("failed to deserialize rpc cache entry: {err}"))
+            .ok()
+    }
+
+    pub fn write<T: Serialize>(&self, tree: &str, key: &[u8], value: &T) {
+        let result = serde_json::to_vec(value)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| Ok(compress(&value)?))
+            .and_then(|value| Ok(self.db.open_tree(tree)?.insert(key, value)?));
+
+        if let Err(err) = result {
+            warn!("failed to write to rpc cache database: {err}");
+        }
+    }
+
+    /// Returns up to `count` entries of the tree, spread evenly across it.
+    pub fn sample(&self, tree: &str, count: usize) -> anyhow::Result<Vec<(Vec<u8>, Value)>> {
+        let tree = self.db.open_tree(tree)?;
+        let step = (tree.len() / count.max(1)).max(1);
+
+        tree.iter()
+            .step_by(step)
+            .take(count)
+            .map(|entry| {
+                let (key, value) = entry?;
+                let value = serde_json::from_slice(&decompress(&value)?)?;
+                Ok((key.to_vec(), value))
+            })
+            .collect()
+    }
+
+    pub fn flush(&self) {
+        if let Err(err) = self.db.flush() {
+            warn!("failed to flush rpc cache database: {err}");
+        }
+    }
+
+    /// Imports the JSON cache of the given block (`rpc_cache/{chain}/{block_number}.json.zst`,
+    /// or a legacy one), if it exists and wasn't imported yet.
+    pub fn migrate_json_cache(
+        &self,
+        chain: &ChainId,
+        block_number: BlockNumber,
+    ) -> anyhow::Result<()> {
+        let migrated_blocks = self.db.open_tree(MIGRATED_BLOCKS)?;
+        let migrated_key = block_number.0.to_be_bytes();
+        if migrated_blocks.contains_key(migrated_key)? {
+            return Ok(());
+        }
+
+        let Some(cache) = read_json_cache(chain, block_number) else {
+            return Ok(());
+        };
+
+        if let Some(block) = &cache.block {
+            self.write(BLOCKS, &block_key(block_number), block);
+        }
+        for (hash, transaction) in &cache.transactions {
+            self.write(TRANSACTIONS, &hash_key(hash), transaction);
+        }
+        for (class_hash, class) in &cache.contract_classes {
+            self.write(CONTRACT_CLASSES, &class_key(class_hash), class);
+        }
+        for ((contract_address, key), value) in &cache.storage {
+            self.write(
+                STORAGE,
+                &storage_key(block_number, contract_address, key),
+                value,
+            );
+        }
+        for (contract_address, nonce) in &cache.nonces {
+            self.write(NONCES, &address_key(block_number, contract_address), nonce);
+        }
+        for (contract_address, class_hash) in &cache.class_hashes {
+            self.write(
+                CLASS_HASHES,
+                &address_key(block_number, contract_address),
+                class_hash,
+            );
+        }
+        for (hash, receipt) in &cache.transaction_receipts {
+            self.write(TRANSACTION_RECEIPTS, &hash_key(hash), receipt);
+        }
+        for (hash, trace) in &cache.transaction_traces {
+            self.write(TRANSACTION_TRACES, &hash_key(hash), trace);
+        }
+        for (class_hash, compiled_class_hash) in &cache.compiled_class_hashes {
+            self.write(
+                COMPILED_CLASS_HASHES,
+                &compiled_class_hash_key(block_number, class_hash),
+                compiled_class_hash,
+            );
+        }
+
+        migrated_blocks.insert(migrated_key, Vec::new())?;
+        self.db.flush()?;
+
+        info!(
+            "migrated {} into the rpc cache database",
+            json_cache_path(chain, block_number).display()
+        );
+
+        Ok(())
+    }
+}
+
+pub fn database_path(chain: &ChainId) -> PathBuf {
+    PathBuf::from(format!("rpc_cache/{chain}/db"))
+}
+
+// JSON caches are sharded by chain and block, so that processes replaying
+// disjoint ranges don't contend for the same files, and the blocks that are
+// no longer needed can be pruned individually.
+
+pub fn json_cache_path(chain: &ChainId, block_number: BlockNumber) -> PathBuf {
+    PathBuf::from(format!("rpc_cache/{chain}/{block_number}.json.zst"))
+}
+
+/// Paths of the JSON caches written before they were compressed, and before
+/// they were sharded by chain, in the order they are read
+fn legacy_json_cache_paths(chain: &ChainId, block_number: BlockNumber) -> [PathBuf; 2] {
+    [
+        PathBuf::from(format!("rpc_cache/{chain}/{block_number}.json")),
+        PathBuf::from(format!("rpc_cache/{block_number}.json")),
+    ]
+}
+
+/// Path of the lock of the JSON cache, shared with the writers of the
+/// uncompressed caches
+fn json_cache_lock_path(chain: &ChainId, block_number: BlockNumber) -> PathBuf {
+    PathBuf::from(format!("rpc_cache/{chain}/{block_number}.json.lock"))
+}
+
+/// Reads the JSON cache of the given block, or its legacy cache if it has
+/// none. The legacy cache is merged into the compressed one when it's
+/// written.
+///
+/// The file is only ever replaced atomically (see `write_json_cache`), so it
+/// can be read without taking any lock, even while other processes write it.
+pub fn read_json_cache(chain: &ChainId, block_number: BlockNumber) -> Option<RpcCache> {
+    let [legacy_path, unsharded_path] = legacy_json_cache_paths(chain, block_number);
+    let data = fs::read(json_cache_path(chain, block_number))
+        .or_else(|_| fs::read(legacy_path))
+        .or_else(|_| fs::read(unsharded_path))
+        .ok()?;
+
+    decompress(&data)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| Ok(serde_json::from_slice(&data)?))
+        .inspect_err(|err| warn!("failed to deserialize json cache of block {block_number}: {err}"))
+        .ok()
+}
+
+/// Merges the given cache into the JSON cache of the block.
+///
+/// Writers are serialized with an advisory lock on a sibling `.lock` file.
+/// The merged cache is written to a temporary file, which then replaces the
+/// cache file, so readers never see a partially written cache.
+pub fn write_json_cache(
+    chain: &ChainId,
+    block_number: BlockNumber,
+    cache: &mut RpcCache,
+) -> anyhow::Result<()> {
+    let path = json_cache_path(chain, block_number);
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    let lock = File::create(json_cache_lock_path(chain, block_number))?;
+    lock.lock_exclusive()?;
+
+    if let Some(old_cache) = read_json_cache(chain, block_number) {
+        merge_cache(cache, old_cache);
+    }
+
+    // The temporary file is unique per process, in case the lock is
+    // not honored (i.e. by some network file systems)
+    let tmp_path = PathBuf::from(format!("{}.{}.tmp", path.display(), process::id()));
+    let mut encoder =
+        zstd::Encoder::new(BufWriter::new(File::create(&tmp_path)?), COMPRESSION_LEVEL)?;
+    serde_json::to_writer(&mut encoder, cache)?;
+    encoder.finish()?.into_inner()?.sync_all()?;
+
+    fs::rename(&tmp_path, &path)?;
+
+    FileExt::unlock(&lock)?;
+
+    Ok(())
+}
+
+/// Removes the JSON caches of the chain for the given blocks, compressed or
+/// not, returning how many blocks were removed. Each cache is removed under
+/// its lock, so that no process is writing it at the same time.
+pub fn prune_json_caches(chain: &ChainId, blocks: RangeInclusive<u64>) -> anyhow::Result<usize> {
+    let mut pruned = 0;
+    for block_number in blocks.map(BlockNumber) {
+        let [legacy_path, _] = legacy_json_cache_paths(chain, block_number);
+        let paths = [json_cache_path(chain, block_number), legacy_path];
+        if !paths.iter().any(|path| path.exists()) {
+            continue;
+        }
+
+        let lock_path = json_cache_lock_path(chain, block_number);
+        let lock = File::create(&lock_path)?;
+        lock.lock_exclusive()?;
+        for path in paths.iter().filter(|path| path.exists()) {
+            fs::remove_file(path)?;
+        }
+        fs::remove_file(&lock_path)?;
+        FileExt::unlock(&lock)?;
+
+        pruned += 1;
+    }
+
+    Ok(pruned)
+}
+
+// Every entry, and the JSON cache, is compressed with zstd. Data written
+// before compression was introduced is plain JSON, which is told apart by
+// not starting with zstd's magic number.
+
+fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::encode_all(data, COMPRESSION_LEVEL)
+}
+
+fn decompress(data: &[u8]) -> io::Result<Cow<[u8]>> {
+    if data.starts_with(&ZSTD_MAGIC) {
+        Ok(Cow::Owned(zstd::decode_all(data)?))
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
+
+// Transactions, classes, receipts and traces are identified by their hash,
+// so they are shared by every block. The rest of the entries depend on the
+// block they were read at.
+
+pub fn block_key(block_number: BlockNumber) -> Vec<u8> {
+    block_number.0.to_be_bytes().to_vec()
+}
+
+pub fn hash_key(hash: &TransactionHash) -> Vec<u8> {
+    hash.0.to_bytes_be().to_vec()
+}
+
+pub fn class_key(class_hash: &ClassHash) -> Vec<u8> {
+    class_hash.0.to_bytes_be().to_vec()
+}
+
+pub fn address_key(block_number: BlockNumber, contract_address: &ContractAddress) -> Vec<u8> {
+    let mut key = block_key(block_number);
+    key.extend(contract_address.0.key().to_bytes_be());
+    key
+}
+
+/// The compiled class hash of a class may change between blocks (e.g. when
+/// its CASM hash function is migrated), so it's keyed by block
+pub fn compiled_class_hash_key(block_number: BlockNumber, class_hash: &ClassHash) -> Vec<u8> {
+    let mut key = block_key(block_number);
+    key.extend(class_key(class_hash));
+    key
+}
+
+pub fn storage_key(
+    block_number: BlockNumber,
+    contract_address: &ContractAddress,
+    storage_key: &StorageKey,
+) -> Vec<u8> {
+    let mut key = address_key(block_number, contract_address);
+    key.extend(storage_key.0.key().to_bytes_be());
+    key
+}
+
+pub fn parse_class_key(key: &[u8]) -> Option<ClassHash> {
+    Some(ClassHash(parse_felt(key)?))
+}
+
+pub fn parse_address_key(key: &[u8]) -> Option<(BlockNumber, ContractAddress)> {
+    let (block_number, contract_address) = key.split_first_chunk::<8>()?;
+    Some((
+        BlockNumber(u64::from_be_bytes(*block_number)),
+        ContractAddress::try_from(parse_felt(contract_address)?).ok()?,
+    ))
+}
+
+pub fn parse_compiled_class_hash_key(key: &[u8]) -> Option<(BlockNumber, ClassHash)> {
+    let (block_number, class_hash) = key.split_first_chunk::<8>()?;
+    Some((
+        BlockNumber(u64::from_be_bytes(*block_number)),
+        parse_class_key(class_hash)?,
+    ))
+}
+
+pub fn parse_storage_key(key: &[u8]) -> Option<(BlockNumber, ContractAddress, StorageKey)> {
+    let (address_key, storage_key) = key.split_at_checked(8 + 32)?;
+    let (block_number, contract_address) = parse_address_key(address_key)?;
+    Some((
+        block_number,
+        contract_address,
+        StorageKey(PatriciaKey::try_from(parse_felt(storage_key)?).ok()?),
+    ))
+}
+
+/// Describes the entry of the tree with the given key, e.g. to report that
+/// it's missing
+pub fn describe_key(tree: &str, key: &[u8]) -> String {
+    let description = match tree {
+        BLOCKS => key
+            .try_into()
+            .ok()
+            .map(|block_number| format!("block {}", u64::from_be_bytes(block_number))),
+        STORAGE => parse_storage_key(key).map(|(block_number, contract_address, key)| {
+            format!(
+                "storage of {} at {} in block {}",
+                contract_address.0.key().to_hex_string(),
+                key.0.key().to_hex_string(),
+                block_number.0
+            )
+        }),
+        NONCES | CLASS_HASHES => parse_address_key(key).map(|(block_number, contract_address)| {
+            format!(
+                "{tree} of {} in block {}",
+                contract_address.0.key().to_hex_string(),
+                block_number.0
+            )
+        }),
+        COMPILED_CLASS_HASHES => {
+            parse_compiled_class_hash_key(key).map(|(block_number, class_hash)| {
+                format!(
+                    "{tree} of {} in block {}",
+                    class_hash.to_hex_string(),
+                    block_number.0
+                )
+            })
+        }
+        _ => parse_felt(key).map(|hash| format!("{tree} of {}", hash.to_hex_string())),
+    };
+    description.unwrap_or_else(|| format!("{tree} entry"))
+}
+
+fn parse_felt(bytes: &[u8]) -> Option<StarkHash> {
+    Some(StarkHash::from_bytes_be(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet_api::patricia_key;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        let block_number = BlockNumber(90000);
+        let contract_address = ContractAddress::from(2u128);
+        let key = StorageKey(patricia_key!(3u128));
+
+        assert_eq!(
+            parse_storage_key(&storage_key(block_number, &contract_address, &key)),
+            Some((block_number, contract_address, key))
+        );
+        assert_eq!(
+            parse_address_key(&address_key(block_number, &contract_address)),
+            Some((block_number, contract_address))
+        );
+        assert_eq!(
+            parse_class_key(&class_key(&ClassHash(4u128.into()))),
+            Some(ClassHash(4u128.into()))
+        );
+        assert_eq!(
+            parse_compiled_class_hash_key(&compiled_class_hash_key(
+                block_number,
+                &ClassHash(4u128.into())
+            )),
+            Some((block_number, ClassHash(4u128.into())))
+        );
+        assert_eq!(parse_address_key(&[0; 8]), None);
+    }
+
+    #[test]
+    fn test_describe_key() {
+        let block_number = BlockNumber(90000);
+        let contract_address = ContractAddress::from(2u128);
+
+        assert_eq!(
+            describe_key(
+                STORAGE,
+                &storage_key(
+                    block_number,
+                    &contract_address,
+                    &StorageKey(patricia_key!(3u128))
+                )
+            ),
+            "storage of 0x2 at 0x3 in block 90000"
+        );
+        assert_eq!(
+            describe_key(NONCES, &address_key(block_number, &contract_address)),
+            "nonces of 0x2 in block 90000"
+        );
+        assert_eq!(
+            describe_key(BLOCKS, &block_key(block_number)),
+            "block 90000"
+        );
+        assert_eq!(
+            describe_key(TRANSACTIONS, &hash_key(&TransactionHash(4u128.into()))),
+            "transactions of 0x4"
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let data = br#"{"block_number":90000}"#;
+
+        let compressed = compress(data).unwrap();
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+        assert_eq!(decompress(&compressed).unwrap().as_ref(), data);
+
+        // Uncompressed entries are read as is
+        assert_eq!(decompress(data).unwrap().as_ref(), data);
+    }
+}
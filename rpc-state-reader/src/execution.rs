@@ -1,13 +1,17 @@
+use std::{collections::HashSet, sync::OnceLock};
+
 use crate::{
+    cache::{prefetch_block, RpcCachedStateReader},
+    chains,
     objects::BlockHeader,
-    reader::{RpcStateReader, StateReader},
+    reader::{precompile_contract_classes, RpcStateReader, StateReader},
 };
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use blockifier::{
     blockifier::block::validated_gas_prices,
     bouncer::BouncerConfig,
     context::{BlockContext, ChainInfo},
-    state::cached_state::CachedState,
+    state::{cached_state::CachedState, state_api::StateReader as _},
     transaction::{
         account_transaction::ExecutionFlags, objects::TransactionExecutionInfo,
         transaction_execution::Transaction as BlockiTransaction,
@@ -15,33 +19,44 @@ use blockifier::{
     },
     versioned_constants::VersionedConstants,
 };
-use blockifier_reexecution::state_reader::{
-    compile::{legacy_to_contract_class_v0, sierra_to_versioned_contract_class_v1},
-    utils::get_fee_token_addresses,
+use blockifier_reexecution::state_reader::compile::{
+    legacy_to_contract_class_v0, sierra_to_versioned_contract_class_v1,
 };
 use starknet::core::types::ContractClass;
 use starknet_api::{
     block::{BlockInfo, BlockNumber, GasPrice, NonzeroGasPrice, StarknetVersion},
     contract_class::{ClassInfo, SierraVersion},
-    core::ChainId,
+    core::{ChainId, ClassHash},
+    data_availability::L1DataAvailabilityMode,
     test_utils::MAX_FEE,
     transaction::{Transaction as SNTransaction, TransactionHash},
 };
 
+/// Oldest version with versioned constants, used for older blocks
+pub const OLDEST_CONSTANTS_VERSION: StarknetVersion = StarknetVersion::V0_13_0;
+
+static CONSTANTS_OVERRIDE: OnceLock<StarknetVersion> = OnceLock::new();
+
 pub fn fetch_block_context(reader: &impl StateReader) -> anyhow::Result<BlockContext> {
     let block = reader.get_block_with_tx_hashes()?;
 
-    let version = StarknetVersion::try_from(block.header.starknet_version.as_str())?;
+    block_context_from_header(block.header, reader.get_chain_id())
+}
+
+/// Builds the context of the block with the given header, which may differ
+/// from the one on chain (e.g. to simulate other gas prices).
+pub fn block_context_from_header(
+    header: BlockHeader,
+    chain_id: ChainId,
+) -> anyhow::Result<BlockContext> {
+    let version = StarknetVersion::try_from(header.starknet_version.as_str())?;
 
     // we must use the starknet constants that corresponds to the starknet transaction's version
-    let versioned_constants = VersionedConstants::get(&version)
-        .unwrap_or_else(|_| VersionedConstants::latest_constants())
-        .clone();
+    let versioned_constants = versioned_constants(&version).clone();
 
-    let block_info = get_block_info(block.header);
+    let block_info = get_block_info(header);
 
-    let chain_id = reader.get_chain_id();
-    let fee_token_addresses = get_fee_token_addresses(&chain_id);
+    let fee_token_addresses = chains::fee_token_addresses(&chain_id);
     let chain_info = ChainInfo {
         chain_id,
         fee_token_addresses,
@@ -55,13 +70,57 @@ pub fn fetch_block_context(reader: &impl StateReader) -> anyhow::Result<BlockCon
     ))
 }
 
+/// Uses the versioned constants of the given version (e.g. `0.13.2`) for
+/// every block, instead of the ones of each block's version. Can only be
+/// called once, before building any block context.
+pub fn override_versioned_constants(version: &str) -> anyhow::Result<()> {
+    let version = StarknetVersion::try_from(version)?;
+    VersionedConstants::get(&version)
+        .map_err(|err| anyhow!("no versioned constants for version {version:?}: {err}"))?;
+    CONSTANTS_OVERRIDE
+        .set(version)
+        .map_err(|_| anyhow!("versioned constants were already overridden"))
+}
+
+pub fn constants_override() -> Option<&'static StarknetVersion> {
+    CONSTANTS_OVERRIDE.get()
+}
+
+/// Returns the versioned constants in force at the given version, unless
+/// overridden. Versions older than the oldest constants use those, and
+/// unknown newer versions use the latest ones.
+pub fn versioned_constants(version: &StarknetVersion) -> &'static VersionedConstants {
+    let version = constants_override().unwrap_or(version);
+    if let Ok(versioned_constants) = VersionedConstants::get(version) {
+        return versioned_constants;
+    }
+
+    if *version < OLDEST_CONSTANTS_VERSION {
+        VersionedConstants::get(&OLDEST_CONSTANTS_VERSION)
+            .expect("the oldest versioned constants should exist")
+    } else {
+        VersionedConstants::latest_constants()
+    }
+}
+
 pub fn fetch_blockifier_transaction(
     reader: &impl StateReader,
     flags: ExecutionFlags,
     hash: TransactionHash,
 ) -> anyhow::Result<BlockiTransaction> {
     let transaction = reader.get_transaction(&hash)?;
+    to_blockifier_transaction(reader, transaction, hash, flags)
+}
 
+/// Converts the transaction for execution, fetching the class of declare
+/// transactions from the reader. The transaction doesn't need to be on chain,
+/// e.g. it may be read from a file.
+pub fn to_blockifier_transaction(
+    reader: &impl StateReader,
+    transaction: SNTransaction,
+    hash: TransactionHash,
+    flags: ExecutionFlags,
+) -> anyhow::Result<BlockiTransaction> {
     let class_info = if let SNTransaction::Declare(declare) = &transaction {
         let class = reader.get_contract_class(&declare.class_hash())?;
         Some(get_class_info(class)?)
@@ -105,6 +164,99 @@ pub fn execute_transaction(
     Ok(execution_info)
 }
 
+/// Summary of the data cached by `fetch_block`
+pub struct FetchedBlock {
+    pub transactions: usize,
+    pub classes: usize,
+}
+
+/// Fetches and caches everything needed to replay the block read by `reader`,
+/// without executing it: the header, the transactions with their receipts and
+/// traces, the storage, nonces and class hashes modified by the transactions,
+/// and the classes of every called contract.
+///
+/// `previous_reader` must read the previous block. As the state is collected
+/// from the traces, reads of entries that were not modified by the block may
+/// still require the node.
+pub fn fetch_block(
+    reader: &RpcCachedStateReader,
+    previous_reader: &RpcCachedStateReader,
+) -> anyhow::Result<FetchedBlock> {
+    let tx_hashes = reader.get_block_with_tx_hashes()?.transactions;
+
+    prefetch_block(reader, previous_reader, &tx_hashes)?;
+
+    let flags = ExecutionFlags {
+        only_query: false,
+        charge_fee: false,
+        validate: true,
+    };
+
+    for hash in &tx_hashes {
+        // Also fetches the class declared by the transaction, if any
+        fetch_blockifier_transaction(reader, flags.clone(), *hash)?;
+        reader.get_transaction_receipt(hash)?;
+    }
+
+    let class_hashes = called_classes(reader, previous_reader, &tx_hashes)?;
+    for class_hash in &class_hashes {
+        previous_reader.get_contract_class(class_hash)?;
+    }
+
+    Ok(FetchedBlock {
+        transactions: tx_hashes.len(),
+        classes: class_hashes.len(),
+    })
+}
+
+/// Starts compiling to native, in the background, the classes called by the
+/// transactions, so that they compile concurrently instead of one at a time
+/// when first executed. Returns the number of classes.
+///
+/// `previous_reader` must read the previous block.
+pub fn precompile_block(
+    reader: &impl StateReader,
+    previous_reader: &impl StateReader,
+    tx_hashes: &[TransactionHash],
+) -> anyhow::Result<usize> {
+    let classes = called_classes(reader, previous_reader, tx_hashes)?
+        .into_iter()
+        .map(|class_hash| Ok((class_hash, previous_reader.get_contract_class(&class_hash)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let count = classes.len();
+
+    precompile_contract_classes(classes);
+
+    Ok(count)
+}
+
+/// Returns the classes of the contracts called by the transactions, according
+/// to their traces, as read by `previous_reader`.
+pub fn called_classes(
+    reader: &impl StateReader,
+    previous_reader: &impl StateReader,
+    tx_hashes: &[TransactionHash],
+) -> anyhow::Result<HashSet<ClassHash>> {
+    let mut contracts = HashSet::new();
+    for hash in tx_hashes {
+        let trace = reader.get_transaction_trace(hash)?;
+        contracts.extend(trace.calls().filter_map(|call| call.contract_address));
+    }
+
+    let mut class_hashes = HashSet::new();
+    for contract_address in contracts {
+        // Contracts deployed by the block don't exist in the previous block
+        match previous_reader.get_class_hash_at(contract_address) {
+            Ok(class_hash) if class_hash != ClassHash::default() => {
+                class_hashes.insert(class_hash);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(class_hashes)
+}
+
 /// Fetches all information needed to execute a given transaction
 ///
 /// Due to limitations in the CachedState, we need to fetch this information
@@ -138,12 +290,15 @@ pub fn fetch_transaction_with_state(
     Ok((transaction, context))
 }
 
-/// Derives `BlockInfo` from the `BlockHeader`
+/// Derives `BlockInfo` from the `BlockHeader`. Nodes that don't report the L2
+/// gas price (e.g. RPC v0.7) get the minimum one.
 pub fn get_block_info(header: BlockHeader) -> BlockInfo {
     fn parse_gas_price(price: GasPrice) -> NonzeroGasPrice {
         NonzeroGasPrice::new(price).unwrap_or(NonzeroGasPrice::MIN)
     }
 
+    let l2_gas_price = header.l2_gas_price.unwrap_or_default();
+
     BlockInfo {
         block_number: header.block_number,
         sequencer_address: header.sequencer_address,
@@ -153,10 +308,10 @@ pub fn get_block_info(header: BlockHeader) -> BlockInfo {
             parse_gas_price(header.l1_gas_price.price_in_fri),
             parse_gas_price(header.l1_data_gas_price.price_in_wei),
             parse_gas_price(header.l1_data_gas_price.price_in_fri),
-            NonzeroGasPrice::MIN,
-            NonzeroGasPrice::MIN,
+            parse_gas_price(l2_gas_price.price_in_wei),
+            parse_gas_price(l2_gas_price.price_in_fri),
         ),
-        use_kzg_da: true,
+        use_kzg_da: header.l1_da_mode == L1DataAvailabilityMode::Blob,
     }
 }
 
@@ -441,7 +596,7 @@ mod tests {
         //assert_eq!(tx_info.fee_transfer_call_info.map(|ref ci| ci.into()), trace.fee_transfer_invocation); TODO: fix charge_fee
     }
 
-    // test cairo-vm's tx execution against cairo-native, using only_cairo_vm feature
+    // test cairo-vm's tx execution against cairo-native, using the vm executor
     #[test_case(
         "0x04ba569a40a866fd1cbb2f3d3ba37ef68fb91267a4931a377d6acc6e5a854f9a",
         648462,
@@ -3221,6 +3376,8 @@ mod tests {
     impl From<&CallInfo> for RpcCallInfo {
         fn from(value: &CallInfo) -> Self {
             Self {
+                contract_address: Some(value.call.storage_address),
+                entry_point_selector: Some(value.call.entry_point_selector),
                 result: Some(value.execution.retdata.0.clone()),
                 calldata: Some((*value.call.calldata.0).clone()),
                 calls: value.inner_calls.iter().map(|ci| ci.into()).collect(),
@@ -0,0 +1,106 @@
+//! Classes executed with another executor than the selected one, e.g. to
+//! work around a known native miscompilation of a contract, while still
+//! executing the rest with Cairo Native. They are defined in `executors.toml`
+//! (or the file set in `EXECUTORS_CONFIG`):
+//!
+//! ```toml
+//! vm = ["<class_hash>"]
+//! native = ["<class_hash>"]
+//! sierra-emu = ["<class_hash>"]
+//! ```
+
+use std::{collections::HashMap, env, fs, path::Path, sync::OnceLock};
+
+use anyhow::{ensure, Context};
+use serde::Deserialize;
+use starknet_api::core::ClassHash;
+use tracing::info;
+
+use crate::reader::{executor, Executor};
+
+/// Config file read when `EXECUTORS_CONFIG` isn't set
+const DEFAULT_CONFIG: &str = "executors.toml";
+
+static OVERRIDES: OnceLock<HashMap<ClassHash, Executor>> = OnceLock::new();
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ExecutorsConfig {
+    #[serde(default)]
+    native: Vec<ClassHash>,
+    #[serde(default)]
+    vm: Vec<ClassHash>,
+    #[serde(default)]
+    sierra_emu: Vec<ClassHash>,
+}
+
+/// Parses the executor of each class of a config file
+fn parse_overrides(config: &str) -> anyhow::Result<HashMap<ClassHash, Executor>> {
+    let config: ExecutorsConfig = toml::from_str(config)?;
+
+    let mut overrides = HashMap::new();
+    for (executor, classes) in [
+        (Executor::Native, config.native),
+        (Executor::Vm, config.vm),
+        (Executor::SierraEmu, config.sierra_emu),
+    ] {
+        for class_hash in classes {
+            let previous = overrides.insert(class_hash, executor);
+            ensure!(
+                previous.is_none_or(|previous| previous == executor),
+                "class {} has several executors",
+                class_hash.to_hex_string()
+            );
+        }
+    }
+
+    Ok(overrides)
+}
+
+fn load() -> anyhow::Result<HashMap<ClassHash, Executor>> {
+    let path = match env::var("EXECUTORS_CONFIG") {
+        Ok(path) => path,
+        Err(_) if !Path::new(DEFAULT_CONFIG).exists() => return Ok(HashMap::new()),
+        Err(_) => DEFAULT_CONFIG.to_string(),
+    };
+    let config = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read executors config {path}"))?;
+    let overrides = parse_overrides(&config)
+        .with_context(|| format!("failed to parse executors config {path}"))?;
+
+    info!(classes = overrides.len(), "overriding class executors");
+    Ok(overrides)
+}
+
+/// Returns the executor of the class: the one of the config file, if it
+/// overrides it, or the selected one
+pub fn executor_for(class_hash: ClassHash) -> Executor {
+    OVERRIDES
+        .get_or_init(|| load().expect("failed to load executors config"))
+        .get(&class_hash)
+        .copied()
+        .unwrap_or_else(executor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_overrides() {
+        let overrides = parse_overrides(
+            r#"
+            vm = ["0x1", "0x2"]
+            sierra-emu = ["0x3"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(overrides.len(), 3);
+        assert_eq!(overrides[&ClassHash(1u128.into())], Executor::Vm);
+        assert_eq!(overrides[&ClassHash(3u128.into())], Executor::SierraEmu);
+
+        assert!(parse_overrides("vm = [\"0x1\"]\nnative = [\"0x1\"]").is_err());
+        assert!(parse_overrides("cairo = [\"0x1\"]").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! Recorded RPC responses, so that tests don't depend on live nodes and
+//! flaky providers.
+//!
+//! With `RPC_FIXTURES=record`, the response to every request sent by the
+//! readers is written to a fixture file. With `RPC_FIXTURES=replay`, the
+//! requests are served from the fixtures instead, without an endpoint, and
+//! panic if their response wasn't recorded. Fixtures are stored in
+//! `fixtures/rpc` (or the directory set in `RPC_FIXTURES_DIR`), by chain and
+//! method:
+//!
+//! ```bash
+//! RPC_FIXTURES=record cargo test -p rpc-state-reader
+//! RPC_FIXTURES=replay cargo test -p rpc-state-reader
+//! ```
+//!
+//! Error responses of the node (e.g. a contract not found) are recorded too,
+//! as the readers depend on them, while connection errors are not.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use starknet_api::core::ChainId;
+use starknet_gateway::errors::RPCStateReaderResult;
+use tracing::warn;
+use ureq::json;
+
+use crate::provider;
+
+/// Directory read when `RPC_FIXTURES_DIR` isn't set
+const DEFAULT_DIR: &str = "fixtures/rpc";
+
+static SELECTED: OnceLock<Option<Fixtures>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug)]
+pub struct Fixtures {
+    mode: Mode,
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    method: String,
+    params: Value,
+    #[serde(flatten)]
+    response: Response,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Result(Value),
+    Error { code: i64 },
+}
+
+/// Returns the fixtures set up in the environment, if any
+pub fn selected() -> Option<&'static Fixtures> {
+    SELECTED
+        .get_or_init(|| {
+            let mode = match env::var("RPC_FIXTURES").ok()?.as_str() {
+                "" => return None,
+                "record" => Mode::Record,
+                "replay" => Mode::Replay,
+                mode => panic!("invalid RPC_FIXTURES mode {mode}, expected record or replay"),
+            };
+            let dir = env::var("RPC_FIXTURES_DIR").unwrap_or_else(|_| DEFAULT_DIR.to_string());
+
+            Some(Fixtures {
+                mode,
+                dir: PathBuf::from(dir),
+            })
+        })
+        .as_ref()
+}
+
+/// Whether the requests are served from the fixtures, without a node
+pub fn replaying() -> bool {
+    selected().is_some_and(|fixtures| fixtures.mode == Mode::Replay)
+}
+
+impl Fixtures {
+    /// Serves the request from its fixture when replaying. Otherwise, sends
+    /// it and records its response.
+    pub(crate) fn serve(
+        &self,
+        chain: &ChainId,
+        method: &str,
+        params: &Value,
+        send: impl FnOnce() -> RPCStateReaderResult<Value>,
+    ) -> RPCStateReaderResult<Value> {
+        let path = self.dir.join(fixture_path(chain, method, params));
+
+        if self.mode == Mode::Replay {
+            let fixture = read_fixture(&path).unwrap_or_else(|err| {
+                panic!(
+                    "missing rpc fixture for {method}, record it with RPC_FIXTURES=record: {err:#}"
+                )
+            });
+            return match fixture.response {
+                Response::Result(result) => Ok(result),
+                Response::Error { code } => Err(provider::rpc_error(
+                    json!({ "method": method, "params": params }),
+                    Some(code),
+                )),
+            };
+        }
+
+        let result = send();
+        let response = match &result {
+            Ok(result) => Response::Result(result.clone()),
+            Err(err) => match provider::error_code(err) {
+                Some(code) => Response::Error { code },
+                None => return result,
+            },
+        };
+        let fixture = Fixture {
+            method: method.to_string(),
+            params: params.clone(),
+            response,
+        };
+        if let Err(err) = write_fixture(&path, &fixture) {
+            warn!("failed to record rpc fixture: {err:#}");
+        }
+
+        result
+    }
+}
+
+/// Path of the fixture of the request, relative to the fixtures directory.
+/// Requests are identified by a stable hash of their params.
+fn fixture_path(chain: &ChainId, method: &str, params: &Value) -> PathBuf {
+    let hash = fnv1a(params.to_string().as_bytes());
+    PathBuf::from(chain.to_string())
+        .join(method)
+        .join(format!("{hash:016x}.json"))
+}
+
+/// 64-bit FNV-1a hash, unlike the std hashers it's stable across releases
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn read_fixture(path: &Path) -> anyhow::Result<Fixture> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("invalid fixture {}", path.display()))
+}
+
+fn write_fixture(path: &Path, fixture: &Fixture) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(fixture)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_path() {
+        assert_eq!(
+            fixture_path(&ChainId::Mainnet, "starknet_blockNumber", &json!([])),
+            Path::new("SN_MAIN/starknet_blockNumber/09612b07b5ecb5a5.json")
+        );
+    }
+}
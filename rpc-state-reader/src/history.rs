@@ -0,0 +1,126 @@
+//! Historical state queries over the cached data.
+//!
+//! The value of a storage slot before a range of blocks is read from the
+//! state of the block before it, and every later write is taken from the
+//! state diffs of the cached transaction traces. Blocks whose traces have
+//! no state diff (e.g. because the node doesn't include them) fall back to
+//! the state update of the whole block.
+
+use blockifier::state::state_api::StateReader as BlockifierStateReader;
+use serde::Serialize;
+use starknet_api::{
+    block::BlockNumber,
+    core::{ChainId, ContractAddress},
+    hash::StarkHash,
+    state::StorageKey,
+    transaction::TransactionHash,
+};
+use tracing::warn;
+
+use crate::{
+    cache::RpcCachedStateReader,
+    objects::RpcStateDiff,
+    reader::{RpcStateReader, StateReader},
+};
+
+#[derive(Debug, Serialize)]
+pub struct StorageChange {
+    pub block_number: u64,
+    /// Transaction that wrote the value, unknown if it was taken from the
+    /// state update of the block
+    pub tx_hash: Option<TransactionHash>,
+    pub value: StarkHash,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageHistory {
+    pub contract_address: ContractAddress,
+    pub key: StorageKey,
+    /// Value before the first block of the range
+    pub initial_value: StarkHash,
+    /// Every change of the value, in order
+    pub changes: Vec<StorageChange>,
+}
+
+/// Returns the sequence of values the storage slot took in the block range
+pub fn get_storage_history(
+    chain: &ChainId,
+    contract_address: ContractAddress,
+    key: StorageKey,
+    block_start: u64,
+    block_end: u64,
+) -> anyhow::Result<StorageHistory> {
+    let reader = |block_number| {
+        RpcCachedStateReader::new(RpcStateReader::new(
+            chain.clone(),
+            BlockNumber(block_number),
+        ))
+    };
+
+    let initial_value = match block_start {
+        0 => StarkHash::ZERO,
+        block_number => reader(block_number - 1).get_storage_at(contract_address, key)?,
+    };
+
+    let mut value = initial_value;
+    let mut changes = Vec::new();
+    for block_number in block_start..=block_end {
+        let reader = reader(block_number);
+
+        let mut writes = Vec::new();
+        let mut complete = true;
+        for tx_hash in reader.get_block_with_tx_hashes()?.transactions {
+            match reader.get_transaction_trace(&tx_hash)?.state_diff {
+                Some(state_diff) => writes.extend(
+                    storage_writes(&state_diff, contract_address, key)
+                        .map(|value| (Some(tx_hash), value)),
+                ),
+                None => complete = false,
+            }
+        }
+
+        if !complete {
+            warn!(
+                block_number,
+                "transaction traces have no state diff, falling back to the block state update"
+            );
+            let state_update = reader.reader.get_state_update()?;
+            writes = storage_writes(&state_update.state_diff, contract_address, key)
+                .map(|value| (None, value))
+                .collect();
+        }
+
+        for (tx_hash, new_value) in writes {
+            if new_value != value {
+                value = new_value;
+                changes.push(StorageChange {
+                    block_number,
+                    tx_hash,
+                    value,
+                });
+            }
+        }
+    }
+
+    Ok(StorageHistory {
+        contract_address,
+        key,
+        initial_value,
+        changes,
+    })
+}
+
+/// Returns the values written to the storage slot by the state diff
+fn storage_writes(
+    state_diff: &RpcStateDiff,
+    contract_address: ContractAddress,
+    key: StorageKey,
+) -> impl Iterator<Item = StarkHash> + '_ {
+    state_diff
+        .storage_diffs
+        .iter()
+        .filter(move |storage_diff| storage_diff.address == contract_address)
+        .flat_map(|storage_diff| &storage_diff.storage_entries)
+        .filter(move |entry| entry.key == key)
+        .map(|entry| entry.value)
+}
@@ -1,7 +1,42 @@
+//! State reader and execution helpers for replaying Starknet transactions
+//! with the blockifier, reading the state from an RPC node.
+//!
+//! Projects that embed the replay should use the `replay-core` crate, which
+//! executes blocks or transactions on top of the cached state of the chain.
+//! The lower level building blocks are also public: `execution::fetch_block_context`
+//! and `execution::fetch_blockifier_transaction` build the inputs of the
+//! blockifier from any `reader::StateReader`, such as the cached
+//! `cache::RpcCachedStateReader`.
+//!
+//! To run sequencer components on top of the chain state, `sequencer`
+//! implements the state reader traits of the sequencer's gateway.
+//!
+//! Besides mainnet and testnet, `chains` reads the endpoint and fee tokens
+//! of custom chains (e.g. appchains) from a config file, and `executors`
+//! reads the classes executed with another executor than the selected one.
+//!
+//! To replay from a local node, `pathfinder::select` makes every reader
+//! query the state from a pathfinder database instead of over RPC.
+//!
+//! `history::get_storage_history` queries the values a storage slot took
+//! over a range of blocks, mostly from cached data.
+//!
+//! To run the tests without a node, `fixtures` records the RPC responses
+//! into files and serves them back.
+
 pub mod cache;
+pub mod chains;
+pub mod disk_cache;
 pub mod execution;
+pub mod executors;
+pub mod fixtures;
+pub mod history;
 pub mod objects;
+pub mod pathfinder;
+pub mod provider;
 pub mod reader;
+pub mod schema;
+pub mod sequencer;
 pub mod utils;
 
 #[cfg(test)]
@@ -4,9 +4,10 @@
 use serde::{Deserialize, Serialize};
 use starknet_api::{
     block::{BlockHash, BlockNumber, BlockStatus, BlockTimestamp, GasPrice},
-    core::{ContractAddress, GlobalRoot},
+    core::{ClassHash, ContractAddress, EntryPointSelector, GlobalRoot, Nonce},
     data_availability::L1DataAvailabilityMode,
     hash::StarkHash,
+    state::StorageKey,
     transaction::{
         fields::Fee, Event, MessageToL1, Transaction, TransactionExecutionStatus, TransactionHash,
     },
@@ -22,10 +23,65 @@ pub struct RpcTransactionTrace {
     )]
     pub execute_invocation: Option<RpcCallInfo>,
     pub fee_transfer_invocation: Option<RpcCallInfo>,
+    #[serde(default)]
+    pub state_diff: Option<RpcStateDiff>,
+}
+
+/// State changes applied by a transaction or a block
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq, Default)]
+pub struct RpcStateDiff {
+    #[serde(default)]
+    pub storage_diffs: Vec<RpcStorageDiff>,
+    #[serde(default)]
+    pub nonces: Vec<RpcNonceUpdate>,
+    #[serde(default)]
+    pub deployed_contracts: Vec<RpcDeployedContract>,
+    #[serde(default)]
+    pub replaced_classes: Vec<RpcReplacedClass>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+pub struct RpcStorageDiff {
+    pub address: ContractAddress,
+    pub storage_entries: Vec<RpcStorageEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+pub struct RpcStorageEntry {
+    pub key: StorageKey,
+    pub value: StarkHash,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+pub struct RpcNonceUpdate {
+    pub contract_address: ContractAddress,
+    pub nonce: Nonce,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+pub struct RpcDeployedContract {
+    pub address: ContractAddress,
+    pub class_hash: ClassHash,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+pub struct RpcReplacedClass {
+    pub contract_address: ContractAddress,
+    pub class_hash: ClassHash,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RpcStateUpdate {
+    pub block_hash: BlockHash,
+    pub old_root: GlobalRoot,
+    pub new_root: GlobalRoot,
+    pub state_diff: RpcStateDiff,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Default, Deserialize, Serialize)]
 pub struct RpcCallInfo {
+    pub contract_address: Option<ContractAddress>,
+    pub entry_point_selector: Option<EntryPointSelector>,
     pub result: Option<Vec<StarkHash>>,
     pub calldata: Option<Vec<StarkHash>>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
@@ -33,6 +89,27 @@ pub struct RpcCallInfo {
     pub revert_reason: Option<String>,
 }
 
+impl RpcTransactionTrace {
+    /// Returns an iterator over every call in the trace, including inner calls
+    pub fn calls(&self) -> impl Iterator<Item = &RpcCallInfo> {
+        [
+            &self.validate_invocation,
+            &self.execute_invocation,
+            &self.fee_transfer_invocation,
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(RpcCallInfo::calls)
+    }
+}
+
+impl RpcCallInfo {
+    /// Returns an iterator over this call and all of its inner calls, in pre-order
+    pub fn calls(&self) -> Box<dyn Iterator<Item = &RpcCallInfo> + '_> {
+        Box::new(std::iter::once(self).chain(self.calls.iter().flat_map(RpcCallInfo::calls)))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RpcTransactionReceipt {
     pub transaction_hash: TransactionHash,
@@ -66,6 +143,9 @@ pub struct BlockHeader {
     pub timestamp: BlockTimestamp,
     pub l1_gas_price: ResourcePrice,
     pub l1_data_gas_price: ResourcePrice,
+    /// Only reported since RPC v0.8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l2_gas_price: Option<ResourcePrice>,
     pub l1_da_mode: L1DataAvailabilityMode,
     pub starknet_version: String,
 }
@@ -122,17 +202,7 @@ pub mod deser {
     pub fn transaction_from_json(
         mut transaction: serde_json::Value,
     ) -> serde_json::Result<Transaction> {
-        // uppercase fields to make it starknet compatible
-        if let Some(resource_bounds) = transaction.get_mut("resource_bounds") {
-            if let Some(l1_gas) = resource_bounds.get_mut("l1_gas") {
-                resource_bounds["L1_GAS"] = l1_gas.clone();
-                resource_bounds.as_object_mut().unwrap().remove("l1_gas");
-            }
-            if let Some(l2_gas) = resource_bounds.get_mut("l2_gas") {
-                resource_bounds["L2_GAS"] = l2_gas.clone();
-                resource_bounds.as_object_mut().unwrap().remove("l2_gas");
-            }
-        }
+        normalize_transaction(&mut transaction);
 
         #[derive(Deserialize)]
         struct Header {
@@ -193,4 +263,42 @@ pub mod deser {
             ))),
         }
     }
+
+    /// Uppercases the resource bounds of the transaction, to make it starknet
+    /// compatible
+    pub fn normalize_transaction(transaction: &mut serde_json::Value) {
+        if let Some(resource_bounds) = transaction.get_mut("resource_bounds") {
+            if let Some(l1_gas) = resource_bounds.get_mut("l1_gas") {
+                resource_bounds["L1_GAS"] = l1_gas.clone();
+                resource_bounds.as_object_mut().unwrap().remove("l1_gas");
+            }
+            if let Some(l2_gas) = resource_bounds.get_mut("l2_gas") {
+                resource_bounds["L2_GAS"] = l2_gas.clone();
+                resource_bounds.as_object_mut().unwrap().remove("l2_gas");
+            }
+        }
+    }
+
+    /// Serializes the transaction as the struct of its type and version,
+    /// which is what `transaction_from_json` deserializes it into.
+    pub fn transaction_to_json(transaction: &Transaction) -> serde_json::Result<serde_json::Value> {
+        match transaction {
+            Transaction::Invoke(InvokeTransaction::V0(tx)) => serde_json::to_value(tx),
+            Transaction::Invoke(InvokeTransaction::V1(tx)) => serde_json::to_value(tx),
+            Transaction::Invoke(InvokeTransaction::V3(tx)) => serde_json::to_value(tx),
+            Transaction::DeployAccount(DeployAccountTransaction::V1(tx)) => {
+                serde_json::to_value(tx)
+            }
+            Transaction::DeployAccount(DeployAccountTransaction::V3(tx)) => {
+                serde_json::to_value(tx)
+            }
+            Transaction::Declare(DeclareTransaction::V0(tx) | DeclareTransaction::V1(tx)) => {
+                serde_json::to_value(tx)
+            }
+            Transaction::Declare(DeclareTransaction::V2(tx)) => serde_json::to_value(tx),
+            Transaction::Declare(DeclareTransaction::V3(tx)) => serde_json::to_value(tx),
+            Transaction::Deploy(tx) => serde_json::to_value(tx),
+            Transaction::L1Handler(tx) => serde_json::to_value(tx),
+        }
+    }
 }
@@ -0,0 +1,215 @@
+//! State reads from a local pathfinder database, instead of over RPC.
+//!
+//! When a database is selected, the storage, nonces and class hashes read
+//! by every `RpcStateReader` are queried from it, so replaying a range of
+//! blocks doesn't pay for the network latency nor the rate limits of the
+//! provider. Blocks, transactions, traces, receipts and class definitions
+//! are still fetched over RPC, and cached as usual.
+//!
+//! Blocks that the database hasn't synced yet are read over RPC. Both the
+//! schema that stores addresses inline and the one that interns them in
+//! the `contract_addresses` and `storage_addresses` tables are supported.
+//! The database must belong to the replayed chain, as it isn't checked.
+
+use std::{
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use blockifier::state::{errors::StateError, state_api::StateResult};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use starknet_api::{
+    block::BlockNumber,
+    core::{ClassHash, ContractAddress, Nonce},
+    hash::StarkHash,
+    state::StorageKey,
+};
+
+static SELECTED: OnceLock<PathfinderDatabase> = OnceLock::new();
+
+pub struct PathfinderDatabase {
+    connection: Mutex<Connection>,
+    /// Whether addresses are interned in their own tables
+    interned: bool,
+    latest_block: u64,
+}
+
+impl PathfinderDatabase {
+    /// Opens the database read only, so that it can be shared with a
+    /// running node.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .with_context(|| format!("failed to open pathfinder database {}", path.display()))?;
+
+        let latest_block = connection
+            .query_row("SELECT MAX(number) FROM block_headers", [], |row| {
+                row.get::<_, Option<u64>>(0)
+            })
+            .context("failed to read the latest block of the pathfinder database")?
+            .context("pathfinder database has no blocks")?;
+        let interned = connection
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'contract_addresses'",
+                [],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            interned,
+            latest_block,
+        })
+    }
+
+    pub fn latest_block(&self) -> u64 {
+        self.latest_block
+    }
+
+    /// Returns the storage value at the end of the block, or `None` if the
+    /// database doesn't have the block
+    pub fn get_storage_at(
+        &self,
+        block_number: BlockNumber,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> anyhow::Result<Option<StarkHash>> {
+        let query = if self.interned {
+            "SELECT storage_value FROM storage_updates
+             WHERE contract_address_id = (SELECT id FROM contract_addresses WHERE contract_address = ?1)
+             AND storage_address_id = (SELECT id FROM storage_addresses WHERE storage_address = ?2)
+             AND block_number <= ?3
+             ORDER BY block_number DESC LIMIT 1"
+        } else {
+            "SELECT storage_value FROM storage_updates
+             WHERE contract_address = ?1 AND storage_address = ?2 AND block_number <= ?3
+             ORDER BY block_number DESC LIMIT 1"
+        };
+        self.latest_felt(query, block_number, contract_address, Some(key))
+    }
+
+    /// Returns the nonce at the end of the block, or `None` if the database
+    /// doesn't have the block
+    pub fn get_nonce_at(
+        &self,
+        block_number: BlockNumber,
+        contract_address: ContractAddress,
+    ) -> anyhow::Result<Option<Nonce>> {
+        let query = if self.interned {
+            "SELECT nonce FROM nonce_updates
+             WHERE contract_address_id = (SELECT id FROM contract_addresses WHERE contract_address = ?1)
+             AND block_number <= ?2
+             ORDER BY block_number DESC LIMIT 1"
+        } else {
+            "SELECT nonce FROM nonce_updates
+             WHERE contract_address = ?1 AND block_number <= ?2
+             ORDER BY block_number DESC LIMIT 1"
+        };
+        Ok(self
+            .latest_felt(query, block_number, contract_address, None)?
+            .map(Nonce))
+    }
+
+    /// Returns the class hash at the end of the block, or `None` if the
+    /// database doesn't have the block
+    pub fn get_class_hash_at(
+        &self,
+        block_number: BlockNumber,
+        contract_address: ContractAddress,
+    ) -> anyhow::Result<Option<ClassHash>> {
+        let query = "SELECT class_hash FROM contract_updates
+             WHERE contract_address = ?1 AND block_number <= ?2
+             ORDER BY block_number DESC LIMIT 1";
+        Ok(self
+            .latest_felt(query, block_number, contract_address, None)?
+            .map(ClassHash))
+    }
+
+    /// Runs a query for the latest value written up to the block, which
+    /// defaults to zero if it was never written.
+    fn latest_felt(
+        &self,
+        query: &str,
+        block_number: BlockNumber,
+        contract_address: ContractAddress,
+        key: Option<StorageKey>,
+    ) -> anyhow::Result<Option<StarkHash>> {
+        if block_number.0 > self.latest_block {
+            return Ok(None);
+        }
+
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(query)?;
+        let address = contract_address.0.key().to_bytes_be();
+        let value = match key {
+            Some(key) => statement
+                .query_row(
+                    rusqlite::params![&address[..], &key.0.key().to_bytes_be()[..], block_number.0],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()?,
+            None => statement
+                .query_row(rusqlite::params![&address[..], block_number.0], |row| {
+                    row.get::<_, Vec<u8>>(0)
+                })
+                .optional()?,
+        };
+
+        value
+            .map(|bytes| decode_felt(&bytes))
+            .transpose()
+            .map(|value| Some(value.unwrap_or_default()))
+    }
+}
+
+/// Decodes a big endian felt, which may be stored without its leading zeros
+fn decode_felt(bytes: &[u8]) -> anyhow::Result<StarkHash> {
+    anyhow::ensure!(bytes.len() <= 32, "invalid felt of {} bytes", bytes.len());
+    let mut padded = [0; 32];
+    padded[32 - bytes.len()..].copy_from_slice(bytes);
+    Ok(StarkHash::from_bytes_be(&padded))
+}
+
+/// Selects the database read by every reader. Can only be called once,
+/// before creating any reader.
+pub fn select(database: PathfinderDatabase) {
+    if SELECTED.set(database).is_err() {
+        panic!("a pathfinder database was already selected");
+    }
+}
+
+pub fn selected() -> Option<&'static PathfinderDatabase> {
+    SELECTED.get()
+}
+
+/// Reads the value from the selected database, returning `None` if there
+/// is no database or it doesn't have the block.
+pub(crate) fn read<T>(
+    read: impl FnOnce(&PathfinderDatabase) -> anyhow::Result<Option<T>>,
+) -> StateResult<Option<T>> {
+    let Some(database) = selected() else {
+        return Ok(None);
+    };
+    read(database).map_err(|err| StateError::StateReadError(format!("{err:#}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_felt() {
+        assert_eq!(decode_felt(&[]).unwrap(), StarkHash::ZERO);
+        assert_eq!(decode_felt(&[1, 0]).unwrap(), StarkHash::from(256u64));
+        assert_eq!(
+            decode_felt(&StarkHash::from(7u64).to_bytes_be()).unwrap(),
+            StarkHash::from(7u64)
+        );
+        assert!(decode_felt(&[0; 33]).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+//! Named RPC provider profiles, read from environment variables (e.g. set
+//! in `.env`) or from a config file, so that switching providers doesn't
+//! require editing the endpoint of the chain.
+//!
+//! A profile named `mainnet-archive` is read from:
+//!
+//! ```bash
+//! RPC_PROVIDER_MAINNET_ARCHIVE_CHAIN=mainnet
+//! RPC_PROVIDER_MAINNET_ARCHIVE_URL=https://rpc.endpoint.mainnet.com
+//! # Optional
+//! RPC_PROVIDER_MAINNET_ARCHIVE_BEARER_TOKEN=<token>
+//! RPC_PROVIDER_MAINNET_ARCHIVE_HEADERS="X-Api-Key: <key>; X-Client: replay"
+//! RPC_PROVIDER_MAINNET_ARCHIVE_SECRET_HEADERS=X-Api-Key
+//! RPC_PROVIDER_MAINNET_ARCHIVE_API_KEY=<key>
+//! RPC_PROVIDER_MAINNET_ARCHIVE_API_KEY_PARAM=apikey
+//! RPC_PROVIDER_MAINNET_ARCHIVE_RATE_LIMIT=20
+//! ```
+//!
+//! Or from a table of the same name in `providers.toml` (or the file set in
+//! `RPC_PROVIDERS_CONFIG`), whose values are overridden by the env vars:
+//!
+//! ```toml
+//! [mainnet-archive]
+//! chain = "mainnet"
+//! url = "https://rpc.endpoint.mainnet.com"
+//! bearer_token = "<token>"
+//! headers = { X-Api-Key = "<key>", X-Client = "replay" }
+//! secret_headers = ["X-Api-Key"]
+//! rate_limit = 20
+//! ```
+//!
+//! The bearer token is sent as the `Authorization` header, along with any
+//! other header, while the API key is appended to the URL as a query
+//! parameter (`apikey` by default). The values of the API key, of the
+//! `Authorization` header and of the headers marked as secret (separated by
+//! `,` in the env var) are redacted from the errors and logs of the providers.
+//!
+//! The rate limit is the maximum amount of requests per second, counting
+//! each JSON-RPC batch as a single request. Without a selected profile, or
+//! for chains other than the one of the profile, the endpoint of each chain
+//! is read from `RPC_ENDPOINT_MAINNET` and `RPC_ENDPOINT_TESTNET`.
+
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use starknet_api::core::ChainId;
+use starknet_gateway::{
+    config::RpcStateReaderConfig,
+    errors::{RPCStateReaderError, RPCStateReaderResult},
+};
+use tracing::warn;
+use ureq::json;
+
+use crate::chains;
+
+// JSON-RPC error codes of the Starknet specification
+const CONTRACT_NOT_FOUND: i64 = 20;
+const BLOCK_NOT_FOUND: i64 = 24;
+const CLASS_HASH_NOT_FOUND: i64 = 28;
+/// HTTP status reported for transport errors, which have none
+const BAD_GATEWAY: u16 = 502;
+
+/// Config file read when `RPC_PROVIDERS_CONFIG` isn't set
+const DEFAULT_CONFIG: &str = "providers.toml";
+const REDACTED: &str = "<redacted>";
+
+static SELECTED: OnceLock<Provider> = OnceLock::new();
+/// Earliest instant at which the next request may be sent
+static NEXT_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Clone)]
+pub struct Provider {
+    pub name: String,
+    pub chain: ChainId,
+    /// Endpoint of the provider, without the API key
+    pub url: String,
+    /// Headers sent with every request, as name and value
+    pub headers: Vec<(String, String)>,
+    /// Names of the headers whose values are redacted, besides `Authorization`
+    pub secret_headers: Vec<String>,
+    /// Query parameter and value of the API key
+    pub api_key: Option<(String, String)>,
+    /// Maximum amount of requests per second
+    pub rate_limit: Option<f64>,
+}
+
+/// A profile of the config file
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileConfig {
+    chain: Option<String>,
+    url: Option<String>,
+    bearer_token: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    secret_headers: Option<Vec<String>>,
+    api_key: Option<String>,
+    api_key_param: Option<String>,
+    rate_limit: Option<f64>,
+}
+
+/// Reads the profile from the config file, if there is one
+fn load_config(name: &str) -> anyhow::Result<ProfileConfig> {
+    let path = match env::var("RPC_PROVIDERS_CONFIG") {
+        Ok(path) => path,
+        Err(_) if !Path::new(DEFAULT_CONFIG).exists() => return Ok(ProfileConfig::default()),
+        Err(_) => DEFAULT_CONFIG.to_string(),
+    };
+    let config = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read providers config {path}"))?;
+    let mut profiles: BTreeMap<String, ProfileConfig> = toml::from_str(&config)
+        .with_context(|| format!("failed to parse providers config {path}"))?;
+    Ok(profiles.remove(name).unwrap_or_default())
+}
+
+/// Reads the profile with the given name from the environment and the
+/// config file
+pub fn load(name: &str) -> anyhow::Result<Provider> {
+    let config = load_config(name)?;
+    let prefix = format!(
+        "RPC_PROVIDER_{}",
+        name.to_uppercase().replace(['-', '.'], "_")
+    );
+    let env_var = |suffix: &str| env::var(format!("{prefix}_{suffix}")).ok();
+    let var = |suffix: &str| {
+        env_var(suffix).or_else(|| match suffix {
+            "CHAIN" => config.chain.clone(),
+            "URL" => config.url.clone(),
+            "BEARER_TOKEN" => config.bearer_token.clone(),
+            "API_KEY" => config.api_key.clone(),
+            "API_KEY_PARAM" => config.api_key_param.clone(),
+            _ => None,
+        })
+    };
+    let required = |suffix: &str| {
+        var(suffix).with_context(|| {
+            format!(
+                "missing {} of provider {name}: set {prefix}_{suffix} or add it to the providers config",
+                suffix.to_lowercase()
+            )
+        })
+    };
+
+    let chain = chains::parse_chain(&required("CHAIN")?)
+        .with_context(|| format!("unknown chain of provider {name}"))?;
+
+    let mut headers = config.headers.clone().into_iter().collect::<Vec<_>>();
+    if let Some(env_headers) = env_var("HEADERS") {
+        for (name, value) in parse_headers(&env_headers)? {
+            headers.retain(|(header, _)| !header.eq_ignore_ascii_case(&name));
+            headers.push((name, value));
+        }
+    }
+    let secret_headers = match env_var("SECRET_HEADERS") {
+        Some(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => config.secret_headers.clone().unwrap_or_default(),
+    };
+    if let Some(token) = var("BEARER_TOKEN") {
+        headers.push(("Authorization".to_string(), format!("Bearer {token}")));
+    }
+
+    let api_key = var("API_KEY").map(|key| {
+        let param = var("API_KEY_PARAM").unwrap_or_else(|| "apikey".to_string());
+        (param, key)
+    });
+
+    let rate_limit = env_var("RATE_LIMIT")
+        .map(|rate_limit| {
+            rate_limit
+                .parse::<f64>()
+                .with_context(|| format!("invalid rate limit of provider {name}"))
+        })
+        .transpose()?
+        .or(config.rate_limit)
+        .map(|rate_limit| {
+            ensure!(
+                rate_limit > 0.0,
+                "rate limit of provider {name} must be positive"
+            );
+            anyhow::Ok(rate_limit)
+        })
+        .transpose()?;
+
+    Ok(Provider {
+        name: name.to_string(),
+        chain,
+        url: required("URL")?,
+        headers,
+        secret_headers,
+        api_key,
+        rate_limit,
+    })
+}
+
+/// Parses headers formatted as `Name: value`, separated by `;`
+fn parse_headers(headers: &str) -> anyhow::Result<Vec<(String, String)>> {
+    headers
+        .split(';')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| {
+            let (name, value) = header.split_once(':').with_context(|| {
+                format!("header should be formatted as `Name: value`: {header}")
+            })?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+impl Provider {
+    /// Returns the URL of the endpoint, including the API key
+    pub fn endpoint(&self) -> String {
+        match &self.api_key {
+            Some((param, key)) => {
+                let separator = if self.url.contains('?') { '&' } else { '?' };
+                format!("{}{separator}{param}={key}", self.url)
+            }
+            None => self.url.clone(),
+        }
+    }
+
+    /// Returns whether the value of the header is redacted
+    pub fn is_secret_header(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case("Authorization")
+            || self
+                .secret_headers
+                .iter()
+                .any(|secret| secret.eq_ignore_ascii_case(name))
+    }
+
+    /// Replaces the values of the secret headers and the API key in the text
+    pub fn redact(&self, text: &str) -> String {
+        let secret_values = self
+            .headers
+            .iter()
+            .filter(|(name, _)| self.is_secret_header(name))
+            .map(|(_, value)| value.as_str());
+        let secrets = secret_values
+            .clone()
+            .chain(secret_values.filter_map(|value| {
+                // The token alone may also appear, e.g. in a server error
+                value.strip_prefix("Bearer ")
+            }))
+            .chain(self.api_key.iter().map(|(_, key)| key.as_str()))
+            .filter(|secret| !secret.is_empty());
+
+        let mut text = text.to_string();
+        for secret in secrets {
+            text = text.replace(secret, REDACTED);
+        }
+        text
+    }
+}
+
+impl fmt::Debug for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Provider")
+            .field("name", &self.name)
+            .field("chain", &self.chain)
+            .field("url", &self.url)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = if self.is_secret_header(name) {
+                            REDACTED
+                        } else {
+                            value.as_str()
+                        };
+                        (name, value)
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .field("secret_headers", &self.secret_headers)
+            .field(
+                "api_key",
+                &self.api_key.as_ref().map(|(param, _)| (param, REDACTED)),
+            )
+            .field("rate_limit", &self.rate_limit)
+            .finish()
+    }
+}
+
+/// Selects the provider used by every reader of its chain. Can only be
+/// called once, before creating any reader.
+pub fn select(provider: Provider) -> anyhow::Result<()> {
+    SELECTED.set(provider).map_err(|provider| {
+        anyhow!(
+            "a provider was already selected, can't select {}",
+            provider.name
+        )
+    })
+}
+
+pub fn selected() -> Option<&'static Provider> {
+    SELECTED.get()
+}
+
+/// Redacts the secrets of the selected provider from the text
+pub fn redact(text: &str) -> String {
+    match selected() {
+        Some(provider) => provider.redact(text),
+        None => text.to_string(),
+    }
+}
+
+/// Returns the selected provider, if it serves the given chain. Readers of
+/// other chains use the endpoint of their chain instead.
+pub(crate) fn for_chain(chain: &ChainId) -> Option<&'static Provider> {
+    selected().filter(|provider| &provider.chain == chain)
+}
+
+/// Blocks until the rate limit of the selected provider allows sending
+/// another request.
+pub(crate) fn throttle() {
+    let Some(rate_limit) = selected().and_then(|provider| provider.rate_limit) else {
+        return;
+    };
+    let interval = Duration::from_secs_f64(1.0 / rate_limit);
+
+    let wait = {
+        let mut next_request = NEXT_REQUEST.lock().unwrap();
+        let now = Instant::now();
+        let slot = next_request.map_or(now, |next| next.max(now));
+        *next_request = Some(slot + interval);
+        slot - now
+    };
+
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+/// Sends the request with the headers of the provider, mapping errors like
+/// the gateway's reader does.
+///
+/// Transport errors (e.g. a refused connection) are logged and reported as
+/// a bad gateway, so they are retried like the gateway's connection errors.
+pub(crate) fn send_rpc_request(
+    config: &RpcStateReaderConfig,
+    headers: &[(String, String)],
+    method: &str,
+    params: impl Serialize,
+) -> RPCStateReaderResult<Value> {
+    let request = json!({
+        "jsonrpc": config.json_rpc_version,
+        "id": 0,
+        "method": method,
+        "params": params,
+    });
+
+    let mut builder = ureq::post(&config.url);
+    for (name, value) in headers {
+        builder = builder.set(name, value);
+    }
+    let response = match builder.send_json(&request) {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => return Err(status_error(status)),
+        Err(ureq::Error::Transport(err)) => {
+            // The errors of ureq include the URL, which may contain the API key
+            warn!("rpc request failed: {}", redact(&err.to_string()));
+            return Err(status_error(BAD_GATEWAY));
+        }
+    };
+
+    let mut response: Value = response.into_json().map_err(serde_json::Error::io)?;
+    if let Some(error) = response.get("error") {
+        return Err(rpc_error(
+            request,
+            error.get("code").and_then(Value::as_i64),
+        ));
+    }
+
+    Ok(response
+        .get_mut("result")
+        .map(Value::take)
+        .unwrap_or_default())
+}
+
+fn status_error(status: u16) -> RPCStateReaderError {
+    RPCStateReaderError::RPCError(
+        status
+            .try_into()
+            .expect("ureq only returns valid status codes"),
+    )
+}
+
+/// Maps the JSON-RPC error code of the response to the request
+pub(crate) fn rpc_error(request: Value, code: Option<i64>) -> RPCStateReaderError {
+    match code {
+        Some(CONTRACT_NOT_FOUND) => RPCStateReaderError::ContractAddressNotFound(request),
+        Some(BLOCK_NOT_FOUND) => RPCStateReaderError::BlockNotFound(request),
+        Some(CLASS_HASH_NOT_FOUND) => RPCStateReaderError::ClassHashNotFound(request),
+        code => RPCStateReaderError::UnexpectedErrorCode(code.unwrap_or_default() as u16),
+    }
+}
+
+/// Returns the JSON-RPC error code of the error, or `None` if it's not an
+/// error response of the node (e.g. a connection error)
+pub(crate) fn error_code(err: &RPCStateReaderError) -> Option<i64> {
+    match err {
+        RPCStateReaderError::ContractAddressNotFound(_) => Some(CONTRACT_NOT_FOUND),
+        RPCStateReaderError::BlockNotFound(_) => Some(BLOCK_NOT_FOUND),
+        RPCStateReaderError::ClassHashNotFound(_) => Some(CLASS_HASH_NOT_FOUND),
+        RPCStateReaderError::UnexpectedErrorCode(code) => Some(i64::from(*code)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_auth() {
+        assert_eq!(
+            parse_headers("X-Api-Key: abc; X-Client: replay;").unwrap(),
+            [
+                ("X-Api-Key".to_string(), "abc".to_string()),
+                ("X-Client".to_string(), "replay".to_string())
+            ]
+        );
+        assert!(parse_headers("X-Api-Key abc").is_err());
+
+        let mut provider = Provider {
+            name: "test".to_string(),
+            chain: ChainId::Mainnet,
+            url: "https://rpc.endpoint.mainnet.com".to_string(),
+            headers: Vec::new(),
+            secret_headers: vec!["X-Api-Key".to_string()],
+            api_key: Some(("apikey".to_string(), "abc".to_string())),
+            rate_limit: None,
+        };
+        assert_eq!(
+            provider.endpoint(),
+            "https://rpc.endpoint.mainnet.com?apikey=abc"
+        );
+        provider.url.push_str("?version=v0_7");
+        assert_eq!(
+            provider.endpoint(),
+            "https://rpc.endpoint.mainnet.com?version=v0_7&apikey=abc"
+        );
+
+        provider
+            .headers
+            .push(("Authorization".to_string(), "Bearer xyz".to_string()));
+        assert_eq!(
+            provider.redact(&format!("{}: token xyz rejected", provider.endpoint())),
+            "https://rpc.endpoint.mainnet.com?version=v0_7&apikey=<redacted>: token <redacted> rejected"
+        );
+        assert!(!format!("{provider:?}").contains("xyz"));
+
+        provider.headers.extend([
+            ("X-Api-Key".to_string(), "k3y".to_string()),
+            ("X-Client".to_string(), "replay".to_string()),
+            ("Content-Type".to_string(), "application/json".to_string()),
+        ]);
+        assert_eq!(
+            provider.redact("key k3y of client replay sent application/json"),
+            "key <redacted> of client replay sent application/json"
+        );
+        assert!(format!("{provider:?}").contains("replay"));
+        assert!(!format!("{provider:?}").contains("k3y"));
+    }
+}
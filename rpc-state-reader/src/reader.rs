@@ -1,5 +1,12 @@
-use std::{env, sync::Arc, thread, time::Duration};
+use std::{
+    env,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    thread,
+    time::Duration,
+};
 
+use anyhow::{anyhow, bail};
 use blockifier::{
     execution::{
         contract_class::{CompiledClassV0, CompiledClassV0Inner, RunnableCompiledClass},
@@ -8,12 +15,13 @@ use blockifier::{
     state::state_api::{StateReader as BlockifierStateReader, StateResult},
 };
 use cairo_vm::types::program::Program;
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 use starknet::core::types::ContractClass as SNContractClass;
 use starknet_api::{
     block::BlockNumber,
-    core::{ChainId, ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    core::{ChainId, ClassHash, CompiledClassHash, ContractAddress, EntryPointSelector, Nonce},
+    hash::StarkHash,
     state::StorageKey,
     transaction::{Transaction, TransactionHash},
 };
@@ -21,7 +29,8 @@ use starknet_gateway::{
     config::RpcStateReaderConfig,
     errors::{serde_err_to_state_err, RPCStateReaderError, RPCStateReaderResult},
     rpc_objects::{
-        GetBlockWithTxHashesParams, GetClassHashAtParams, GetNonceParams, GetStorageAtParams,
+        BlockId, GetBlockWithTxHashesParams, GetClassHashAtParams, GetNonceParams,
+        GetStorageAtParams,
     },
     rpc_state_reader::RpcStateReader as GatewayRpcStateReader,
 };
@@ -29,12 +38,69 @@ use tracing::{info_span, warn};
 use ureq::json;
 
 use crate::{
-    objects::{self, BlockWithTxHahes, RpcTransactionReceipt, RpcTransactionTrace},
+    chains,
+    executors::executor_for,
+    fixtures,
+    objects::{self, BlockWithTxHahes, RpcStateUpdate, RpcTransactionReceipt, RpcTransactionTrace},
+    pathfinder, provider, schema,
     utils::{self, bytecode_size, get_casm_compiled_class, get_native_executor},
 };
 
 const MAX_RETRIES: u32 = 10;
 const RETRY_SLEEP_MS: u64 = 10000;
+/// Maximum amount of requests sent in a single JSON-RPC batch
+const MAX_BATCH_SIZE: usize = 100;
+
+static EXECUTOR: OnceLock<Executor> = OnceLock::new();
+
+/// Backend executing the Sierra classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Executor {
+    #[default]
+    #[serde(rename = "native")]
+    Native,
+    #[serde(rename = "vm")]
+    Vm,
+    #[serde(rename = "sierra-emu")]
+    SierraEmu,
+}
+
+impl Executor {
+    pub const ALL: [Executor; 3] = [Executor::Native, Executor::Vm, Executor::SierraEmu];
+
+    /// Name of the executor, as serialized and parsed
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Vm => "vm",
+            Self::SierraEmu => "sierra-emu",
+        }
+    }
+}
+
+impl FromStr for Executor {
+    type Err = anyhow::Error;
+
+    fn from_str(executor: &str) -> Result<Self, Self::Err> {
+        let Some(executor) = Self::ALL.into_iter().find(|known| known.name() == executor) else {
+            bail!("unknown executor {executor}, expected native, vm or sierra-emu");
+        };
+        Ok(executor)
+    }
+}
+
+/// Selects the executor of the Sierra classes. Can only be called once,
+/// before compiling any class.
+pub fn select_executor(executor: Executor) -> anyhow::Result<()> {
+    EXECUTOR
+        .set(executor)
+        .map_err(|_| anyhow!("an executor was already selected"))
+}
+
+/// Returns the selected executor, Cairo Native by default
+pub fn executor() -> Executor {
+    EXECUTOR.get().copied().unwrap_or_default()
+}
 
 pub trait StateReader: BlockifierStateReader {
     fn get_block_with_tx_hashes(&self) -> StateResult<BlockWithTxHahes>;
@@ -54,25 +120,68 @@ pub struct RpcStateReader {
     chain: ChainId,
     pub block_number: BlockNumber,
     inner: GatewayRpcStateReader,
+    config: RpcStateReaderConfig,
 }
 
 impl RpcStateReader {
+    /// Builds a reader of the given block, sending its requests to the
+    /// endpoint of the chain.
+    ///
+    /// Panics if the chain is not mainnet, testnet nor a chain of the chains
+    /// config, so chains should be parsed with `chains::parse_chain` first.
     pub fn new(chain: ChainId, block_number: BlockNumber) -> Self {
         let config = build_config(&chain);
 
         Self {
             inner: GatewayRpcStateReader::from_number(&config, block_number),
+            config,
             chain,
             block_number,
         }
     }
 
+    /// Builds a reader without a node to send its requests to, e.g. to only
+    /// read cached data
+    pub fn without_node(chain: ChainId, block_number: BlockNumber) -> Self {
+        let config = nodeless_config();
+
+        Self {
+            inner: GatewayRpcStateReader::from_number(&config, block_number),
+            config,
+            chain,
+            block_number,
+        }
+    }
+
+    pub fn block_id(&self) -> BlockId {
+        self.inner.block_id
+    }
+
     pub fn send_rpc_request_with_retry(
         &self,
         method: &str,
         params: impl Serialize,
     ) -> RPCStateReaderResult<Value> {
-        let result = retry(|| self.inner.send_rpc_request(method, &params));
+        let Some(fixtures) = fixtures::selected() else {
+            return self.send_to_node(method, &params);
+        };
+
+        let params = serde_json::to_value(&params).expect("rpc params are always serializable");
+        fixtures.serve(&self.chain, method, &params, || {
+            self.send_to_node(method, &params)
+        })
+    }
+
+    fn send_to_node(&self, method: &str, params: &impl Serialize) -> RPCStateReaderResult<Value> {
+        let result = retry(|| {
+            provider::throttle();
+            match provider::for_chain(&self.chain) {
+                Some(provider) if !provider.headers.is_empty() => {
+                    provider::send_rpc_request(&self.config, &provider.headers, method, params)
+                }
+                _ => self.inner.send_rpc_request(method, params),
+            }
+        });
 
         if let Err(RPCStateReaderError::ReqwestError(err)) = result {
             Err(RPCStateReaderError::ReqwestError(err.without_url()))
@@ -80,6 +189,87 @@ impl RpcStateReader {
             result
         }
     }
+
+    /// Returns the number of the latest block of the chain
+    pub fn get_latest_block_number(&self) -> StateResult<BlockNumber> {
+        let block_number = self.send_rpc_request_with_retry("starknet_blockNumber", json!([]))?;
+
+        serde_json::from_value(block_number).map_err(serde_err_to_state_err)
+    }
+
+    /// Returns the state changes applied by the block
+    pub fn get_state_update(&self) -> StateResult<RpcStateUpdate> {
+        let params = json!({ "block_id": self.inner.block_id });
+
+        deserialize_checked(
+            "starknet_getStateUpdate",
+            self.send_rpc_request_with_retry("starknet_getStateUpdate", params)?,
+        )
+    }
+
+    /// Sends the requests in JSON-RPC batches of up to `MAX_BATCH_SIZE` requests.
+    /// The batches are sent sequentially, blocking until each one completes.
+    ///
+    /// Returns the result of each request, in order, or `None` if it failed.
+    /// Failed requests are not retried, so callers are expected to fall back to
+    /// sending them individually. With RPC fixtures, the requests are sent
+    /// individually too, so that each one is recorded.
+    pub fn send_rpc_batch(&self, requests: &[(&str, Value)]) -> anyhow::Result<Vec<Option<Value>>> {
+        if fixtures::selected().is_some() {
+            return Ok(requests
+                .iter()
+                .map(|(method, params)| self.send_rpc_request_with_retry(method, params).ok())
+                .collect());
+        }
+
+        let mut results = Vec::with_capacity(requests.len());
+
+        for chunk in requests.chunks(MAX_BATCH_SIZE) {
+            let batch = chunk
+                .iter()
+                .enumerate()
+                .map(|(id, (method, params))| {
+                    json!({
+                        "jsonrpc": self.config.json_rpc_version,
+                        "id": id,
+                        "method": method,
+                        "params": params,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            provider::throttle();
+            let mut request = ureq::post(&self.config.url);
+            for (name, value) in provider::for_chain(&self.chain)
+                .iter()
+                .flat_map(|provider| &provider.headers)
+            {
+                request = request.set(name, value);
+            }
+            // The errors of ureq include the URL, which may contain the API key
+            let responses: Vec<Value> = request
+                .send_json(Value::Array(batch))
+                .map_err(|err| anyhow!(provider::redact(&err.to_string())))?
+                .into_json()?;
+
+            // The responses of a batch may arrive in any order
+            let mut chunk_results = vec![None; chunk.len()];
+            for mut response in responses {
+                let Some(result) = response
+                    .get("id")
+                    .and_then(Value::as_u64)
+                    .and_then(|id| chunk_results.get_mut(id as usize))
+                else {
+                    continue;
+                };
+                *result = response.get_mut("result").map(Value::take);
+            }
+
+            results.extend(chunk_results);
+        }
+
+        Ok(results)
+    }
 }
 
 impl StateReader for RpcStateReader {
@@ -89,25 +279,37 @@ impl StateReader for RpcStateReader {
             "class_hash": class_hash.to_hex_string(),
         });
 
-        serde_json::from_value(self.send_rpc_request_with_retry("starknet_getClass", params)?)
-            .map_err(serde_err_to_state_err)
+        deserialize_checked(
+            "starknet_getClass",
+            self.send_rpc_request_with_retry("starknet_getClass", params)?,
+        )
     }
 
     fn get_transaction_trace(&self, hash: &TransactionHash) -> StateResult<RpcTransactionTrace> {
         let params = json!([hash]);
 
-        serde_json::from_value(
+        deserialize_checked(
+            "starknet_traceTransaction",
             self.send_rpc_request_with_retry("starknet_traceTransaction", params)?,
         )
-        .map_err(serde_err_to_state_err)
     }
 
     fn get_transaction(&self, hash: &TransactionHash) -> StateResult<Transaction> {
         let params = json!([hash]);
 
-        let tx = self.send_rpc_request_with_retry("starknet_getTransactionByHash", params)?;
+        let mut response =
+            self.send_rpc_request_with_retry("starknet_getTransactionByHash", params)?;
+        // Checked against the fields as they are deserialized
+        objects::deser::normalize_transaction(&mut response);
+
+        let tx = objects::deser::transaction_from_json(response.clone())
+            .map_err(serde_err_to_state_err)?;
+
+        let deserialized =
+            objects::deser::transaction_to_json(&tx).map_err(serde_err_to_state_err)?;
+        schema::check("starknet_getTransactionByHash", &response, &deserialized)?;
 
-        objects::deser::transaction_from_json(tx).map_err(serde_err_to_state_err)
+        Ok(tx)
     }
 
     fn get_block_with_tx_hashes(&self) -> StateResult<BlockWithTxHahes> {
@@ -115,10 +317,10 @@ impl StateReader for RpcStateReader {
             block_id: self.inner.block_id,
         };
 
-        serde_json::from_value(
+        deserialize_checked(
+            "starknet_getBlockWithTxHashes",
             self.send_rpc_request_with_retry("starknet_getBlockWithTxHashes", params)?,
         )
-        .map_err(serde_err_to_state_err)
     }
 
     fn get_transaction_receipt(
@@ -127,10 +329,10 @@ impl StateReader for RpcStateReader {
     ) -> StateResult<RpcTransactionReceipt> {
         let params = json!([hash]);
 
-        serde_json::from_value(
+        deserialize_checked(
+            "starknet_getTransactionReceipt",
             self.send_rpc_request_with_retry("starknet_getTransactionReceipt", params)?,
         )
-        .map_err(serde_err_to_state_err)
     }
 
     fn get_chain_id(&self) -> ChainId {
@@ -138,7 +340,33 @@ impl StateReader for RpcStateReader {
     }
 }
 
+/// Deserializes the response of the method, checking it for schema drifts.
+/// See the `schema` module.
+fn deserialize_checked<T: DeserializeOwned + Serialize>(
+    method: &str,
+    response: Value,
+) -> StateResult<T> {
+    let value: T = serde_json::from_value(response.clone()).map_err(serde_err_to_state_err)?;
+
+    let deserialized = serde_json::to_value(&value).map_err(serde_err_to_state_err)?;
+    schema::check(method, &response, &deserialized)?;
+
+    Ok(value)
+}
+
 fn build_config(chain: &ChainId) -> RpcStateReaderConfig {
+    // Requests are never sent, so the endpoint doesn't need to be set
+    if fixtures::replaying() {
+        return nodeless_config();
+    }
+
+    if let Some(provider) = provider::for_chain(chain) {
+        return RpcStateReaderConfig {
+            url: provider.endpoint(),
+            json_rpc_version: "2.0".to_string(),
+        };
+    }
+
     let url = match chain {
         ChainId::Mainnet => {
             env::var("RPC_ENDPOINT_MAINNET").expect("Missing env var: RPC_ENDPOINT_MAINNET")
@@ -146,8 +374,16 @@ fn build_config(chain: &ChainId) -> RpcStateReaderConfig {
         ChainId::Sepolia => {
             env::var("RPC_ENDPOINT_TESTNET").expect("Missing env var: RPC_ENDPOINT_TESTNET")
         }
-        ChainId::IntegrationSepolia => todo!(),
-        ChainId::Other(_) => todo!(),
+        // Chains parsed with `chains::parse_chain` are always defined
+        ChainId::IntegrationSepolia | ChainId::Other(_) => chains::custom_chain(chain)
+            .unwrap_or_else(|| {
+                panic!(
+                    "chain {chain} is not defined in the chains config {}",
+                    chains::config_path()
+                )
+            })
+            .url
+            .clone(),
     };
 
     RpcStateReaderConfig {
@@ -156,12 +392,25 @@ fn build_config(chain: &ChainId) -> RpcStateReaderConfig {
     }
 }
 
+fn nodeless_config() -> RpcStateReaderConfig {
+    RpcStateReaderConfig {
+        url: String::new(),
+        json_rpc_version: "2.0".to_string(),
+    }
+}
+
 impl BlockifierStateReader for RpcStateReader {
     fn get_storage_at(
         &self,
         contract_address: ContractAddress,
         key: StorageKey,
     ) -> StateResult<cairo_vm::Felt252> {
+        if let Some(value) = pathfinder::read(|database| {
+            database.get_storage_at(self.block_number, contract_address, key)
+        })? {
+            return Ok(value);
+        }
+
         let get_storage_at_params = GetStorageAtParams {
             block_id: self.inner.block_id,
             contract_address,
@@ -171,7 +420,7 @@ impl BlockifierStateReader for RpcStateReader {
         let result =
             self.send_rpc_request_with_retry("starknet_getStorageAt", &get_storage_at_params);
         match result {
-            Ok(value) => Ok(serde_json::from_value(value).map_err(serde_err_to_state_err)?),
+            Ok(value) => deserialize_checked("starknet_getStorageAt", value),
             Err(RPCStateReaderError::ContractAddressNotFound(_)) => {
                 Ok(cairo_vm::Felt252::default())
             }
@@ -183,6 +432,12 @@ impl BlockifierStateReader for RpcStateReader {
         &self,
         contract_address: ContractAddress,
     ) -> StateResult<starknet_api::core::Nonce> {
+        if let Some(nonce) =
+            pathfinder::read(|database| database.get_nonce_at(self.block_number, contract_address))?
+        {
+            return Ok(nonce);
+        }
+
         let get_nonce_params = GetNonceParams {
             block_id: self.inner.block_id,
             contract_address,
@@ -190,16 +445,19 @@ impl BlockifierStateReader for RpcStateReader {
 
         let result = self.send_rpc_request_with_retry("starknet_getNonce", get_nonce_params);
         match result {
-            Ok(value) => {
-                let nonce: Nonce = serde_json::from_value(value).map_err(serde_err_to_state_err)?;
-                Ok(nonce)
-            }
+            Ok(value) => deserialize_checked("starknet_getNonce", value),
             Err(RPCStateReaderError::ContractAddressNotFound(_)) => Ok(Nonce::default()),
             Err(e) => Err(e)?,
         }
     }
 
     fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        if let Some(class_hash) = pathfinder::read(|database| {
+            database.get_class_hash_at(self.block_number, contract_address)
+        })? {
+            return Ok(class_hash);
+        }
+
         let get_class_hash_at_params = GetClassHashAtParams {
             contract_address,
             block_id: self.inner.block_id,
@@ -208,11 +466,7 @@ impl BlockifierStateReader for RpcStateReader {
         let result =
             self.send_rpc_request_with_retry("starknet_getClassHashAt", get_class_hash_at_params);
         match result {
-            Ok(value) => {
-                let class_hash: ClassHash =
-                    serde_json::from_value(value).map_err(serde_err_to_state_err)?;
-                Ok(class_hash)
-            }
+            Ok(value) => deserialize_checked("starknet_getClassHashAt", value),
             Err(RPCStateReaderError::ContractAddressNotFound(_)) => Ok(ClassHash::default()),
             Err(e) => Err(e)?,
         }
@@ -231,29 +485,109 @@ impl BlockifierStateReader for RpcStateReader {
 }
 
 pub fn compile_contract_class(class: SNContractClass, hash: ClassHash) -> RunnableCompiledClass {
+    compile_contract_class_with(class, hash, executor_for(hash))
+}
+
+/// Compiles the class for the given executor, regardless of the selected
+/// one. Legacy classes are always executed with the Cairo VM.
+pub fn compile_contract_class_with(
+    class: SNContractClass,
+    hash: ClassHash,
+    executor: Executor,
+) -> RunnableCompiledClass {
     match class {
         SNContractClass::Legacy(compressed_legacy_cc) => compile_legacy_cc(compressed_legacy_cc),
         SNContractClass::Sierra(flattened_sierra_cc) => {
-            compile_sierra_cc(flattened_sierra_cc, hash)
+            compile_sierra_cc(flattened_sierra_cc, hash, executor)
         }
     }
 }
 
-fn compile_sierra_cc(
+/// Returns the selectors of every entry point of the class (constructor,
+/// external and L1 handler).
+pub fn entry_point_selectors(class: &SNContractClass) -> Vec<EntryPointSelector> {
+    let selectors = match class {
+        SNContractClass::Sierra(sierra_cc) => {
+            let entry_points = &sierra_cc.entry_points_by_type;
+            [
+                &entry_points.constructor,
+                &entry_points.external,
+                &entry_points.l1_handler,
+            ]
+            .into_iter()
+            .flatten()
+            .map(|entry_point| entry_point.selector)
+            .collect::<Vec<_>>()
+        }
+        SNContractClass::Legacy(legacy_cc) => {
+            let entry_points = &legacy_cc.entry_points_by_type;
+            [
+                &entry_points.constructor,
+                &entry_points.external,
+                &entry_points.l1_handler,
+            ]
+            .into_iter()
+            .flatten()
+            .map(|entry_point| entry_point.selector)
+            .collect::<Vec<_>>()
+        }
+    };
+
+    selectors
+        .into_iter()
+        .map(|selector| EntryPointSelector(StarkHash::from_bytes_be(&selector.to_bytes_be())))
+        .collect()
+}
+
+/// Starts compiling the Sierra classes to native in the background, so that
+/// they are ready by the time they are executed. Classes executed with
+/// another executor are skipped.
+pub fn precompile_contract_classes(
+    classes: impl IntoIterator<Item = (ClassHash, SNContractClass)>,
+) {
+    let sierra_classes = classes
+        .into_iter()
+        .filter(|(class_hash, _)| executor_for(*class_hash) == Executor::Native)
+        .filter_map(|(class_hash, class)| Some((class_hash, sierra_contract_class(class)?)))
+        .collect();
+
+    utils::compile_native_in_background(sierra_classes);
+}
+
+/// Returns the Sierra class to compile, or `None` for legacy classes
+pub fn sierra_contract_class(
+    class: SNContractClass,
+) -> Option<cairo_lang_starknet_classes::contract_class::ContractClass> {
+    match class {
+        SNContractClass::Sierra(flattened_sierra_cc) => {
+            Some(to_sierra_contract_class(flattened_sierra_cc))
+        }
+        SNContractClass::Legacy(_) => None,
+    }
+}
+
+fn to_sierra_contract_class(
     flattened_sierra_cc: starknet::core::types::FlattenedSierraClass,
-    class_hash: ClassHash,
-) -> RunnableCompiledClass {
+) -> cairo_lang_starknet_classes::contract_class::ContractClass {
     let middle_sierra: utils::MiddleSierraContractClass = {
         let v = serde_json::to_value(flattened_sierra_cc).unwrap();
         serde_json::from_value(v).unwrap()
     };
-    let sierra_cc = cairo_lang_starknet_classes::contract_class::ContractClass {
+    cairo_lang_starknet_classes::contract_class::ContractClass {
         sierra_program: middle_sierra.sierra_program,
         contract_class_version: middle_sierra.contract_class_version,
         entry_points_by_type: middle_sierra.entry_points_by_type,
         sierra_program_debug_info: None,
         abi: None,
-    };
+    }
+}
+
+fn compile_sierra_cc(
+    flattened_sierra_cc: starknet::core::types::FlattenedSierraClass,
+    class_hash: ClassHash,
+    executor: Executor,
+) -> RunnableCompiledClass {
+    let sierra_cc = to_sierra_contract_class(flattened_sierra_cc);
 
     let _span = info_span!(
         "contract compilation",
@@ -262,21 +596,21 @@ fn compile_sierra_cc(
     )
     .entered();
 
-    if cfg!(feature = "only_casm") {
-        let casm_compiled_class = get_casm_compiled_class(sierra_cc, class_hash);
-        RunnableCompiledClass::V1(casm_compiled_class)
-    } else {
-        let executor = if cfg!(feature = "with-sierra-emu") {
+    let executor = match executor {
+        Executor::Vm => {
+            let casm_compiled_class = get_casm_compiled_class(sierra_cc, class_hash);
+            return RunnableCompiledClass::V1(casm_compiled_class);
+        }
+        Executor::SierraEmu => {
             let program = Arc::new(sierra_cc.extract_sierra_program().unwrap());
             (program, sierra_cc.entry_points_by_type.clone()).into()
-        } else {
-            get_native_executor(&sierra_cc, class_hash).into()
-        };
+        }
+        Executor::Native => get_native_executor(&sierra_cc, class_hash).into(),
+    };
 
-        let casm_compiled_class = get_casm_compiled_class(sierra_cc, class_hash);
+    let casm_compiled_class = get_casm_compiled_class(sierra_cc, class_hash);
 
-        RunnableCompiledClass::V1Native(NativeCompiledClassV1::new(executor, casm_compiled_class))
-    }
+    RunnableCompiledClass::V1Native(NativeCompiledClassV1::new(executor, casm_compiled_class))
 }
 
 fn compile_legacy_cc(
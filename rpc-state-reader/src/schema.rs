@@ -0,0 +1,329 @@
+//! Detection of changes in the schema of RPC responses.
+//!
+//! Each checked response is compared against the re-serialization of the
+//! value it was deserialized into. Fields only present in the response are
+//! unknown to us, and fields that were absent from the response but have
+//! a non-null value after deserializing were silently defaulted.
+//!
+//! Every drift is logged once per method and field. In strict mode, responses
+//! with drifts are also rejected.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use blockifier::state::errors::StateError;
+use serde_json::{Map, Value};
+use tracing::warn;
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+static REPORTED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// Differences between the response of a method and the types we
+/// deserialize it into
+struct Schema {
+    method: &'static str,
+    /// Paths of the fields that are returned by the node, but that we
+    /// intentionally don't deserialize. See [`path_matches`].
+    ignored: &'static [&'static str],
+    /// Fields of the response that are deserialized under another name, by
+    /// path in the response
+    aliases: &'static [(&'static str, &'static str)],
+}
+
+const SCHEMAS: &[Schema] = &[
+    Schema {
+        method: "starknet_getTransactionReceipt",
+        ignored: &[
+            "finality_status",
+            "execution_resources",
+            "contract_address",
+            "message_hash",
+        ],
+        aliases: &[],
+    },
+    Schema {
+        method: "starknet_traceTransaction",
+        ignored: &[
+            "type",
+            "execution_resources",
+            "state_diff.deprecated_declared_classes",
+            "state_diff.declared_classes",
+            // Fields of the calls, at any depth
+            "*_invocation.**.caller_address",
+            "*_invocation.**.class_hash",
+            "*_invocation.**.entry_point_type",
+            "*_invocation.**.call_type",
+            "*_invocation.**.events",
+            "*_invocation.**.messages",
+            "*_invocation.**.execution_resources",
+            "*_invocation.**.is_reverted",
+        ],
+        aliases: &[
+            ("constructor_invocation", "execute_invocation"),
+            ("function_invocation", "execute_invocation"),
+        ],
+    },
+    Schema {
+        method: "starknet_getStateUpdate",
+        ignored: &[
+            "state_diff.deprecated_declared_classes",
+            "state_diff.declared_classes",
+        ],
+        aliases: &[],
+    },
+    Schema {
+        method: "starknet_getTransactionByHash",
+        // The type and the version select the variant, and the hash is
+        // computed instead
+        ignored: &["type", "version", "transaction_hash"],
+        aliases: &[],
+    },
+];
+
+impl Schema {
+    fn of(method: &str) -> &'static Schema {
+        const EMPTY: Schema = Schema {
+            method: "",
+            ignored: &[],
+            aliases: &[],
+        };
+
+        SCHEMAS
+            .iter()
+            .find(|schema| schema.method == method)
+            .unwrap_or(&EMPTY)
+    }
+
+    fn is_ignored(&self, path: &str) -> bool {
+        self.ignored
+            .iter()
+            .any(|pattern| path_matches(pattern, path))
+    }
+
+    /// Name of the field after deserializing it
+    fn deserialized_name<'a>(&self, path: &str, field: &'a str) -> &'a str {
+        match self.aliases.iter().find(|(alias, _)| *alias == path) {
+            Some((_, name)) => *name,
+            None => field,
+        }
+    }
+}
+
+/// Rejects responses that don't match the expected schema
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Compares the response of the method against the re-serialization of
+/// its deserialized value, logging every drift. In strict mode, fails if
+/// there is any drift.
+pub fn check(method: &str, response: &Value, deserialized: &Value) -> Result<(), StateError> {
+    let mut drifts = Vec::new();
+    find_drifts("", response, deserialized, Schema::of(method), &mut drifts);
+
+    if drifts.is_empty() {
+        return Ok(());
+    }
+    drifts.sort();
+    drifts.dedup();
+
+    let mut reported = REPORTED.get_or_init(Default::default).lock().unwrap();
+    for drift in &drifts {
+        if reported.insert(format!("{method}: {drift}")) {
+            warn!(method, "rpc schema drift: {drift}");
+        }
+    }
+
+    if STRICT.load(Ordering::Relaxed) {
+        return Err(StateError::StateReadError(format!(
+            "response of {method} doesn't match the expected schema: {}",
+            drifts.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+fn find_drifts(
+    path: &str,
+    response: &Value,
+    deserialized: &Value,
+    schema: &Schema,
+    drifts: &mut Vec<String>,
+) {
+    match (response, deserialized) {
+        (Value::Object(response), Value::Object(deserialized)) => {
+            find_object_drifts(path, response, deserialized, schema, drifts)
+        }
+        (Value::Array(response), Value::Array(deserialized)) => {
+            // Elements share the same path, so drifts are reported once per field
+            let path = format!("{path}[]");
+            for (response, deserialized) in response.iter().zip(deserialized) {
+                find_drifts(&path, response, deserialized, schema, drifts);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn find_object_drifts(
+    path: &str,
+    response: &Map<String, Value>,
+    deserialized: &Map<String, Value>,
+    schema: &Schema,
+    drifts: &mut Vec<String>,
+) {
+    let field_path = |field: &str| {
+        if path.is_empty() {
+            field.to_string()
+        } else {
+            format!("{path}.{field}")
+        }
+    };
+
+    let response: Vec<_> = response
+        .iter()
+        .map(|(field, value)| {
+            let path = field_path(field);
+            (schema.deserialized_name(&path, field), path, value)
+        })
+        .collect();
+
+    for (field, path, value) in &response {
+        match deserialized.get(*field) {
+            Some(deserialized_value) => {
+                find_drifts(path, value, deserialized_value, schema, drifts)
+            }
+            None if !schema.is_ignored(path) => drifts.push(format!("unknown field `{path}`")),
+            None => {}
+        }
+    }
+
+    for (field, value) in deserialized {
+        let in_response = response.iter().any(|(name, ..)| name == field);
+        if !in_response && !value.is_null() {
+            drifts.push(format!("missing field `{}`", field_path(field)));
+        }
+    }
+}
+
+/// Matches a path of the response against a pattern of the same form, with
+/// segments separated by dots and array elements marked with `[]`.
+///
+/// In a pattern, a `*` within a segment matches any part of a segment, and a
+/// `**` segment matches any number of segments.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[&str], path: &[&str]) -> bool {
+        match (pattern.split_first(), path.split_first()) {
+            (None, None) => true,
+            (Some((&"**", rest)), _) => {
+                matches(rest, path) || (!path.is_empty() && matches(pattern, &path[1..]))
+            }
+            (Some((segment, pattern)), Some((field, path))) => {
+                segment_matches(segment, field) && matches(pattern, path)
+            }
+            _ => false,
+        }
+    }
+
+    fn segment_matches(segment: &str, field: &str) -> bool {
+        match segment.split_once('*') {
+            Some((prefix, suffix)) => {
+                field.len() >= prefix.len() + suffix.len()
+                    && field.starts_with(prefix)
+                    && field.ends_with(suffix)
+            }
+            None => segment == field,
+        }
+    }
+
+    let pattern: Vec<_> = pattern.split('.').collect();
+    let path: Vec<_> = path.split('.').collect();
+    matches(&pattern, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_find_drifts() {
+        let response = json!({
+            "a": 1,
+            "b": [{ "c": 2, "new": 3 }, { "c": 4, "new": 5 }],
+            "finality_status": "ACCEPTED_ON_L2",
+            "nested": { "finality_status": "ACCEPTED_ON_L2" },
+        });
+        let deserialized = json!({
+            "a": 1,
+            "b": [{ "c": 2 }, { "c": 4 }],
+            "nested": {},
+            "optional": null,
+            "defaulted": [],
+        });
+
+        let mut drifts = Vec::new();
+        let schema = Schema::of("starknet_getTransactionReceipt");
+        find_drifts("", &response, &deserialized, schema, &mut drifts);
+
+        assert_eq!(
+            drifts,
+            [
+                "unknown field `b[].new`",
+                "unknown field `b[].new`",
+                "unknown field `nested.finality_status`",
+                "missing field `defaulted`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_trace_drifts() {
+        let response = json!({
+            "constructor_invocation": {
+                "class_hash": "0x1",
+                "calls": [{ "class_hash": "0x2", "new": 3 }],
+            },
+            "class_hash": "0x1",
+        });
+        let deserialized = json!({
+            "execute_invocation": { "calls": [{}] },
+        });
+
+        let mut drifts = Vec::new();
+        let schema = Schema::of("starknet_traceTransaction");
+        find_drifts("", &response, &deserialized, schema, &mut drifts);
+
+        assert_eq!(
+            drifts,
+            [
+                "unknown field `class_hash`",
+                "unknown field `constructor_invocation.calls[].new`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("finality_status", "finality_status"));
+        assert!(!path_matches("finality_status", "a.finality_status"));
+        assert!(path_matches("b[].new", "b[].new"));
+        assert!(path_matches(
+            "*_invocation.**.events",
+            "execute_invocation.events"
+        ));
+        assert!(path_matches(
+            "*_invocation.**.events",
+            "validate_invocation.calls[].calls[].events"
+        ));
+        assert!(!path_matches("*_invocation.**.events", "events"));
+        assert!(!path_matches("*_invocation.**.events", "state_diff.events"));
+    }
+}
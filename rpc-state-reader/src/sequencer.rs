@@ -0,0 +1,106 @@
+//! Adapters implementing the state reader traits of the sequencer's gateway,
+//! backed by the cached RPC reader.
+//!
+//! They allow running the sequencer components that read state (e.g. the
+//! gateway's stateful validation) on top of mainnet state, sharing this
+//! crate's caches. See `examples/shadow_execution.rs`.
+
+use std::sync::Mutex;
+
+use blockifier::{
+    execution::contract_class::RunnableCompiledClass,
+    state::{
+        errors::StateError,
+        state_api::{StateReader as BlockifierStateReader, StateResult},
+    },
+};
+use cairo_vm::Felt252;
+use starknet_api::{
+    block::{BlockInfo, BlockNumber},
+    core::{ChainId, ClassHash, CompiledClassHash, ContractAddress, Nonce},
+    state::StorageKey,
+};
+use starknet_gateway::state_reader::{MempoolStateReader, StateReaderFactory};
+
+use crate::{
+    cache::RpcCachedStateReader,
+    execution::get_block_info,
+    reader::{RpcStateReader, StateReader},
+};
+
+/// A cached reader of the state at the end of the given block.
+///
+/// The cached reader is not thread safe, so it's accessed behind a lock.
+pub struct SequencerStateReader {
+    reader: Mutex<RpcCachedStateReader>,
+}
+
+impl SequencerStateReader {
+    pub fn new(chain: ChainId, block_number: BlockNumber) -> Self {
+        Self {
+            reader: Mutex::new(RpcCachedStateReader::new(RpcStateReader::new(
+                chain,
+                block_number,
+            ))),
+        }
+    }
+}
+
+impl BlockifierStateReader for SequencerStateReader {
+    fn get_storage_at(
+        &self,
+        contract_address: ContractAddress,
+        key: StorageKey,
+    ) -> StateResult<Felt252> {
+        self.reader
+            .lock()
+            .unwrap()
+            .get_storage_at(contract_address, key)
+    }
+
+    fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
+        self.reader.lock().unwrap().get_nonce_at(contract_address)
+    }
+
+    fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
+        self.reader
+            .lock()
+            .unwrap()
+            .get_class_hash_at(contract_address)
+    }
+
+    fn get_compiled_class(&self, class_hash: ClassHash) -> StateResult<RunnableCompiledClass> {
+        self.reader.lock().unwrap().get_compiled_class(class_hash)
+    }
+
+    fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.reader
+            .lock()
+            .unwrap()
+            .get_compiled_class_hash(class_hash)
+    }
+}
+
+impl MempoolStateReader for SequencerStateReader {
+    fn get_block_info(&self) -> Result<BlockInfo, StateError> {
+        let block = self.reader.lock().unwrap().get_block_with_tx_hashes()?;
+        Ok(get_block_info(block.header))
+    }
+}
+
+/// Creates the state readers of the chain requested by the sequencer.
+pub struct SequencerStateReaderFactory {
+    pub chain: ChainId,
+}
+
+impl StateReaderFactory for SequencerStateReaderFactory {
+    fn get_state_reader_from_latest_block(&self) -> StateResult<Box<dyn MempoolStateReader>> {
+        let latest_block =
+            RpcStateReader::new(self.chain.clone(), BlockNumber(0)).get_latest_block_number()?;
+        Ok(self.get_state_reader(latest_block))
+    }
+
+    fn get_state_reader(&self, block_number: BlockNumber) -> Box<dyn MempoolStateReader> {
+        Box::new(SequencerStateReader::new(self.chain.clone(), block_number))
+    }
+}
@@ -1,17 +1,27 @@
 use std::{
+    cmp::Reverse,
     collections::HashMap,
-    fs::{self},
+    fs::{self, File},
     io::{self, Read},
-    path::PathBuf,
-    sync::{OnceLock, RwLock},
-    time::Instant,
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use blockifier::execution::contract_class::CompiledClassV1;
 use cairo_lang_starknet_classes::contract_class::{ContractClass, ContractEntryPoints};
 use cairo_lang_utils::bigint::BigUintAsHex;
 use cairo_native::{executor::AotContractExecutor, OptLevel};
-use serde::Deserialize;
+use fs2::FileExt;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
 use starknet::core::types::{LegacyContractEntryPoint, LegacyEntryPointsByType};
 use starknet_api::{
     contract_class::{EntryPointType, SierraVersion},
@@ -19,7 +29,9 @@ use starknet_api::{
     deprecated_contract_class::{EntryPointOffset, EntryPointV0},
     hash::StarkHash,
 };
-use tracing::info;
+use tracing::{info, info_span};
+
+use crate::cache::{record_reads, CacheCategory};
 
 #[derive(Debug, Deserialize)]
 pub struct MiddleSierraContractClass {
@@ -28,8 +40,124 @@ pub struct MiddleSierraContractClass {
     pub entry_points_by_type: ContractEntryPoints,
 }
 
-static AOT_PROGRAM_CACHE: OnceLock<RwLock<HashMap<ClassHash, AotContractExecutor>>> =
+/// Executors by class hash, by last use. Each entry is initialized by the
+/// first thread that requests it, while any other thread requesting it
+/// blocks until the compilation finishes, so every class is compiled once.
+static AOT_PROGRAM_CACHE: OnceLock<Mutex<LruCache<ClassHash, Arc<OnceLock<AotContractExecutor>>>>> =
     OnceLock::new();
+/// Maximum amount of loaded executors. Zero means unlimited.
+static EXECUTOR_LIMIT: AtomicUsize = AtomicUsize::new(0);
+static EXECUTOR_UNLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Optimization level of the native compilations, from 0 to 3
+static NATIVE_OPT_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_NATIVE_OPT_LEVEL);
+pub const DEFAULT_NATIVE_OPT_LEVEL: u8 = 3;
+
+/// Version of cairo-native this replay is built with
+const CAIRO_NATIVE_VERSION: &str = env!("CAIRO_NATIVE_VERSION");
+
+/// How a native library was compiled, saved along with it. Libraries
+/// compiled differently are compiled again when loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct NativeBuild {
+    cairo_native_version: String,
+    opt_level: u8,
+}
+
+/// Sets the optimization level of the native compilations (from 0 to 3),
+/// e.g. to trade execution speed for compilation time.
+///
+/// Libraries of each level are cached separately, so runs with different
+/// levels can share the `compiled_programs` directory.
+pub fn set_native_opt_level(opt_level: u8) {
+    assert!(opt_level <= 3, "invalid optimization level: {opt_level}");
+    NATIVE_OPT_LEVEL.store(opt_level, Ordering::Relaxed);
+}
+
+fn native_build() -> NativeBuild {
+    NativeBuild {
+        cairo_native_version: CAIRO_NATIVE_VERSION.to_string(),
+        opt_level: NATIVE_OPT_LEVEL.load(Ordering::Relaxed),
+    }
+}
+
+/// Reads how a native library was compiled, if it was saved
+fn read_native_build(library_path: &Path) -> Option<NativeBuild> {
+    let build = fs::read_to_string(library_path.with_extension("build.json")).ok()?;
+    serde_json::from_str(&build).ok()
+}
+
+/// Path of the native library of the class, compiled with the optimization
+/// level
+fn native_library_path(class_hash: ClassHash, opt_level: u8) -> PathBuf {
+    let mut path = PathBuf::from("compiled_programs");
+    // The default level keeps the path of the libraries compiled before it
+    // could be chosen
+    if opt_level != DEFAULT_NATIVE_OPT_LEVEL {
+        path.push(format!("O{opt_level}"));
+    }
+    path.push(format!(
+        "{}.{}",
+        class_hash.to_hex_string(),
+        if cfg!(target_os = "macos") {
+            "dylib"
+        } else {
+            "so"
+        }
+    ));
+    path
+}
+
+fn parse_opt_level(level: u8) -> OptLevel {
+    match level {
+        0 => OptLevel::None,
+        1 => OptLevel::Less,
+        2 => OptLevel::Default,
+        3 => OptLevel::Aggressive,
+        level => panic!("invalid optimization level: {level}"),
+    }
+}
+
+/// Approximate memory held by each compiled class loaded by this process
+static CLASS_FOOTPRINTS: OnceLock<Mutex<HashMap<ClassHash, ClassFootprint>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ClassFootprint {
+    /// Size of the CASM bytecode, as felts
+    pub casm_bytes: u64,
+    /// Size of the mapped native library
+    pub native_bytes: u64,
+}
+
+impl ClassFootprint {
+    pub fn total_bytes(&self) -> u64 {
+        self.casm_bytes + self.native_bytes
+    }
+}
+
+fn record_footprint(class_hash: ClassHash, update: impl FnOnce(&mut ClassFootprint)) {
+    update(
+        CLASS_FOOTPRINTS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(class_hash)
+            .or_default(),
+    );
+}
+
+/// Returns the footprint of every loaded class, from the largest to the smallest
+pub fn class_footprints() -> Vec<(ClassHash, ClassFootprint)> {
+    let mut footprints = CLASS_FOOTPRINTS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(class_hash, footprint)| (*class_hash, *footprint))
+        .collect::<Vec<_>>();
+    footprints.sort_by_key(|(_, footprint)| Reverse(footprint.total_bytes()));
+    footprints
+}
 
 pub fn map_entry_points_by_type_legacy(
     entry_points_by_type: LegacyEntryPointsByType,
@@ -73,63 +201,149 @@ pub fn decode_reader(bytes: Vec<u8>) -> io::Result<String> {
     Ok(s)
 }
 
+/// Limits the amount of native executors kept loaded, unloading the least
+/// recently used ones when exceeded, e.g. so that long ranges don't keep
+/// tens of thousands of libraries mapped.
+///
+/// Unloaded classes are loaded again from `compiled_programs` when needed.
+/// Their library is only unmapped once no execution uses it.
+pub fn set_native_executor_limit(limit: Option<usize>) {
+    EXECUTOR_LIMIT.store(limit.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Number of native executors unloaded to stay within the limit
+pub fn native_executor_unloads() -> u64 {
+    EXECUTOR_UNLOADS.load(Ordering::Relaxed)
+}
+
 pub fn get_native_executor(contract: &ContractClass, class_hash: ClassHash) -> AotContractExecutor {
-    let cache_lock = AOT_PROGRAM_CACHE.get_or_init(|| RwLock::new(HashMap::new()));
-
-    let executor = cache_lock.read().unwrap().get(&class_hash).cloned();
-
-    match executor {
-        Some(executor) => executor,
-        None => {
-            let mut cache = cache_lock.write().unwrap();
-            let path = PathBuf::from(format!(
-                "compiled_programs/{}.{}",
-                class_hash.to_hex_string(),
-                {
-                    if cfg!(target_os = "macos") {
-                        "dylib"
-                    } else {
-                        "so"
-                    }
-                }
-            ));
-
-            let executor = if path.exists() {
-                AotContractExecutor::load(&path).unwrap()
-            } else {
-                info!("starting native contract compilation");
-
-                let pre_compilation_instant = Instant::now();
-                let mut executor = AotContractExecutor::new(
-                    &contract.extract_sierra_program().unwrap(),
-                    &contract.entry_points_by_type,
-                    OptLevel::Aggressive,
-                )
-                .unwrap();
-                let compilation_time = pre_compilation_instant.elapsed().as_millis();
-
-                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-                executor.save(&path).unwrap();
-
-                let library_size = fs::metadata(path).unwrap().len();
-
-                info!(
-                    time = compilation_time,
-                    size = library_size,
-                    "native contract compilation finished"
-                );
-
-                executor
+    let executor = {
+        let mut executors = AOT_PROGRAM_CACHE
+            .get_or_init(|| Mutex::new(LruCache::unbounded()))
+            .lock()
+            .unwrap();
+        let executor = executors
+            .get_or_insert(class_hash, Default::default)
+            .clone();
+
+        let limit = EXECUTOR_LIMIT.load(Ordering::Relaxed);
+        while limit != 0 && executors.len() > limit {
+            if let Some((class_hash, _)) = executors.pop_lru() {
+                record_footprint(class_hash, |footprint| footprint.native_bytes = 0);
+            }
+            EXECUTOR_UNLOADS.fetch_add(1, Ordering::Relaxed);
+        }
+        executor
+    };
+
+    executor
+        .get_or_init(|| load_or_compile_native(contract, class_hash))
+        .clone()
+}
+
+/// Compiles the classes to native in background threads, so that they
+/// compile concurrently instead of one at a time when first executed.
+///
+/// Executing a class that is still compiling blocks until it's ready.
+pub fn compile_native_in_background(classes: Vec<(ClassHash, ContractClass)>) {
+    let threads = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(classes.len());
+    let queue = Arc::new(Mutex::new(classes));
+
+    for _ in 0..threads {
+        let queue = queue.clone();
+        thread::spawn(move || loop {
+            let Some((class_hash, contract)) = queue.lock().unwrap().pop() else {
+                break;
             };
+            let _span = info_span!(
+                "background contract compilation",
+                class_hash = class_hash.to_hex_string()
+            )
+            .entered();
+            get_native_executor(&contract, class_hash);
+        });
+    }
+}
+
+fn load_or_compile_native(contract: &ContractClass, class_hash: ClassHash) -> AotContractExecutor {
+    let build = native_build();
+    let path = native_library_path(class_hash, build.opt_level);
 
-            cache.insert(class_hash, executor.clone());
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    // Held until the class is loaded, so that other processes compiling the
+    // same class wait for it and then load it. Closing the file unlocks it.
+    let lock = File::create(path.with_extension("lock")).unwrap();
+    lock.lock_exclusive().unwrap();
 
-            executor
+    if path.exists() {
+        // Libraries compiled before the build was saved have none, so they
+        // are compiled again too
+        match read_native_build(&path) {
+            Some(saved) if saved == build => {
+                record_reads(CacheCategory::CompiledClass, 1, 0);
+                let executor = AotContractExecutor::load(&path).unwrap();
+                let library_size = fs::metadata(&path).unwrap().len();
+                record_footprint(class_hash, |footprint| {
+                    footprint.native_bytes = library_size
+                });
+                return executor;
+            }
+            saved => info!(
+                compiled_with = saved.map(|saved| saved.cairo_native_version),
+                "native library is stale, compiling it again"
+            ),
         }
     }
+
+    record_reads(CacheCategory::CompiledClass, 0, 1);
+    info!(
+        opt_level = build.opt_level,
+        "starting native contract compilation"
+    );
+
+    let pre_compilation_instant = Instant::now();
+    let mut executor = AotContractExecutor::new(
+        &contract.extract_sierra_program().unwrap(),
+        &contract.entry_points_by_type,
+        parse_opt_level(build.opt_level),
+    )
+    .unwrap();
+    let compilation_time = pre_compilation_instant.elapsed().as_millis();
+
+    // Saved to a temporary path first, so that other processes (which
+    // may not honor the lock) never load a partially written library
+    let tmp_path = path.with_extension(format!("{}.tmp", process::id()));
+    executor.save(&tmp_path).unwrap();
+    let tmp_info_path = tmp_path.with_extension("json");
+    if tmp_info_path.exists() {
+        fs::rename(tmp_info_path, path.with_extension("json")).unwrap();
+    }
+    fs::rename(&tmp_path, &path).unwrap();
+    // Saved last, so that an interrupted compilation is never taken as
+    // up to date
+    fs::write(
+        path.with_extension("build.json"),
+        serde_json::to_string(&build).unwrap(),
+    )
+    .unwrap();
+
+    let library_size = fs::metadata(path).unwrap().len();
+    record_footprint(class_hash, |footprint| {
+        footprint.native_bytes = library_size
+    });
+
+    info!(
+        time = compilation_time,
+        size = library_size,
+        "native contract compilation finished"
+    );
+
+    executor
 }
 
-pub fn get_casm_compiled_class(class: ContractClass, _class_hash: ClassHash) -> CompiledClassV1 {
+pub fn get_casm_compiled_class(class: ContractClass, class_hash: ClassHash) -> CompiledClassV1 {
     let sierra_program_values = class
         .sierra_program
         .iter()
@@ -158,11 +372,64 @@ pub fn get_casm_compiled_class(class: ContractClass, _class_hash: ClassHash) ->
         "vm contract compilation finished"
     );
 
+    let casm_size = (casm_class.bytecode.len() * mem::size_of::<StarkHash>()) as u64;
+    record_footprint(class_hash, |footprint| footprint.casm_bytes = casm_size);
+
     let versioned_casm = (casm_class, sierra_version);
 
     CompiledClassV1::try_from(versioned_casm).unwrap()
 }
 
+/// Compiles the class to native with the given optimization level (from 0
+/// to 3), without caching it, returning the compilation time and the size
+/// of the library.
+pub fn measure_native_compilation(
+    contract: &ContractClass,
+    class_hash: ClassHash,
+    opt_level: u8,
+) -> (Duration, u64) {
+    let pre_compilation_instant = Instant::now();
+    let mut executor = AotContractExecutor::new(
+        &contract.extract_sierra_program().unwrap(),
+        &contract.entry_points_by_type,
+        parse_opt_level(opt_level),
+    )
+    .unwrap();
+    let compilation_time = pre_compilation_instant.elapsed();
+
+    let path = std::env::temp_dir().join(format!(
+        "replay-{}-O{opt_level}.{}",
+        class_hash.to_hex_string(),
+        if cfg!(target_os = "macos") {
+            "dylib"
+        } else {
+            "so"
+        }
+    ));
+    executor.save(&path).unwrap();
+    let library_size = fs::metadata(&path).unwrap().len();
+    let _ = fs::remove_file(&path);
+
+    (compilation_time, library_size)
+}
+
+/// Compiles the class to CASM, without caching it, returning the
+/// compilation time and the size of the bytecode, as felts.
+pub fn measure_casm_compilation(contract: ContractClass) -> (Duration, u64) {
+    let pre_compilation_instant = Instant::now();
+    let casm_class =
+        cairo_lang_starknet_classes::casm_contract_class::CasmContractClass::from_contract_class(
+            contract,
+            false,
+            usize::MAX,
+        )
+        .unwrap();
+    let compilation_time = pre_compilation_instant.elapsed();
+
+    let casm_size = (casm_class.bytecode.len() * mem::size_of::<StarkHash>()) as u64;
+    (compilation_time, casm_size)
+}
+
 pub fn bytecode_size(data: &[BigUintAsHex]) -> usize {
     data.iter().map(|n| n.value.to_bytes_be().len()).sum()
 }
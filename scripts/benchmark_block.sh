@@ -18,11 +18,10 @@ if ! [ "$#" -ge "4" ]; then
 fi
 
 DIR=$(dirname "$0")
-NATIVE_TARGET=$DIR/../target/release/replay-bench-native
-VM_TARGET=$DIR/../target/release/replay-bench-vm
+TARGET=$DIR/../target/release/replay-bench
 PLOTTING_SCRIPT=$DIR/../plotting/plot_execution_time.py
 
-if [ ! -x "$NATIVE_TARGET" ] || [ ! -x "$VM_TARGET" ]; then
+if [ ! -x "$TARGET" ]; then
     echo "benchmark target is missing, please run: make deps-bench"
     exit 1
 fi
@@ -46,7 +45,7 @@ vm_data_output="$DATA_DIR/vm-$data_output"
 plotting_output="$DATA_DIR/plot-$START-$END-$NET"
 
 echo "Executing with Native"
-$NATIVE_TARGET bench-block-range "$START" "$END" "$NET" "$LAPS" -o "$native_data_output" > "$native_log_output"
+$TARGET --executor native bench-block-range "$START" "$END" "$NET" "$LAPS" -o "$native_data_output" > "$native_log_output"
 
 native_time_secs=$(jq .average_time.secs "$native_data_output")
 native_time_nanos=$(jq .average_time.nanos "$native_data_output")
@@ -54,7 +53,7 @@ native_time=$(bc -l <<< "$native_time_secs * 1000000000 + $native_time_nanos")
 echo "Average Native time: $native_time ns"
 
 echo "Executing with VM"
-$VM_TARGET bench-block-range "$START" "$END" "$NET" "$LAPS" -o "$vm_data_output" > "$vm_log_output"
+$TARGET --executor vm bench-block-range "$START" "$END" "$NET" "$LAPS" -o "$vm_data_output" > "$vm_log_output"
 
 vm_time_secs=$(jq .average_time.secs "$vm_data_output")
 vm_time_nanos=$(jq .average_time.nanos "$vm_data_output")